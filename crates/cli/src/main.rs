@@ -1,11 +1,30 @@
-use std::path::PathBuf;
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
 
 use anyhow::Result;
 use clap::{Parser, Subcommand};
 use common::{
-    import::csv_to_bom,
-    models::{Part, User},
+    backend::{PartsBackend, check_variant},
+    buildable::{build_stock, buildable_together, unreserved_stock},
+    error::is_unauthorized,
+    export::{ExportFormat, export_purchase_plan},
+    import::{
+        csv_to_bom, csv_to_headers, is_workbook, link_existing, schematic_file_to_bom,
+        workbook_to_bom, workbook_to_headers,
+    },
+    kicad::kicad_csv_columns,
+    local::LocalBackend,
+    mapping::{ColumnMapping, guess_columns},
+    matching::{DismissedDuplicates, MatchKind, find_duplicates},
+    models::{
+        BinLocation, BomChange, BuildStatus, Part, PurchaseRules, SupplierSku, User,
+        designators_to_string, diff_boms, draw_with_alternates, select_variant,
+    },
     network::NetworkClient,
+    parameters::PartQuery,
+    planner::{PlannedBuild, purchase_plan},
 };
 use tabled::{
     Table, Tabled,
@@ -24,6 +43,64 @@ fn print_table(rows: &[impl Tabled]) {
     }
 }
 
+/// A line of `show-bom`, with its designators spelled out
+#[derive(Tabled)]
+struct BomRow {
+    id: i64,
+    name: String,
+    description: String,
+    count: i64,
+    designators: String,
+    stock: i64,
+    alternates: String,
+}
+
+/// A part of `find-duplicates`, numbered by the group of look-alikes it is in
+#[derive(Tabled)]
+struct DuplicateRow {
+    group: usize,
+    kind: MatchKind,
+    id: i64,
+    name: String,
+    description: String,
+    mpn: String,
+}
+
+/// A line of `bom-diff`, with the designators that came and went
+#[derive(Tabled)]
+struct DiffRow {
+    change: BomChange,
+    id: i64,
+    name: String,
+    old_count: i64,
+    new_count: i64,
+    designators: String,
+}
+
+/// A BOM of `where-used`, with how many boards the available stock of the part covers
+#[derive(Tabled)]
+struct UsageRow {
+    bom_id: i64,
+    bom: String,
+    revision: i64,
+    per_board: i64,
+    variants: String,
+    boards_covered: String,
+}
+
+/// A part of `purchase-plan`
+#[derive(Tabled)]
+struct PlanRow {
+    id: i64,
+    name: String,
+    stock: i64,
+    reserved: i64,
+    required: i64,
+    shortfall: i64,
+    order: i64,
+    alternates_used: String,
+}
+
 /// Simple inventory management CLI
 #[derive(Debug, Parser)]
 #[command(name = "Pcb Parts Cli")]
@@ -31,6 +108,9 @@ fn print_table(rows: &[impl Tabled]) {
 #[command(version = "0.1.0")]
 #[command(about = "Manage your parts inventory", long_about = None)]
 struct Cli {
+    /// Use a local SQLite inventory instead of the server
+    #[arg(long, global = true)]
+    local: Option<PathBuf>,
     #[command(subcommand)]
     command: Commands,
 }
@@ -50,15 +130,58 @@ enum Commands {
         name: Option<String>,
         #[arg(short, long)]
         description: Option<String>,
+        /// Parameter search, e.g. "R=10k pkg=0603 V>=25"
+        #[arg(short, long)]
+        query: Option<String>,
     },
     AddPart {
         name: String,
         description: String,
+        #[arg(short, long, default_value = "")]
+        manufacturer: String,
+        /// Manufacturer part number
+        #[arg(long, default_value = "")]
+        mpn: String,
+        /// Supplier part number as supplier:sku, e.g. LCSC:C1525. Can be repeated
+        #[arg(long)]
+        sku: Vec<SupplierSku>,
+    },
+    /// Change the fields of a part, the ones left out stay as they are
+    UpdatePart {
+        part_id: i64,
+        #[arg(short, long)]
+        name: Option<String>,
+        #[arg(short, long)]
+        description: Option<String>,
+        #[arg(short, long)]
+        manufacturer: Option<String>,
+        #[arg(long)]
+        mpn: Option<String>,
+        /// Replaces every supplier part number of the part. Can be repeated
+        #[arg(long)]
+        sku: Vec<SupplierSku>,
+    },
+    /// Delete a part and its stock history. Parts still in a BOM or a bin can't be deleted
+    DeletePart {
+        part_id: i64,
+    },
+    /// List groups of parts that look like the same part entered more than once
+    FindDuplicates,
+    /// Merge parts into the one kept, combining their stock and BOM lines. Their names stay
+    /// as aliases of the kept part, so imports keep linking to it
+    MergeParts {
+        keep: i64,
+        #[arg(required = true)]
+        merged: Vec<i64>,
     },
     ListProfiles,
     CreateProfile {
         name: String,
     },
+    RenameProfile {
+        profile_id: i64,
+        name: String,
+    },
     StockPart {
         profile_id: i64,
         part_id: i64,
@@ -67,24 +190,179 @@ enum Commands {
         row: i64,
         z: i64,
     },
+    /// Change the stock of a part relative to what it is now
+    AdjustStock {
+        profile_id: i64,
+        part_id: i64,
+        #[arg(allow_negative_numbers = true)]
+        delta: i64,
+        col: i64,
+        row: i64,
+        z: i64,
+        #[arg(short, long, default_value = "manual adjustment")]
+        reason: String,
+    },
+    /// Take a part out of its bin, recording its stock as removed
+    RemoveFromBin {
+        profile_id: i64,
+        part_id: i64,
+    },
     ListStock {
         profile_id: i64,
     },
+    /// Show who changed stock, when and why
+    History {
+        profile_id: i64,
+        #[arg(short, long)]
+        part_id: Option<i64>,
+    },
+    /// List the BOMs using a part and how many boards its available stock covers in each
+    WhereUsed {
+        profile_id: i64,
+        part_id: i64,
+    },
     ListBoms {
         profile_id: i64,
     },
+    /// Add a BOM from a CSV file or an .xlsx/.ods workbook
     AddBom {
         profile_id: i64,
         csv_path: PathBuf,
         name: String,
         description: String,
-        name_col: String,
-        desc_col: String,
-        count_col: String,
+        /// Columns holding the name, description and count, guessed from the headers when left out
+        name_col: Option<String>,
+        desc_col: Option<String>,
+        count_col: Option<String>,
+        /// Worksheet holding the BOM, the first one by default
+        #[arg(short, long)]
+        sheet: Option<String>,
+        /// Import the BOM as the next revision of this one
+        #[arg(short, long)]
+        revision_of: Option<i64>,
+    },
+    /// Add a BOM from an Altium schematic or project, a KiCad schematic, or a CSV exported by
+    /// KiCad's BOM tool
+    AddSchematicBom {
+        profile_id: i64,
+        path: PathBuf,
+        name: String,
+        description: String,
+        /// Import the BOM as the next revision of this one
+        #[arg(short, long)]
+        revision_of: Option<i64>,
+    },
+    /// Change the name or description of a BOM
+    UpdateBom {
+        profile_id: i64,
+        bom_id: i64,
+        #[arg(short, long)]
+        name: Option<String>,
+        #[arg(short, long)]
+        description: Option<String>,
+    },
+    /// Delete a BOM and its lines. BOMs with build orders can't be deleted
+    DeleteBom {
+        profile_id: i64,
+        bom_id: i64,
+    },
+    /// Change how many of a part a BOM holds
+    SetBomLine {
+        profile_id: i64,
+        bom_id: i64,
+        part_id: i64,
+        count: i64,
+    },
+    DeleteBomLine {
+        profile_id: i64,
+        bom_id: i64,
+        part_id: i64,
+    },
+    /// Approve parts to stand in for a part in one BOM when it runs short, in priority order.
+    /// Give none to clear them
+    SetBomAlternates {
+        profile_id: i64,
+        bom_id: i64,
+        part_id: i64,
+        alternates: Vec<i64>,
+    },
+    /// Approve parts to stand in for a part in every BOM, tried after the alternates of the BOM
+    /// line. Give none to clear them
+    SetPartAlternates {
+        part_id: i64,
+        alternates: Vec<i64>,
+    },
+    /// Set how a part is bought, which purchase plans round their order quantities to. Rules
+    /// left out are cleared
+    SetPurchaseRules {
+        part_id: i64,
+        /// Minimum order quantity
+        #[arg(long, default_value_t = 0)]
+        moq: i64,
+        /// Pack size to order in, e.g. 100 for a cut tape
+        #[arg(long, default_value_t = 0)]
+        order_multiple: i64,
+        /// Stock to keep once the planned builds are done
+        #[arg(long, default_value_t = 0)]
+        safety_stock: i64,
+        /// Percentage of parts lost during assembly
+        #[arg(long, default_value_t = 0.0)]
+        attrition: f64,
     },
     ShowBom {
         profile_id: i64,
         bom_id: i64,
+        /// Show the lines and counts of an assembly variant instead of the base design
+        #[arg(short, long)]
+        variant: Option<String>,
+        /// Show how many boards the unreserved stock covers and which parts limit it
+        #[arg(short, long)]
+        buildable: bool,
+        /// With --buildable, build one board of these BOMs alongside each one, sharing the stock
+        #[arg(short, long)]
+        with: Vec<i64>,
+    },
+    /// List the lines added, removed or changed from one BOM to another, by default from the
+    /// revision it was imported as the next one of
+    BomDiff {
+        profile_id: i64,
+        bom_id: i64,
+        /// BOM to compare to instead of the previous revision
+        other_bom_id: Option<i64>,
+    },
+    ListBuilds {
+        profile_id: i64,
+    },
+    /// Plan a build of a BOM, reserving its parts
+    PlanBuild {
+        profile_id: i64,
+        bom_id: i64,
+        quantity: i64,
+        /// Build an assembly variant, reserving only the parts it fits
+        #[arg(short, long)]
+        variant: Option<String>,
+    },
+    /// Move a build order to planned, kitted, built or cancelled. Built depletes its parts
+    SetBuildStatus {
+        profile_id: i64,
+        order_id: i64,
+        status: BuildStatus,
+    },
+    /// Show how much of each part open build orders hold on to
+    ListReservations {
+        profile_id: i64,
+    },
+    /// List what needs buying to build BOMs, given as bom_id:quantity
+    PurchasePlan {
+        profile_id: i64,
+        #[arg(required = true)]
+        builds: Vec<String>,
+        /// Write the plan to this file as well
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+        /// Layout of the written plan: generic, digikey, mouser or lcsc
+        #[arg(short, long, default_value = "generic")]
+        format: ExportFormat,
     },
 }
 
@@ -92,9 +370,32 @@ enum Commands {
 async fn main() -> Result<()> {
     let args = Cli::parse();
 
-    let mut network = NetworkClient::production_client();
+    let mut network: Box<dyn PartsBackend> = match args.local {
+        Some(path) => Box::new(LocalBackend::open(&path)?),
+        None => Box::new(NetworkClient::production_client()),
+    };
+    // Parts dismissed as not duplicates in the GUI stay apart
+    let dismissed = DismissedDuplicates::open(Path::new(".dismissed_duplicates.json"));
+    if let Err(e) = run(args.command, network.as_mut(), &dismissed).await {
+        if is_unauthorized(&e) {
+            eprintln!(
+                "{}, log in with `login <email> <password>` and try again",
+                e
+            );
+        } else {
+            eprintln!("Error: {}", e);
+        }
+        std::process::exit(1);
+    }
+    Ok(())
+}
 
-    match args.command {
+async fn run(
+    command: Commands,
+    network: &mut dyn PartsBackend,
+    dismissed: &DismissedDuplicates,
+) -> Result<()> {
+    match command {
         Commands::CreateUser { email, password } => {
             network
                 .create_user(User {
@@ -113,18 +414,91 @@ async fn main() -> Result<()> {
                 })
                 .await?;
         }
-        Commands::ListParts { name, description } => {
-            let parts = network.get_parts(name, description).await?;
+        Commands::ListParts {
+            name,
+            description,
+            query,
+        } => {
+            let mut parts = network.get_parts(name, description).await?;
+            if let Some(query) = query {
+                let query = PartQuery::parse(&query).map_err(anyhow::Error::msg)?;
+                parts.retain(|p| query.matches_part(p));
+            }
             print_table(&parts);
         }
-        Commands::AddPart { name, description } => {
-            network
-                .new_part(Part {
-                    id: 0,
-                    name,
-                    description,
+        Commands::AddPart {
+            name,
+            description,
+            manufacturer,
+            mpn,
+            sku,
+        } => {
+            let mut part = Part {
+                id: 0,
+                name,
+                description,
+                manufacturer,
+                mpn,
+                skus: sku,
+                ..Default::default()
+            };
+            part.parse_parameters();
+            network.new_part(part).await?;
+        }
+        Commands::UpdatePart {
+            part_id,
+            name,
+            description,
+            manufacturer,
+            mpn,
+            sku,
+        } => {
+            let mut part = network
+                .get_parts(None, None)
+                .await?
+                .into_iter()
+                .find(|p| p.id == part_id)
+                .ok_or_else(|| anyhow::anyhow!("Part {} does not exist", part_id))?;
+            part.name = name.unwrap_or(part.name);
+            part.description = description.unwrap_or(part.description);
+            part.manufacturer = manufacturer.unwrap_or(part.manufacturer);
+            part.mpn = mpn.unwrap_or(part.mpn);
+            if !sku.is_empty() {
+                part.skus = sku;
+            }
+            part.parse_parameters();
+            network.update_part(part).await?;
+            println!("Part updated");
+        }
+        Commands::DeletePart { part_id } => {
+            network.delete_part(part_id).await?;
+            println!("Part deleted");
+        }
+        Commands::FindDuplicates => {
+            let groups = find_duplicates(&network.get_parts(None, None).await?, dismissed);
+            let rows: Vec<DuplicateRow> = groups
+                .iter()
+                .enumerate()
+                .flat_map(|(i, g)| {
+                    g.parts.iter().map(move |p| DuplicateRow {
+                        group: i + 1,
+                        kind: g.kind,
+                        id: p.id,
+                        name: p.name.clone(),
+                        description: p.description.clone(),
+                        mpn: p.mpn.clone(),
+                    })
                 })
-                .await?;
+                .collect();
+            print_table(&rows);
+        }
+        Commands::MergeParts { keep, merged } => {
+            let part = network.merge_parts(keep, merged).await?;
+            println!(
+                "Merged into {}, also known as {}",
+                part.name,
+                part.aliases.join(", ")
+            );
         }
         Commands::ListProfiles => {
             let profiles = network.get_profiles(None).await?;
@@ -134,6 +508,10 @@ async fn main() -> Result<()> {
             network.new_profile(name.clone()).await?;
             println!("Profile: {} created", name);
         }
+        Commands::RenameProfile { profile_id, name } => {
+            network.update_profile(profile_id, name.clone()).await?;
+            println!("Profile renamed to {}", name);
+        }
         Commands::StockPart {
             profile_id,
             part_id,
@@ -147,10 +525,85 @@ async fn main() -> Result<()> {
                 .await?;
             println!("Part stocked");
         }
+        Commands::AdjustStock {
+            profile_id,
+            part_id,
+            delta,
+            col,
+            row,
+            z,
+            reason,
+        } => {
+            let bin = BinLocation {
+                column: col,
+                row,
+                z,
+            };
+            let stock = network
+                .adjust_stock(profile_id, part_id, bin, delta, reason)
+                .await?;
+            println!("Stock is now {}", stock);
+        }
+        Commands::RemoveFromBin {
+            profile_id,
+            part_id,
+        } => {
+            network.remove_from_bin(profile_id, part_id).await?;
+            println!("Part removed from its bin");
+        }
         Commands::ListStock { profile_id } => {
             let stock = network.list_stock(profile_id).await?;
             print_table(&stock);
         }
+        Commands::History {
+            profile_id,
+            part_id,
+        } => {
+            let history = network.stock_history(profile_id, part_id).await?;
+            print_table(&history);
+        }
+        Commands::WhereUsed {
+            profile_id,
+            part_id,
+        } => {
+            let usages = network.where_used(profile_id, part_id).await?;
+            let stock = network
+                .parts_with_stock(None, None, profile_id)
+                .await?
+                .into_iter()
+                .find(|p| p.id == part_id)
+                .map_or(0, |p| p.stock);
+            let reserved = network
+                .reservations(profile_id)
+                .await?
+                .into_iter()
+                .find(|r| r.part_id == part_id)
+                .map_or(0, |r| r.reserved);
+            let available = stock - reserved;
+            println!(
+                " Available: {} ({} in stock, {} reserved)",
+                available, stock, reserved
+            );
+            let rows: Vec<UsageRow> = usages
+                .iter()
+                .map(|u| UsageRow {
+                    bom_id: u.bom_id,
+                    bom: u.bom_name.clone(),
+                    revision: u.revision,
+                    per_board: u.count,
+                    variants: u
+                        .variants
+                        .iter()
+                        .map(|v| format!("{}: {}", v.variant, v.count))
+                        .collect::<Vec<_>>()
+                        .join(", "),
+                    boards_covered: u
+                        .boards(available, None)
+                        .map_or(String::from("-"), |b| b.to_string()),
+                })
+                .collect();
+            print_table(&rows);
+        }
         Commands::ListBoms { profile_id } => {
             let boms = network.list_boms(profile_id, None, None).await?;
             print_table(&boms);
@@ -163,40 +616,604 @@ async fn main() -> Result<()> {
             count_col,
             name,
             description,
+            sheet,
+            revision_of,
         } => {
-            // Parse csv to a list of parts
-            let mut candidates = csv_to_bom(&csv_path, &name_col, &desc_col, &count_col)?;
-            // Compare to a fetched list of parts
-            let parts = network.get_parts(None, None).await?;
-            // Assign ids
-            for (_, part) in &mut candidates {
-                match parts.iter().find(|p| p.name == part.name) {
-                    Some(p) => {
-                        part.id = p.id;
-                    }
-                    None => {}
+            let ColumnMapping {
+                name: name_col,
+                description: desc_col,
+                count: count_col,
+            } = match (name_col, desc_col, count_col) {
+                (Some(name), Some(description), Some(count)) => ColumnMapping {
+                    name,
+                    description,
+                    count,
+                },
+                _ => {
+                    let headers = if is_workbook(&csv_path) {
+                        workbook_to_headers(&csv_path, sheet.as_deref())?
+                    } else {
+                        csv_to_headers(&csv_path)?
+                    };
+                    let mapping = guess_columns(&headers).ok_or(anyhow::anyhow!(
+                        "Couldn't tell the columns apart, pass them explicitly: {}",
+                        headers.join(", ")
+                    ))?;
+                    println!(
+                        "Using columns {}, {} and {}",
+                        mapping.name, mapping.description, mapping.count
+                    );
+                    mapping
                 }
-            }
+            };
+            // Parse the table to a list of parts
+            let mut candidates = if is_workbook(&csv_path) {
+                workbook_to_bom(
+                    &csv_path,
+                    sheet.as_deref(),
+                    &name_col,
+                    &desc_col,
+                    &count_col,
+                )?
+            } else {
+                csv_to_bom(&csv_path, &name_col, &desc_col, &count_col)?
+            };
+            // Compare to a fetched list of parts and assign ids
+            let parts = network.get_parts(None, None).await?;
+            link_existing(&mut candidates, &parts);
             // Send BOM request
             network
-                .new_bom(profile_id, name, description, candidates)
+                .new_bom(profile_id, name, description, candidates, revision_of)
+                .await?;
+            println!("BOM created");
+        }
+        Commands::AddSchematicBom {
+            profile_id,
+            path,
+            name,
+            description,
+            revision_of,
+        } => {
+            let mut candidates = if path.extension().is_some_and(|e| e == "csv") {
+                let headers = csv_to_headers(&path)?;
+                let (name_col, desc_col, count_col) = kicad_csv_columns(&headers)
+                    .ok_or(anyhow::anyhow!("Not a KiCad BOM export, use add-bom"))?;
+                csv_to_bom(&path, &name_col, &desc_col, &count_col)?
+            } else {
+                schematic_file_to_bom(&path)?
+            };
+            let parts = network.get_parts(None, None).await?;
+            link_existing(&mut candidates, &parts);
+            network
+                .new_bom(profile_id, name, description, candidates, revision_of)
                 .await?;
             println!("BOM created");
         }
-        Commands::ShowBom { profile_id, bom_id } => {
+        Commands::UpdateBom {
+            profile_id,
+            bom_id,
+            name,
+            description,
+        } => {
+            let bom = network
+                .list_boms(profile_id, Some(bom_id), None)
+                .await?
+                .into_iter()
+                .next()
+                .ok_or_else(|| anyhow::anyhow!("BOM {} does not exist", bom_id))?;
+            network
+                .update_bom(
+                    profile_id,
+                    bom_id,
+                    name.unwrap_or(bom.name),
+                    description.unwrap_or(bom.description),
+                )
+                .await?;
+            println!("BOM updated");
+        }
+        Commands::DeleteBom { profile_id, bom_id } => {
+            network.delete_bom(profile_id, bom_id).await?;
+            println!("BOM deleted");
+        }
+        Commands::SetBomLine {
+            profile_id,
+            bom_id,
+            part_id,
+            count,
+        } => {
+            network
+                .update_bom_line(profile_id, bom_id, part_id, count)
+                .await?;
+            println!("BOM line updated");
+        }
+        Commands::DeleteBomLine {
+            profile_id,
+            bom_id,
+            part_id,
+        } => {
+            network.delete_bom_line(profile_id, bom_id, part_id).await?;
+            println!("BOM line deleted");
+        }
+        Commands::SetBomAlternates {
+            profile_id,
+            bom_id,
+            part_id,
+            alternates,
+        } => {
+            network
+                .set_bom_line_alternates(profile_id, bom_id, part_id, alternates)
+                .await?;
+            println!("Alternates updated");
+        }
+        Commands::SetPartAlternates {
+            part_id,
+            alternates,
+        } => {
+            network.set_part_alternates(part_id, alternates).await?;
+            println!("Alternates updated");
+        }
+        Commands::SetPurchaseRules {
+            part_id,
+            moq,
+            order_multiple,
+            safety_stock,
+            attrition,
+        } => {
+            let rules = PurchaseRules {
+                moq,
+                order_multiple,
+                safety_stock,
+                attrition_percent: attrition,
+            };
+            network.set_purchase_rules(part_id, rules).await?;
+            println!("Purchase rules updated");
+        }
+        Commands::ShowBom {
+            profile_id,
+            bom_id,
+            variant,
+            buildable,
+            with,
+        } => {
             let bom = network.list_boms(profile_id, Some(bom_id), None).await?;
+            check_variant(&bom[0], variant.as_deref())?;
             println!("");
-            let header = format!(" BOM: {}", bom[0].name);
+            let header = match &variant {
+                Some(variant) => format!(" BOM: {} ({})", bom[0].name, variant),
+                None => format!(" BOM: {}", bom[0].name),
+            };
             print!("{}\n ", header);
             for _ in 0..header.len() {
                 print!("-");
             }
             println!("");
-            let parts = network.parts_in_bom(profile_id, bom_id).await?;
-            print_table(&parts);
+            let parts = select_variant(
+                &network.parts_in_bom(profile_id, bom_id).await?,
+                variant.as_deref(),
+            );
+            let rows: Vec<BomRow> = parts
+                .iter()
+                .cloned()
+                .map(|p| BomRow {
+                    id: p.id,
+                    name: p.name,
+                    description: p.description,
+                    count: p.count,
+                    designators: designators_to_string(&p.designators),
+                    stock: p.stock,
+                    alternates: p
+                        .alternates
+                        .iter()
+                        .map(|a| format!("{} ({})", a.name, a.stock))
+                        .collect::<Vec<_>>()
+                        .join(", "),
+                })
+                .collect();
+            print_table(&rows);
+            if !bom[0].variants.is_empty() {
+                println!(" Variants: {}", bom[0].variants.join(", "));
+            }
+            if buildable {
+                let reserved: HashMap<i64, i64> = network
+                    .reservations(profile_id)
+                    .await?
+                    .into_iter()
+                    .map(|r| (r.part_id, r.reserved))
+                    .collect();
+                let mut names = vec![];
+                let mut boms = vec![parts];
+                for other_id in with {
+                    let other = network
+                        .list_boms(profile_id, Some(other_id), None)
+                        .await?
+                        .pop()
+                        .ok_or(anyhow::anyhow!("BOM {} does not exist", other_id))?;
+                    names.push(other.name);
+                    boms.push(network.parts_in_bom(profile_id, other_id).await?);
+                }
+                let available = unreserved_stock(&boms.concat(), &reserved);
+                let sets: Vec<(&[_], i64)> = boms.iter().map(|b| (b.as_slice(), 1)).collect();
+                let result = buildable_together(&sets, &available);
+                let quantity = match result.quantity {
+                    Some(quantity) => quantity.to_string(),
+                    None => String::from("any number"),
+                };
+                if names.is_empty() {
+                    println!(" Buildable: {}", quantity);
+                } else {
+                    println!(
+                        " Buildable alongside {}: {} of each",
+                        names.join(", "),
+                        quantity
+                    );
+                }
+                if !result.limiting.is_empty() {
+                    println!(" Limited by {}", result.limiting_to_string());
+                }
+            }
             println!("");
         }
+        Commands::BomDiff {
+            profile_id,
+            bom_id,
+            other_bom_id,
+        } => {
+            let bom = network
+                .list_boms(profile_id, Some(bom_id), None)
+                .await?
+                .pop()
+                .ok_or(anyhow::anyhow!("BOM {} does not exist", bom_id))?;
+            let old_id = other_bom_id.or(bom.revision_of).ok_or(anyhow::anyhow!(
+                "{} is the first revision, give a BOM to compare to",
+                bom.name
+            ))?;
+            let old = network
+                .list_boms(profile_id, Some(old_id), None)
+                .await?
+                .pop()
+                .ok_or(anyhow::anyhow!("BOM {} does not exist", old_id))?;
+            let diff = diff_boms(
+                &network.parts_in_bom(profile_id, old.id).await?,
+                &network.parts_in_bom(profile_id, bom.id).await?,
+            );
+            println!(
+                " {} (rev {}) -> {} (rev {})",
+                old.name, old.revision, bom.name, bom.revision
+            );
+            let rows: Vec<DiffRow> = diff
+                .into_iter()
+                .map(|l| {
+                    let added = l.added_designators.iter().map(|d| format!("+{}", d));
+                    let removed = l.removed_designators.iter().map(|d| format!("-{}", d));
+                    DiffRow {
+                        change: l.change,
+                        id: l.part_id,
+                        name: l.name,
+                        old_count: l.old_count,
+                        new_count: l.new_count,
+                        designators: added.chain(removed).collect::<Vec<_>>().join(" "),
+                    }
+                })
+                .collect();
+            print_table(&rows);
+        }
+        Commands::ListBuilds { profile_id } => {
+            let orders = network.list_build_orders(profile_id).await?;
+            print_table(&orders);
+        }
+        Commands::PlanBuild {
+            profile_id,
+            bom_id,
+            quantity,
+            variant,
+        } => {
+            let order = network
+                .new_build_order(profile_id, bom_id, quantity, variant)
+                .await?;
+            println!("Build #{} planned", order.id);
+        }
+        Commands::SetBuildStatus {
+            profile_id,
+            order_id,
+            status,
+        } => {
+            // Work out which alternates completing the build takes from while the stock is
+            // still there to look at
+            let completed = match status {
+                BuildStatus::Built => network
+                    .list_build_orders(profile_id)
+                    .await?
+                    .into_iter()
+                    .find(|o| o.id == order_id),
+                _ => None,
+            };
+            let mut substitutions = vec![];
+            if let Some(order) = completed {
+                let parts = select_variant(
+                    &network.parts_in_bom(profile_id, order.bom_id).await?,
+                    order.variant(),
+                );
+                let reserved = network
+                    .reservations(profile_id)
+                    .await?
+                    .into_iter()
+                    .map(|r| (r.part_id, r.reserved))
+                    .collect();
+                let mut available = build_stock(&parts, &reserved, order.quantity);
+                substitutions = draw_with_alternates(&parts, order.quantity, &mut available)
+                    .into_iter()
+                    .flat_map(|d| d.substitutions)
+                    .collect();
+            }
+            network
+                .set_build_status(profile_id, order_id, status)
+                .await?;
+            println!("Build #{} is now {}", order_id, status);
+            for substitution in substitutions {
+                println!(" Used {}", substitution);
+            }
+        }
+        Commands::ListReservations { profile_id } => {
+            let reservations = network.reservations(profile_id).await?;
+            print_table(&reservations);
+        }
+        Commands::PurchasePlan {
+            profile_id,
+            builds,
+            output,
+            format,
+        } => {
+            let mut planned = vec![];
+            for build in builds {
+                let (bom_id, quantity) = build.split_once(':').unwrap_or((&build, "1"));
+                let (Ok(bom_id), Ok(quantity)) = (bom_id.parse::<i64>(), quantity.parse::<i64>())
+                else {
+                    return Err(anyhow::anyhow!("Expected bom_id:quantity, got {}", build));
+                };
+                let bom = network
+                    .list_boms(profile_id, Some(bom_id), None)
+                    .await?
+                    .pop()
+                    .ok_or(anyhow::anyhow!("BOM {} does not exist", bom_id))?;
+                planned.push(PlannedBuild {
+                    bom,
+                    quantity,
+                    variant: None,
+                });
+            }
+            let mut requirements = purchase_plan(&mut *network, profile_id, &planned).await?;
+            requirements.sort_by(|a, b| a.part.name.cmp(&b.part.name));
+            let rows: Vec<PlanRow> = requirements
+                .iter()
+                .map(|r| PlanRow {
+                    id: r.part.id,
+                    name: r.part.name.clone(),
+                    stock: r.part.stock,
+                    reserved: r.reserved,
+                    required: r.required,
+                    shortfall: r.shortfall,
+                    order: r.order_quantity,
+                    alternates_used: r
+                        .substitutions
+                        .iter()
+                        .map(|s| format!("{} {}", s.count, s.alternate_name))
+                        .collect::<Vec<_>>()
+                        .join(", "),
+                })
+                .collect();
+            print_table(&rows);
+            if let Some(output) = output {
+                export_purchase_plan(&output, &requirements, format)?;
+                println!(
+                    "Purchase plan written to {} as {}",
+                    output.display(),
+                    format
+                );
+            }
+        }
     }
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+    use clap::Parser;
+    use common::{
+        backend::PartsBackend, error::is_unauthorized, matching::DismissedDuplicates,
+        memory::MemoryBackend,
+    };
+
+    use super::{Cli, run};
+
+    /// Runs the command line `args` against `backend` the way `main` does
+    async fn cli(backend: &mut MemoryBackend, args: &[&str]) -> Result<()> {
+        let cli = Cli::try_parse_from(std::iter::once("cli").chain(args.iter().copied()))?;
+        run(cli.command, backend, &DismissedDuplicates::in_memory()).await
+    }
+
+    /// Signs up and logs in with a "Workshop" profile, returning its id
+    async fn logged_in(backend: &mut MemoryBackend) -> String {
+        cli(backend, &["create-user", "test@example.com", "hunter2"])
+            .await
+            .unwrap();
+        cli(backend, &["login", "test@example.com", "hunter2"])
+            .await
+            .unwrap();
+        cli(backend, &["create-profile", "Workshop"]).await.unwrap();
+        // Logging in again selects the new profile
+        cli(backend, &["login", "test@example.com", "hunter2"])
+            .await
+            .unwrap();
+        backend.user_data().profile.as_ref().unwrap().id.to_string()
+    }
+
+    async fn part_id(backend: &mut MemoryBackend, name: &str) -> String {
+        let parts = backend.get_parts(None, None).await.unwrap();
+        parts
+            .iter()
+            .find(|p| p.name == name)
+            .unwrap()
+            .id
+            .to_string()
+    }
+
+    async fn stock_of(backend: &mut MemoryBackend, profile: &str, part: &str) -> i64 {
+        backend
+            .list_stock(profile.parse().unwrap())
+            .await
+            .unwrap()
+            .iter()
+            .filter(|s| s.part_id.to_string() == part)
+            .map(|s| s.stock)
+            .sum()
+    }
+
+    #[tokio::test]
+    async fn asks_to_log_in_first() {
+        let mut backend = MemoryBackend::new();
+        let e = cli(&mut backend, &["list-profiles"]).await.unwrap_err();
+        assert!(is_unauthorized(&e));
+    }
+
+    #[tokio::test]
+    async fn updating_a_part_keeps_the_fields_left_out() {
+        let mut backend = MemoryBackend::new();
+        logged_in(&mut backend).await;
+        cli(
+            &mut backend,
+            &[
+                "add-part",
+                "100nF",
+                "Capacitor 0402",
+                "--mpn",
+                "GRM155R71C104KA88D",
+                "--sku",
+                "LCSC:C1525",
+            ],
+        )
+        .await
+        .unwrap();
+        let part = part_id(&mut backend, "100nF").await;
+        cli(
+            &mut backend,
+            &["update-part", &part, "--description", "Capacitor 0402 16V"],
+        )
+        .await
+        .unwrap();
+
+        let parts = backend.get_parts(None, None).await.unwrap();
+        assert_eq!(parts.len(), 1);
+        assert_eq!(parts[0].description, "Capacitor 0402 16V");
+        assert_eq!(parts[0].mpn, "GRM155R71C104KA88D");
+        assert_eq!(parts[0].skus.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn adjusting_stock_takes_negative_deltas() {
+        let mut backend = MemoryBackend::new();
+        let profile = logged_in(&mut backend).await;
+        cli(&mut backend, &["add-part", "100nF", "Capacitor 0402"])
+            .await
+            .unwrap();
+        let part = part_id(&mut backend, "100nF").await;
+        cli(
+            &mut backend,
+            &["stock-part", &profile, &part, "50", "1", "1", "0"],
+        )
+        .await
+        .unwrap();
+        cli(
+            &mut backend,
+            &["adjust-stock", &profile, &part, "-3", "1", "1", "0"],
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(stock_of(&mut backend, &profile, &part).await, 47);
+        let history = backend
+            .stock_history(profile.parse().unwrap(), None)
+            .await
+            .unwrap();
+        assert!(
+            history
+                .iter()
+                .any(|e| e.delta == -3 && e.reason == "manual adjustment")
+        );
+    }
+
+    #[tokio::test]
+    async fn parts_in_a_bin_are_not_deleted() {
+        let mut backend = MemoryBackend::new();
+        let profile = logged_in(&mut backend).await;
+        cli(&mut backend, &["add-part", "100nF", "Capacitor 0402"])
+            .await
+            .unwrap();
+        let part = part_id(&mut backend, "100nF").await;
+        cli(
+            &mut backend,
+            &["stock-part", &profile, &part, "50", "1", "1", "0"],
+        )
+        .await
+        .unwrap();
+
+        assert!(cli(&mut backend, &["delete-part", &part]).await.is_err());
+        cli(&mut backend, &["remove-from-bin", &profile, &part])
+            .await
+            .unwrap();
+        cli(&mut backend, &["delete-part", &part]).await.unwrap();
+        assert!(backend.get_parts(None, None).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn building_a_schematic_bom_depletes_its_parts() {
+        let mut backend = MemoryBackend::new();
+        let profile = logged_in(&mut backend).await;
+        let schematic = concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/../common/assets/kicad/amp.kicad_sch"
+        );
+        cli(
+            &mut backend,
+            &["add-schematic-bom", &profile, schematic, "Amp", "Amplifier"],
+        )
+        .await
+        .unwrap();
+        let parts = backend.get_parts(None, None).await.unwrap();
+        assert_eq!(parts.len(), 4);
+        for (column, part) in parts.iter().enumerate() {
+            let column = column.to_string();
+            let part = part.id.to_string();
+            cli(
+                &mut backend,
+                &["stock-part", &profile, &part, "10", &column, "0", "0"],
+            )
+            .await
+            .unwrap();
+        }
+        let profile_id = profile.parse().unwrap();
+        let bom = backend.list_boms(profile_id, None, None).await.unwrap()[0]
+            .id
+            .to_string();
+
+        cli(&mut backend, &["plan-build", &profile, &bom, "2"])
+            .await
+            .unwrap();
+        let order = backend.list_build_orders(profile_id).await.unwrap()[0]
+            .id
+            .to_string();
+        cli(
+            &mut backend,
+            &["set-build-status", &profile, &order, "built"],
+        )
+        .await
+        .unwrap();
+
+        let capacitor = part_id(&mut backend, "100n").await;
+        let fuse = part_id(&mut backend, "500mA").await;
+        assert_eq!(stock_of(&mut backend, &profile, &capacitor).await, 6);
+        assert_eq!(stock_of(&mut backend, &profile, &fuse).await, 8);
+        assert!(backend.reservations(profile_id).await.unwrap().is_empty());
+    }
+}
@@ -0,0 +1,563 @@
+use std::fmt::Debug;
+
+use async_trait::async_trait;
+
+use tracing::error;
+
+use crate::{
+    error::{BatchFailure, FailedLine, NetworkError},
+    models::{
+        BinLocation, Bom, BomLine, BomPartRelation, BomUsage, BuildOrder, BuildStatus, Part,
+        PartWithCountAndStock, PartWithStock, Profile, PurchaseRules, Reservation,
+        StockLedgerEntry, StockRows, User, VariantCount, count_in_variant, sort_designators,
+    },
+    network::UserData,
+};
+
+/// Ledger reason for absolute stock writes, which don't carry one of their own
+pub const STOCK_SET_REASON: &str = "stock set";
+
+/// Ledger reason for taking a part out of its bin altogether
+pub const REMOVED_FROM_BIN_REASON: &str = "removed from bin";
+
+/// Ledger reason for the depletion of a completed build order
+pub fn build_reason(order: &BuildOrder) -> String {
+    match order.variant() {
+        Some(variant) => format!(
+            "Build #{}: {} ({}) x{}",
+            order.id, order.bom_name, variant, order.quantity
+        ),
+        None => format!(
+            "Build #{}: {} x{}",
+            order.id, order.bom_name, order.quantity
+        ),
+    }
+}
+
+/// Refuses building a variant the BOM doesn't have.
+pub fn check_variant(bom: &Bom, variant: Option<&str>) -> Result<(), NetworkError> {
+    match variant {
+        Some(variant) if bom.variants.is_empty() => Err(NetworkError::Validation {
+            field: String::from("variant"),
+            msg: format!(
+                "{} has no assembly variants (asked for {})",
+                bom.name, variant
+            ),
+        }),
+        Some(variant) if !bom.variants.iter().any(|v| v == variant) => {
+            Err(NetworkError::Validation {
+                field: String::from("variant"),
+                msg: format!("{} has no variant {}", bom.name, variant),
+            })
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Every variant some line of a new BOM differs in, sorted
+pub fn line_variants(lines: &[BomLine]) -> Vec<String> {
+    let mut variants: Vec<String> = lines
+        .iter()
+        .flat_map(|l| l.variants.iter().map(|v| v.variant.clone()))
+        .collect();
+    variants.sort();
+    variants.dedup();
+    variants
+}
+
+/// Every variant some line of a stored BOM differs in, sorted
+pub fn part_variants(parts: &[PartWithCountAndStock]) -> Vec<String> {
+    let mut variants: Vec<String> = parts
+        .iter()
+        .flat_map(|p| p.variants.iter().map(|v| v.variant.clone()))
+        .collect();
+    variants.sort();
+    variants.dedup();
+    variants
+}
+
+/// Refuses merges without parts to merge or merging a part into itself.
+pub fn check_merge(keep: i64, merged: &[i64]) -> Result<(), NetworkError> {
+    if merged.is_empty() || merged.contains(&keep) {
+        Err(NetworkError::Validation {
+            field: String::from("merged"),
+            msg: String::from("Pick at least one part other than the one kept"),
+        })
+    } else {
+        Ok(())
+    }
+}
+
+/// `keep` with the names of `merged` as aliases and any part numbers it lacks
+pub fn merged_part(keep: &Part, merged: &[Part]) -> Part {
+    let mut part = keep.clone();
+    for other in merged {
+        for alias in std::iter::once(&other.name).chain(&other.aliases) {
+            if *alias != part.name && !part.aliases.contains(alias) {
+                part.aliases.push(alias.clone());
+            }
+        }
+        for sku in &other.skus {
+            if !part.skus.contains(sku) {
+                part.skus.push(sku.clone());
+            }
+        }
+        if part.mpn.is_empty() {
+            part.mpn = other.mpn.clone();
+        }
+        if part.manufacturer.is_empty() {
+            part.manufacturer = other.manufacturer.clone();
+        }
+        for alternate in &other.alternates {
+            if *alternate != part.id
+                && !merged.iter().any(|m| m.id == *alternate)
+                && !part.alternates.contains(alternate)
+            {
+                part.alternates.push(*alternate);
+            }
+        }
+    }
+    part.alternates
+        .retain(|a| !merged.iter().any(|m| m.id == *a));
+    part
+}
+
+/// One line holding the parts of both lines, for a BOM that lists the same part twice after a
+/// merge. Every variant counts what both lines counted in it.
+pub fn combine_bom_lines(line: &BomPartRelation, other: &BomPartRelation) -> BomPartRelation {
+    let mut names: Vec<&String> = line
+        .variants
+        .iter()
+        .chain(&other.variants)
+        .map(|v| &v.variant)
+        .collect();
+    names.sort();
+    names.dedup();
+    let variants = names
+        .into_iter()
+        .map(|name| VariantCount {
+            variant: name.clone(),
+            count: count_in_variant(line.count, &line.variants, Some(name))
+                + count_in_variant(other.count, &other.variants, Some(name)),
+        })
+        .collect();
+    let mut designators: Vec<String> = line
+        .designators
+        .iter()
+        .chain(&other.designators)
+        .cloned()
+        .collect();
+    sort_designators(&mut designators);
+    designators.dedup();
+    let mut alternates = line.alternates.clone();
+    for alternate in &other.alternates {
+        if !alternates.contains(alternate) {
+            alternates.push(*alternate);
+        }
+    }
+    BomPartRelation {
+        count: line.count + other.count,
+        designators,
+        variants,
+        alternates,
+        ..line.clone()
+    }
+}
+
+/// Refuses alternates listing the part itself or a part twice.
+pub fn check_alternates(part_id: i64, alternates: &[i64]) -> Result<(), NetworkError> {
+    let msg = if alternates.contains(&part_id) {
+        "A part can't stand in for itself"
+    } else if alternates
+        .iter()
+        .enumerate()
+        .any(|(i, a)| alternates[..i].contains(a))
+    {
+        "Lists a part more than once"
+    } else {
+        return Ok(());
+    };
+    Err(NetworkError::Validation {
+        field: String::from("alternates"),
+        msg: String::from(msg),
+    })
+}
+
+pub fn check_purchase_rules(rules: &PurchaseRules) -> Result<(), NetworkError> {
+    let msg = if rules.moq < 0 || rules.order_multiple < 0 || rules.safety_stock < 0 {
+        "Quantities can't be negative"
+    } else if !(0.0..=100.0).contains(&rules.attrition_percent) {
+        "Attrition has to be between 0 and 100%"
+    } else {
+        return Ok(());
+    };
+    Err(NetworkError::Validation {
+        field: String::from("purchasing"),
+        msg: String::from(msg),
+    })
+}
+
+/// Alternates of a BOM line followed by the ones of its part, each once
+pub fn line_alternates(part_id: i64, line: &[i64], part: &[i64]) -> Vec<i64> {
+    let mut alternates: Vec<i64> = vec![];
+    for alternate in line.iter().chain(part) {
+        if *alternate != part_id && !alternates.contains(alternate) {
+            alternates.push(*alternate);
+        }
+    }
+    alternates
+}
+
+/// Refuses BOM lines holding none of their part, deleting the line is the way to drop it.
+pub fn check_bom_line_count(count: i64) -> Result<(), NetworkError> {
+    if count < 1 {
+        Err(NetworkError::Validation {
+            field: String::from("count"),
+            msg: String::from("Must be at least 1"),
+        })
+    } else {
+        Ok(())
+    }
+}
+
+/// Refuses moving a build order to a state it can't reach from where it is.
+pub fn check_build_transition(order: &BuildOrder, next: BuildStatus) -> Result<(), NetworkError> {
+    if order.status.can_become(next) {
+        Ok(())
+    } else {
+        Err(NetworkError::Validation {
+            field: String::from("status"),
+            msg: format!(
+                "Build #{} is {} and can't become {}",
+                order.id, order.status, next
+            ),
+        })
+    }
+}
+
+/// Everything the GUI and CLI need from a parts inventory. Implemented by the HTTP
+/// [`crate::network::NetworkClient`], the SQLite [`crate::local::LocalBackend`] and the
+/// in-memory [`crate::memory::MemoryBackend`].
+#[async_trait]
+pub trait PartsBackend: Debug + Send {
+    fn user_data(&self) -> &UserData;
+
+    fn user_data_mut(&mut self) -> &mut UserData;
+
+    fn host_name(&self) -> String;
+
+    async fn create_user(&mut self, pending: User) -> Result<(), NetworkError>;
+
+    async fn login(&mut self, user: User) -> Result<(), NetworkError>;
+
+    async fn get_parts(
+        &mut self,
+        name: Option<String>,
+        description: Option<String>,
+    ) -> Result<Vec<Part>, NetworkError>;
+
+    async fn new_part(&mut self, part: Part) -> Result<(), NetworkError>;
+
+    /// Overwrites everything but the id, aliases, alternates and purchasing rules of an existing
+    /// part
+    async fn update_part(&mut self, part: Part) -> Result<(), NetworkError>;
+
+    /// Sets the parts approved to stand in for a part in every BOM, in priority order
+    async fn set_part_alternates(
+        &mut self,
+        part_id: i64,
+        alternates: Vec<i64>,
+    ) -> Result<(), NetworkError>;
+
+    /// Sets how a part is bought, see [`PurchaseRules`]
+    async fn set_purchase_rules(
+        &mut self,
+        part_id: i64,
+        rules: PurchaseRules,
+    ) -> Result<(), NetworkError>;
+
+    /// Deletes a part along with its stock history. Refused with a conflict while a BOM lists
+    /// the part or it sits in a bin.
+    async fn delete_part(&mut self, part_id: i64) -> Result<(), NetworkError>;
+
+    /// Merges the `merged` parts into `keep`. Their stock is added to it in every profile, BOMs
+    /// list it instead and their names become its aliases, see [`merged_part`].
+    async fn merge_parts(&mut self, keep: i64, merged: Vec<i64>) -> Result<Part, NetworkError>;
+
+    async fn get_profiles(&mut self, name: Option<String>) -> Result<Vec<Profile>, NetworkError>;
+
+    async fn new_profile(&mut self, name: String) -> Result<(), NetworkError>;
+
+    async fn update_profile(&mut self, profile_id: i64, name: String) -> Result<(), NetworkError>;
+
+    async fn list_stock(&mut self, profile_id: i64) -> Result<Vec<StockRows>, NetworkError>;
+
+    async fn stock_part(
+        &mut self,
+        profile_id: i64,
+        part_id: i64,
+        stock: i64,
+        column: i64,
+        row: i64,
+        z: i64,
+    ) -> Result<(), NetworkError>;
+
+    /// Changes the stock of a part by `delta` relative to whatever the backend has right now,
+    /// placing it in `bin`. Returns the resulting stock.
+    async fn adjust_stock(
+        &mut self,
+        profile_id: i64,
+        part_id: i64,
+        bin: BinLocation,
+        delta: i64,
+        reason: String,
+    ) -> Result<i64, NetworkError>;
+
+    /// Adjusts every part by `diff` times its count, e.g. building or restocking a BOM. Either
+    /// every part is adjusted or none is, failures are reported as [`NetworkError::Batch`].
+    async fn stock_parts(
+        &mut self,
+        profile_id: i64,
+        parts: &[PartWithCountAndStock],
+        diff: i64,
+        reason: String,
+    ) -> Result<(), NetworkError>;
+
+    /// Takes a part out of its bin, recording the stock it had as removed.
+    async fn remove_from_bin(&mut self, profile_id: i64, part_id: i64) -> Result<(), NetworkError>;
+
+    /// Every recorded stock change in a profile, optionally for a single part, newest first.
+    async fn stock_history(
+        &mut self,
+        profile_id: i64,
+        part_id: Option<i64>,
+    ) -> Result<Vec<StockLedgerEntry>, NetworkError>;
+
+    async fn list_boms(
+        &mut self,
+        profile_id: i64,
+        bom_id: Option<i64>,
+        bom_name: Option<String>,
+    ) -> Result<Vec<Bom>, NetworkError>;
+
+    /// Creates a BOM, as the next revision of the BOM `revision_of` when it is given
+    async fn new_bom(
+        &mut self,
+        profile_id: i64,
+        name: String,
+        description: String,
+        candidates: Vec<BomLine>,
+        revision_of: Option<i64>,
+    ) -> Result<(), NetworkError>;
+
+    async fn update_bom(
+        &mut self,
+        profile_id: i64,
+        bom_id: i64,
+        name: String,
+        description: String,
+    ) -> Result<(), NetworkError>;
+
+    /// Deletes a BOM and its lines. Refused with a conflict once it has build orders, later
+    /// revisions become revisions of the one it was made from.
+    async fn delete_bom(&mut self, profile_id: i64, bom_id: i64) -> Result<(), NetworkError>;
+
+    /// Changes how many of a part a BOM holds, at least 1
+    async fn update_bom_line(
+        &mut self,
+        profile_id: i64,
+        bom_id: i64,
+        part_id: i64,
+        count: i64,
+    ) -> Result<(), NetworkError>;
+
+    async fn delete_bom_line(
+        &mut self,
+        profile_id: i64,
+        bom_id: i64,
+        part_id: i64,
+    ) -> Result<(), NetworkError>;
+
+    /// Sets the parts approved to stand in for a part in one BOM, in priority order. They are
+    /// tried before the alternates of the part itself.
+    async fn set_bom_line_alternates(
+        &mut self,
+        profile_id: i64,
+        bom_id: i64,
+        part_id: i64,
+        alternates: Vec<i64>,
+    ) -> Result<(), NetworkError>;
+
+    async fn parts_in_bom(
+        &mut self,
+        profile_id: i64,
+        bom_id: i64,
+    ) -> Result<Vec<PartWithCountAndStock>, NetworkError>;
+
+    /// The BOMs of a profile with a line for the part, by BOM id
+    async fn where_used(
+        &mut self,
+        profile_id: i64,
+        part_id: i64,
+    ) -> Result<Vec<BomUsage>, NetworkError>;
+
+    async fn parts_with_stock(
+        &mut self,
+        name: Option<String>,
+        description: Option<String>,
+        profile_id: i64,
+    ) -> Result<Vec<PartWithStock>, NetworkError>;
+
+    async fn list_build_orders(&mut self, profile_id: i64)
+    -> Result<Vec<BuildOrder>, NetworkError>;
+
+    /// Plans building `quantity` boards of a BOM, reserving its parts. With a `variant` only the
+    /// parts fitted in that assembly variant are reserved.
+    async fn new_build_order(
+        &mut self,
+        profile_id: i64,
+        bom_id: i64,
+        quantity: i64,
+        variant: Option<String>,
+    ) -> Result<BuildOrder, NetworkError>;
+
+    /// Moves a build order along. Marking it built depletes its parts in one batch, which turns
+    /// the reservation into a real stock change; cancelling it only drops the reservation. Lines
+    /// short of their part draw from its alternates, see [`crate::models::draw_with_alternates`].
+    async fn set_build_status(
+        &mut self,
+        profile_id: i64,
+        order_id: i64,
+        status: BuildStatus,
+    ) -> Result<(), NetworkError>;
+
+    /// Stock held by planned and kitted build orders, per part.
+    async fn reservations(&mut self, profile_id: i64) -> Result<Vec<Reservation>, NetworkError>;
+}
+
+/// Batch stock change for backends that can only adjust one part at a time. Tries every line and
+/// if any of them fails, takes back the ones that went through.
+pub async fn stock_parts_with_reverts(
+    backend: &mut dyn PartsBackend,
+    profile_id: i64,
+    parts: &[PartWithCountAndStock],
+    diff: i64,
+    reason: String,
+) -> Result<(), NetworkError> {
+    let mut applied = vec![];
+    let mut failed = vec![];
+    for p in parts {
+        match backend
+            .adjust_stock(profile_id, p.id, p.bin(), diff * p.count, reason.clone())
+            .await
+        {
+            Ok(_) => applied.push(p),
+            Err(e) => {
+                error!("Error: {:?} while stocking {:?}", e, p.name);
+                failed.push(FailedLine {
+                    part_id: p.id,
+                    name: p.name.clone(),
+                    error: e,
+                });
+            }
+        }
+    }
+    if failed.is_empty() {
+        return Ok(());
+    }
+
+    let mut unreverted = vec![];
+    for p in applied.into_iter().rev() {
+        if let Err(e) = backend
+            .adjust_stock(
+                profile_id,
+                p.id,
+                p.bin(),
+                -diff * p.count,
+                format!("revert: {}", reason),
+            )
+            .await
+        {
+            error!("Error: {:?} while reverting {:?}", e, p.name);
+            unreverted.push(FailedLine {
+                part_id: p.id,
+                name: p.name.clone(),
+                error: e,
+            });
+        }
+    }
+    Err(NetworkError::Batch(BatchFailure { failed, unreverted }))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        error::NetworkError,
+        memory::MemoryBackend,
+        models::{Part, PartWithCountAndStock},
+        test_util::logged_in,
+    };
+
+    use super::{PartsBackend, stock_parts_with_reverts};
+
+    #[tokio::test]
+    async fn reverts_applied_lines_when_one_fails() {
+        let mut backend = logged_in(MemoryBackend::new()).await;
+        let profile_id = backend.user_data().profile.as_ref().unwrap().id;
+        for name in ["100nF", "10k"] {
+            backend
+                .new_part(Part {
+                    id: 0,
+                    name: String::from(name),
+                    ..Default::default()
+                })
+                .await
+                .unwrap();
+        }
+        let mut parts: Vec<PartWithCountAndStock> = vec![];
+        for (i, p) in backend
+            .get_parts(None, None)
+            .await
+            .unwrap()
+            .into_iter()
+            .enumerate()
+        {
+            backend
+                .stock_part(profile_id, p.id, 20, 1, i as i64, 0)
+                .await
+                .unwrap();
+            parts.push(PartWithCountAndStock {
+                id: p.id,
+                name: p.name,
+                description: p.description,
+                count: 2,
+                designators: vec![],
+                variants: vec![],
+                stock: 20,
+                column: 1,
+                row: i as i64,
+                z: 0,
+                alternates: vec![],
+            });
+        }
+        // Never created, so the backend refuses it after the first part went through
+        let mut missing = parts[0].clone();
+        missing.id = 999;
+        missing.name = String::from("ghost");
+        parts.insert(1, missing);
+
+        let err = stock_parts_with_reverts(&mut backend, profile_id, &parts, -1, String::new())
+            .await
+            .unwrap_err();
+        match err {
+            NetworkError::Batch(failure) => {
+                assert_eq!(failure.failed.len(), 1);
+                assert_eq!(failure.failed[0].part_id, 999);
+                assert!(failure.unreverted.is_empty());
+            }
+            e => panic!("Unexpected error {:?}", e),
+        }
+        let stock = backend.list_stock(profile_id).await.unwrap();
+        assert!(stock.iter().all(|s| s.stock == 20));
+    }
+}
@@ -0,0 +1,251 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::models::{PartWithCountAndStock, draw_with_alternates};
+
+/// A part that runs out when building one more than the buildable quantity
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LimitingPart {
+    pub part_id: i64,
+    pub name: String,
+    /// How many of it one build takes, summed over the BOMs sharing it
+    pub count: i64,
+    /// Stock of the part itself, not counting its alternates
+    pub available: i64,
+}
+
+/// How many builds the stock covers and what stops it at that
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Buildable {
+    /// `None` when nothing limits it, as for a BOM without lines
+    pub quantity: Option<i64>,
+    pub limiting: Vec<LimitingPart>,
+}
+
+impl Buildable {
+    /// The limiting parts on one line, like "10k (4 per build, 7 available)"
+    pub fn limiting_to_string(&self) -> String {
+        self.limiting
+            .iter()
+            .map(|p| {
+                format!(
+                    "{} ({} per build, {} available)",
+                    p.name, p.count, p.available
+                )
+            })
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+}
+
+/// Stock of the parts of `lines` and their alternates less what build orders hold, see
+/// [`crate::backend::PartsBackend::reservations`]
+pub fn unreserved_stock(
+    lines: &[PartWithCountAndStock],
+    reserved: &HashMap<i64, i64>,
+) -> HashMap<i64, i64> {
+    let mut out = HashMap::new();
+    for line in lines {
+        let alternates = line.alternates.iter().map(|a| (a.id, a.stock));
+        for (part_id, stock) in std::iter::once((line.id, line.stock)).chain(alternates) {
+            out.insert(
+                part_id,
+                stock - reserved.get(&part_id).copied().unwrap_or(0),
+            );
+        }
+    }
+    out
+}
+
+/// Stock a build of `lines` can draw on without taking what other build orders hold. `held` is
+/// how many boards the build itself reserves when it is a build order, those reservations are
+/// its own to use.
+pub fn build_stock(
+    lines: &[PartWithCountAndStock],
+    reserved: &HashMap<i64, i64>,
+    held: i64,
+) -> HashMap<i64, i64> {
+    let mut others = reserved.clone();
+    for line in lines {
+        if let Some(r) = others.get_mut(&line.id) {
+            *r -= line.count * held;
+        }
+    }
+    unreserved_stock(lines, &others)
+}
+
+/// How many boards of a BOM can be built from `available`, drawing from alternates the way a
+/// build would. `lines` are the [`crate::backend::PartsBackend::parts_in_bom`] results, narrowed
+/// to a variant with [`crate::models::select_variant`] if needed. Parts missing from `available`
+/// give the stock they have in `lines`.
+pub fn buildable(lines: &[PartWithCountAndStock], available: &HashMap<i64, i64>) -> Buildable {
+    buildable_together(&[(lines, 1)], available)
+}
+
+/// How many sets of BOMs can be built from the same stock, each set being the given number of
+/// boards of every BOM. BOMs sharing a part split its stock between them.
+pub fn buildable_together(
+    boms: &[(&[PartWithCountAndStock], i64)],
+    available: &HashMap<i64, i64>,
+) -> Buildable {
+    let lines: Vec<PartWithCountAndStock> = boms
+        .iter()
+        .flat_map(|(lines, boards)| {
+            lines.iter().map(move |line| PartWithCountAndStock {
+                count: line.count * boards,
+                ..line.clone()
+            })
+        })
+        .filter(|line| line.count > 0)
+        .collect();
+    let stock = |part_id: i64, stock: i64| available.get(&part_id).copied().unwrap_or(stock);
+
+    // No line can go beyond what its part and all of its alternates hold together
+    let Some(mut high) = lines
+        .iter()
+        .map(|line| {
+            let alternates: i64 = line
+                .alternates
+                .iter()
+                .map(|a| stock(a.id, a.stock).max(0))
+                .sum();
+            (stock(line.id, line.stock).max(0) + alternates) / line.count
+        })
+        .min()
+    else {
+        return Buildable::default();
+    };
+    let covers = |quantity: i64| {
+        draw_with_alternates(&lines, quantity, &mut available.clone())
+            .iter()
+            .all(|d| d.short() <= 0)
+    };
+    let mut low = 0;
+    while low < high {
+        let mid = low + (high - low + 1) / 2;
+        if covers(mid) {
+            low = mid;
+        } else {
+            high = mid - 1;
+        }
+    }
+
+    let short: Vec<i64> = draw_with_alternates(&lines, low + 1, &mut available.clone())
+        .iter()
+        .filter(|d| d.short() > 0)
+        .map(|d| d.part_id)
+        .collect();
+    let mut limiting: Vec<LimitingPart> = vec![];
+    for line in lines.iter().filter(|l| short.contains(&l.id)) {
+        match limiting.iter_mut().find(|p| p.part_id == line.id) {
+            Some(part) => part.count += line.count,
+            None => limiting.push(LimitingPart {
+                part_id: line.id,
+                name: line.name.clone(),
+                count: line.count,
+                available: stock(line.id, line.stock),
+            }),
+        }
+    }
+    Buildable {
+        quantity: Some(low),
+        limiting,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use crate::models::{PartWithCountAndStock, PartWithStock};
+
+    use super::{buildable, buildable_together};
+
+    fn line(id: i64, name: &str, count: i64, stock: i64) -> PartWithCountAndStock {
+        PartWithCountAndStock {
+            id,
+            name: name.to_string(),
+            description: String::new(),
+            count,
+            designators: vec![],
+            variants: vec![],
+            stock,
+            column: -1,
+            row: -1,
+            z: -1,
+            alternates: vec![],
+        }
+    }
+
+    fn alternate(id: i64, name: &str, stock: i64) -> PartWithStock {
+        PartWithStock {
+            id,
+            name: name.to_string(),
+            description: String::new(),
+            manufacturer: String::new(),
+            mpn: String::new(),
+            skus: vec![],
+            parameters: Default::default(),
+            purchasing: Default::default(),
+            stock,
+            column: -1,
+            row: -1,
+            z: -1,
+        }
+    }
+
+    fn limiting_names(lines: &[PartWithCountAndStock]) -> Vec<String> {
+        buildable(lines, &HashMap::new())
+            .limiting
+            .into_iter()
+            .map(|p| p.name)
+            .collect()
+    }
+
+    #[test]
+    fn scarcest_part_limits_the_build() {
+        let lines = vec![line(1, "OPA2134", 2, 9), line(2, "10k", 4, 100)];
+        let result = buildable(&lines, &HashMap::new());
+        assert_eq!(result.quantity, Some(4));
+        assert_eq!(limiting_names(&lines), ["OPA2134"]);
+
+        // Reserved stock isn't available
+        let result = buildable(&lines, &HashMap::from([(2, 12)]));
+        assert_eq!(result.quantity, Some(3));
+        assert_eq!(result.limiting[0].name, "10k");
+        assert_eq!(result.limiting[0].available, 12);
+
+        let lines = vec![line(1, "OPA2134", 2, 8), line(2, "10k", 4, 16)];
+        assert_eq!(limiting_names(&lines), ["OPA2134", "10k"]);
+        assert_eq!(buildable(&[], &HashMap::new()).quantity, None);
+    }
+
+    #[test]
+    fn alternates_raise_the_buildable_quantity() {
+        let mut capacitor = line(1, "100nF Murata", 4, 5);
+        capacitor.alternates = vec![alternate(3, "100nF Yageo", 6)];
+        let lines = vec![capacitor, line(2, "10k", 1, 10)];
+        let result = buildable(&lines, &HashMap::new());
+        assert_eq!(result.quantity, Some(2));
+        assert_eq!(result.limiting[0].name, "100nF Murata");
+        assert_eq!(result.limiting[0].available, 5);
+    }
+
+    #[test]
+    fn shared_parts_are_split_between_boms() {
+        let amplifier = vec![line(1, "OPA2134", 2, 20), line(2, "10k", 4, 30)];
+        let sensor = vec![line(2, "10k", 3, 30), line(3, "BME280", 1, 50)];
+        assert_eq!(buildable(&amplifier, &HashMap::new()).quantity, Some(7));
+        assert_eq!(buildable(&sensor, &HashMap::new()).quantity, Some(10));
+
+        // One amplifier and two sensors take 10 resistors a set
+        let result = buildable_together(&[(&amplifier, 1), (&sensor, 2)], &HashMap::new());
+        assert_eq!(result.quantity, Some(3));
+        assert_eq!(result.limiting.len(), 1);
+        assert_eq!(result.limiting[0].name, "10k");
+        assert_eq!(result.limiting[0].count, 10);
+    }
+}
@@ -0,0 +1,163 @@
+use std::fmt::Display;
+
+use reqwest::StatusCode;
+use serde::Deserialize;
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum NetworkError {
+    #[error("Not logged in or the session has expired")]
+    Unauthorized,
+    #[error("Not found: {0}")]
+    NotFound(String),
+    #[error("Conflict: {0}")]
+    Conflict(String),
+    #[error("Invalid {field}: {msg}")]
+    Validation { field: String, msg: String },
+    #[error("Server responded with {status}: {body}")]
+    Server { status: u16, body: String },
+    #[error("Could not reach the server: {0}")]
+    Transport(#[from] reqwest::Error),
+    #[error("Could not decode the server response: {0}")]
+    Decode(#[from] serde_json::Error),
+    #[error("Local database error: {0}")]
+    Storage(#[from] rusqlite::Error),
+    #[error("{0}")]
+    Batch(BatchFailure),
+    /// The server has no endpoint for the request, e.g. because it is an older version
+    #[error("The server doesn't support {0}")]
+    Unsupported(String),
+}
+
+/// A line of a batch stock change that did not go through
+#[derive(Debug)]
+pub struct FailedLine {
+    pub part_id: i64,
+    pub name: String,
+    pub error: NetworkError,
+}
+
+/// Why a batch stock change was not applied. Lines that did go through were reverted, except
+/// for the ones in `unreverted`.
+#[derive(Debug)]
+pub struct BatchFailure {
+    pub failed: Vec<FailedLine>,
+    pub unreverted: Vec<FailedLine>,
+}
+
+fn join_lines(lines: &[FailedLine]) -> String {
+    lines
+        .iter()
+        .map(|l| format!("{} ({})", l.name, l.error))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+impl Display for BatchFailure {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} stock change(s) failed: {}",
+            self.failed.len(),
+            join_lines(&self.failed)
+        )?;
+        if self.unreverted.is_empty() {
+            write!(f, ". Nothing was applied")
+        } else {
+            write!(
+                f,
+                ". Could not revert {}, check their stock",
+                join_lines(&self.unreverted)
+            )
+        }
+    }
+}
+
+/// The error body the server sends along with non-2xx responses, when it sends one at all.
+#[derive(Debug, Default, Deserialize)]
+struct ErrorBody {
+    #[serde(default)]
+    field: Option<String>,
+    #[serde(default, alias = "error", alias = "msg")]
+    message: Option<String>,
+}
+
+impl NetworkError {
+    pub fn from_response(status: StatusCode, body: String) -> Self {
+        let decoded: ErrorBody = serde_json::from_str(&body).unwrap_or_default();
+        let message = decoded.message.unwrap_or_else(|| body.clone());
+        match status {
+            StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => NetworkError::Unauthorized,
+            StatusCode::NOT_FOUND => NetworkError::NotFound(message),
+            StatusCode::CONFLICT => NetworkError::Conflict(message),
+            StatusCode::BAD_REQUEST | StatusCode::UNPROCESSABLE_ENTITY => {
+                NetworkError::Validation {
+                    field: decoded.field.unwrap_or_default(),
+                    msg: message,
+                }
+            }
+            _ => NetworkError::Server {
+                status: status.as_u16(),
+                body,
+            },
+        }
+    }
+
+    /// Whether the same request could succeed later, i.e. the server was out of reach rather than
+    /// refusing it.
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            NetworkError::Transport(_) => true,
+            NetworkError::Server { status, .. } => *status >= 500,
+            // Only safe to retry if nothing stuck and every line failed for lack of a server
+            NetworkError::Batch(failure) => {
+                failure.unreverted.is_empty()
+                    && failure.failed.iter().all(|l| l.error.is_retryable())
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Whether an error bubbling up through `anyhow` means the user has to log in (again).
+pub fn is_unauthorized(error: &anyhow::Error) -> bool {
+    matches!(
+        error.downcast_ref::<NetworkError>(),
+        Some(NetworkError::Unauthorized)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use reqwest::StatusCode;
+
+    use super::NetworkError;
+
+    #[test]
+    fn decodes_validation_body() {
+        let err = NetworkError::from_response(
+            StatusCode::BAD_REQUEST,
+            String::from(r#"{"field":"email","message":"already taken"}"#),
+        );
+        match err {
+            NetworkError::Validation { field, msg } => {
+                assert_eq!(field, "email");
+                assert_eq!(msg, "already taken");
+            }
+            e => panic!("Unexpected error {:?}", e),
+        }
+    }
+
+    #[test]
+    fn keeps_plain_text_bodies() {
+        let err = NetworkError::from_response(StatusCode::NOT_FOUND, String::from("No such BOM"));
+        assert!(matches!(err, NetworkError::NotFound(m) if m == "No such BOM"));
+    }
+
+    #[test]
+    fn maps_unknown_statuses_to_server_errors() {
+        let err =
+            NetworkError::from_response(StatusCode::INTERNAL_SERVER_ERROR, String::from("oops"));
+        assert!(matches!(err, NetworkError::Server { status: 500, .. }));
+    }
+}
@@ -1,24 +1,111 @@
-use std::path::Path;
+use std::{fmt::Display, io, path::Path, str::FromStr};
 
 use anyhow::Result;
 use csv::Writer;
 
-use crate::models::PurchaseRequirement;
+use crate::{
+    mapping::normalize,
+    models::{PartWithStock, PurchaseRequirement},
+};
+
+/// Layouts a purchase plan can be exported in
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ExportFormat {
+    /// Every requirement with its stock and the BOMs needing it
+    #[default]
+    Generic,
+    /// DigiKey's bulk add to cart
+    DigiKey,
+    /// Mouser's BOM tool
+    Mouser,
+    /// LCSC's BOM upload, which also orders the parts JLCPCB assembles with
+    Lcsc,
+}
+
+impl ExportFormat {
+    pub const ALL: [ExportFormat; 4] = [
+        ExportFormat::Generic,
+        ExportFormat::DigiKey,
+        ExportFormat::Mouser,
+        ExportFormat::Lcsc,
+    ];
+
+    /// Suppliers whose part numbers the format takes, normalized
+    fn suppliers(self) -> &'static [&'static str] {
+        match self {
+            ExportFormat::Generic => &[],
+            ExportFormat::DigiKey => &["digikey"],
+            ExportFormat::Mouser => &["mouser"],
+            ExportFormat::Lcsc => &["lcsc", "jlc", "jlcpcb"],
+        }
+    }
+}
+
+impl Display for ExportFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            ExportFormat::Generic => "Generic CSV",
+            ExportFormat::DigiKey => "DigiKey bulk add",
+            ExportFormat::Mouser => "Mouser BOM tool",
+            ExportFormat::Lcsc => "LCSC/JLC upload",
+        })
+    }
+}
+
+impl FromStr for ExportFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match normalize(s).as_str() {
+            "generic" | "csv" => Ok(ExportFormat::Generic),
+            "digikey" => Ok(ExportFormat::DigiKey),
+            "mouser" => Ok(ExportFormat::Mouser),
+            "lcsc" | "jlc" | "jlcpcb" => Ok(ExportFormat::Lcsc),
+            _ => Err(format!("Unknown export format {}", s)),
+        }
+    }
+}
+
+/// Writes the purchase plan to `path` in `format`. The supplier formats only list what needs
+/// buying, by the supplier's part number where the part has one and by MPN otherwise.
+pub fn export_purchase_plan(
+    path: &Path,
+    requirements: &[PurchaseRequirement],
+    format: ExportFormat,
+) -> Result<()> {
+    write_purchase_plan(std::fs::File::create(path)?, requirements, format)
+}
 
 pub fn export_purchase_plan_to_csv(
     path: &Path,
     requirements: &[PurchaseRequirement],
 ) -> Result<()> {
-    let mut wtr = Writer::from_path(path)?;
+    export_purchase_plan(path, requirements, ExportFormat::Generic)
+}
+
+pub fn write_purchase_plan(
+    writer: impl io::Write,
+    requirements: &[PurchaseRequirement],
+    format: ExportFormat,
+) -> Result<()> {
+    let mut wtr = Writer::from_writer(writer);
+    if format != ExportFormat::Generic {
+        write_cart(&mut wtr, requirements, format)?;
+        wtr.flush()?;
+        return Ok(());
+    }
 
     // Write header
     wtr.write_record(&[
         "Part Name",
         "Description",
         "Current Stock",
+        "Reserved",
         "Total Required",
-        "Need to Purchase",
+        "Strict Shortfall",
+        "Order Quantity",
         "Required By",
+        "Alternates Used",
     ])?;
 
     // Write each requirement
@@ -37,17 +124,211 @@ pub fn export_purchase_plan_to_csv(
             })
             .collect::<Vec<_>>()
             .join(", ");
+        let substitutes = req
+            .substitutions
+            .iter()
+            .map(|s| format!("{} {}", s.count, s.alternate_name))
+            .collect::<Vec<_>>()
+            .join(", ");
 
         wtr.write_record(&[
             &req.part.name,
             &req.part.description,
             &req.part.stock.to_string(),
+            &req.reserved.to_string(),
             &req.required.to_string(),
             &req.shortfall.to_string(),
+            &req.order_quantity.to_string(),
             &required_by,
+            &substitutes,
         ])?;
     }
 
     wtr.flush()?;
     Ok(())
 }
+
+/// The part number `format`'s supplier has the part under
+fn supplier_sku(part: &PartWithStock, format: ExportFormat) -> Option<&str> {
+    part.skus
+        .iter()
+        .find(|s| {
+            format
+                .suppliers()
+                .contains(&normalize(&s.supplier).as_str())
+        })
+        .map(|s| s.sku.as_str())
+}
+
+/// The manufacturer part number, or the name for parts without one
+fn mpn(part: &PartWithStock) -> &str {
+    if part.mpn.is_empty() {
+        &part.name
+    } else {
+        &part.mpn
+    }
+}
+
+fn write_cart<W: io::Write>(
+    wtr: &mut Writer<W>,
+    requirements: &[PurchaseRequirement],
+    format: ExportFormat,
+) -> Result<()> {
+    match format {
+        ExportFormat::Generic => {}
+        ExportFormat::DigiKey => {
+            wtr.write_record(["Quantity", "Part Number", "Customer Reference"])?
+        }
+        ExportFormat::Mouser => wtr.write_record([
+            "Mouser Part Number",
+            "Manufacturer Part Number",
+            "Manufacturer Name",
+            "Description",
+            "Quantity 1",
+            "Customer Part Number",
+        ])?,
+        ExportFormat::Lcsc => wtr.write_record([
+            "Quantity",
+            "Manufacture Part Number",
+            "Manufacturer",
+            "Description",
+            "LCSC Part Number",
+            "Customer Part Number",
+        ])?,
+    }
+    for req in requirements.iter().filter(|r| r.order_quantity > 0) {
+        let part = &req.part;
+        let quantity = req.order_quantity.to_string();
+        let sku = supplier_sku(part, format);
+        match format {
+            ExportFormat::Generic => {}
+            ExportFormat::DigiKey => {
+                wtr.write_record([quantity.as_str(), sku.unwrap_or(mpn(part)), &part.name])?
+            }
+            ExportFormat::Mouser => wtr.write_record([
+                sku.unwrap_or_default(),
+                mpn(part),
+                &part.manufacturer,
+                &part.description,
+                &quantity,
+                &part.name,
+            ])?,
+            ExportFormat::Lcsc => wtr.write_record([
+                quantity.as_str(),
+                mpn(part),
+                &part.manufacturer,
+                &part.description,
+                sku.unwrap_or_default(),
+                &part.name,
+            ])?,
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::models::{PartWithStock, PurchaseRequirement, SupplierSku};
+
+    use super::{ExportFormat, write_purchase_plan};
+
+    fn requirement(
+        name: &str,
+        mpn: &str,
+        skus: &[(&str, &str)],
+        shortfall: i64,
+        order_quantity: i64,
+    ) -> PurchaseRequirement {
+        PurchaseRequirement {
+            part: PartWithStock {
+                id: 1,
+                name: name.to_string(),
+                description: String::new(),
+                manufacturer: String::new(),
+                mpn: mpn.to_string(),
+                skus: skus
+                    .iter()
+                    .map(|(supplier, sku)| SupplierSku {
+                        supplier: supplier.to_string(),
+                        sku: sku.to_string(),
+                    })
+                    .collect(),
+                parameters: Default::default(),
+                purchasing: Default::default(),
+                stock: 0,
+                column: -1,
+                row: -1,
+                z: -1,
+            },
+            reserved: 0,
+            required: shortfall,
+            shortfall,
+            order_quantity,
+            bom_sources: vec![],
+            substitutions: vec![],
+        }
+    }
+
+    fn export(requirements: &[PurchaseRequirement], format: ExportFormat) -> String {
+        let mut out = vec![];
+        write_purchase_plan(&mut out, requirements, format).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn carts_use_supplier_part_numbers_where_known() {
+        let requirements = [
+            requirement(
+                "10k",
+                "RC0603FR-0710KL",
+                &[("Digi-Key", "311-10.0KHRCT-ND")],
+                40,
+                40,
+            ),
+            requirement("OPA2134", "", &[("LCSC", "C94284")], 3, 3),
+            requirement("BME280", "BME280", &[], 0, 0),
+        ];
+        assert_eq!(
+            export(&requirements, ExportFormat::DigiKey),
+            "Quantity,Part Number,Customer Reference\n\
+             40,311-10.0KHRCT-ND,10k\n\
+             3,OPA2134,OPA2134\n"
+        );
+        assert_eq!(
+            export(&requirements, ExportFormat::Lcsc),
+            "Quantity,Manufacture Part Number,Manufacturer,Description,LCSC Part Number,\
+             Customer Part Number\n\
+             40,RC0603FR-0710KL,,,,10k\n\
+             3,OPA2134,,,C94284,OPA2134\n"
+        );
+    }
+
+    #[test]
+    fn carts_order_the_rounded_quantity() {
+        let requirements = [
+            requirement("10k", "RC0603FR-0710KL", &[], 40, 100),
+            // Covered by stock but below its safety stock
+            requirement("BME280", "BME280", &[], 0, 5),
+        ];
+        assert_eq!(
+            export(&requirements, ExportFormat::DigiKey),
+            "Quantity,Part Number,Customer Reference\n\
+             100,RC0603FR-0710KL,10k\n\
+             5,BME280,BME280\n"
+        );
+        assert_eq!(
+            export(&requirements, ExportFormat::Generic),
+            "Part Name,Description,Current Stock,Reserved,Total Required,Strict Shortfall,\
+             Order Quantity,Required By,Alternates Used\n\
+             10k,,0,0,40,40,100,,\n\
+             BME280,,0,0,0,0,5,,\n"
+        );
+    }
+
+    #[test]
+    fn formats_parse_from_their_supplier() {
+        assert_eq!("digi-key".parse(), Ok(ExportFormat::DigiKey));
+        assert_eq!("JLCPCB".parse(), Ok(ExportFormat::Lcsc));
+        assert!("farnell".parse::<ExportFormat>().is_err());
+    }
+}
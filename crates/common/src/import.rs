@@ -1,7 +1,7 @@
 use std::{
     collections::HashMap,
     fs::{self, File},
-    io::BufReader,
+    io::{BufReader, Cursor},
     path::Path,
 };
 
@@ -15,19 +15,213 @@ use altium_format::{
     records::sch::{SchPrimitive, SchRecord},
 };
 use anyhow::{Result, anyhow};
-use csv::{Reader, ReaderBuilder};
+use calamine::{Data, Reader as _, open_workbook_auto};
+use csv::{Reader, ReaderBuilder, Writer};
+use encoding_rs::{Encoding, UTF_16BE, UTF_16LE, WINDOWS_1251, WINDOWS_1252};
 use tracing::info;
 
-use crate::models::Part;
+use crate::{
+    kicad::{MAX_SHEET_DEPTH, kicad_schematic_file_to_bom},
+    mapping::normalize,
+    models::{BomLine, Part, SupplierSku, VariantCount, designators_to_string, sort_designators},
+    parameters::Parameters,
+};
+
+/// Headers holding the manufacturer, as exported by Altium and KiCad
+pub(crate) const MANUFACTURER_HEADERS: &[&str] = &["Manufacturer", "Manufacturer 1"];
+/// Headers holding the manufacturer part number
+pub(crate) const MPN_HEADERS: &[&str] = &[
+    "Manufacturer Part Number",
+    "Manufacturer Part Number 1",
+    "MPN",
+];
+/// Headers holding the reference designators of a line, as exported by Altium and KiCad
+pub(crate) const DESIGNATOR_HEADERS: &[&str] =
+    &["Designator", "Reference", "References", "Refs", "Ref"];
+/// Parameters and headers marking a part as not fitted when set, e.g. `DNP=1`
+pub(crate) const DNP_HEADERS: &[&str] = &["DNP", "DNF", "Do Not Populate", "Not Fitted", "NoPop"];
+/// Parameters and headers marking a part as not fitted when cleared, e.g. `Fitted=Not Fitted`
+pub(crate) const FITTED_HEADERS: &[&str] = &["Fitted", "Populate", "Populated"];
+/// Headers a BOM table usually has, used to tell its header row from the title block above it
+const TABLE_HEADERS: &[&str] = &[
+    "Name",
+    "Description",
+    "Comment",
+    "Value",
+    "Qty",
+    "Quantity",
+    "Footprint",
+    "Part Number",
+];
+/// How far down a worksheet the header row is looked for
+const MAX_TITLE_ROWS: usize = 30;
+/// Pairs of headers holding a supplier and the part number at that supplier
+pub(crate) const SUPPLIER_HEADERS: &[(&str, &str)] = &[
+    ("Supplier", "Supplier Part Number"),
+    ("Supplier 1", "Supplier Part Number 1"),
+    ("Supplier 2", "Supplier Part Number 2"),
+];
+/// Headers named after a supplier, holding the part number at that supplier
+pub(crate) const SKU_HEADERS: &[(&str, &str)] = &[
+    ("DigiKey", "DigiKey"),
+    ("Digi-Key Part Number", "DigiKey"),
+    ("Mouser", "Mouser"),
+    ("Mouser Part Number", "Mouser"),
+    ("LCSC", "LCSC"),
+    ("LCSC Part #", "LCSC"),
+];
+
+/// Links BOM lines to the existing parts they describe, see [`Part::matches`]. Lines that
+/// match nothing keep id 0 and become new parts.
+pub fn link_existing(candidates: &mut [BomLine], existing: &[Part]) {
+    for candidate in candidates.iter_mut() {
+        if let Some(part) = existing.iter().find(|p| p.matches(&candidate.part)) {
+            candidate.part.id = part.id;
+        }
+    }
+}
+
+fn is_any_of(names: &[&str], name: &str) -> bool {
+    names.iter().any(|n| n.eq_ignore_ascii_case(name.trim()))
+}
+
+/// Whether a parameter or column called `name` holding `value` marks a part as do-not-populate
+pub(crate) fn marks_dnp(name: &str, value: &str) -> bool {
+    // Altium shows unset parameters as "*"
+    let value = value.trim().to_lowercase();
+    if is_any_of(DNP_HEADERS, name) {
+        !matches!(
+            value.as_str(),
+            "" | "*" | "0" | "n" | "no" | "false" | "fitted"
+        )
+    } else if is_any_of(FITTED_HEADERS, name) {
+        matches!(
+            value.as_str(),
+            "0" | "n" | "no" | "false" | "not fitted" | "dnp"
+        )
+    } else {
+        false
+    }
+}
+
+/// Splits a designator cell like "R1, R5 R12" and sorts it naturally
+fn split_designators(cell: &str) -> Vec<String> {
+    let mut designators: Vec<String> = cell
+        .split([',', ';', ' '])
+        .map(str::trim)
+        .filter(|d| !d.is_empty())
+        .map(String::from)
+        .collect();
+    sort_designators(&mut designators);
+    designators
+}
+
+/// Text of a CSV file in whatever encoding it was saved in. Without a byte order mark or valid
+/// UTF-8 it is taken to be one of the Windows code pages spreadsheets save as.
+fn decode_text(bytes: &[u8]) -> String {
+    if let Some((encoding, bom_length)) = Encoding::for_bom(bytes) {
+        let (text, _) = encoding.decode_without_bom_handling(&bytes[bom_length..]);
+        return text.into_owned();
+    }
+    // UTF-16 without a byte order mark still has every other byte zero in mostly ASCII text
+    let head = &bytes[..bytes.len().min(64)];
+    let zeros_at = |parity: usize| {
+        head.iter()
+            .skip(parity)
+            .step_by(2)
+            .filter(|b| **b == 0)
+            .count()
+    };
+    if head.len() >= 4 && zeros_at(1) * 4 >= head.len() && zeros_at(0) == 0 {
+        return UTF_16LE.decode_without_bom_handling(bytes).0.into_owned();
+    }
+    if head.len() >= 4 && zeros_at(0) * 4 >= head.len() && zeros_at(1) == 0 {
+        return UTF_16BE.decode_without_bom_handling(bytes).0.into_owned();
+    }
+    match std::str::from_utf8(bytes) {
+        Ok(text) => text.to_string(),
+        Err(_) => decode_legacy(bytes),
+    }
+}
+
+/// Decodes text that isn't UTF-8 as Windows-1251 or Windows-1252. Cyrillic words are runs of
+/// bytes above 0x7F, while accents and symbols like ° and µ in Latin text stand alone.
+fn decode_legacy(bytes: &[u8]) -> String {
+    let high = |b: &u8| *b >= 0x80;
+    let in_runs = bytes
+        .windows(2)
+        .filter(|pair| high(&pair[0]) && high(&pair[1]))
+        .count();
+    let alone = bytes
+        .iter()
+        .filter(|b| high(b))
+        .count()
+        .saturating_sub(in_runs * 2);
+    let encoding = if in_runs > alone {
+        WINDOWS_1251
+    } else {
+        WINDOWS_1252
+    };
+    encoding.decode_without_bom_handling(bytes).0.into_owned()
+}
+
+/// The delimiter splitting the first lines of a CSV into the same, largest number of fields
+fn sniff_delimiter(text: &str) -> u8 {
+    let lines: Vec<&str> = text
+        .lines()
+        .filter(|l| !l.trim().is_empty())
+        .take(10)
+        .collect();
+    if lines.is_empty() {
+        return b',';
+    }
+    let fields = |line: &str, delimiter: char| {
+        let mut quoted = false;
+        let mut count = 0;
+        for c in line.chars() {
+            if c == '"' {
+                quoted = !quoted;
+            } else if c == delimiter && !quoted {
+                count += 1;
+            }
+        }
+        count
+    };
+    [b',', b';', b'\t', b'|']
+        .into_iter()
+        .max_by_key(|d| {
+            let counts: Vec<usize> = lines.iter().map(|l| fields(l, *d as char)).collect();
+            let first = counts[0];
+            (first > 0 && counts.iter().all(|c| *c == first), first)
+        })
+        .unwrap_or(b',')
+}
+
+/// Opens a CSV whatever its encoding and delimiter, handing it on as UTF-8
+fn open_csv(path: &Path) -> Result<Reader<Cursor<Vec<u8>>>> {
+    let text = decode_text(&fs::read(path)?);
+    let delimiter = sniff_delimiter(&text);
+    Ok(ReaderBuilder::new()
+        .has_headers(true)
+        .delimiter(delimiter)
+        .from_reader(Cursor::new(text.into_bytes())))
+}
 
 pub fn csv_to_bom(
     path: &Path,
     name_col: &str,
     desc_col: &str,
     count_col: &str,
-) -> Result<Vec<(i64, Part)>> {
-    let rdr = ReaderBuilder::new().has_headers(true).from_path(path)?;
-    reader_to_bom(rdr, name_col, desc_col, count_col)
+) -> Result<Vec<BomLine>> {
+    reader_to_bom(open_csv(path)?, name_col, desc_col, count_col)
+}
+
+/// Finds a column by its header, letting case, spacing and punctuation differ
+fn find_column(headers: &csv::StringRecord, column: &str) -> Option<usize> {
+    headers.iter().position(|h| h == column).or_else(|| {
+        let column = normalize(column);
+        headers.iter().position(|h| normalize(h) == column)
+    })
 }
 
 pub fn reader_to_bom<T>(
@@ -35,31 +229,34 @@ pub fn reader_to_bom<T>(
     name_col: &str,
     desc_col: &str,
     count_col: &str,
-) -> Result<Vec<(i64, Part)>>
+) -> Result<Vec<BomLine>>
 where
     T: std::io::Read,
 {
     let mut out = vec![];
 
     let headers = rdr.headers()?;
-    let name_idx = headers
+    let name_idx = find_column(headers, name_col).ok_or(anyhow!("Name column not found"))?;
+    let desc_idx = find_column(headers, desc_col).ok_or(anyhow!("Description column not found"))?;
+    let count_idx = find_column(headers, count_col).ok_or(anyhow!("Count column not found"))?;
+    let find = |candidates: &[&str]| headers.iter().position(|h| candidates.contains(&h));
+    let manufacturer_idx = find(MANUFACTURER_HEADERS);
+    let mpn_idx = find(MPN_HEADERS);
+    let designator_idx = find(DESIGNATOR_HEADERS);
+    let dnp_idxs: Vec<(usize, String)> = headers
         .iter()
         .enumerate()
-        .find(|(_, s)| *s == name_col)
-        .ok_or(anyhow!("Name column not found"))?
-        .0;
-    let desc_idx = headers
+        .filter(|(_, h)| is_any_of(DNP_HEADERS, h) || is_any_of(FITTED_HEADERS, h))
+        .map(|(i, h)| (i, h.to_string()))
+        .collect();
+    let supplier_idxs: Vec<(usize, usize)> = SUPPLIER_HEADERS
         .iter()
-        .enumerate()
-        .find(|(_, s)| *s == desc_col)
-        .ok_or(anyhow!("Description column not found"))?
-        .0;
-    let count_idx = headers
+        .filter_map(|(supplier, sku)| Some((find(&[*supplier])?, find(&[*sku])?)))
+        .collect();
+    let sku_idxs: Vec<(usize, &str)> = SKU_HEADERS
         .iter()
-        .enumerate()
-        .find(|(_, s)| *s == count_col)
-        .ok_or(anyhow!("Count column not found"))?
-        .0;
+        .filter_map(|(header, supplier)| Some((find(&[*header])?, *supplier)))
+        .collect();
 
     for record in rdr.byte_records() {
         let r = record?;
@@ -72,57 +269,435 @@ where
             .to_owned();
         let description = match String::from_utf8(desc_bytes.clone()) {
             Ok(s) => s,
-            Err(_) => {
-                let (s, _, _) = encoding_rs::WINDOWS_1251.decode(&desc_bytes);
-                s.to_string()
-            }
+            Err(_) => decode_legacy(&desc_bytes),
         };
-        let count = String::from_utf8_lossy(
+        let count: i64 = String::from_utf8_lossy(
             r.get(count_idx)
                 .ok_or(anyhow!("Non-homogeneous csv file"))?,
         )
         .parse()?;
+        let field = |idx: Option<usize>| {
+            idx.and_then(|i| r.get(i))
+                .map(|b| String::from_utf8_lossy(b).trim().to_string())
+                .unwrap_or_default()
+        };
+        if dnp_idxs
+            .iter()
+            .any(|(i, header)| marks_dnp(header, &field(Some(*i))))
+        {
+            continue;
+        }
+        let designators = split_designators(&field(designator_idx));
+        if !designators.is_empty() && designators.len() as i64 != count {
+            return Err(anyhow!(
+                "{} has a quantity of {} but {} designators ({})",
+                name,
+                count,
+                designators.len(),
+                designators_to_string(&designators)
+            ));
+        }
+        let mut skus: Vec<SupplierSku> = supplier_idxs
+            .iter()
+            .map(|(supplier, sku)| SupplierSku {
+                supplier: field(Some(*supplier)),
+                sku: field(Some(*sku)),
+            })
+            .collect();
+        skus.extend(sku_idxs.iter().map(|(idx, supplier)| SupplierSku {
+            supplier: supplier.to_string(),
+            sku: field(Some(*idx)),
+        }));
+        skus.retain(|s| !s.supplier.is_empty() && !s.sku.is_empty());
+        let parameters = Parameters::from_text(&format!("{} {}", name, description));
 
-        out.push((
+        out.push(BomLine {
             count,
-            Part {
+            part: Part {
                 id: 0,
                 name,
                 description,
+                manufacturer: field(manufacturer_idx),
+                mpn: field(mpn_idx),
+                skus,
+                parameters,
+                ..Default::default()
             },
-        ));
+            designators,
+            variants: vec![],
+        });
     }
-    out.sort_by(|(_, a), (_, b)| a.name.cmp(&b.name));
+    out.sort_by(|a, b| a.part.name.cmp(&b.part.name));
     Ok(out)
 }
 
 pub fn csv_to_headers(path: &Path) -> Result<Vec<String>> {
-    let mut rdr = ReaderBuilder::new().has_headers(true).from_path(path)?;
+    let mut rdr = open_csv(path)?;
     let headers = rdr.headers()?;
     Ok(headers.iter().map(String::from).collect())
 }
 
-pub fn altium_schematic_file_to_bom(path: &Path) -> Result<Vec<(i64, Part)>> {
+/// Whether `path` is a spreadsheet workbook rather than a CSV, going by the extension
+pub fn is_workbook(path: &Path) -> bool {
+    path.extension()
+        .and_then(|e| e.to_str())
+        .is_some_and(|e| ["xlsx", "xlsm", "xls", "ods"].contains(&e.to_lowercase().as_str()))
+}
+
+/// Names of the worksheets in an `.xlsx` or `.ods` workbook, in order
+pub fn workbook_sheets(path: &Path) -> Result<Vec<String>> {
+    Ok(open_workbook_auto(path)?.sheet_names())
+}
+
+/// The header row of a worksheet, see [`workbook_to_bom`]. Without a `sheet` the first one is
+/// read.
+pub fn workbook_to_headers(path: &Path, sheet: Option<&str>) -> Result<Vec<String>> {
+    Ok(read_worksheet(path, sheet)?
+        .into_iter()
+        .next()
+        .unwrap_or_default())
+}
+
+/// Reads a BOM table from a worksheet the way [`csv_to_bom`] reads a CSV. Title blocks above the
+/// header row are skipped.
+pub fn workbook_to_bom(
+    path: &Path,
+    sheet: Option<&str>,
+    name_col: &str,
+    desc_col: &str,
+    count_col: &str,
+) -> Result<Vec<BomLine>> {
+    let rows = read_worksheet(path, sheet)?;
+    reader_to_bom(table_to_reader(&rows)?, name_col, desc_col, count_col)
+}
+
+/// The non-empty rows of a worksheet from its header row down, as text
+fn read_worksheet(path: &Path, sheet: Option<&str>) -> Result<Vec<Vec<String>>> {
+    let mut workbook = open_workbook_auto(path)?;
+    let sheet = match sheet {
+        Some(sheet) => sheet.to_string(),
+        None => workbook
+            .sheet_names()
+            .into_iter()
+            .next()
+            .ok_or(anyhow!("{} has no sheets", path.display()))?,
+    };
+    let range = workbook.worksheet_range(&sheet)?;
+    let rows: Vec<Vec<String>> = range
+        .rows()
+        .map(|row| row.iter().map(cell_to_string).collect())
+        .filter(|row: &Vec<String>| row.iter().any(|c| !c.is_empty()))
+        .collect();
+    let header = header_row(&rows);
+    Ok(rows.into_iter().skip(header).collect())
+}
+
+fn cell_to_string(cell: &Data) -> String {
+    match cell {
+        // Quantities are stored as floats
+        Data::Float(f) if f.fract() == 0.0 => format!("{}", *f as i64),
+        _ => cell.to_string().trim().to_string(),
+    }
+}
+
+/// Index of the row that names the columns. That's the one with the most well known headers, or
+/// failing that the first one filling as many cells as the widest row.
+fn header_row(rows: &[Vec<String>]) -> usize {
+    let candidates = &rows[..rows.len().min(MAX_TITLE_ROWS)];
+    let known = |row: &Vec<String>| {
+        row.iter()
+            .filter(|c| {
+                TABLE_HEADERS
+                    .iter()
+                    .chain(DESIGNATOR_HEADERS)
+                    .chain(MPN_HEADERS)
+                    .any(|h| h.eq_ignore_ascii_case(c))
+            })
+            .count()
+    };
+    let best = candidates.iter().map(known).max().unwrap_or(0);
+    if best >= 2 {
+        return candidates
+            .iter()
+            .position(|r| known(r) == best)
+            .unwrap_or(0);
+    }
+    let filled = |row: &Vec<String>| row.iter().filter(|c| !c.is_empty()).count();
+    let widest = candidates.iter().map(filled).max().unwrap_or(0);
+    candidates
+        .iter()
+        .position(|r| filled(r) == widest)
+        .unwrap_or(0)
+}
+
+/// Feeds worksheet rows through the CSV reader so both kinds of files import the same way
+fn table_to_reader(rows: &[Vec<String>]) -> Result<Reader<Cursor<Vec<u8>>>> {
+    let mut writer = Writer::from_writer(vec![]);
+    for row in rows {
+        writer.write_record(row)?;
+    }
+    let csv = writer.into_inner().map_err(|e| anyhow!("{}", e))?;
+    Ok(ReaderBuilder::new()
+        .has_headers(true)
+        .from_reader(Cursor::new(csv)))
+}
+
+/// How many of a BOM line each sheet of a project holds, by sheet file name
+pub type SheetCounts = Vec<(String, i64)>;
+
+/// Reads an Altium `.SchDoc` or `.PrjPcb`, or a KiCad `.kicad_sch` schematic, going by the
+/// extension
+pub fn schematic_file_to_bom(path: &Path) -> Result<Vec<BomLine>> {
+    match path.extension().and_then(|e| e.to_str()) {
+        Some("SchDoc") => altium_schematic_file_to_bom(path),
+        Some("PrjPcb") => Ok(altium_project_file_to_bom(path)?
+            .into_iter()
+            .map(|(line, _)| line)
+            .collect()),
+        Some("kicad_sch") => kicad_schematic_file_to_bom(path),
+        _ => Err(anyhow!("Unsupported schematic {}", path.display())),
+    }
+}
+
+pub fn altium_schematic_file_to_bom(path: &Path) -> Result<Vec<BomLine>> {
     let file = fs::File::open(path)?;
     let rdr = BufReader::new(file);
     altium_schematic_reader_to_bom(rdr)
 }
 
-pub fn altium_schematic_reader_to_bom<T>(rdr: BufReader<T>) -> Result<Vec<(i64, Part)>>
+pub fn altium_schematic_reader_to_bom<T>(rdr: BufReader<T>) -> Result<Vec<BomLine>>
+where
+    T: std::io::Read + std::io::Seek,
+{
+    let mut out: Vec<BomLine> = read_altium_sheet(rdr)?.bom.into_values().collect();
+    for line in &mut out {
+        sort_designators(&mut line.designators);
+    }
+    out.sort_by(|a, b| a.part.name.cmp(&b.part.name));
+
+    Ok(out)
+}
+
+/// Reads every schematic sheet of an Altium `.PrjPcb` into one BOM. Sheets placed several times,
+/// through several sheet symbols or a `Repeat(...)` channel symbol, count that many times.
+pub fn altium_project_file_to_bom(path: &Path) -> Result<Vec<(BomLine, SheetCounts)>> {
+    let dir = path.parent().map(Path::to_path_buf).unwrap_or_default();
+    let project = String::from_utf8_lossy(&fs::read(path)?).to_string();
+    let mut sheets = vec![];
+    for document in project_documents(&project) {
+        let file = fs::File::open(dir.join(&document))?;
+        let name = sheet_key(&document).to_string();
+        sheets.push((name, read_altium_sheet(BufReader::new(file))?));
+    }
+    let mut bom = merge_altium_sheets(sheets)?;
+    apply_variants(&mut bom, &project_variants(&project));
+    Ok(bom)
+}
+
+/// The assembly variants of a `.PrjPcb` along with the designators each one doesn't fit
+fn project_variants(project: &str) -> Vec<(String, Vec<String>)> {
+    let mut variants: Vec<(String, Vec<String>)> = vec![];
+    let mut in_variant = false;
+    for line in project.lines().map(str::trim) {
+        if line.starts_with('[') {
+            in_variant = line.starts_with("[ProjectVariant");
+            if in_variant {
+                variants.push((format!("Variant {}", variants.len() + 1), vec![]));
+            }
+            continue;
+        }
+        let Some((name, not_fitted)) = variants.last_mut().filter(|_| in_variant) else {
+            continue;
+        };
+        if let Some(description) = line.strip_prefix("Description=") {
+            if !description.is_empty() {
+                *name = description.to_string();
+            }
+        } else if let Some((_, variation)) = line
+            .strip_prefix("Variation")
+            .and_then(|l| l.split_once('='))
+        {
+            // e.g. Variation1=Designator=R5|UniqueId=\ABCDEFGH|Kind=1|AlternatePart=
+            let fields: HashMap<&str, &str> = variation
+                .split('|')
+                .filter_map(|f| f.split_once('='))
+                .collect();
+            // Kind 1 is "Not Fitted", the others swap in an alternate part
+            if let (Some(designator), Some(&"1")) = (fields.get("Designator"), fields.get("Kind")) {
+                not_fitted.push(designator.to_string());
+            }
+        }
+    }
+    variants
+}
+
+/// Records how many of each line the variants that leave some of its designators out fit. A
+/// variation of `R5` covers every channel of it, `R5_1`, `R5_2` and so on.
+fn apply_variants(bom: &mut [(BomLine, SheetCounts)], variants: &[(String, Vec<String>)]) {
+    for (line, _) in bom.iter_mut() {
+        for (variant, not_fitted) in variants {
+            let removed = line
+                .designators
+                .iter()
+                .filter(|d| {
+                    not_fitted.iter().any(|n| {
+                        d.eq_ignore_ascii_case(n)
+                            || d.strip_prefix(n.as_str())
+                                .is_some_and(|channel| channel.starts_with('_'))
+                    })
+                })
+                .count() as i64;
+            if removed > 0 {
+                line.variants.push(VariantCount {
+                    variant: variant.clone(),
+                    count: line.count - removed,
+                });
+            }
+        }
+    }
+}
+
+/// Schematic documents listed in a `.PrjPcb`, relative to the project file
+fn project_documents(project: &str) -> Vec<String> {
+    project
+        .lines()
+        .filter_map(|l| l.trim().strip_prefix("DocumentPath="))
+        .filter(|p| p.to_lowercase().ends_with(".schdoc"))
+        .map(|p| p.replace('\\', "/"))
+        .collect()
+}
+
+/// Sheet symbols refer to their sheet by file name alone
+fn sheet_key(path: &str) -> &str {
+    path.rsplit(['/', '\\']).next().unwrap_or(path)
+}
+
+/// The rooms a sheet symbol places its sheet in. Altium names the channels of a symbol like
+/// `Repeat(CH, 1, 4)` CH1 to CH4, and any other symbol's room after the symbol itself.
+fn sheet_rooms(sheet_name: &str) -> Vec<String> {
+    let name = sheet_name.trim();
+    let args = name
+        .get(..7)
+        .filter(|p| p.eq_ignore_ascii_case("repeat("))
+        .and_then(|_| name[7..].strip_suffix(')'));
+    let Some(args) = args else {
+        return vec![name.to_string()];
+    };
+    let args: Vec<&str> = args.split(',').map(str::trim).collect();
+    let bounds: Vec<Option<i64>> = args.iter().map(|a| a.parse().ok()).collect();
+    match bounds[..] {
+        [_, Some(first), Some(last)] => (first..=last)
+            .map(|channel| format!("{}{}", args[0], channel))
+            .collect(),
+        _ => vec![name.to_string()],
+    }
+}
+
+fn merge_altium_sheets(sheets: Vec<(String, AltiumSheet)>) -> Result<Vec<(BomLine, SheetCounts)>> {
+    let index = |file: &str| {
+        sheets
+            .iter()
+            .position(|(name, _)| name.eq_ignore_ascii_case(sheet_key(file)))
+    };
+    // Top sheets are the ones no sheet symbol places
+    let placed: Vec<usize> = sheets
+        .iter()
+        .flat_map(|(_, s)| s.children.iter().filter_map(|(file, _)| index(file)))
+        .collect();
+    let mut pending: Vec<(usize, String, usize)> = (0..sheets.len())
+        .filter(|i| !placed.contains(i))
+        .map(|i| (i, String::new(), 0))
+        .collect();
+    if pending.is_empty() && !sheets.is_empty() {
+        return Err(anyhow!("Every sheet of the project is placed by another"));
+    }
+    // Every room each sheet is placed in, named by the rooms leading down to it
+    let mut placements: Vec<Vec<String>> = vec![vec![]; sheets.len()];
+    while let Some((i, room, depth)) = pending.pop() {
+        if depth > MAX_SHEET_DEPTH {
+            return Err(anyhow!("Altium sheets nest too deep"));
+        }
+        for (file, rooms) in &sheets[i].1.children {
+            if let Some(child) = index(file) {
+                for child_room in rooms {
+                    let path = if room.is_empty() {
+                        child_room.clone()
+                    } else {
+                        format!("{}_{}", room, child_room)
+                    };
+                    pending.push((child, path, depth + 1));
+                }
+            }
+        }
+        placements[i].push(room);
+    }
+
+    let mut merged: HashMap<String, (BomLine, SheetCounts)> = HashMap::new();
+    for ((name, sheet), rooms) in sheets.iter().zip(placements) {
+        let times = rooms.len() as i64;
+        for (key, line) in &sheet.bom {
+            let (merged_line, counts) = merged
+                .entry(key.clone())
+                .or_insert_with(|| (BomLine::new(0, line.part.clone()), vec![]));
+            merged_line.count += line.count * times;
+            counts.push((name.clone(), line.count * times));
+            // Altium suffixes the designators of a sheet placed several times with the room of
+            // each placement, e.g. R1_CH1 and R1_CH2
+            if times == 1 {
+                merged_line
+                    .designators
+                    .extend(line.designators.iter().cloned());
+            } else {
+                for room in &rooms {
+                    merged_line
+                        .designators
+                        .extend(line.designators.iter().map(|d| format!("{}_{}", d, room)));
+                }
+            }
+        }
+    }
+    let mut out: Vec<(BomLine, SheetCounts)> = merged
+        .into_values()
+        .filter(|(line, _)| line.count > 0)
+        .collect();
+    for (line, sheets) in &mut out {
+        sort_designators(&mut line.designators);
+        sheets.sort();
+    }
+    out.sort_by(|(a, _), (b, _)| a.part.name.cmp(&b.part.name));
+    Ok(out)
+}
+
+/// The parts on one Altium sheet, and the sheets it places with the rooms of each
+#[derive(Debug, Default)]
+struct AltiumSheet {
+    bom: HashMap<String, BomLine>,
+    children: Vec<(String, Vec<String>)>,
+}
+
+fn read_altium_sheet<T>(rdr: BufReader<T>) -> Result<AltiumSheet>
 where
     T: std::io::Read + std::io::Seek,
 {
     let doc = SchDoc::open(rdr)?;
     let tree = RecordTree::from_records(doc.primitives.clone());
 
-    let mut bom: HashMap<String, (i64, Part)> = HashMap::new();
+    let mut sheet = AltiumSheet::default();
     for (id, record) in tree.iter() {
         if let SchRecord::Component(c) = record {
             let mut comment = None;
+            let mut designator = None;
             let mut part_number = None;
             let mut manufacturer_part_number = None;
+            let mut manufacturer = None;
+            let mut supplier = None;
+            let mut supplier_part_number = None;
+            let mut dnp = false;
             for (_id, child) in tree.children(id) {
                 match child {
+                    SchRecord::Parameter(p) if marks_dnp(&p.name, &p.label.text) => {
+                        dnp = true;
+                    }
                     SchRecord::Parameter(p) => {
                         // The part name is stored in the comment for some reason
                         match p.name.as_str() {
@@ -132,54 +707,111 @@ where
                             "Part Number" => {
                                 part_number = Some(p.label.text.clone());
                             }
-                            "Manufacturer Part Number" => {
+                            "Manufacturer Part Number" | "Manufacturer Part Number 1" => {
                                 manufacturer_part_number = Some(p.label.text.clone());
                             }
+                            "Manufacturer" | "Manufacturer 1" => {
+                                manufacturer = Some(p.label.text.clone());
+                            }
+                            "Supplier 1" => {
+                                supplier = Some(p.label.text.clone());
+                            }
+                            "Supplier Part Number 1" => {
+                                supplier_part_number = Some(p.label.text.clone());
+                            }
                             _ => {}
                         }
                     }
+                    SchRecord::Designator(d) => {
+                        designator = Some(d.label.text.clone());
+                    }
                     _ => {}
                 }
             }
+            if dnp {
+                continue;
+            }
+            let mpn = manufacturer_part_number.clone().unwrap_or_default();
+            let skus = match (supplier, supplier_part_number) {
+                (Some(supplier), Some(sku)) if !supplier.is_empty() && !sku.is_empty() => {
+                    vec![SupplierSku { supplier, sku }]
+                }
+                _ => vec![],
+            };
+            // The comment usually holds the value, e.g. "10k", when the name is a part number
+            let parameters = Parameters::from_text(&format!(
+                "{} {}",
+                comment.as_deref().unwrap_or_default(),
+                c.component_description
+            ));
             let name_prio = vec![manufacturer_part_number, part_number, comment];
             let name = name_prio
                 .into_iter()
                 .find(|p| p.is_some())
                 .unwrap()
                 .unwrap();
-            if !bom.contains_key(&name) {
-                bom.insert(
+            if !sheet.bom.contains_key(&name) {
+                sheet.bom.insert(
                     name.clone(),
-                    (
-                        1,
+                    BomLine::new(
+                        0,
                         Part {
                             id: 0,
                             name: name.clone(),
                             description: c.component_description.clone(),
+                            manufacturer: manufacturer.unwrap_or_default(),
+                            mpn,
+                            skus,
+                            parameters,
+                            ..Default::default()
                         },
                     ),
                 );
-            } else {
-                bom.get_mut(&name).unwrap().0 += 1;
+            }
+            let line = sheet.bom.get_mut(&name).unwrap();
+            line.count += 1;
+            // Components not annotated yet show "*" in place of a designator
+            line.designators
+                .extend(designator.filter(|d| !d.is_empty() && d != "*"));
+        }
+        if let SchRecord::SheetSymbol(_) = record {
+            let mut sheet_name = String::new();
+            let mut file = None;
+            for (_id, child) in tree.children(id) {
+                match child {
+                    SchRecord::SheetName(n) => sheet_name = n.label.text.clone(),
+                    SchRecord::SheetFileName(f) => file = Some(f.label.text.clone()),
+                    _ => {}
+                }
+            }
+            if let Some(file) = file {
+                sheet.children.push((file, sheet_rooms(&sheet_name)));
             }
         }
     }
 
-    let mut out: Vec<(i64, Part)> = bom.into_values().collect();
-    out.sort_by(|(_, a), (_, b)| a.name.cmp(&b.name));
-
-    Ok(out)
+    Ok(sheet)
 }
 
 #[cfg(test)]
 mod tests {
-    use std::io::{self, BufReader};
+    use std::{
+        io::{self, BufReader},
+        path::Path,
+    };
 
     use csv::ReaderBuilder;
 
-    use crate::import::altium_schematic_reader_to_bom;
+    use crate::{
+        import::altium_schematic_reader_to_bom,
+        models::{BomLine, Part, SupplierSku, VariantCount},
+    };
 
-    use super::reader_to_bom;
+    use super::{
+        AltiumSheet, apply_variants, decode_text, header_row, link_existing, merge_altium_sheets,
+        project_documents, project_variants, reader_to_bom, sheet_rooms, sniff_delimiter,
+        workbook_sheets, workbook_to_bom, workbook_to_headers,
+    };
 
     #[test]
     fn can_parse_altium_bom() {
@@ -192,6 +824,291 @@ mod tests {
         assert_eq!(bom.len(), 13, "BOM should have 10 entires");
     }
 
+    #[test]
+    fn keeps_part_numbers_from_csv() {
+        let bytes = include_bytes!("../assets/Magnet Harvesting 1.2.csv");
+        let rdr = ReaderBuilder::new()
+            .has_headers(true)
+            .from_reader(&bytes[..]);
+
+        let bom = reader_to_bom(rdr, "Name", "Description", "Quantity").unwrap();
+        let line = bom
+            .iter()
+            .find(|l| l.part.name == "TMK107B7105KA-T")
+            .unwrap();
+        assert_eq!(line.designators, ["C1"]);
+        let cap = &line.part;
+        assert_eq!(cap.manufacturer, "Taiyo Yuden");
+        assert_eq!(cap.mpn, "TMK107B7105KA-T");
+        assert_eq!(
+            cap.skus,
+            vec![SupplierSku {
+                supplier: String::from("Digikey"),
+                sku: String::from("587-2984-1-ND"),
+            }]
+        );
+    }
+
+    #[test]
+    fn reads_csv_in_any_encoding_and_delimiter() {
+        let text = "Name;Description;Qty.\n10k;\"Resistor; 0603\";4\n100n;Capacitor 25V;2\n";
+        let mut utf16 = vec![0xFF, 0xFE];
+        utf16.extend(text.encode_utf16().flat_map(u16::to_le_bytes));
+        assert_eq!(decode_text(&utf16), text);
+        let bare: Vec<u8> = text.encode_utf16().flat_map(u16::to_be_bytes).collect();
+        assert_eq!(decode_text(&bare), text);
+
+        assert_eq!(
+            decode_text(b"Name,Description\n10k,Thermistor -40\xB0C\n")
+                .lines()
+                .last(),
+            Some("10k,Thermistor -40°C")
+        );
+        assert_eq!(decode_text(b"\xD0\xE5\xE7\xE8\xF1\xF2\xEE\xF0"), "Резистор");
+
+        assert_eq!(sniff_delimiter(text), b';');
+        assert_eq!(sniff_delimiter("Name\tQty\n10k, 1%\t4\n"), b'\t');
+        assert_eq!(sniff_delimiter("Name,Qty\n10k,4\n"), b',');
+
+        let rdr = ReaderBuilder::new()
+            .has_headers(true)
+            .delimiter(b';')
+            .from_reader(text.as_bytes());
+        // Headers only have to match up to case and punctuation
+        let bom = reader_to_bom(rdr, "name", "DESCRIPTION", "Qty").unwrap();
+        let line = bom.iter().find(|l| l.part.name == "10k").unwrap();
+        assert_eq!(line.part.description, "Resistor; 0603");
+        assert_eq!(line.count, 4);
+    }
+
+    #[test]
+    fn sniffs_commas_in_empty_csv() {
+        assert_eq!(sniff_delimiter(""), b',');
+        assert_eq!(sniff_delimiter("\n  \r\n\t\n"), b',');
+    }
+
+    #[test]
+    fn reads_bom_from_workbook_below_title_block() {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("assets/Magnet Harvesting 1.2.xlsx");
+        assert_eq!(workbook_sheets(&path).unwrap(), ["Notes", "BOM"]);
+        let headers = workbook_to_headers(&path, Some("BOM")).unwrap();
+        assert_eq!(headers[..3], ["Line #", "Name", "Description"]);
+
+        let bytes = include_bytes!("../assets/Magnet Harvesting 1.2.csv");
+        let rdr = ReaderBuilder::new()
+            .has_headers(true)
+            .from_reader(&bytes[..]);
+        let csv_bom = reader_to_bom(rdr, "Name", "Description", "Quantity").unwrap();
+        let xlsx_bom =
+            workbook_to_bom(&path, Some("BOM"), "Name", "Description", "Quantity").unwrap();
+        pretty_assertions::assert_eq!(csv_bom, xlsx_bom);
+
+        let rows = |rows: &[&[&str]]| -> Vec<Vec<String>> {
+            rows.iter()
+                .map(|r| r.iter().map(|c| c.to_string()).collect())
+                .collect()
+        };
+        // No well known headers, so the first full row it is
+        assert_eq!(
+            header_row(&rows(&[
+                &["Order 1234", "", ""],
+                &["Part", "Text", "Amount"],
+                &["10k", "Resistor", "4"],
+            ])),
+            1
+        );
+    }
+
+    #[test]
+    fn links_by_part_number_before_name() {
+        let existing = vec![
+            Part {
+                id: 1,
+                name: String::from("1uF"),
+                mpn: String::from("GRM188R61E105KA12D"),
+                ..Default::default()
+            },
+            Part {
+                id: 2,
+                name: String::from("Cap 1u"),
+                mpn: String::from("TMK107B7105KA-T"),
+                ..Default::default()
+            },
+        ];
+        let mut candidates = vec![BomLine::new(
+            1,
+            Part {
+                name: String::from("1uF"),
+                mpn: String::from("tmk107b7105ka-t"),
+                ..Default::default()
+            },
+        )];
+        link_existing(&mut candidates, &existing);
+        assert_eq!(candidates[0].part.id, 2);
+    }
+
+    #[test]
+    fn checks_quantity_against_designators() {
+        let read = |csv: &str| {
+            let rdr = ReaderBuilder::new()
+                .has_headers(true)
+                .from_reader(csv.as_bytes());
+            reader_to_bom(rdr, "Name", "Description", "Quantity")
+        };
+        let bom =
+            read("Name,Description,Designator,Quantity\n10k,Resistor,\"R10, R2,R1\",3\n").unwrap();
+        assert_eq!(bom[0].designators, ["R1", "R2", "R10"]);
+
+        let err =
+            read("Name,Description,Designator,Quantity\n10k,Resistor,\"R1, R2\",3\n").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "10k has a quantity of 3 but 2 designators (R1, R2)"
+        );
+    }
+
+    #[test]
+    fn leaves_out_parts_not_fitted() {
+        let csv = "Name,Description,Designator,Quantity,DNP,Fitted\n\
+                   10k,Resistor,R1,1,,Fitted\n\
+                   0R,Jumper,R2,1,DNP,Fitted\n\
+                   1k,Resistor,R3,1,,Not Fitted\n";
+        let rdr = ReaderBuilder::new()
+            .has_headers(true)
+            .from_reader(csv.as_bytes());
+        let bom = reader_to_bom(rdr, "Name", "Description", "Quantity").unwrap();
+        let names: Vec<&str> = bom.iter().map(|l| l.part.name.as_str()).collect();
+        assert_eq!(names, ["10k"]);
+
+        let project = "[ProjectVariant1]\r\nDescription=Lite\r\nVariationCount=2\r\n\
+                       Variation1=Designator=R1|UniqueId=\\ABCD|Kind=1|AlternatePart=\r\n\
+                       Variation2=Designator=R10|UniqueId=\\EFGH|Kind=0|AlternatePart=1k\r\n\
+                       [ProjectVariant2]\r\nDescription=\r\n\
+                       Variation1=Designator=R10|UniqueId=\\EFGH|Kind=1|AlternatePart=\r\n\
+                       [Document1]\r\nDocumentPath=Top.SchDoc\r\n";
+        let variants = project_variants(project);
+        assert_eq!(
+            variants,
+            vec![
+                (String::from("Lite"), vec![String::from("R1")]),
+                (String::from("Variant 2"), vec![String::from("R10")]),
+            ]
+        );
+
+        let line = BomLine {
+            designators: ["R1", "R10_1", "R10_2", "R100"].map(String::from).to_vec(),
+            ..BomLine::new(4, Part::default())
+        };
+        let mut bom = vec![(line, vec![])];
+        apply_variants(&mut bom, &variants);
+        assert_eq!(
+            bom[0].0.variants,
+            vec![
+                VariantCount {
+                    variant: String::from("Lite"),
+                    count: 3
+                },
+                VariantCount {
+                    variant: String::from("Variant 2"),
+                    count: 2
+                },
+            ]
+        );
+    }
+
+    /// A merged BOM line's count and part name with how many it takes from each sheet
+    type SummedLine<'a> = (i64, &'a str, Vec<(String, i64)>);
+
+    #[test]
+    fn counts_repeated_altium_sheets() {
+        let project = "[Document1]\r\nDocumentPath=Top.SchDoc\r\n\r\n[Document2]\r\n\
+                       DocumentPath=Sheets\\Channel.SchDoc\r\n\r\n[Document3]\r\n\
+                       DocumentPath=Board.PcbDoc\r\n";
+        assert_eq!(
+            project_documents(project),
+            vec![
+                String::from("Top.SchDoc"),
+                String::from("Sheets/Channel.SchDoc")
+            ]
+        );
+        assert_eq!(
+            sheet_rooms("Repeat(CH, 1, 4)"),
+            ["CH1", "CH2", "CH3", "CH4"]
+        );
+        assert_eq!(sheet_rooms("Power"), ["Power"]);
+
+        let sheet = |parts: &[(&str, &[&str])], children: &[(&str, &str)]| AltiumSheet {
+            bom: parts
+                .iter()
+                .map(|(name, designators)| {
+                    let part = Part {
+                        name: name.to_string(),
+                        ..Default::default()
+                    };
+                    let line = BomLine {
+                        designators: designators.iter().map(|d| d.to_string()).collect(),
+                        ..BomLine::new(designators.len() as i64, part)
+                    };
+                    (name.to_string(), line)
+                })
+                .collect(),
+            children: children
+                .iter()
+                .map(|(f, name)| (f.to_string(), sheet_rooms(name)))
+                .collect(),
+        };
+        let bom = merge_altium_sheets(vec![
+            (
+                String::from("Top.SchDoc"),
+                sheet(
+                    &[("10k", &["R1", "R2"])],
+                    &[
+                        ("Channel.SchDoc", "Repeat(CH, 1, 4)"),
+                        ("Power.SchDoc", "Power"),
+                    ],
+                ),
+            ),
+            (
+                String::from("Channel.SchDoc"),
+                sheet(&[("10k", &["R10"]), ("OPA2134", &["U1"])], &[]),
+            ),
+            (
+                String::from("Power.SchDoc"),
+                sheet(&[("OPA2134", &["U20"])], &[]),
+            ),
+        ])
+        .unwrap();
+        let lines: Vec<SummedLine> = bom
+            .iter()
+            .map(|(line, sheets)| (line.count, line.part.name.as_str(), sheets.clone()))
+            .collect();
+        assert_eq!(
+            lines,
+            vec![
+                (
+                    6,
+                    "10k",
+                    vec![
+                        (String::from("Channel.SchDoc"), 4),
+                        (String::from("Top.SchDoc"), 2)
+                    ]
+                ),
+                (
+                    5,
+                    "OPA2134",
+                    vec![
+                        (String::from("Channel.SchDoc"), 4),
+                        (String::from("Power.SchDoc"), 1)
+                    ]
+                ),
+            ]
+        );
+        assert_eq!(
+            bom[0].0.designators,
+            ["R1", "R2", "R10_CH1", "R10_CH2", "R10_CH3", "R10_CH4"]
+        );
+    }
+
     #[test]
     fn can_parse_altium_schdoc() {
         let bytes = include_bytes!("../assets/MagnetV1_2.SchDoc");
@@ -0,0 +1,435 @@
+use std::{
+    collections::{HashMap, hash_map::Entry},
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use tracing::{error, warn};
+
+use crate::{
+    backend::PartsBackend,
+    error::NetworkError,
+    models::{BinLocation, PartWithCountAndStock},
+};
+
+/// A stock change made against a known starting point, so replaying it later can tell whether
+/// someone else touched the same part in the meantime.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StockChange {
+    pub profile_id: i64,
+    pub part_id: i64,
+    /// Only kept for showing the change to the user
+    pub name: String,
+    /// The stock the change was based on
+    pub expected: i64,
+    pub delta: i64,
+    pub column: i64,
+    pub row: i64,
+    pub z: i64,
+    #[serde(default)]
+    pub reason: String,
+}
+
+impl StockChange {
+    pub fn stock(&self) -> i64 {
+        self.expected + self.delta
+    }
+
+    pub fn bin(&self) -> BinLocation {
+        BinLocation {
+            column: self.column,
+            row: self.row,
+            z: self.z,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct JournalEntry {
+    pub id: u64,
+    /// Seconds since the unix epoch
+    pub queued_at: u64,
+    pub change: StockChange,
+    pub last_error: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum ConflictReason {
+    /// The server's stock no longer matches what the change was based on
+    StockChanged { found: i64 },
+    /// The server refused the change outright
+    Rejected(String),
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Conflict {
+    pub entry: JournalEntry,
+    pub reason: ConflictReason,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Resolution {
+    /// Apply the change again, on top of whatever the server has now
+    KeepMine,
+    /// Drop the change
+    KeepServer,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Submitted {
+    Applied,
+    Queued,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ReplaySummary {
+    pub applied: usize,
+    pub conflicts: usize,
+    pub remaining: usize,
+}
+
+/// Stock changes that couldn't reach the server yet, plus the ones that could not be replayed
+/// cleanly and are waiting for the user to reconcile them. Persisted next to `.userdata.json`.
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Journal {
+    #[serde(skip)]
+    path: Option<PathBuf>,
+    next_id: u64,
+    pending: Vec<JournalEntry>,
+    conflicts: Vec<Conflict>,
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+impl Journal {
+    pub fn open(path: &Path) -> Self {
+        let mut journal: Journal = match std::fs::read_to_string(path) {
+            Ok(contents) => serde_json::from_str(&contents).unwrap_or_else(|e| {
+                error!("Couldnt parse journal {}: {}", path.display(), e);
+                Journal::default()
+            }),
+            Err(_) => Journal::default(),
+        };
+        journal.path = Some(path.to_path_buf());
+        journal
+    }
+
+    /// A journal that is never written to disk
+    pub fn in_memory() -> Self {
+        Self::default()
+    }
+
+    pub fn pending(&self) -> &[JournalEntry] {
+        &self.pending
+    }
+
+    pub fn conflicts(&self) -> &[Conflict] {
+        &self.conflicts
+    }
+
+    fn save(&self) -> Result<()> {
+        if let Some(path) = &self.path {
+            std::fs::write(path, serde_json::to_string(&self)?)?;
+        }
+        Ok(())
+    }
+
+    fn persist(&self) {
+        if let Err(e) = self.save() {
+            error!("Couldnt save journal: {}", e);
+        }
+    }
+
+    /// The stock a part will end up at once every queued change to it has been replayed
+    fn queued_stock(&self, profile_id: i64, part_id: i64) -> Option<i64> {
+        self.pending
+            .iter()
+            .rev()
+            .find(|e| e.change.profile_id == profile_id && e.change.part_id == part_id)
+            .map(|e| e.change.stock())
+    }
+
+    fn queue(&mut self, change: StockChange, last_error: Option<String>) {
+        self.next_id += 1;
+        self.pending.push(JournalEntry {
+            id: self.next_id,
+            queued_at: now(),
+            change,
+            last_error,
+        });
+        self.persist();
+    }
+
+    /// Applies a stock change right away, or queues it if the server can't be reached. Errors
+    /// that retrying won't fix are returned as is.
+    pub async fn submit(
+        &mut self,
+        backend: &mut dyn PartsBackend,
+        mut change: StockChange,
+    ) -> Result<Submitted, NetworkError> {
+        // Changes to the same part have to reach the server in order, and whatever the caller
+        // based this one on can't include the changes that are still queued.
+        if let Some(stock) = self.queued_stock(change.profile_id, change.part_id) {
+            change.expected = stock;
+            self.queue(change, None);
+            return Ok(Submitted::Queued);
+        }
+        match backend
+            .adjust_stock(
+                change.profile_id,
+                change.part_id,
+                change.bin(),
+                change.delta,
+                change.reason.clone(),
+            )
+            .await
+        {
+            Ok(_) => Ok(Submitted::Applied),
+            Err(e) if e.is_retryable() => {
+                warn!("Queueing stock change for {}: {}", change.name, e);
+                self.queue(change, Some(e.to_string()));
+                Ok(Submitted::Queued)
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Applies `diff` times the count of every part as a single all-or-nothing batch, or queues
+    /// every line if the server can't be reached. Queued lines are replayed one by one like any
+    /// other change.
+    pub async fn submit_batch(
+        &mut self,
+        backend: &mut dyn PartsBackend,
+        profile_id: i64,
+        parts: &[PartWithCountAndStock],
+        diff: i64,
+        reason: String,
+    ) -> Result<Submitted, NetworkError> {
+        let mut last_error = None;
+        if !parts
+            .iter()
+            .any(|p| self.queued_stock(profile_id, p.id).is_some())
+        {
+            match backend
+                .stock_parts(profile_id, parts, diff, reason.clone())
+                .await
+            {
+                Ok(()) => return Ok(Submitted::Applied),
+                Err(e) if e.is_retryable() => {
+                    warn!("Queueing {} stock changes: {}", parts.len(), e);
+                    last_error = Some(e.to_string());
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        for p in parts {
+            let change = StockChange {
+                profile_id,
+                part_id: p.id,
+                name: p.name.clone(),
+                expected: self.queued_stock(profile_id, p.id).unwrap_or(p.stock),
+                delta: diff * p.count,
+                column: p.column,
+                row: p.row,
+                z: p.z,
+                reason: reason.clone(),
+            };
+            self.queue(change, last_error.clone());
+        }
+        Ok(Submitted::Queued)
+    }
+
+    /// Replays queued changes in order. Stops at the first error that means the server is still
+    /// out of reach, leaving that change and everything after it queued.
+    pub async fn replay(
+        &mut self,
+        backend: &mut dyn PartsBackend,
+    ) -> Result<ReplaySummary, NetworkError> {
+        let mut summary = ReplaySummary::default();
+        let mut server_stock: HashMap<i64, HashMap<i64, i64>> = HashMap::new();
+        let mut queue = std::mem::take(&mut self.pending).into_iter();
+
+        while let Some(mut entry) = queue.next() {
+            let change = &entry.change;
+            if let Entry::Vacant(vacant) = server_stock.entry(change.profile_id) {
+                match backend.list_stock(change.profile_id).await {
+                    Ok(rows) => {
+                        vacant.insert(rows.into_iter().map(|r| (r.part_id, r.stock)).collect());
+                    }
+                    Err(e) => {
+                        entry.last_error = Some(e.to_string());
+                        self.pending = std::iter::once(entry).chain(queue).collect();
+                        self.persist();
+                        return Err(e);
+                    }
+                }
+            }
+            let stock = server_stock.get_mut(&change.profile_id).unwrap();
+            // Parts that were never stocked in the profile are at zero
+            let found = stock.get(&change.part_id).copied().unwrap_or(0);
+            if found != change.expected {
+                self.conflicts.push(Conflict {
+                    entry,
+                    reason: ConflictReason::StockChanged { found },
+                });
+                summary.conflicts += 1;
+                continue;
+            }
+            match backend
+                .adjust_stock(
+                    change.profile_id,
+                    change.part_id,
+                    change.bin(),
+                    change.delta,
+                    change.reason.clone(),
+                )
+                .await
+            {
+                Ok(new_stock) => {
+                    stock.insert(change.part_id, new_stock);
+                    summary.applied += 1;
+                }
+                Err(e) if e.is_retryable() || matches!(e, NetworkError::Unauthorized) => {
+                    entry.last_error = Some(e.to_string());
+                    self.pending = std::iter::once(entry).chain(queue).collect();
+                    self.persist();
+                    return Err(e);
+                }
+                Err(e) => {
+                    self.conflicts.push(Conflict {
+                        entry,
+                        reason: ConflictReason::Rejected(e.to_string()),
+                    });
+                    summary.conflicts += 1;
+                }
+            }
+        }
+        summary.remaining = self.pending.len();
+        self.persist();
+        Ok(summary)
+    }
+
+    /// Settles a conflict. Keeping the local change requeues it on top of the stock the server
+    /// reported, so the next replay applies the same difference the user originally made.
+    pub fn resolve(&mut self, entry_id: u64, resolution: Resolution) {
+        let Some(index) = self.conflicts.iter().position(|c| c.entry.id == entry_id) else {
+            warn!("No conflict with id {}", entry_id);
+            return;
+        };
+        let conflict = self.conflicts.remove(index);
+        if resolution == Resolution::KeepMine {
+            let mut change = conflict.entry.change;
+            if let ConflictReason::StockChanged { found } = conflict.reason {
+                change.expected = found;
+            }
+            if let Some(stock) = self.queued_stock(change.profile_id, change.part_id) {
+                change.expected = stock;
+            }
+            self.queue(change, None);
+        } else {
+            self.persist();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{backend::PartsBackend, memory::MemoryBackend, models::Part, test_util::logged_in};
+
+    use super::{ConflictReason, Journal, Resolution, StockChange, Submitted};
+
+    async fn stocked_backend() -> (MemoryBackend, StockChange) {
+        let mut backend = logged_in(MemoryBackend::new()).await;
+        backend
+            .new_part(Part {
+                id: 0,
+                name: String::from("100nF"),
+                description: String::from("Capacitor 0402"),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        let profile_id = backend.user_data().profile.as_ref().unwrap().id;
+        let part_id = backend.get_parts(None, None).await.unwrap()[0].id;
+        backend
+            .stock_part(profile_id, part_id, 50, 1, 1, 0)
+            .await
+            .unwrap();
+        let change = StockChange {
+            profile_id,
+            part_id,
+            name: String::from("100nF"),
+            expected: 50,
+            delta: -3,
+            column: 1,
+            row: 1,
+            z: 0,
+            reason: String::from("manual deplete"),
+        };
+        (backend, change)
+    }
+
+    async fn stock_of(backend: &mut MemoryBackend, change: &StockChange) -> i64 {
+        backend.list_stock(change.profile_id).await.unwrap()[0].stock
+    }
+
+    #[tokio::test]
+    async fn queues_while_unreachable_and_replays_in_order() {
+        let (mut backend, change) = stocked_backend().await;
+        let mut journal = Journal::in_memory();
+
+        backend.unreachable = true;
+        let first = journal.submit(&mut backend, change.clone()).await.unwrap();
+        // The second change is based on the same stale stock the UI still shows
+        let second = journal.submit(&mut backend, change.clone()).await.unwrap();
+        assert_eq!((first, second), (Submitted::Queued, Submitted::Queued));
+        assert!(journal.replay(&mut backend).await.is_err());
+        assert_eq!(journal.pending().len(), 2);
+
+        backend.unreachable = false;
+        let summary = journal.replay(&mut backend).await.unwrap();
+        assert_eq!((summary.applied, summary.conflicts), (2, 0));
+        assert!(journal.pending().is_empty());
+        assert_eq!(stock_of(&mut backend, &change).await, 44);
+    }
+
+    #[tokio::test]
+    async fn reports_and_rebases_conflicts() {
+        let (mut backend, change) = stocked_backend().await;
+        let mut journal = Journal::in_memory();
+
+        backend.unreachable = true;
+        journal.submit(&mut backend, change.clone()).await.unwrap();
+        backend.unreachable = false;
+        // Someone else took some capacitors while we were offline
+        backend
+            .stock_part(change.profile_id, change.part_id, 40, 1, 1, 0)
+            .await
+            .unwrap();
+
+        let summary = journal.replay(&mut backend).await.unwrap();
+        assert_eq!(summary.conflicts, 1);
+        assert!(matches!(
+            journal.conflicts()[0].reason,
+            ConflictReason::StockChanged { found: 40 }
+        ));
+
+        let id = journal.conflicts()[0].entry.id;
+        journal.resolve(id, Resolution::KeepMine);
+        journal.replay(&mut backend).await.unwrap();
+        assert!(journal.conflicts().is_empty());
+        assert_eq!(stock_of(&mut backend, &change).await, 37);
+    }
+}
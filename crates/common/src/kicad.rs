@@ -0,0 +1,453 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    path::{Component, Path, PathBuf},
+};
+
+use anyhow::{Result, anyhow};
+
+use crate::{
+    import::{
+        DESIGNATOR_HEADERS, MANUFACTURER_HEADERS, MPN_HEADERS, SKU_HEADERS, SUPPLIER_HEADERS,
+        marks_dnp,
+    },
+    models::{BomLine, Part, SupplierSku, sort_designators},
+    parameters::Parameters,
+};
+
+/// Sheets nested deeper than this are assumed to include themselves
+pub(crate) const MAX_SHEET_DEPTH: usize = 32;
+const QUANTITY_HEADERS: &[&str] = &["Qty", "Quantity"];
+
+/// A node of the S-expression files KiCad saves its documents as
+#[derive(Debug, Clone, PartialEq)]
+enum Sexpr {
+    Atom(String),
+    List(Vec<Sexpr>),
+}
+
+impl Sexpr {
+    fn parse(input: &str) -> Result<Self> {
+        let mut stack: Vec<Vec<Sexpr>> = vec![vec![]];
+        let mut chars = input.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '(' => stack.push(vec![]),
+                ')' => {
+                    let list = stack.pop().filter(|_| !stack.is_empty());
+                    let list = list.ok_or(anyhow!("Unbalanced ) in KiCad file"))?;
+                    stack.last_mut().unwrap().push(Sexpr::List(list));
+                }
+                '"' => {
+                    let mut atom = String::new();
+                    loop {
+                        match chars.next() {
+                            Some('"') => break,
+                            Some('\\') => match chars.next() {
+                                Some('n') => atom.push('\n'),
+                                Some(c) => atom.push(c),
+                                None => break,
+                            },
+                            Some(c) => atom.push(c),
+                            None => return Err(anyhow!("Unterminated string in KiCad file")),
+                        }
+                    }
+                    stack.last_mut().unwrap().push(Sexpr::Atom(atom));
+                }
+                c if c.is_whitespace() => {}
+                c => {
+                    let mut atom = String::from(c);
+                    while let Some(&c) = chars.peek() {
+                        if c.is_whitespace() || c == '(' || c == ')' {
+                            break;
+                        }
+                        atom.push(c);
+                        chars.next();
+                    }
+                    stack.last_mut().unwrap().push(Sexpr::Atom(atom));
+                }
+            }
+        }
+        match (stack.pop(), stack.is_empty()) {
+            (Some(mut top), true) if top.len() == 1 => Ok(top.remove(0)),
+            _ => Err(anyhow!("Malformed KiCad file")),
+        }
+    }
+
+    fn items(&self) -> &[Sexpr] {
+        match self {
+            Sexpr::List(items) => items,
+            Sexpr::Atom(_) => &[],
+        }
+    }
+
+    fn atom(&self, idx: usize) -> Option<&str> {
+        match self.items().get(idx)? {
+            Sexpr::Atom(s) => Some(s),
+            Sexpr::List(_) => None,
+        }
+    }
+
+    fn head(&self) -> Option<&str> {
+        self.atom(0)
+    }
+
+    fn children<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a Sexpr> {
+        self.items().iter().filter(move |c| c.head() == Some(name))
+    }
+
+    fn child<'a>(&'a self, name: &'a str) -> Option<&'a Sexpr> {
+        self.children(name).next()
+    }
+
+    /// `(property "Key" "Value" ...)` children as a map
+    fn properties(&self) -> HashMap<&str, &str> {
+        self.children("property")
+            .filter_map(|p| Some((p.atom(1)?, p.atom(2)?)))
+            .collect()
+    }
+}
+
+/// Picks the name, description and count columns of a CSV exported by KiCad's BOM tool, which
+/// lists one line per value with its references, e.g. `Refs,Value,Footprint,Qty,DNP`.
+pub fn kicad_csv_columns(headers: &[String]) -> Option<(String, String, String)> {
+    let find = |candidates: &[&str]| {
+        headers
+            .iter()
+            .find(|h| candidates.contains(&h.as_str()))
+            .cloned()
+    };
+    find(DESIGNATOR_HEADERS)?;
+    let value = find(&["Value"])?;
+    let count = find(QUANTITY_HEADERS)?;
+    let description = find(&["Description"]).or(find(&["Footprint"]))?;
+    Some((value, description, count))
+}
+
+pub fn kicad_schematic_file_to_bom(path: &Path) -> Result<Vec<BomLine>> {
+    let dir = path.parent().map(Path::to_path_buf).unwrap_or_default();
+    let root = fs::read_to_string(path)?;
+    kicad_schematic_to_bom(&root, |sheet| Ok(fs::read_to_string(dir.join(sheet))?))
+}
+
+/// Groups the symbols of a schematic and its sub-sheets, which `load_sheet` reads by their path
+/// relative to the root schematic's directory. Sheets refer to their sub-sheets relative to
+/// their own directory.
+pub fn kicad_schematic_to_bom(
+    root: &str,
+    load_sheet: impl Fn(&Path) -> Result<String>,
+) -> Result<Vec<BomLine>> {
+    let root = Sexpr::parse(root)?;
+    let mut collector = Collector {
+        symbol_instances: symbol_instances(&root),
+        ..Default::default()
+    };
+    collector.collect(&root, &load_sheet, Path::new(""), 0)?;
+
+    let mut out: Vec<BomLine> = collector.bom.into_values().collect();
+    for line in &mut out {
+        sort_designators(&mut line.designators);
+    }
+    out.sort_by(|a, b| a.part.name.cmp(&b.part.name));
+    Ok(out)
+}
+
+/// The references KiCad 6 lists in the root schematic's `symbol_instances` table, by symbol
+/// UUID. Later versions list them under each symbol instead.
+fn symbol_instances(root: &Sexpr) -> HashMap<String, Vec<String>> {
+    let mut out: HashMap<String, Vec<String>> = HashMap::new();
+    for path in root
+        .child("symbol_instances")
+        .into_iter()
+        .flat_map(|i| i.children("path"))
+    {
+        let (Some(instance), Some(reference)) = (
+            path.atom(1),
+            path.child("reference").and_then(|r| r.atom(1)),
+        ) else {
+            continue;
+        };
+        // The path runs through the sheet instances and ends at the symbol
+        let symbol = instance.rsplit('/').next().unwrap_or(instance);
+        out.entry(symbol.to_string())
+            .or_default()
+            .push(reference.to_string());
+    }
+    out
+}
+
+/// `path` with its `.` and `..` components resolved, so a sheet reached from different
+/// directories is still read once
+fn normalize(path: &Path) -> PathBuf {
+    let mut out = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir
+                if matches!(out.components().next_back(), Some(Component::Normal(_))) =>
+            {
+                out.pop();
+            }
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+#[derive(Default)]
+struct Collector {
+    /// Lines by MPN or value, and footprint
+    bom: HashMap<(String, String), BomLine>,
+    /// References already counted, so the units of a multi-unit symbol count once
+    references: HashSet<String>,
+    /// Sheet files already read, by their normalized path. Every instance of a reused sheet
+    /// lists its own references, so each file only has to be read once.
+    sheets: HashSet<PathBuf>,
+    /// See [`symbol_instances`]
+    symbol_instances: HashMap<String, Vec<String>>,
+}
+
+impl Collector {
+    fn collect(
+        &mut self,
+        doc: &Sexpr,
+        load_sheet: &impl Fn(&Path) -> Result<String>,
+        dir: &Path,
+        depth: usize,
+    ) -> Result<()> {
+        if doc.head() != Some("kicad_sch") {
+            return Err(anyhow!("Not a KiCad schematic"));
+        }
+        if depth > MAX_SHEET_DEPTH {
+            return Err(anyhow!("KiCad sheets nest too deep"));
+        }
+        for symbol in doc.children("symbol") {
+            self.add_symbol(symbol);
+        }
+        for sheet in doc.children("sheet") {
+            let properties = sheet.properties();
+            let Some(file) = properties.get("Sheetfile").or(properties.get("Sheet file")) else {
+                continue;
+            };
+            let path = normalize(&dir.join(file));
+            if self.sheets.insert(path.clone()) {
+                let contents = load_sheet(&path)?;
+                let sheet_dir = path.parent().unwrap_or(Path::new(""));
+                self.collect(&Sexpr::parse(&contents)?, load_sheet, sheet_dir, depth + 1)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn add_symbol(&mut self, symbol: &Sexpr) {
+        if symbol.child("in_bom").and_then(|s| s.atom(1)) == Some("no")
+            || symbol.child("dnp").and_then(|s| s.atom(1)) == Some("yes")
+        {
+            return;
+        }
+        let properties = symbol.properties();
+        if properties
+            .iter()
+            .any(|(name, value)| marks_dnp(name, value))
+        {
+            return;
+        }
+        let field = |candidates: &[&str]| {
+            candidates
+                .iter()
+                .find_map(|c| properties.get(c))
+                .map(|v| v.trim())
+                .filter(|v| !v.is_empty() && *v != "~")
+                .unwrap_or_default()
+                .to_string()
+        };
+        let reference = field(&["Reference"]);
+        // Power symbols and flags are symbols too
+        if reference.is_empty() || reference.starts_with('#') {
+            return;
+        }
+
+        // Each instance of a sheet has its own annotation, listed under the symbol
+        let mut references: Vec<String> = symbol
+            .child("instances")
+            .into_iter()
+            .flat_map(|i| i.children("project"))
+            .flat_map(|p| p.children("path"))
+            .filter_map(|p| p.child("reference")?.atom(1).map(String::from))
+            .collect();
+        if references.is_empty() {
+            references = symbol
+                .child("uuid")
+                .and_then(|u| u.atom(1))
+                .and_then(|u| self.symbol_instances.get(u))
+                .cloned()
+                .unwrap_or_default();
+        }
+        if references.is_empty() {
+            references.push(reference);
+        }
+        // Unannotated symbols like "R?" all share a reference but are separate parts
+        references.retain(|r| r.ends_with('?') || self.references.insert(r.clone()));
+        if references.is_empty() {
+            return;
+        }
+
+        let value = field(&["Value"]);
+        let footprint = field(&["Footprint"]);
+        let mpn = field(MPN_HEADERS);
+        let description = match field(&["Description", "ki_description"]) {
+            d if d.is_empty() => footprint.clone(),
+            d => d,
+        };
+        let mut skus: Vec<SupplierSku> = SUPPLIER_HEADERS
+            .iter()
+            .map(|(supplier, sku)| SupplierSku {
+                supplier: field(&[*supplier]),
+                sku: field(&[*sku]),
+            })
+            .collect();
+        skus.extend(SKU_HEADERS.iter().map(|(header, supplier)| SupplierSku {
+            supplier: supplier.to_string(),
+            sku: field(&[*header]),
+        }));
+        skus.retain(|s| !s.supplier.is_empty() && !s.sku.is_empty());
+
+        let name = if mpn.is_empty() {
+            value.clone()
+        } else {
+            mpn.clone()
+        };
+        let parameters = Parameters::from_text(&format!("{} {} {}", value, footprint, description));
+        let line = self
+            .bom
+            .entry((name.clone(), footprint))
+            .or_insert_with(|| {
+                BomLine::new(
+                    0,
+                    Part {
+                        id: 0,
+                        name,
+                        description,
+                        manufacturer: field(MANUFACTURER_HEADERS),
+                        mpn,
+                        skus,
+                        parameters,
+                        ..Default::default()
+                    },
+                )
+            });
+        line.count += references.len() as i64;
+        line.designators.extend(references);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use anyhow::anyhow;
+
+    use super::{kicad_csv_columns, kicad_schematic_file_to_bom, kicad_schematic_to_bom};
+
+    const ROOT: &str = r##"(kicad_sch (version 20231120) (generator "eeschema")
+  (lib_symbols (symbol "Device:R" (property "Reference" "R")))
+  (symbol (lib_id "Device:R") (in_bom yes)
+    (property "Reference" "R1") (property "Value" "10k")
+    (property "Footprint" "Resistor_SMD:R_0603_1608Metric"))
+  (symbol (lib_id "Device:R") (in_bom yes)
+    (property "Reference" "R2") (property "Value" "10k")
+    (property "Footprint" "Resistor_SMD:R_0603_1608Metric"))
+  (symbol (lib_id "Device:R") (in_bom yes)
+    (property "Reference" "R3") (property "Value" "10k")
+    (property "Footprint" "Resistor_SMD:R_0805_2012Metric"))
+  (symbol (lib_id "power:GND") (in_bom yes) (property "Reference" "#PWR01"))
+  (symbol (lib_id "Amplifier:LM324") (unit 1)
+    (property "Reference" "U1") (property "Value" "LM324")
+    (property "MPN" "LM324DR") (property "Manufacturer" "TI"))
+  (symbol (lib_id "Amplifier:LM324") (unit 2)
+    (property "Reference" "U1") (property "Value" "LM324")
+    (property "MPN" "LM324DR") (property "Manufacturer" "TI"))
+  (sheet (property "Sheetname" "Channel A") (property "Sheetfile" "channel.kicad_sch"))
+  (sheet (property "Sheetname" "Channel B") (property "Sheetfile" "channel.kicad_sch"))
+)"##;
+
+    const CHANNEL: &str = r##"(kicad_sch (version 20231120)
+  (symbol (lib_id "Device:C") (in_bom yes)
+    (property "Reference" "C1") (property "Value" "100n")
+    (property "Footprint" "Capacitor_SMD:C_0402_1005Metric")
+    (property "LCSC" "C1525")
+    (instances (project "amp"
+      (path "/a" (reference "C1") (unit 1))
+      (path "/b" (reference "C2") (unit 1)))))
+  (symbol (lib_id "Device:R") (in_bom no)
+    (property "Reference" "R9") (property "Value" "0R"))
+  (symbol (lib_id "Device:R") (in_bom yes) (dnp yes)
+    (property "Reference" "R10") (property "Value" "0R"))
+  (symbol (lib_id "Device:R") (in_bom yes)
+    (property "Reference" "R11") (property "Value" "0R") (property "DNP" "1"))
+)"##;
+
+    #[test]
+    fn groups_symbols_across_sheets() {
+        let bom = kicad_schematic_to_bom(ROOT, |file| match file.to_str() {
+            Some("channel.kicad_sch") => Ok(CHANNEL.to_string()),
+            _ => Err(anyhow!("No sheet {}", file.display())),
+        })
+        .unwrap();
+        let summary: Vec<(i64, &str, &str)> = bom
+            .iter()
+            .map(|l| (l.count, l.part.name.as_str(), l.part.description.as_str()))
+            .collect();
+        assert_eq!(summary.len(), 4);
+        assert!(summary.contains(&(2, "10k", "Resistor_SMD:R_0603_1608Metric")));
+        assert!(summary.contains(&(1, "10k", "Resistor_SMD:R_0805_2012Metric")));
+        // The reused sheet's capacitor is annotated once per instance
+        assert!(summary.contains(&(2, "100n", "Capacitor_SMD:C_0402_1005Metric")));
+        assert!(summary.contains(&(1, "LM324DR", "")));
+
+        let cap = bom.iter().find(|l| l.part.name == "100n").unwrap();
+        assert_eq!(cap.part.skus[0].sku, "C1525");
+        assert_eq!(cap.part.parameters.package.as_deref(), Some("0402"));
+        assert_eq!(cap.designators, ["C1", "C2"]);
+        let opamp = bom.iter().find(|l| l.part.name == "LM324DR").unwrap();
+        assert_eq!(opamp.designators, ["U1"]);
+    }
+
+    #[test]
+    fn follows_nested_sheets_from_their_own_folder() {
+        // A KiCad 6 project whose channel sheet, used twice, has a regulator sheet of its own
+        // next to it, named like the root's input protection sheet
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("assets/kicad/amp.kicad_sch");
+        let bom = kicad_schematic_file_to_bom(&path).unwrap();
+        let lines: Vec<(&str, Vec<String>)> = bom
+            .iter()
+            .map(|l| (l.part.name.as_str(), l.designators.clone()))
+            .collect();
+        assert_eq!(
+            lines,
+            [
+                ("100n", vec![String::from("C1"), String::from("C2")]),
+                ("500mA", vec![String::from("F1")]),
+                ("AP2112K-3.3", vec![String::from("U1"), String::from("U2")]),
+                ("Conn_01x02", vec![String::from("J1")]),
+            ]
+        );
+    }
+
+    #[test]
+    fn recognizes_kicad_csv() {
+        let headers: Vec<String> = ["Refs", "Value", "Footprint", "Qty", "DNP"]
+            .into_iter()
+            .map(String::from)
+            .collect();
+        assert_eq!(
+            kicad_csv_columns(&headers),
+            Some((
+                String::from("Value"),
+                String::from("Footprint"),
+                String::from("Qty")
+            ))
+        );
+        assert_eq!(kicad_csv_columns(&headers[1..]), None);
+    }
+}
@@ -1,4 +1,17 @@
+pub mod backend;
+pub mod buildable;
+pub mod error;
 pub mod export;
 pub mod import;
+pub mod journal;
+pub mod kicad;
+pub mod local;
+pub mod mapping;
+pub mod matching;
+pub mod memory;
 pub mod models;
 pub mod network;
+pub mod parameters;
+pub mod planner;
+#[cfg(test)]
+mod test_util;
@@ -0,0 +1,2440 @@
+use std::path::{Path, PathBuf};
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use rusqlite::{
+    Connection, ErrorCode, OptionalExtension, Row, ToSql, params,
+    types::{FromSql, FromSqlError, FromSqlResult, ToSqlOutput, ValueRef},
+};
+
+use crate::{
+    backend::{
+        PartsBackend, REMOVED_FROM_BIN_REASON, STOCK_SET_REASON, build_reason, check_alternates,
+        check_bom_line_count, check_build_transition, check_merge, check_purchase_rules,
+        check_variant, combine_bom_lines, line_alternates, merged_part,
+    },
+    buildable::build_stock,
+    error::{BatchFailure, FailedLine, NetworkError},
+    models::{
+        BinLocation, Bom, BomLine, BomPartRelation, BomUsage, BuildOrder, BuildStatus, Part,
+        PartWithCountAndStock, PartWithStock, Profile, PurchaseRules, Reservation,
+        StockLedgerEntry, StockRows, SupplierSku, User, VariantCount, draw_with_alternates,
+        drawn_parts, first_revision, select_variant,
+    },
+    network::UserData,
+    parameters::Parameters,
+};
+
+const SCHEMA: &str = r#"
+CREATE TABLE IF NOT EXISTS users (
+    id INTEGER PRIMARY KEY,
+    email TEXT NOT NULL UNIQUE,
+    password TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS profiles (
+    id INTEGER PRIMARY KEY,
+    user_id INTEGER NOT NULL REFERENCES users(id),
+    name TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS parts (
+    id INTEGER PRIMARY KEY,
+    name TEXT NOT NULL,
+    description TEXT NOT NULL,
+    manufacturer TEXT NOT NULL DEFAULT '',
+    mpn TEXT NOT NULL DEFAULT '',
+    parameters TEXT NOT NULL DEFAULT '{}',
+    moq INTEGER NOT NULL DEFAULT 0,
+    order_multiple INTEGER NOT NULL DEFAULT 0,
+    safety_stock INTEGER NOT NULL DEFAULT 0,
+    attrition_percent REAL NOT NULL DEFAULT 0
+);
+CREATE TABLE IF NOT EXISTS part_skus (
+    id INTEGER PRIMARY KEY,
+    part_id INTEGER NOT NULL REFERENCES parts(id),
+    supplier TEXT NOT NULL,
+    sku TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS part_aliases (
+    id INTEGER PRIMARY KEY,
+    part_id INTEGER NOT NULL REFERENCES parts(id),
+    alias TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS part_alternates (
+    id INTEGER PRIMARY KEY,
+    part_id INTEGER NOT NULL REFERENCES parts(id),
+    alternate_id INTEGER NOT NULL REFERENCES parts(id)
+);
+CREATE TABLE IF NOT EXISTS bins (
+    id INTEGER PRIMARY KEY,
+    profile_id INTEGER NOT NULL REFERENCES profiles(id),
+    "row" INTEGER NOT NULL,
+    "column" INTEGER NOT NULL,
+    z INTEGER NOT NULL,
+    UNIQUE (profile_id, "row", "column", z)
+);
+CREATE TABLE IF NOT EXISTS part_bins (
+    id INTEGER PRIMARY KEY,
+    part_id INTEGER NOT NULL REFERENCES parts(id),
+    bin_id INTEGER NOT NULL REFERENCES bins(id),
+    stock INTEGER NOT NULL
+);
+CREATE TABLE IF NOT EXISTS boms (
+    id INTEGER PRIMARY KEY,
+    name TEXT NOT NULL,
+    description TEXT NOT NULL,
+    revision INTEGER NOT NULL DEFAULT 1,
+    revision_of INTEGER REFERENCES boms(id)
+);
+CREATE TABLE IF NOT EXISTS profile_boms (
+    id INTEGER PRIMARY KEY,
+    profile_id INTEGER NOT NULL REFERENCES profiles(id),
+    bom_id INTEGER NOT NULL REFERENCES boms(id)
+);
+CREATE TABLE IF NOT EXISTS bom_parts (
+    id INTEGER PRIMARY KEY,
+    bom_id INTEGER NOT NULL REFERENCES boms(id),
+    part_id INTEGER NOT NULL REFERENCES parts(id),
+    count INTEGER NOT NULL,
+    designators TEXT NOT NULL DEFAULT ''
+);
+CREATE TABLE IF NOT EXISTS bom_part_variants (
+    id INTEGER PRIMARY KEY,
+    bom_part_id INTEGER NOT NULL REFERENCES bom_parts(id),
+    variant TEXT NOT NULL,
+    count INTEGER NOT NULL
+);
+CREATE TABLE IF NOT EXISTS bom_part_alternates (
+    id INTEGER PRIMARY KEY,
+    bom_part_id INTEGER NOT NULL REFERENCES bom_parts(id),
+    part_id INTEGER NOT NULL REFERENCES parts(id)
+);
+CREATE TABLE IF NOT EXISTS stock_ledger (
+    id INTEGER PRIMARY KEY,
+    timestamp INTEGER NOT NULL,
+    profile_id INTEGER NOT NULL REFERENCES profiles(id),
+    user TEXT NOT NULL,
+    part_id INTEGER NOT NULL REFERENCES parts(id),
+    "row" INTEGER NOT NULL,
+    "column" INTEGER NOT NULL,
+    z INTEGER NOT NULL,
+    delta INTEGER NOT NULL,
+    stock INTEGER NOT NULL,
+    reason TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS build_orders (
+    id INTEGER PRIMARY KEY,
+    profile_id INTEGER NOT NULL REFERENCES profiles(id),
+    bom_id INTEGER NOT NULL REFERENCES boms(id),
+    variant TEXT NOT NULL DEFAULT '',
+    quantity INTEGER NOT NULL,
+    status TEXT NOT NULL,
+    created INTEGER NOT NULL
+);
+CREATE TABLE IF NOT EXISTS session (
+    id INTEGER PRIMARY KEY CHECK (id = 1),
+    user_id INTEGER REFERENCES users(id),
+    profile_id INTEGER REFERENCES profiles(id)
+);
+"#;
+
+/// Columns added to existing tables after their first release, added to older databases on open
+const ADDED_COLUMNS: &[(&str, &str, &str)] = &[
+    ("parts", "manufacturer", "TEXT NOT NULL DEFAULT ''"),
+    ("parts", "mpn", "TEXT NOT NULL DEFAULT ''"),
+    ("parts", "parameters", "TEXT NOT NULL DEFAULT '{}'"),
+    ("parts", "moq", "INTEGER NOT NULL DEFAULT 0"),
+    ("parts", "order_multiple", "INTEGER NOT NULL DEFAULT 0"),
+    ("parts", "safety_stock", "INTEGER NOT NULL DEFAULT 0"),
+    ("parts", "attrition_percent", "REAL NOT NULL DEFAULT 0"),
+    ("bom_parts", "designators", "TEXT NOT NULL DEFAULT ''"),
+    ("build_orders", "variant", "TEXT NOT NULL DEFAULT ''"),
+    ("boms", "revision", "INTEGER NOT NULL DEFAULT 1"),
+    ("boms", "revision_of", "INTEGER REFERENCES boms(id)"),
+];
+
+/// Matches parts whose name or part numbers contain ?1, or whose description contains ?2. Both
+/// being NULL matches everything.
+const PART_FILTER: &str = r#"
+(?1 IS NULL AND ?2 IS NULL)
+OR p.name LIKE '%' || ?1 || '%'
+OR p.manufacturer LIKE '%' || ?1 || '%'
+OR p.mpn LIKE '%' || ?1 || '%'
+OR EXISTS (SELECT 1 FROM part_skus s WHERE s.part_id = p.id AND s.sku LIKE '%' || ?1 || '%')
+OR EXISTS (SELECT 1 FROM part_aliases a WHERE a.part_id = p.id AND a.alias LIKE '%' || ?1 || '%')
+OR p.description LIKE '%' || ?2 || '%'
+"#;
+
+/// Stock of every part in a profile, joined with the bin it lives in.
+const PROFILE_STOCK: &str = r#"
+SELECT pb.id, pb.part_id, pb.stock, b.id AS bin_id, b."row", b."column", b.z
+FROM part_bins pb JOIN bins b ON b.id = pb.bin_id
+WHERE b.profile_id = ?1
+"#;
+
+/// A [`PartsBackend`] storing the whole inventory in a SQLite file, for running without a server.
+#[derive(Debug)]
+pub struct LocalBackend {
+    conn: Connection,
+    path: PathBuf,
+    pub user_data: UserData,
+}
+
+fn part_from_row(row: &Row) -> rusqlite::Result<Part> {
+    Ok(Part {
+        id: row.get("id")?,
+        name: row.get("name")?,
+        description: row.get("description")?,
+        manufacturer: row.get("manufacturer")?,
+        mpn: row.get("mpn")?,
+        skus: vec![],
+        parameters: row.get("parameters")?,
+        aliases: vec![],
+        alternates: vec![],
+        purchasing: purchase_rules_from_row(row)?,
+    })
+}
+
+fn purchase_rules_from_row(row: &Row) -> rusqlite::Result<PurchaseRules> {
+    Ok(PurchaseRules {
+        moq: row.get("moq")?,
+        order_multiple: row.get("order_multiple")?,
+        safety_stock: row.get("safety_stock")?,
+        attrition_percent: row.get("attrition_percent")?,
+    })
+}
+
+fn part_with_stock_from_row(row: &Row) -> rusqlite::Result<PartWithStock> {
+    Ok(PartWithStock {
+        id: row.get("id")?,
+        name: row.get("name")?,
+        description: row.get("description")?,
+        manufacturer: row.get("manufacturer")?,
+        mpn: row.get("mpn")?,
+        skus: vec![],
+        parameters: row.get("parameters")?,
+        purchasing: purchase_rules_from_row(row)?,
+        stock: row.get("stock")?,
+        column: row.get("column")?,
+        row: row.get("row")?,
+        z: row.get("z")?,
+    })
+}
+
+fn load_skus(conn: &Connection, part_id: i64) -> rusqlite::Result<Vec<SupplierSku>> {
+    let mut stmt =
+        conn.prepare("SELECT supplier, sku FROM part_skus WHERE part_id = ?1 ORDER BY id")?;
+    stmt.query_map([part_id], |r| {
+        Ok(SupplierSku {
+            supplier: r.get("supplier")?,
+            sku: r.get("sku")?,
+        })
+    })?
+    .collect()
+}
+
+fn load_aliases(conn: &Connection, part_id: i64) -> rusqlite::Result<Vec<String>> {
+    let mut stmt = conn.prepare("SELECT alias FROM part_aliases WHERE part_id = ?1 ORDER BY id")?;
+    stmt.query_map([part_id], |r| r.get(0))?.collect()
+}
+
+fn load_part_alternates(conn: &Connection, part_id: i64) -> rusqlite::Result<Vec<i64>> {
+    let mut stmt =
+        conn.prepare("SELECT alternate_id FROM part_alternates WHERE part_id = ?1 ORDER BY id")?;
+    stmt.query_map([part_id], |r| r.get(0))?.collect()
+}
+
+fn load_line_alternates(conn: &Connection, bom_part_id: i64) -> rusqlite::Result<Vec<i64>> {
+    let mut stmt =
+        conn.prepare("SELECT part_id FROM bom_part_alternates WHERE bom_part_id = ?1 ORDER BY id")?;
+    stmt.query_map([bom_part_id], |r| r.get(0))?.collect()
+}
+
+fn insert_line_alternates(
+    conn: &Connection,
+    bom_part_id: i64,
+    alternates: &[i64],
+) -> rusqlite::Result<()> {
+    conn.execute(
+        "DELETE FROM bom_part_alternates WHERE bom_part_id = ?1",
+        [bom_part_id],
+    )?;
+    for alternate in alternates {
+        conn.execute(
+            "INSERT INTO bom_part_alternates (bom_part_id, part_id) VALUES (?1, ?2)",
+            params![bom_part_id, alternate],
+        )?;
+    }
+    Ok(())
+}
+
+fn insert_skus(conn: &Connection, part_id: i64, skus: &[SupplierSku]) -> rusqlite::Result<()> {
+    for sku in skus {
+        conn.execute(
+            "INSERT INTO part_skus (part_id, supplier, sku) VALUES (?1, ?2, ?3)",
+            params![part_id, sku.supplier, sku.sku],
+        )?;
+    }
+    Ok(())
+}
+
+/// Every BOM line listing a part, in any profile
+fn bom_lines_of_part(conn: &Connection, part_id: i64) -> rusqlite::Result<Vec<BomPartRelation>> {
+    let mut stmt = conn.prepare(
+        "SELECT id, bom_id, part_id, count, designators FROM bom_parts WHERE part_id = ?1",
+    )?;
+    let mut lines = stmt
+        .query_map([part_id], |r| {
+            Ok(BomPartRelation {
+                id: r.get("id")?,
+                bom_id: r.get("bom_id")?,
+                part_id: r.get("part_id")?,
+                count: r.get("count")?,
+                designators: r
+                    .get::<_, String>("designators")?
+                    .split(',')
+                    .filter(|d| !d.is_empty())
+                    .map(String::from)
+                    .collect(),
+                variants: vec![],
+                alternates: vec![],
+            })
+        })?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    for line in &mut lines {
+        line.variants = load_variant_counts(conn, line.id)?;
+        line.alternates = load_line_alternates(conn, line.id)?;
+    }
+    Ok(lines)
+}
+
+fn load_variant_counts(conn: &Connection, bom_part_id: i64) -> rusqlite::Result<Vec<VariantCount>> {
+    let mut stmt = conn.prepare(
+        "SELECT variant, count FROM bom_part_variants WHERE bom_part_id = ?1 ORDER BY id",
+    )?;
+    stmt.query_map([bom_part_id], |r| {
+        Ok(VariantCount {
+            variant: r.get("variant")?,
+            count: r.get("count")?,
+        })
+    })?
+    .collect()
+}
+
+fn load_bom_variants(conn: &Connection, bom_id: i64) -> rusqlite::Result<Vec<String>> {
+    let mut stmt = conn.prepare(
+        "SELECT DISTINCT v.variant FROM bom_part_variants v
+         JOIN bom_parts bp ON bp.id = v.bom_part_id
+         WHERE bp.bom_id = ?1
+         ORDER BY v.variant",
+    )?;
+    stmt.query_map([bom_id], |r| r.get(0))?.collect()
+}
+
+fn insert_part(conn: &Connection, part: &Part) -> rusqlite::Result<i64> {
+    conn.execute(
+        "INSERT INTO parts (name, description, manufacturer, mpn, parameters, moq,
+                            order_multiple, safety_stock, attrition_percent)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+        params![
+            part.name,
+            part.description,
+            part.manufacturer,
+            part.mpn,
+            part.parameters,
+            part.purchasing.moq,
+            part.purchasing.order_multiple,
+            part.purchasing.safety_stock,
+            part.purchasing.attrition_percent
+        ],
+    )?;
+    let part_id = conn.last_insert_rowid();
+    insert_skus(conn, part_id, &part.skus)?;
+    for alias in &part.aliases {
+        conn.execute(
+            "INSERT INTO part_aliases (part_id, alias) VALUES (?1, ?2)",
+            params![part_id, alias],
+        )?;
+    }
+    Ok(part_id)
+}
+
+fn migrate(conn: &Connection) -> rusqlite::Result<()> {
+    for (table, column, definition) in ADDED_COLUMNS {
+        let exists: bool = conn.query_row(
+            "SELECT EXISTS (SELECT 1 FROM pragma_table_info(?1) WHERE name = ?2)",
+            params![table, column],
+            |r| r.get(0),
+        )?;
+        if !exists {
+            conn.execute_batch(&format!(
+                "ALTER TABLE {} ADD COLUMN {} {};",
+                table, column, definition
+            ))?;
+        }
+    }
+    Ok(())
+}
+
+fn profile_from_row(row: &Row) -> rusqlite::Result<Profile> {
+    Ok(Profile {
+        id: row.get("id")?,
+        user_id: row.get("user_id")?,
+        name: row.get("name")?,
+    })
+}
+
+/// Build orders of a profile along with the name of their BOM
+const BUILD_ORDERS: &str = r#"
+SELECT o.*, b.name AS bom_name FROM build_orders o JOIN boms b ON b.id = o.bom_id
+WHERE o.profile_id = ?1
+"#;
+
+impl ToSql for BuildStatus {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        Ok(ToSqlOutput::from(self.as_str()))
+    }
+}
+
+impl FromSql for BuildStatus {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        value
+            .as_str()?
+            .parse()
+            .map_err(|e: String| FromSqlError::Other(e.into()))
+    }
+}
+
+/// Parameters are stored as JSON since most parts only have a few of them
+impl ToSql for Parameters {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        serde_json::to_string(self)
+            .map(ToSqlOutput::from)
+            .map_err(|e| rusqlite::Error::ToSqlConversionFailure(e.into()))
+    }
+}
+
+impl FromSql for Parameters {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        serde_json::from_str(value.as_str()?).map_err(|e| FromSqlError::Other(e.into()))
+    }
+}
+
+fn build_order_from_row(row: &Row) -> rusqlite::Result<BuildOrder> {
+    Ok(BuildOrder {
+        id: row.get("id")?,
+        bom_id: row.get("bom_id")?,
+        bom_name: row.get("bom_name")?,
+        variant: row.get("variant")?,
+        quantity: row.get("quantity")?,
+        status: row.get("status")?,
+        created: DateTime::from_timestamp(row.get("created")?, 0).unwrap_or_default(),
+    })
+}
+
+fn user_from_row(row: &Row) -> rusqlite::Result<User> {
+    Ok(User {
+        id: row.get("id")?,
+        email: row.get("email")?,
+        password: row.get("password")?,
+    })
+}
+
+impl LocalBackend {
+    pub fn open(path: &Path) -> Result<Self, NetworkError> {
+        let conn = Connection::open(path)?;
+        Self::from_connection(conn, path.to_path_buf())
+    }
+
+    pub fn open_in_memory() -> Result<Self, NetworkError> {
+        let conn = Connection::open_in_memory()?;
+        Self::from_connection(conn, PathBuf::from(":memory:"))
+    }
+
+    fn from_connection(conn: Connection, path: PathBuf) -> Result<Self, NetworkError> {
+        conn.execute_batch("PRAGMA foreign_keys = ON;")?;
+        conn.execute_batch(SCHEMA)?;
+        migrate(&conn)?;
+        let mut backend = Self {
+            conn,
+            path,
+            user_data: UserData::default(),
+        };
+        backend.user_data = backend.load_session()?;
+        Ok(backend)
+    }
+
+    fn load_session(&self) -> Result<UserData, NetworkError> {
+        let ids: Option<(Option<i64>, Option<i64>)> = self
+            .conn
+            .query_row(
+                "SELECT user_id, profile_id FROM session WHERE id = 1",
+                [],
+                |r| Ok((r.get(0)?, r.get(1)?)),
+            )
+            .optional()?;
+        let Some((user_id, profile_id)) = ids else {
+            return Ok(UserData::default());
+        };
+        let user = match user_id {
+            Some(id) => self
+                .conn
+                .query_row("SELECT * FROM users WHERE id = ?1", [id], user_from_row)
+                .optional()?,
+            None => None,
+        };
+        let profile = match profile_id {
+            Some(id) => self
+                .conn
+                .query_row(
+                    "SELECT * FROM profiles WHERE id = ?1",
+                    [id],
+                    profile_from_row,
+                )
+                .optional()?,
+            None => None,
+        };
+        Ok(UserData { profile, user })
+    }
+
+    fn save_session(&self) -> Result<(), NetworkError> {
+        self.conn.execute(
+            "INSERT OR REPLACE INTO session (id, user_id, profile_id) VALUES (1, ?1, ?2)",
+            params![
+                self.user_data.user.as_ref().map(|u| u.id),
+                self.user_data.profile.as_ref().map(|p| p.id)
+            ],
+        )?;
+        Ok(())
+    }
+
+    fn logged_in_user_id(&self) -> Result<i64, NetworkError> {
+        self.user_data
+            .user
+            .as_ref()
+            .map(|u| u.id)
+            .ok_or(NetworkError::Unauthorized)
+    }
+
+    fn ensure_part_exists(&self, part_id: i64) -> Result<(), NetworkError> {
+        let exists: bool = self.conn.query_row(
+            "SELECT EXISTS (SELECT 1 FROM parts WHERE id = ?1)",
+            [part_id],
+            |r| r.get(0),
+        )?;
+        if exists {
+            Ok(())
+        } else {
+            Err(NetworkError::NotFound(format!(
+                "Part {} does not exist",
+                part_id
+            )))
+        }
+    }
+
+    /// A part with its stock in the profile, without its supplier part numbers
+    fn stocked_part(&self, profile_id: i64, part_id: i64) -> Result<PartWithStock, NetworkError> {
+        self.conn
+            .query_row(
+                &format!(
+                    "SELECT p.id, p.name, p.description, p.manufacturer, p.mpn, p.parameters,
+                            p.moq, p.order_multiple, p.safety_stock, p.attrition_percent,
+                            COALESCE(s.stock, 0) AS stock, COALESCE(s.\"column\", -1) AS \"column\",
+                            COALESCE(s.\"row\", -1) AS \"row\", COALESCE(s.z, -1) AS z
+                     FROM parts p
+                     LEFT JOIN ({}) s ON s.part_id = p.id
+                     WHERE p.id = ?2",
+                    PROFILE_STOCK
+                ),
+                params![profile_id, part_id],
+                part_with_stock_from_row,
+            )
+            .optional()?
+            .ok_or(NetworkError::NotFound(format!(
+                "Part {} does not exist",
+                part_id
+            )))
+    }
+
+    /// Name and the revision it was made from of a BOM in the profile
+    fn bom_in_profile(
+        &self,
+        profile_id: i64,
+        bom_id: i64,
+    ) -> Result<(String, Option<i64>), NetworkError> {
+        self.conn
+            .query_row(
+                "SELECT b.name, b.revision_of FROM boms b
+                 JOIN profile_boms pb ON pb.bom_id = b.id
+                 WHERE pb.profile_id = ?1 AND b.id = ?2",
+                params![profile_id, bom_id],
+                |r| Ok((r.get(0)?, r.get(1)?)),
+            )
+            .optional()?
+            .ok_or(NetworkError::NotFound(format!(
+                "BOM {} does not exist",
+                bom_id
+            )))
+    }
+
+    fn build_order(&self, profile_id: i64, order_id: i64) -> Result<BuildOrder, NetworkError> {
+        self.conn
+            .query_row(
+                &format!("{} AND o.id = ?2", BUILD_ORDERS),
+                params![profile_id, order_id],
+                build_order_from_row,
+            )
+            .optional()?
+            .ok_or(NetworkError::NotFound(format!(
+                "Build order {} does not exist",
+                order_id
+            )))
+    }
+
+    fn user_email(&self) -> String {
+        self.user_data
+            .user
+            .as_ref()
+            .map(|u| u.email.clone())
+            .unwrap_or_default()
+    }
+
+    fn current_stock(
+        conn: &Connection,
+        profile_id: i64,
+        part_id: i64,
+    ) -> Result<i64, NetworkError> {
+        let stock = conn
+            .query_row(
+                &format!("SELECT stock FROM ({}) WHERE part_id = ?2", PROFILE_STOCK),
+                params![profile_id, part_id],
+                |r| r.get(0),
+            )
+            .optional()?;
+        Ok(stock.unwrap_or(0))
+    }
+
+    fn adjust(
+        conn: &Connection,
+        profile_id: i64,
+        part_id: i64,
+        bin: BinLocation,
+        delta: i64,
+        user: &str,
+        reason: &str,
+    ) -> Result<i64, NetworkError> {
+        let stock = Self::current_stock(conn, profile_id, part_id)? + delta;
+        Self::set_stock(conn, profile_id, part_id, stock, bin, user, reason)?;
+        Ok(stock)
+    }
+
+    fn set_stock(
+        conn: &Connection,
+        profile_id: i64,
+        part_id: i64,
+        stock: i64,
+        bin: BinLocation,
+        user: &str,
+        reason: &str,
+    ) -> Result<(), NetworkError> {
+        let previous = Self::current_stock(conn, profile_id, part_id)?;
+        conn.execute(
+            r#"INSERT OR IGNORE INTO bins (profile_id, "row", "column", z) VALUES (?1, ?2, ?3, ?4)"#,
+            params![profile_id, bin.row, bin.column, bin.z],
+        )?;
+        let bin_id: i64 = conn.query_row(
+            r#"SELECT id FROM bins WHERE profile_id = ?1 AND "row" = ?2 AND "column" = ?3 AND z = ?4"#,
+            params![profile_id, bin.row, bin.column, bin.z],
+            |r| r.get(0),
+        )?;
+        // A part lives in a single bin per profile, so stocking it elsewhere moves it
+        conn.execute(
+            "DELETE FROM part_bins WHERE part_id = ?1
+             AND bin_id IN (SELECT id FROM bins WHERE profile_id = ?2)",
+            params![part_id, profile_id],
+        )?;
+        conn.execute(
+            "INSERT INTO part_bins (part_id, bin_id, stock) VALUES (?1, ?2, ?3)",
+            params![part_id, bin_id, stock],
+        )?;
+        conn.execute(
+            r#"INSERT INTO stock_ledger
+               (timestamp, profile_id, user, part_id, "row", "column", z, delta, stock, reason)
+               VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)"#,
+            params![
+                Utc::now().timestamp(),
+                profile_id,
+                user,
+                part_id,
+                bin.row,
+                bin.column,
+                bin.z,
+                stock - previous,
+                stock,
+                reason
+            ],
+        )?;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl PartsBackend for LocalBackend {
+    fn user_data(&self) -> &UserData {
+        &self.user_data
+    }
+
+    fn user_data_mut(&mut self) -> &mut UserData {
+        &mut self.user_data
+    }
+
+    fn host_name(&self) -> String {
+        self.path.display().to_string()
+    }
+
+    async fn create_user(&mut self, pending: User) -> Result<(), NetworkError> {
+        match self.conn.execute(
+            "INSERT INTO users (email, password) VALUES (?1, ?2)",
+            params![pending.email, pending.password],
+        ) {
+            Ok(_) => Ok(()),
+            Err(rusqlite::Error::SqliteFailure(e, _))
+                if e.code == ErrorCode::ConstraintViolation =>
+            {
+                Err(NetworkError::Conflict(format!(
+                    "A user with email {} already exists",
+                    pending.email
+                )))
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    async fn login(&mut self, user: User) -> Result<(), NetworkError> {
+        let known = self
+            .conn
+            .query_row(
+                "SELECT * FROM users WHERE email = ?1 AND password = ?2",
+                params![user.email, user.password],
+                user_from_row,
+            )
+            .optional()?
+            .ok_or(NetworkError::Unauthorized)?;
+        self.user_data.user = Some(known);
+        let profiles = self.get_profiles(None).await?;
+        self.user_data.profile = profiles.first().cloned();
+        self.save_session()
+    }
+
+    async fn get_parts(
+        &mut self,
+        name: Option<String>,
+        description: Option<String>,
+    ) -> Result<Vec<Part>, NetworkError> {
+        let mut stmt = self.conn.prepare(&format!(
+            "SELECT p.* FROM parts p WHERE {} ORDER BY p.id",
+            PART_FILTER
+        ))?;
+        let mut parts = stmt
+            .query_map(params![name, description], part_from_row)?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        for part in &mut parts {
+            part.skus = load_skus(&self.conn, part.id)?;
+            part.aliases = load_aliases(&self.conn, part.id)?;
+            part.alternates = load_part_alternates(&self.conn, part.id)?;
+        }
+        Ok(parts)
+    }
+
+    async fn new_part(&mut self, part: Part) -> Result<(), NetworkError> {
+        let tx = self.conn.transaction()?;
+        insert_part(&tx, &part)?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    async fn update_part(&mut self, part: Part) -> Result<(), NetworkError> {
+        self.ensure_part_exists(part.id)?;
+        let tx = self.conn.transaction()?;
+        tx.execute(
+            "UPDATE parts SET name = ?2, description = ?3, manufacturer = ?4, mpn = ?5,
+             parameters = ?6 WHERE id = ?1",
+            params![
+                part.id,
+                part.name,
+                part.description,
+                part.manufacturer,
+                part.mpn,
+                part.parameters
+            ],
+        )?;
+        tx.execute("DELETE FROM part_skus WHERE part_id = ?1", [part.id])?;
+        insert_skus(&tx, part.id, &part.skus)?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    async fn set_part_alternates(
+        &mut self,
+        part_id: i64,
+        alternates: Vec<i64>,
+    ) -> Result<(), NetworkError> {
+        check_alternates(part_id, &alternates)?;
+        self.ensure_part_exists(part_id)?;
+        for alternate in &alternates {
+            self.ensure_part_exists(*alternate)?;
+        }
+        let tx = self.conn.transaction()?;
+        tx.execute("DELETE FROM part_alternates WHERE part_id = ?1", [part_id])?;
+        for alternate in &alternates {
+            tx.execute(
+                "INSERT INTO part_alternates (part_id, alternate_id) VALUES (?1, ?2)",
+                params![part_id, alternate],
+            )?;
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    async fn set_purchase_rules(
+        &mut self,
+        part_id: i64,
+        rules: PurchaseRules,
+    ) -> Result<(), NetworkError> {
+        check_purchase_rules(&rules)?;
+        self.ensure_part_exists(part_id)?;
+        self.conn.execute(
+            "UPDATE parts SET moq = ?2, order_multiple = ?3, safety_stock = ?4,
+             attrition_percent = ?5 WHERE id = ?1",
+            params![
+                part_id,
+                rules.moq,
+                rules.order_multiple,
+                rules.safety_stock,
+                rules.attrition_percent
+            ],
+        )?;
+        Ok(())
+    }
+
+    async fn delete_part(&mut self, part_id: i64) -> Result<(), NetworkError> {
+        self.ensure_part_exists(part_id)?;
+        let name: String =
+            self.conn
+                .query_row("SELECT name FROM parts WHERE id = ?1", [part_id], |r| {
+                    r.get(0)
+                })?;
+        let bom: Option<String> = self
+            .conn
+            .query_row(
+                "SELECT b.name FROM bom_parts bp JOIN boms b ON b.id = bp.bom_id
+                 WHERE bp.part_id = ?1 LIMIT 1",
+                [part_id],
+                |r| r.get(0),
+            )
+            .optional()?;
+        if let Some(bom) = bom {
+            return Err(NetworkError::Conflict(format!(
+                "{} is used by BOM {}",
+                name, bom
+            )));
+        }
+        let stocked: bool = self.conn.query_row(
+            "SELECT EXISTS (SELECT 1 FROM part_bins WHERE part_id = ?1)",
+            [part_id],
+            |r| r.get(0),
+        )?;
+        if stocked {
+            return Err(NetworkError::Conflict(format!(
+                "{} is still in a bin, remove it first",
+                name
+            )));
+        }
+        let tx = self.conn.transaction()?;
+        tx.execute("DELETE FROM stock_ledger WHERE part_id = ?1", [part_id])?;
+        tx.execute("DELETE FROM part_skus WHERE part_id = ?1", [part_id])?;
+        tx.execute("DELETE FROM part_aliases WHERE part_id = ?1", [part_id])?;
+        tx.execute(
+            "DELETE FROM part_alternates WHERE part_id = ?1 OR alternate_id = ?1",
+            [part_id],
+        )?;
+        tx.execute(
+            "DELETE FROM bom_part_alternates WHERE part_id = ?1",
+            [part_id],
+        )?;
+        tx.execute("DELETE FROM parts WHERE id = ?1", [part_id])?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    async fn merge_parts(&mut self, keep: i64, merged: Vec<i64>) -> Result<Part, NetworkError> {
+        check_merge(keep, &merged)?;
+        self.ensure_part_exists(keep)?;
+        for part_id in &merged {
+            self.ensure_part_exists(*part_id)?;
+        }
+        let parts = self.get_parts(None, None).await?;
+        let kept = parts.iter().find(|p| p.id == keep).unwrap();
+        let others: Vec<Part> = parts
+            .iter()
+            .filter(|p| merged.contains(&p.id))
+            .cloned()
+            .collect();
+        let part = merged_part(kept, &others);
+
+        let tx = self.conn.transaction()?;
+        for other in &others {
+            // History of the merged part carries on as the history of the kept one
+            tx.execute(
+                "UPDATE stock_ledger SET part_id = ?1 WHERE part_id = ?2",
+                params![keep, other.id],
+            )?;
+            let stocked = {
+                let mut stmt = tx.prepare(
+                    "SELECT b.profile_id, pb.bin_id, pb.stock
+                     FROM part_bins pb JOIN bins b ON b.id = pb.bin_id
+                     WHERE pb.part_id = ?1",
+                )?;
+                stmt.query_map([other.id], |r| {
+                    Ok((
+                        r.get::<_, i64>(0)?,
+                        r.get::<_, i64>(1)?,
+                        r.get::<_, i64>(2)?,
+                    ))
+                })?
+                .collect::<rusqlite::Result<Vec<_>>>()?
+            };
+            tx.execute("DELETE FROM part_bins WHERE part_id = ?1", [other.id])?;
+            for (profile_id, bin_id, stock) in stocked {
+                // The kept part stays where it is, or moves into the merged part's bin
+                let added = tx.execute(
+                    "UPDATE part_bins SET stock = stock + ?3 WHERE part_id = ?1
+                     AND bin_id IN (SELECT id FROM bins WHERE profile_id = ?2)",
+                    params![keep, profile_id, stock],
+                )?;
+                if added == 0 {
+                    tx.execute(
+                        "INSERT INTO part_bins (part_id, bin_id, stock) VALUES (?1, ?2, ?3)",
+                        params![keep, bin_id, stock],
+                    )?;
+                }
+            }
+
+            let kept_lines = bom_lines_of_part(&tx, keep)?;
+            for line in bom_lines_of_part(&tx, other.id)? {
+                match kept_lines.iter().find(|l| l.bom_id == line.bom_id) {
+                    Some(kept_line) => {
+                        let combined = combine_bom_lines(kept_line, &line);
+                        tx.execute(
+                            "UPDATE bom_parts SET count = ?2, designators = ?3 WHERE id = ?1",
+                            params![combined.id, combined.count, combined.designators.join(",")],
+                        )?;
+                        tx.execute(
+                            "DELETE FROM bom_part_variants WHERE bom_part_id IN (?1, ?2)",
+                            params![combined.id, line.id],
+                        )?;
+                        for variant in &combined.variants {
+                            tx.execute(
+                                "INSERT INTO bom_part_variants (bom_part_id, variant, count)
+                                 VALUES (?1, ?2, ?3)",
+                                params![combined.id, variant.variant, variant.count],
+                            )?;
+                        }
+                        tx.execute(
+                            "DELETE FROM bom_part_alternates WHERE bom_part_id = ?1",
+                            [line.id],
+                        )?;
+                        insert_line_alternates(&tx, combined.id, &combined.alternates)?;
+                        tx.execute("DELETE FROM bom_parts WHERE id = ?1", [line.id])?;
+                    }
+                    None => {
+                        tx.execute(
+                            "UPDATE bom_parts SET part_id = ?1 WHERE id = ?2",
+                            params![keep, line.id],
+                        )?;
+                    }
+                }
+            }
+            tx.execute("DELETE FROM part_skus WHERE part_id = ?1", [other.id])?;
+            tx.execute("DELETE FROM part_aliases WHERE part_id = ?1", [other.id])?;
+            tx.execute("DELETE FROM part_alternates WHERE part_id = ?1", [other.id])?;
+            tx.execute(
+                "UPDATE part_alternates SET alternate_id = ?1 WHERE alternate_id = ?2",
+                params![keep, other.id],
+            )?;
+            tx.execute(
+                "UPDATE bom_part_alternates SET part_id = ?1 WHERE part_id = ?2",
+                params![keep, other.id],
+            )?;
+            tx.execute("DELETE FROM parts WHERE id = ?1", [other.id])?;
+        }
+        // The moved entries only counted the merged part's stock, the kept part's ledger adds
+        // up to both
+        tx.execute(
+            "UPDATE stock_ledger SET stock = (
+                 SELECT SUM(l.delta) FROM stock_ledger l
+                 WHERE l.part_id = stock_ledger.part_id
+                 AND l.profile_id = stock_ledger.profile_id AND l.id <= stock_ledger.id
+             )
+             WHERE part_id = ?1",
+            [keep],
+        )?;
+        // Alternates pointing at a merged part now point at the kept one, which may have made
+        // some stand in for themselves or appear twice
+        tx.execute(
+            "DELETE FROM part_alternates WHERE part_id = alternate_id
+             OR id NOT IN (SELECT MIN(id) FROM part_alternates GROUP BY part_id, alternate_id)",
+            [],
+        )?;
+        tx.execute(
+            "DELETE FROM bom_part_alternates
+             WHERE part_id = (SELECT bp.part_id FROM bom_parts bp WHERE bp.id = bom_part_id)
+             OR id NOT IN (SELECT MIN(id) FROM bom_part_alternates GROUP BY bom_part_id, part_id)",
+            [],
+        )?;
+        tx.execute(
+            "UPDATE parts SET manufacturer = ?2, mpn = ?3 WHERE id = ?1",
+            params![keep, part.manufacturer, part.mpn],
+        )?;
+        tx.execute("DELETE FROM part_skus WHERE part_id = ?1", [keep])?;
+        insert_skus(&tx, keep, &part.skus)?;
+        tx.execute("DELETE FROM part_aliases WHERE part_id = ?1", [keep])?;
+        for alias in &part.aliases {
+            tx.execute(
+                "INSERT INTO part_aliases (part_id, alias) VALUES (?1, ?2)",
+                params![keep, alias],
+            )?;
+        }
+        tx.execute("DELETE FROM part_alternates WHERE part_id = ?1", [keep])?;
+        for alternate in &part.alternates {
+            tx.execute(
+                "INSERT INTO part_alternates (part_id, alternate_id) VALUES (?1, ?2)",
+                params![keep, alternate],
+            )?;
+        }
+        tx.commit()?;
+        Ok(part)
+    }
+
+    async fn get_profiles(&mut self, name: Option<String>) -> Result<Vec<Profile>, NetworkError> {
+        let user_id = self.logged_in_user_id()?;
+        let mut stmt = self.conn.prepare(
+            "SELECT * FROM profiles
+             WHERE user_id = ?1 AND (?2 IS NULL OR name LIKE '%' || ?2 || '%')
+             ORDER BY id",
+        )?;
+        let profiles = stmt
+            .query_map(params![user_id, name], profile_from_row)?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        Ok(profiles)
+    }
+
+    async fn new_profile(&mut self, name: String) -> Result<(), NetworkError> {
+        let user_id = self.logged_in_user_id()?;
+        self.conn.execute(
+            "INSERT INTO profiles (user_id, name) VALUES (?1, ?2)",
+            params![user_id, name],
+        )?;
+        Ok(())
+    }
+
+    async fn update_profile(&mut self, profile_id: i64, name: String) -> Result<(), NetworkError> {
+        let user_id = self.logged_in_user_id()?;
+        let changed = self.conn.execute(
+            "UPDATE profiles SET name = ?3 WHERE id = ?1 AND user_id = ?2",
+            params![profile_id, user_id, name],
+        )?;
+        if changed == 0 {
+            return Err(NetworkError::NotFound(format!(
+                "Profile {} does not exist",
+                profile_id
+            )));
+        }
+        // Keep the selected profile in step with its new name
+        if let Some(selected) = self
+            .user_data
+            .profile
+            .as_mut()
+            .filter(|p| p.id == profile_id)
+        {
+            selected.name = name;
+        }
+        Ok(())
+    }
+
+    async fn list_stock(&mut self, profile_id: i64) -> Result<Vec<StockRows>, NetworkError> {
+        let mut stmt = self.conn.prepare(&format!(
+            "SELECT s.*, p.name, p.description FROM ({}) s
+             JOIN parts p ON p.id = s.part_id ORDER BY s.id",
+            PROFILE_STOCK
+        ))?;
+        let rows = stmt
+            .query_map([profile_id], |r| {
+                Ok(StockRows {
+                    id: r.get("id")?,
+                    bin_id: r.get("bin_id")?,
+                    row: r.get("row")?,
+                    column: r.get("column")?,
+                    z: r.get("z")?,
+                    part_id: r.get("part_id")?,
+                    name: r.get("name")?,
+                    description: r.get("description")?,
+                    stock: r.get("stock")?,
+                })
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        Ok(rows)
+    }
+
+    async fn stock_part(
+        &mut self,
+        profile_id: i64,
+        part_id: i64,
+        stock: i64,
+        column: i64,
+        row: i64,
+        z: i64,
+    ) -> Result<(), NetworkError> {
+        self.ensure_part_exists(part_id)?;
+        let user = self.user_email();
+        let bin = BinLocation { column, row, z };
+        let tx = self.conn.transaction()?;
+        Self::set_stock(
+            &tx,
+            profile_id,
+            part_id,
+            stock,
+            bin,
+            &user,
+            STOCK_SET_REASON,
+        )?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    async fn adjust_stock(
+        &mut self,
+        profile_id: i64,
+        part_id: i64,
+        bin: BinLocation,
+        delta: i64,
+        reason: String,
+    ) -> Result<i64, NetworkError> {
+        self.ensure_part_exists(part_id)?;
+        let user = self.user_email();
+        let tx = self.conn.transaction()?;
+        let stock = Self::adjust(&tx, profile_id, part_id, bin, delta, &user, &reason)?;
+        tx.commit()?;
+        Ok(stock)
+    }
+
+    async fn remove_from_bin(&mut self, profile_id: i64, part_id: i64) -> Result<(), NetworkError> {
+        self.ensure_part_exists(part_id)?;
+        let bin = self
+            .conn
+            .query_row(
+                &format!(
+                    r#"SELECT "row", "column", z FROM ({}) WHERE part_id = ?2"#,
+                    PROFILE_STOCK
+                ),
+                params![profile_id, part_id],
+                |r| {
+                    Ok(BinLocation {
+                        row: r.get(0)?,
+                        column: r.get(1)?,
+                        z: r.get(2)?,
+                    })
+                },
+            )
+            .optional()?
+            .ok_or(NetworkError::NotFound(format!(
+                "Part {} is not in a bin",
+                part_id
+            )))?;
+        let user = self.user_email();
+        let tx = self.conn.transaction()?;
+        // Emptying the bin first records the stock that left with the part
+        Self::set_stock(
+            &tx,
+            profile_id,
+            part_id,
+            0,
+            bin,
+            &user,
+            REMOVED_FROM_BIN_REASON,
+        )?;
+        tx.execute(
+            "DELETE FROM part_bins WHERE part_id = ?1
+             AND bin_id IN (SELECT id FROM bins WHERE profile_id = ?2)",
+            params![part_id, profile_id],
+        )?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    async fn stock_parts(
+        &mut self,
+        profile_id: i64,
+        parts: &[PartWithCountAndStock],
+        diff: i64,
+        reason: String,
+    ) -> Result<(), NetworkError> {
+        let mut failed: Vec<FailedLine> = vec![];
+        for p in parts {
+            if let Err(error) = self.ensure_part_exists(p.id) {
+                failed.push(FailedLine {
+                    part_id: p.id,
+                    name: p.name.clone(),
+                    error,
+                });
+            }
+        }
+        let user = self.user_email();
+        let tx = self.conn.transaction()?;
+        if failed.is_empty() {
+            for p in parts {
+                if let Err(error) = Self::adjust(
+                    &tx,
+                    profile_id,
+                    p.id,
+                    p.bin(),
+                    diff * p.count,
+                    &user,
+                    &reason,
+                ) {
+                    failed.push(FailedLine {
+                        part_id: p.id,
+                        name: p.name.clone(),
+                        error,
+                    });
+                }
+            }
+        }
+        if !failed.is_empty() {
+            // Dropping the transaction rolls back the lines that went through
+            return Err(NetworkError::Batch(BatchFailure {
+                failed,
+                unreverted: vec![],
+            }));
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    async fn stock_history(
+        &mut self,
+        profile_id: i64,
+        part_id: Option<i64>,
+    ) -> Result<Vec<StockLedgerEntry>, NetworkError> {
+        let mut stmt = self.conn.prepare(
+            "SELECT l.*, p.name FROM stock_ledger l JOIN parts p ON p.id = l.part_id
+             WHERE l.profile_id = ?1 AND (?2 IS NULL OR l.part_id = ?2)
+             ORDER BY l.id DESC",
+        )?;
+        let entries = stmt
+            .query_map(params![profile_id, part_id], |r| {
+                Ok(StockLedgerEntry {
+                    id: r.get("id")?,
+                    timestamp: DateTime::from_timestamp(r.get("timestamp")?, 0).unwrap_or_default(),
+                    user: r.get("user")?,
+                    part_id: r.get("part_id")?,
+                    name: r.get("name")?,
+                    row: r.get("row")?,
+                    column: r.get("column")?,
+                    z: r.get("z")?,
+                    delta: r.get("delta")?,
+                    stock: r.get("stock")?,
+                    reason: r.get("reason")?,
+                })
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        Ok(entries)
+    }
+
+    async fn list_boms(
+        &mut self,
+        profile_id: i64,
+        bom_id: Option<i64>,
+        bom_name: Option<String>,
+    ) -> Result<Vec<Bom>, NetworkError> {
+        let mut stmt = self.conn.prepare(
+            "SELECT b.* FROM boms b JOIN profile_boms pb ON pb.bom_id = b.id
+             WHERE pb.profile_id = ?1
+               AND (?2 IS NULL OR b.id = ?2)
+               AND (?3 IS NULL OR b.name LIKE '%' || ?3 || '%')
+             ORDER BY b.id",
+        )?;
+        let mut boms = stmt
+            .query_map(params![profile_id, bom_id, bom_name], |r| {
+                Ok(Bom {
+                    id: r.get("id")?,
+                    name: r.get("name")?,
+                    description: r.get("description")?,
+                    variants: vec![],
+                    revision: r.get("revision")?,
+                    revision_of: r.get("revision_of")?,
+                })
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        for bom in &mut boms {
+            bom.variants = load_bom_variants(&self.conn, bom.id)?;
+        }
+        Ok(boms)
+    }
+
+    async fn new_bom(
+        &mut self,
+        profile_id: i64,
+        name: String,
+        description: String,
+        candidates: Vec<BomLine>,
+        revision_of: Option<i64>,
+    ) -> Result<(), NetworkError> {
+        for line in &candidates {
+            if line.part.id != 0 {
+                self.ensure_part_exists(line.part.id)?;
+            }
+        }
+        let revision = match revision_of {
+            Some(previous_id) => {
+                let previous = self
+                    .list_boms(profile_id, Some(previous_id), None)
+                    .await?
+                    .pop()
+                    .ok_or(NetworkError::NotFound(format!(
+                        "BOM {} does not exist",
+                        previous_id
+                    )))?;
+                previous.revision + 1
+            }
+            None => first_revision(),
+        };
+        let tx = self.conn.transaction()?;
+        tx.execute(
+            "INSERT INTO boms (name, description, revision, revision_of) VALUES (?1, ?2, ?3, ?4)",
+            params![name, description, revision, revision_of],
+        )?;
+        let bom_id = tx.last_insert_rowid();
+        tx.execute(
+            "INSERT INTO profile_boms (profile_id, bom_id) VALUES (?1, ?2)",
+            params![profile_id, bom_id],
+        )?;
+        for line in candidates {
+            // Unlinked candidates become new parts, just like on the server
+            let part_id = if line.part.id == 0 {
+                insert_part(&tx, &line.part)?
+            } else {
+                line.part.id
+            };
+            tx.execute(
+                "INSERT INTO bom_parts (bom_id, part_id, count, designators)
+                 VALUES (?1, ?2, ?3, ?4)",
+                params![bom_id, part_id, line.count, line.designators.join(",")],
+            )?;
+            let bom_part_id = tx.last_insert_rowid();
+            for variant in &line.variants {
+                tx.execute(
+                    "INSERT INTO bom_part_variants (bom_part_id, variant, count)
+                     VALUES (?1, ?2, ?3)",
+                    params![bom_part_id, variant.variant, variant.count],
+                )?;
+            }
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    async fn update_bom(
+        &mut self,
+        profile_id: i64,
+        bom_id: i64,
+        name: String,
+        description: String,
+    ) -> Result<(), NetworkError> {
+        self.bom_in_profile(profile_id, bom_id)?;
+        self.conn.execute(
+            "UPDATE boms SET name = ?2, description = ?3 WHERE id = ?1",
+            params![bom_id, name, description],
+        )?;
+        Ok(())
+    }
+
+    async fn delete_bom(&mut self, profile_id: i64, bom_id: i64) -> Result<(), NetworkError> {
+        let (name, revision_of) = self.bom_in_profile(profile_id, bom_id)?;
+        let built: bool = self.conn.query_row(
+            "SELECT EXISTS (SELECT 1 FROM build_orders WHERE bom_id = ?1)",
+            [bom_id],
+            |r| r.get(0),
+        )?;
+        if built {
+            return Err(NetworkError::Conflict(format!("{} has build orders", name)));
+        }
+        let tx = self.conn.transaction()?;
+        tx.execute(
+            "DELETE FROM bom_part_variants
+             WHERE bom_part_id IN (SELECT id FROM bom_parts WHERE bom_id = ?1)",
+            [bom_id],
+        )?;
+        tx.execute(
+            "DELETE FROM bom_part_alternates
+             WHERE bom_part_id IN (SELECT id FROM bom_parts WHERE bom_id = ?1)",
+            [bom_id],
+        )?;
+        tx.execute("DELETE FROM bom_parts WHERE bom_id = ?1", [bom_id])?;
+        tx.execute("DELETE FROM profile_boms WHERE bom_id = ?1", [bom_id])?;
+        tx.execute(
+            "UPDATE boms SET revision_of = ?2 WHERE revision_of = ?1",
+            params![bom_id, revision_of],
+        )?;
+        tx.execute("DELETE FROM boms WHERE id = ?1", [bom_id])?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    async fn update_bom_line(
+        &mut self,
+        profile_id: i64,
+        bom_id: i64,
+        part_id: i64,
+        count: i64,
+    ) -> Result<(), NetworkError> {
+        check_bom_line_count(count)?;
+        self.bom_in_profile(profile_id, bom_id)?;
+        let changed = self.conn.execute(
+            "UPDATE bom_parts SET count = ?3 WHERE bom_id = ?1 AND part_id = ?2",
+            params![bom_id, part_id, count],
+        )?;
+        if changed == 0 {
+            return Err(NetworkError::NotFound(format!(
+                "BOM {} has no part {}",
+                bom_id, part_id
+            )));
+        }
+        Ok(())
+    }
+
+    async fn delete_bom_line(
+        &mut self,
+        profile_id: i64,
+        bom_id: i64,
+        part_id: i64,
+    ) -> Result<(), NetworkError> {
+        self.bom_in_profile(profile_id, bom_id)?;
+        let tx = self.conn.transaction()?;
+        tx.execute(
+            "DELETE FROM bom_part_variants WHERE bom_part_id IN
+             (SELECT id FROM bom_parts WHERE bom_id = ?1 AND part_id = ?2)",
+            params![bom_id, part_id],
+        )?;
+        tx.execute(
+            "DELETE FROM bom_part_alternates WHERE bom_part_id IN
+             (SELECT id FROM bom_parts WHERE bom_id = ?1 AND part_id = ?2)",
+            params![bom_id, part_id],
+        )?;
+        let changed = tx.execute(
+            "DELETE FROM bom_parts WHERE bom_id = ?1 AND part_id = ?2",
+            params![bom_id, part_id],
+        )?;
+        if changed == 0 {
+            return Err(NetworkError::NotFound(format!(
+                "BOM {} has no part {}",
+                bom_id, part_id
+            )));
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    async fn set_bom_line_alternates(
+        &mut self,
+        profile_id: i64,
+        bom_id: i64,
+        part_id: i64,
+        alternates: Vec<i64>,
+    ) -> Result<(), NetworkError> {
+        check_alternates(part_id, &alternates)?;
+        self.bom_in_profile(profile_id, bom_id)?;
+        for alternate in &alternates {
+            self.ensure_part_exists(*alternate)?;
+        }
+        let bom_part_id: i64 = self
+            .conn
+            .query_row(
+                "SELECT id FROM bom_parts WHERE bom_id = ?1 AND part_id = ?2",
+                params![bom_id, part_id],
+                |r| r.get(0),
+            )
+            .optional()?
+            .ok_or(NetworkError::NotFound(format!(
+                "BOM {} has no part {}",
+                bom_id, part_id
+            )))?;
+        let tx = self.conn.transaction()?;
+        insert_line_alternates(&tx, bom_part_id, &alternates)?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    async fn parts_in_bom(
+        &mut self,
+        profile_id: i64,
+        bom_id: i64,
+    ) -> Result<Vec<PartWithCountAndStock>, NetworkError> {
+        let mut stmt = self.conn.prepare(&format!(
+            "SELECT bp.id AS bom_part_id, p.id, p.name, p.description, bp.count, bp.designators,
+                    COALESCE(s.stock, 0) AS stock, COALESCE(s.\"column\", -1) AS \"column\",
+                    COALESCE(s.\"row\", -1) AS \"row\", COALESCE(s.z, -1) AS z
+             FROM bom_parts bp
+             JOIN parts p ON p.id = bp.part_id
+             LEFT JOIN ({}) s ON s.part_id = p.id
+             WHERE bp.bom_id = ?2
+             ORDER BY bp.id",
+            PROFILE_STOCK
+        ))?;
+        let rows = stmt
+            .query_map(params![profile_id, bom_id], |r| {
+                let part = PartWithCountAndStock {
+                    id: r.get("id")?,
+                    name: r.get("name")?,
+                    description: r.get("description")?,
+                    count: r.get("count")?,
+                    designators: r
+                        .get::<_, String>("designators")?
+                        .split(',')
+                        .filter(|d| !d.is_empty())
+                        .map(String::from)
+                        .collect(),
+                    variants: vec![],
+                    stock: r.get("stock")?,
+                    column: r.get("column")?,
+                    row: r.get("row")?,
+                    z: r.get("z")?,
+                    alternates: vec![],
+                };
+                Ok((r.get::<_, i64>("bom_part_id")?, part))
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        let mut parts = vec![];
+        for (bom_part_id, mut part) in rows {
+            part.variants = load_variant_counts(&self.conn, bom_part_id)?;
+            let alternates = line_alternates(
+                part.id,
+                &load_line_alternates(&self.conn, bom_part_id)?,
+                &load_part_alternates(&self.conn, part.id)?,
+            );
+            for alternate in alternates {
+                part.alternates
+                    .push(self.stocked_part(profile_id, alternate)?);
+            }
+            parts.push(part);
+        }
+        Ok(parts)
+    }
+
+    async fn where_used(
+        &mut self,
+        profile_id: i64,
+        part_id: i64,
+    ) -> Result<Vec<BomUsage>, NetworkError> {
+        self.ensure_part_exists(part_id)?;
+        let mut stmt = self.conn.prepare(
+            "SELECT bp.id AS bom_part_id, b.id, b.name, b.revision, bp.count
+             FROM bom_parts bp
+             JOIN boms b ON b.id = bp.bom_id
+             JOIN profile_boms pb ON pb.bom_id = b.id
+             WHERE pb.profile_id = ?1 AND bp.part_id = ?2
+             ORDER BY b.id",
+        )?;
+        let rows = stmt
+            .query_map(params![profile_id, part_id], |r| {
+                let usage = BomUsage {
+                    bom_id: r.get("id")?,
+                    bom_name: r.get("name")?,
+                    revision: r.get("revision")?,
+                    count: r.get("count")?,
+                    variants: vec![],
+                };
+                Ok((r.get::<_, i64>("bom_part_id")?, usage))
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        let mut usages = vec![];
+        for (bom_part_id, mut usage) in rows {
+            usage.variants = load_variant_counts(&self.conn, bom_part_id)?;
+            usages.push(usage);
+        }
+        Ok(usages)
+    }
+
+    async fn parts_with_stock(
+        &mut self,
+        name: Option<String>,
+        description: Option<String>,
+        profile_id: i64,
+    ) -> Result<Vec<PartWithStock>, NetworkError> {
+        // The part filter binds ?1 and ?2, so the profile moves to ?3
+        let mut stmt = self.conn.prepare(&format!(
+            "SELECT p.id, p.name, p.description, p.manufacturer, p.mpn, p.parameters,
+                    p.moq, p.order_multiple, p.safety_stock, p.attrition_percent,
+                    COALESCE(s.stock, 0) AS stock, COALESCE(s.\"column\", -1) AS \"column\",
+                    COALESCE(s.\"row\", -1) AS \"row\", COALESCE(s.z, -1) AS z
+             FROM parts p
+             LEFT JOIN ({}) s ON s.part_id = p.id
+             WHERE {}
+             ORDER BY p.id",
+            PROFILE_STOCK.replace("?1", "?3"),
+            PART_FILTER
+        ))?;
+        let mut parts = stmt
+            .query_map(
+                params![name, description, profile_id],
+                part_with_stock_from_row,
+            )?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        for part in &mut parts {
+            part.skus = load_skus(&self.conn, part.id)?;
+        }
+        Ok(parts)
+    }
+
+    async fn list_build_orders(
+        &mut self,
+        profile_id: i64,
+    ) -> Result<Vec<BuildOrder>, NetworkError> {
+        let mut stmt = self
+            .conn
+            .prepare(&format!("{} ORDER BY o.id", BUILD_ORDERS))?;
+        let orders = stmt
+            .query_map([profile_id], build_order_from_row)?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        Ok(orders)
+    }
+
+    async fn new_build_order(
+        &mut self,
+        profile_id: i64,
+        bom_id: i64,
+        quantity: i64,
+        variant: Option<String>,
+    ) -> Result<BuildOrder, NetworkError> {
+        if quantity <= 0 {
+            return Err(NetworkError::Validation {
+                field: String::from("quantity"),
+                msg: String::from("Must be at least 1"),
+            });
+        }
+        let bom = self
+            .list_boms(profile_id, Some(bom_id), None)
+            .await?
+            .pop()
+            .ok_or(NetworkError::NotFound(format!(
+                "BOM {} does not exist",
+                bom_id
+            )))?;
+        check_variant(&bom, variant.as_deref())?;
+        self.conn.execute(
+            "INSERT INTO build_orders (profile_id, bom_id, variant, quantity, status, created)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            params![
+                profile_id,
+                bom_id,
+                variant.unwrap_or_default(),
+                quantity,
+                BuildStatus::Planned,
+                Utc::now().timestamp()
+            ],
+        )?;
+        let id = self.conn.last_insert_rowid();
+        self.build_order(profile_id, id)
+    }
+
+    async fn set_build_status(
+        &mut self,
+        profile_id: i64,
+        order_id: i64,
+        status: BuildStatus,
+    ) -> Result<(), NetworkError> {
+        let order = self.build_order(profile_id, order_id)?;
+        check_build_transition(&order, status)?;
+        if status == BuildStatus::Built {
+            let parts = select_variant(
+                &self.parts_in_bom(profile_id, order.bom_id).await?,
+                order.variant(),
+            );
+            let reserved = self
+                .reservations(profile_id)
+                .await?
+                .into_iter()
+                .map(|r| (r.part_id, r.reserved))
+                .collect();
+            // What other build orders hold stays theirs
+            let mut available = build_stock(&parts, &reserved, order.quantity);
+            let draws = draw_with_alternates(&parts, order.quantity, &mut available);
+            self.stock_parts(
+                profile_id,
+                &drawn_parts(&parts, &draws),
+                -1,
+                build_reason(&order),
+            )
+            .await?;
+        }
+        self.conn.execute(
+            "UPDATE build_orders SET status = ?1 WHERE id = ?2",
+            params![status, order_id],
+        )?;
+        Ok(())
+    }
+
+    async fn reservations(&mut self, profile_id: i64) -> Result<Vec<Reservation>, NetworkError> {
+        let mut stmt = self.conn.prepare(
+            "SELECT bp.part_id, SUM(COALESCE(v.count, bp.count) * o.quantity) AS reserved
+             FROM build_orders o JOIN bom_parts bp ON bp.bom_id = o.bom_id
+             LEFT JOIN bom_part_variants v ON v.bom_part_id = bp.id AND v.variant = o.variant
+             WHERE o.profile_id = ?1 AND o.status IN (?2, ?3)
+             GROUP BY bp.part_id
+             ORDER BY bp.part_id",
+        )?;
+        let reservations = stmt
+            .query_map(
+                params![profile_id, BuildStatus::Planned, BuildStatus::Kitted],
+                |r| {
+                    Ok(Reservation {
+                        part_id: r.get("part_id")?,
+                        reserved: r.get("reserved")?,
+                    })
+                },
+            )?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        Ok(reservations)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        backend::PartsBackend,
+        error::NetworkError,
+        import::link_existing,
+        models::{
+            BinLocation, BomLine, BuildStatus, Part, PurchaseRules, SupplierSku, User, VariantCount,
+        },
+        test_util::{logged_in, part},
+    };
+
+    use super::LocalBackend;
+
+    async fn logged_in_backend() -> LocalBackend {
+        logged_in(LocalBackend::open_in_memory().unwrap()).await
+    }
+
+    #[tokio::test]
+    async fn duplicate_users_conflict() {
+        let mut backend = logged_in_backend().await;
+        let result = backend
+            .create_user(User {
+                id: 0,
+                email: String::from("test@example.com"),
+                password: String::from("other"),
+            })
+            .await;
+        assert!(matches!(
+            result,
+            Err(crate::error::NetworkError::Conflict(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn session_survives_reopening() {
+        let dir = std::env::temp_dir().join(format!("pcb-parts-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("session.sqlite");
+        let _ = std::fs::remove_file(&path);
+        {
+            let mut backend = LocalBackend::open(&path).unwrap();
+            let user = User {
+                id: 0,
+                email: String::from("solo@example.com"),
+                password: String::from("pw"),
+            };
+            backend.create_user(user.clone()).await.unwrap();
+            backend.login(user.clone()).await.unwrap();
+            backend.new_profile(String::from("Bench")).await.unwrap();
+            backend.login(user).await.unwrap();
+        }
+        let backend = LocalBackend::open(&path).unwrap();
+        assert_eq!(
+            backend
+                .user_data()
+                .profile
+                .as_ref()
+                .map(|p| p.name.as_str()),
+            Some("Bench")
+        );
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn search_matches_name_or_description() {
+        let mut backend = logged_in_backend().await;
+        backend
+            .new_part(part("LTC3531", "Buck-boost"))
+            .await
+            .unwrap();
+        backend
+            .new_part(part("10k", "Resistor 0603"))
+            .await
+            .unwrap();
+
+        let profile_id = backend.user_data().profile.as_ref().unwrap().id;
+        let found = backend
+            .parts_with_stock(
+                Some(String::from("0603")),
+                Some(String::from("0603")),
+                profile_id,
+            )
+            .await
+            .unwrap();
+        assert_eq!(found.len(), 1);
+        assert_eq!((found[0].stock, found[0].row), (0, -1));
+        assert_eq!(backend.get_parts(None, None).await.unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn search_finds_part_numbers() {
+        let mut backend = logged_in_backend().await;
+        backend
+            .new_part(Part {
+                manufacturer: String::from("Taiyo Yuden"),
+                mpn: String::from("TMK107B7105KA-T"),
+                skus: vec![SupplierSku {
+                    supplier: String::from("DigiKey"),
+                    sku: String::from("587-2984-1-ND"),
+                }],
+                ..part("1uF", "Capacitor 0603")
+            })
+            .await
+            .unwrap();
+
+        let profile_id = backend.user_data().profile.as_ref().unwrap().id;
+        for query in ["tmk107", "587-2984"] {
+            let found = backend
+                .parts_with_stock(Some(String::from(query)), None, profile_id)
+                .await
+                .unwrap();
+            assert_eq!(found.len(), 1);
+            assert_eq!(found[0].skus[0].sku, "587-2984-1-ND");
+        }
+    }
+
+    #[tokio::test]
+    async fn bom_creates_unlinked_parts_and_depletes() {
+        let mut backend = logged_in_backend().await;
+        let profile_id = backend.user_data().profile.as_ref().unwrap().id;
+        backend.new_part(part("10k", "Resistor")).await.unwrap();
+        let existing = backend.get_parts(None, None).await.unwrap()[0].clone();
+        backend
+            .stock_part(profile_id, existing.id, 100, 2, 1, 0)
+            .await
+            .unwrap();
+
+        backend
+            .new_bom(
+                profile_id,
+                String::from("Magnet Harvesting"),
+                String::from("1.2"),
+                vec![
+                    BomLine {
+                        designators: ["R1", "R2", "R5", "R12"].map(String::from).to_vec(),
+                        ..BomLine::new(4, existing)
+                    },
+                    BomLine::new(1, part("LTC3531", "Buck-boost")),
+                ],
+                None,
+            )
+            .await
+            .unwrap();
+
+        let bom = backend
+            .list_boms(profile_id, None, Some(String::from("magnet")))
+            .await
+            .unwrap()[0]
+            .clone();
+        let parts = backend.parts_in_bom(profile_id, bom.id).await.unwrap();
+        assert_eq!(parts[0].designators, ["R1", "R2", "R5", "R12"]);
+        assert!(parts[1].designators.is_empty());
+        backend
+            .stock_parts(profile_id, &parts, -3, String::from("BOM build"))
+            .await
+            .unwrap();
+
+        let stock = backend.list_stock(profile_id).await.unwrap();
+        let resistor = stock.iter().find(|s| s.name == "10k").unwrap();
+        assert_eq!((resistor.stock, resistor.column, resistor.row), (88, 2, 1));
+    }
+
+    #[tokio::test]
+    async fn ledger_records_every_change() {
+        let mut backend = logged_in_backend().await;
+        let profile_id = backend.user_data().profile.as_ref().unwrap().id;
+        backend.new_part(part("100nF", "Capacitor")).await.unwrap();
+        let id = backend.get_parts(None, None).await.unwrap()[0].id;
+        backend
+            .stock_part(profile_id, id, 50, 0, 0, 0)
+            .await
+            .unwrap();
+        backend
+            .adjust_stock(
+                profile_id,
+                id,
+                BinLocation {
+                    column: 0,
+                    row: 0,
+                    z: 0,
+                },
+                -47,
+                String::from("BOM build: Magnet Harvesting x3"),
+            )
+            .await
+            .unwrap();
+
+        let history = backend.stock_history(profile_id, Some(id)).await.unwrap();
+        let changes: Vec<_> = history
+            .iter()
+            .map(|e| (e.delta, e.stock, e.reason.as_str(), e.user.as_str()))
+            .collect();
+        assert_eq!(
+            changes,
+            vec![
+                (
+                    -47,
+                    3,
+                    "BOM build: Magnet Harvesting x3",
+                    "test@example.com"
+                ),
+                (50, 50, "stock set", "test@example.com"),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn cancelled_builds_release_reservations() {
+        let mut backend = logged_in_backend().await;
+        let profile_id = backend.user_data().profile.as_ref().unwrap().id;
+        backend
+            .new_bom(
+                profile_id,
+                String::from("Sensor"),
+                String::new(),
+                vec![
+                    BomLine::new(2, part("10k", "Resistor")),
+                    BomLine::new(1, part("100nF", "Capacitor")),
+                ],
+                None,
+            )
+            .await
+            .unwrap();
+        let bom = backend.list_boms(profile_id, None, None).await.unwrap()[0].clone();
+        let first = backend
+            .new_build_order(profile_id, bom.id, 3, None)
+            .await
+            .unwrap();
+        backend
+            .new_build_order(profile_id, bom.id, 1, None)
+            .await
+            .unwrap();
+        let reserved: Vec<i64> = backend
+            .reservations(profile_id)
+            .await
+            .unwrap()
+            .iter()
+            .map(|r| r.reserved)
+            .collect();
+        assert_eq!(reserved, vec![8, 4]);
+
+        backend
+            .set_build_status(profile_id, first.id, BuildStatus::Cancelled)
+            .await
+            .unwrap();
+        let orders = backend.list_build_orders(profile_id).await.unwrap();
+        assert_eq!(orders[0].status, BuildStatus::Cancelled);
+        assert_eq!(orders[0].bom_name, "Sensor");
+        assert_eq!(
+            backend.reservations(profile_id).await.unwrap()[0].reserved,
+            2
+        );
+    }
+
+    #[tokio::test]
+    async fn revisions_link_to_the_previous_bom() {
+        let mut backend = logged_in_backend().await;
+        let profile_id = backend.user_data().profile.as_ref().unwrap().id;
+        for revision_of in [None, Some(1), Some(2)] {
+            backend
+                .new_bom(
+                    profile_id,
+                    String::from("Sensor"),
+                    String::new(),
+                    vec![],
+                    revision_of,
+                )
+                .await
+                .unwrap();
+        }
+        let boms = backend.list_boms(profile_id, None, None).await.unwrap();
+        let revisions: Vec<(i64, i64, Option<i64>)> = boms
+            .iter()
+            .map(|b| (b.id, b.revision, b.revision_of))
+            .collect();
+        assert_eq!(revisions, [(1, 1, None), (2, 2, Some(1)), (3, 3, Some(2))]);
+    }
+
+    #[tokio::test]
+    async fn parts_in_use_cant_be_deleted() {
+        let mut backend = logged_in_backend().await;
+        let profile_id = backend.user_data().profile.as_ref().unwrap().id;
+        backend.new_part(part("10k", "Resistor")).await.unwrap();
+        let resistor = backend.get_parts(None, None).await.unwrap()[0].clone();
+        backend
+            .stock_part(profile_id, resistor.id, 10, 1, 1, 0)
+            .await
+            .unwrap();
+        backend
+            .new_bom(
+                profile_id,
+                String::from("Sensor"),
+                String::new(),
+                vec![BomLine::new(2, resistor.clone())],
+                None,
+            )
+            .await
+            .unwrap();
+
+        assert!(matches!(
+            backend.delete_part(resistor.id).await,
+            Err(NetworkError::Conflict(_))
+        ));
+        assert!(matches!(
+            backend.update_bom_line(profile_id, 1, resistor.id, 0).await,
+            Err(NetworkError::Validation { .. })
+        ));
+        backend
+            .update_bom_line(profile_id, 1, resistor.id, 3)
+            .await
+            .unwrap();
+        assert_eq!(
+            backend.parts_in_bom(profile_id, 1).await.unwrap()[0].count,
+            3
+        );
+        backend
+            .delete_bom_line(profile_id, 1, resistor.id)
+            .await
+            .unwrap();
+        assert!(matches!(
+            backend.delete_part(resistor.id).await,
+            Err(NetworkError::Conflict(_))
+        ));
+
+        backend
+            .remove_from_bin(profile_id, resistor.id)
+            .await
+            .unwrap();
+        let history = backend
+            .stock_history(profile_id, Some(resistor.id))
+            .await
+            .unwrap();
+        assert_eq!((history[0].delta, history[0].stock), (-10, 0));
+        backend.delete_part(resistor.id).await.unwrap();
+        assert!(backend.get_parts(None, None).await.unwrap().is_empty());
+        backend.delete_bom(profile_id, 1).await.unwrap();
+        assert!(
+            backend
+                .list_boms(profile_id, None, None)
+                .await
+                .unwrap()
+                .is_empty()
+        );
+    }
+
+    #[tokio::test]
+    async fn merging_combines_stock_and_bom_lines() {
+        let mut backend = logged_in_backend().await;
+        let profile_id = backend.user_data().profile.as_ref().unwrap().id;
+        for name in ["LTC3531", "LTC3531EDD", "ltc3531 "] {
+            backend.new_part(part(name, "Buck-boost")).await.unwrap();
+        }
+        backend.stock_part(profile_id, 1, 5, 1, 1, 0).await.unwrap();
+        backend.stock_part(profile_id, 2, 3, 4, 2, 0).await.unwrap();
+        let parts = backend.get_parts(None, None).await.unwrap();
+        backend
+            .new_bom(
+                profile_id,
+                String::from("Harvester"),
+                String::new(),
+                vec![
+                    BomLine {
+                        designators: vec![String::from("U1")],
+                        ..BomLine::new(1, parts[0].clone())
+                    },
+                    BomLine {
+                        designators: vec![String::from("U2")],
+                        ..BomLine::new(1, parts[2].clone())
+                    },
+                ],
+                None,
+            )
+            .await
+            .unwrap();
+
+        let merged = backend.merge_parts(1, vec![2, 3]).await.unwrap();
+        assert_eq!(merged.aliases, ["LTC3531EDD", "ltc3531 "]);
+        assert_eq!(backend.get_parts(None, None).await.unwrap().len(), 1);
+        let stock = backend.list_stock(profile_id).await.unwrap();
+        assert_eq!((stock[0].stock, stock[0].column, stock[0].row), (8, 1, 1));
+        let lines = backend.parts_in_bom(profile_id, 1).await.unwrap();
+        assert_eq!(lines.len(), 1);
+        assert_eq!(
+            (lines[0].count, lines[0].designators.clone()),
+            (2, vec![String::from("U1"), String::from("U2")])
+        );
+        // Both stockings, the merged part's one included, adding up to the merged stock
+        let history = backend.stock_history(profile_id, Some(1)).await.unwrap();
+        assert_eq!(history.len(), 2);
+        assert_eq!(history.iter().map(|e| e.delta).sum::<i64>(), 8);
+        assert_eq!(history[0].stock, 8);
+
+        // Imports link the old names to the kept part
+        let existing = backend
+            .get_parts(Some(String::from("EDD")), None)
+            .await
+            .unwrap();
+        let mut lines = vec![BomLine::new(1, part("LTC3531EDD", ""))];
+        link_existing(&mut lines, &existing);
+        assert_eq!(lines[0].part.id, 1);
+    }
+
+    #[tokio::test]
+    async fn variants_reserve_and_deplete_their_own_counts() {
+        let mut backend = logged_in_backend().await;
+        let profile_id = backend.user_data().profile.as_ref().unwrap().id;
+        backend
+            .new_bom(
+                profile_id,
+                String::from("Amplifier"),
+                String::new(),
+                vec![
+                    BomLine {
+                        variants: vec![VariantCount {
+                            variant: String::from("Lite"),
+                            count: 0,
+                        }],
+                        ..BomLine::new(2, part("OPA2134", "Op amp"))
+                    },
+                    BomLine::new(4, part("10k", "Resistor")),
+                ],
+                None,
+            )
+            .await
+            .unwrap();
+        let bom = backend.list_boms(profile_id, None, None).await.unwrap()[0].clone();
+        assert_eq!(bom.variants, ["Lite"]);
+        assert!(
+            backend
+                .new_build_order(profile_id, bom.id, 1, Some(String::from("Pro")))
+                .await
+                .is_err()
+        );
+
+        let order = backend
+            .new_build_order(profile_id, bom.id, 3, Some(String::from("Lite")))
+            .await
+            .unwrap();
+        let reserved: Vec<i64> = backend
+            .reservations(profile_id)
+            .await
+            .unwrap()
+            .iter()
+            .map(|r| r.reserved)
+            .collect();
+        assert_eq!(reserved, vec![0, 12]);
+
+        let parts = backend.parts_in_bom(profile_id, bom.id).await.unwrap();
+        backend
+            .stock_parts(profile_id, &parts, 10, String::from("restock"))
+            .await
+            .unwrap();
+        backend
+            .set_build_status(profile_id, order.id, BuildStatus::Built)
+            .await
+            .unwrap();
+        let stock: Vec<(String, i64)> = backend
+            .list_stock(profile_id)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|s| (s.name, s.stock))
+            .collect();
+        assert!(stock.contains(&(String::from("OPA2134"), 20)));
+        assert!(stock.contains(&(String::from("10k"), 28)));
+    }
+
+    #[tokio::test]
+    async fn short_lines_draw_from_alternates_in_order() {
+        let mut backend = logged_in_backend().await;
+        let profile_id = backend.user_data().profile.as_ref().unwrap().id;
+        backend
+            .new_bom(
+                profile_id,
+                String::from("Sensor"),
+                String::new(),
+                vec![
+                    BomLine::new(4, part("100nF Murata", "Capacitor 0402")),
+                    BomLine::new(1, part("10k", "Resistor")),
+                ],
+                None,
+            )
+            .await
+            .unwrap();
+        for name in ["100nF Yageo", "100nF Samsung"] {
+            backend
+                .new_part(part(name, "Capacitor 0402"))
+                .await
+                .unwrap();
+        }
+        let ids: Vec<i64> = backend
+            .get_parts(None, None)
+            .await
+            .unwrap()
+            .iter()
+            .map(|p| p.id)
+            .collect();
+        let (murata, resistor, yageo, samsung) = (ids[0], ids[1], ids[2], ids[3]);
+        for (i, (part_id, stock)) in [(murata, 5), (resistor, 3), (yageo, 6), (samsung, 10)]
+            .into_iter()
+            .enumerate()
+        {
+            backend
+                .stock_part(profile_id, part_id, stock, 1, i as i64, 0)
+                .await
+                .unwrap();
+        }
+        let bom = backend.list_boms(profile_id, None, None).await.unwrap()[0].clone();
+        assert!(
+            backend
+                .set_bom_line_alternates(profile_id, bom.id, murata, vec![murata])
+                .await
+                .is_err()
+        );
+        backend
+            .set_bom_line_alternates(profile_id, bom.id, murata, vec![yageo])
+            .await
+            .unwrap();
+        backend
+            .set_part_alternates(murata, vec![samsung, yageo])
+            .await
+            .unwrap();
+        let alternates: Vec<String> = backend.parts_in_bom(profile_id, bom.id).await.unwrap()[0]
+            .alternates
+            .iter()
+            .map(|a| a.name.clone())
+            .collect();
+        assert_eq!(alternates, ["100nF Yageo", "100nF Samsung"]);
+
+        // 12 capacitors: 5 of its own, then 6 from the BOM's alternate and 1 from the part's
+        let order = backend
+            .new_build_order(profile_id, bom.id, 3, None)
+            .await
+            .unwrap();
+        backend
+            .set_build_status(profile_id, order.id, BuildStatus::Built)
+            .await
+            .unwrap();
+        let stock: Vec<(String, i64)> = backend
+            .list_stock(profile_id)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|s| (s.name, s.stock))
+            .collect();
+        assert!(stock.contains(&(String::from("100nF Murata"), 0)));
+        assert!(stock.contains(&(String::from("100nF Yageo"), 0)));
+        assert!(stock.contains(&(String::from("100nF Samsung"), 9)));
+        assert!(stock.contains(&(String::from("10k"), 0)));
+    }
+
+    #[tokio::test]
+    async fn builds_leave_alternates_other_orders_hold() {
+        let mut backend = logged_in_backend().await;
+        let profile_id = backend.user_data().profile.as_ref().unwrap().id;
+        backend
+            .new_bom(
+                profile_id,
+                String::from("Sensor"),
+                String::new(),
+                vec![BomLine::new(4, part("100nF Murata", "Capacitor 0402"))],
+                None,
+            )
+            .await
+            .unwrap();
+        for name in ["100nF Yageo", "100nF Samsung"] {
+            backend
+                .new_part(part(name, "Capacitor 0402"))
+                .await
+                .unwrap();
+        }
+        let ids: Vec<i64> = backend
+            .get_parts(None, None)
+            .await
+            .unwrap()
+            .iter()
+            .map(|p| p.id)
+            .collect();
+        let (murata, yageo, samsung) = (ids[0], ids[1], ids[2]);
+        let yageo_part = backend.get_parts(None, None).await.unwrap()[1].clone();
+        backend
+            .new_bom(
+                profile_id,
+                String::from("Filter"),
+                String::new(),
+                vec![BomLine::new(2, yageo_part)],
+                None,
+            )
+            .await
+            .unwrap();
+        for (i, (part_id, stock)) in [(murata, 2), (yageo, 6), (samsung, 10)]
+            .into_iter()
+            .enumerate()
+        {
+            backend
+                .stock_part(profile_id, part_id, stock, 1, i as i64, 0)
+                .await
+                .unwrap();
+        }
+        let boms = backend.list_boms(profile_id, None, None).await.unwrap();
+        let sensor = boms.iter().find(|b| b.name == "Sensor").unwrap().id;
+        let filter = boms.iter().find(|b| b.name == "Filter").unwrap().id;
+        backend
+            .set_bom_line_alternates(profile_id, sensor, murata, vec![yageo, samsung])
+            .await
+            .unwrap();
+
+        // The filters hold 4 of the Yageo ones, so the sensor only gets the 2 left
+        backend
+            .new_build_order(profile_id, filter, 2, None)
+            .await
+            .unwrap();
+        let order = backend
+            .new_build_order(profile_id, sensor, 2, None)
+            .await
+            .unwrap();
+        backend
+            .set_build_status(profile_id, order.id, BuildStatus::Built)
+            .await
+            .unwrap();
+        let stock: Vec<(String, i64)> = backend
+            .list_stock(profile_id)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|s| (s.name, s.stock))
+            .collect();
+        assert!(stock.contains(&(String::from("100nF Murata"), 0)));
+        assert!(stock.contains(&(String::from("100nF Yageo"), 4)));
+        assert!(stock.contains(&(String::from("100nF Samsung"), 6)));
+    }
+
+    #[tokio::test]
+    async fn where_used_lists_every_bom_with_the_part() {
+        let mut backend = logged_in_backend().await;
+        let profile_id = backend.user_data().profile.as_ref().unwrap().id;
+        backend
+            .new_bom(
+                profile_id,
+                String::from("Amplifier"),
+                String::new(),
+                vec![
+                    BomLine::new(2, part("OPA2134", "Op amp")),
+                    BomLine::new(4, part("10k", "Resistor")),
+                ],
+                None,
+            )
+            .await
+            .unwrap();
+        let resistor = backend
+            .get_parts(Some(String::from("10k")), None)
+            .await
+            .unwrap()[0]
+            .clone();
+        backend
+            .new_bom(
+                profile_id,
+                String::from("Sensor"),
+                String::new(),
+                vec![BomLine {
+                    variants: vec![VariantCount {
+                        variant: String::from("Lite"),
+                        count: 0,
+                    }],
+                    ..BomLine::new(3, resistor.clone())
+                }],
+                None,
+            )
+            .await
+            .unwrap();
+
+        let usages = backend.where_used(profile_id, resistor.id).await.unwrap();
+        let counts: Vec<(&str, i64)> = usages
+            .iter()
+            .map(|u| (u.bom_name.as_str(), u.count))
+            .collect();
+        assert_eq!(counts, [("Amplifier", 4), ("Sensor", 3)]);
+        assert_eq!(usages[0].boards(10, None), Some(2));
+        assert_eq!(usages[1].boards(10, None), Some(3));
+        assert_eq!(usages[1].boards(10, Some("Lite")), None);
+        assert!(backend.where_used(profile_id, 999).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn purchase_rules_outlive_part_edits() {
+        let mut backend = logged_in_backend().await;
+        let profile_id = backend.user_data().profile.as_ref().unwrap().id;
+        backend.new_part(part("10k", "Resistor")).await.unwrap();
+        let resistor = backend.get_parts(None, None).await.unwrap()[0].clone();
+        let rules = PurchaseRules {
+            moq: 10,
+            order_multiple: 100,
+            safety_stock: 50,
+            attrition_percent: 2.5,
+        };
+        backend
+            .set_purchase_rules(resistor.id, rules)
+            .await
+            .unwrap();
+        backend
+            .update_part(Part {
+                description: String::from("0603 resistor"),
+                ..resistor.clone()
+            })
+            .await
+            .unwrap();
+        assert_eq!(
+            backend.get_parts(None, None).await.unwrap()[0].purchasing,
+            rules
+        );
+        let stocked = backend
+            .parts_with_stock(None, None, profile_id)
+            .await
+            .unwrap();
+        assert_eq!(stocked[0].purchasing, rules);
+
+        let invalid = PurchaseRules {
+            attrition_percent: 150.0,
+            ..rules
+        };
+        assert!(matches!(
+            backend.set_purchase_rules(resistor.id, invalid).await,
+            Err(NetworkError::Validation { .. })
+        ));
+        assert!(matches!(
+            backend.set_purchase_rules(999, rules).await,
+            Err(NetworkError::NotFound(_))
+        ));
+    }
+}
@@ -0,0 +1,240 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use tracing::error;
+
+/// Headers holding the part name, best first. Compared after [`normalize`].
+const NAME_HEADERS: &[&str] = &[
+    "name",
+    "partnumber",
+    "comment",
+    "value",
+    "mpn",
+    "manufacturerpartnumber",
+    "manufacturerpartnumber1",
+    "part",
+    "partname",
+];
+const DESCRIPTION_HEADERS: &[&str] = &[
+    "description",
+    "desc",
+    "partdescription",
+    "footprint",
+    "package",
+    "libref",
+];
+const COUNT_HEADERS: &[&str] = &[
+    "quantity",
+    "qty",
+    "designatorcount",
+    "count",
+    "amount",
+    "pcs",
+];
+
+/// Which columns of a BOM table hold the name, description and count of each line
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ColumnMapping {
+    pub name: String,
+    pub description: String,
+    pub count: String,
+}
+
+/// Lowercase letters and digits only, so "Qty." and "QTY" or "Part Number" and "Part_Number"
+/// compare equal
+pub fn normalize(header: &str) -> String {
+    header
+        .chars()
+        .filter(|c| c.is_alphanumeric())
+        .flat_map(char::to_lowercase)
+        .collect()
+}
+
+/// Picks the name, description and count columns by their headers. Exact matches win over
+/// headers that merely contain a known one, like "Qty per board".
+pub fn guess_columns(headers: &[String]) -> Option<ColumnMapping> {
+    let normalized: Vec<String> = headers.iter().map(|h| normalize(h)).collect();
+    let mut taken: Vec<usize> = vec![];
+    let mut pick = |candidates: &[&str]| {
+        let free = |i: &usize| !taken.contains(i);
+        let found = candidates
+            .iter()
+            .find_map(|c| {
+                (0..headers.len())
+                    .filter(free)
+                    .find(|i| normalized[*i] == *c)
+            })
+            .or_else(|| {
+                candidates.iter().find_map(|c| {
+                    (0..headers.len())
+                        .filter(free)
+                        .find(|i| normalized[*i].contains(c))
+                })
+            })?;
+        taken.push(found);
+        Some(headers[found].clone())
+    };
+    let count = pick(COUNT_HEADERS)?;
+    let name = pick(NAME_HEADERS)?;
+    let description = pick(DESCRIPTION_HEADERS)?;
+    Some(ColumnMapping {
+        name,
+        description,
+        count,
+    })
+}
+
+/// A mapping the user settled on for BOMs with these headers, e.g. one supplier's export
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MappingPreset {
+    pub headers: Vec<String>,
+    pub mapping: ColumnMapping,
+}
+
+impl MappingPreset {
+    fn fits(&self, headers: &[String]) -> bool {
+        [
+            &self.mapping.name,
+            &self.mapping.description,
+            &self.mapping.count,
+        ]
+        .iter()
+        .all(|column| headers.contains(column))
+    }
+
+    /// Share of headers the two tables have in common
+    fn similarity(&self, headers: &[String]) -> f64 {
+        let shared = headers.iter().filter(|h| self.headers.contains(h)).count();
+        let all = self.headers.len() + headers.len() - shared;
+        shared as f64 / all.max(1) as f64
+    }
+}
+
+/// Column mappings remembered between imports, persisted like the journal
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MappingPresets {
+    #[serde(skip)]
+    path: Option<PathBuf>,
+    presets: Vec<MappingPreset>,
+}
+
+impl MappingPresets {
+    pub fn open(path: &Path) -> Self {
+        let mut presets: MappingPresets = match std::fs::read_to_string(path) {
+            Ok(contents) => serde_json::from_str(&contents).unwrap_or_else(|e| {
+                error!("Couldnt parse mapping presets {}: {}", path.display(), e);
+                MappingPresets::default()
+            }),
+            Err(_) => MappingPresets::default(),
+        };
+        presets.path = Some(path.to_path_buf());
+        presets
+    }
+
+    /// Presets that are never written to disk
+    pub fn in_memory() -> Self {
+        Self::default()
+    }
+
+    pub fn presets(&self) -> &[MappingPreset] {
+        &self.presets
+    }
+
+    /// The mapping saved for the most similar table whose columns this one has too
+    pub fn suggest(&self, headers: &[String]) -> Option<ColumnMapping> {
+        self.presets
+            .iter()
+            .filter(|p| p.fits(headers))
+            .max_by(|a, b| a.similarity(headers).total_cmp(&b.similarity(headers)))
+            .map(|p| p.mapping.clone())
+    }
+
+    /// Saves the mapping used for a table, replacing the one for the same headers
+    pub fn remember(&mut self, headers: &[String], mapping: ColumnMapping) {
+        self.presets.retain(|p| p.headers != headers);
+        self.presets.push(MappingPreset {
+            headers: headers.to_vec(),
+            mapping,
+        });
+        if let Err(e) = self.save() {
+            error!("Couldnt save mapping presets: {}", e);
+        }
+    }
+
+    fn save(&self) -> Result<()> {
+        if let Some(path) = &self.path {
+            std::fs::write(path, serde_json::to_string(&self)?)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ColumnMapping, MappingPresets, guess_columns};
+
+    fn headers(names: &[&str]) -> Vec<String> {
+        names.iter().map(|n| n.to_string()).collect()
+    }
+
+    fn mapping(name: &str, description: &str, count: &str) -> ColumnMapping {
+        ColumnMapping {
+            name: name.to_string(),
+            description: description.to_string(),
+            count: count.to_string(),
+        }
+    }
+
+    #[test]
+    fn guesses_common_exports() {
+        assert_eq!(
+            guess_columns(&headers(&["Refs", "Value", "Footprint", "Qty", "DNP"])),
+            Some(mapping("Value", "Footprint", "Qty"))
+        );
+        assert_eq!(
+            guess_columns(&headers(&[
+                "Comment",
+                "Description",
+                "Designator",
+                "Footprint",
+                "LibRef",
+                "Quantity"
+            ])),
+            Some(mapping("Comment", "Description", "Quantity"))
+        );
+        assert_eq!(
+            guess_columns(&headers(&[
+                "Part_Number",
+                "Part Description",
+                "QTY per board"
+            ])),
+            Some(mapping("Part_Number", "Part Description", "QTY per board"))
+        );
+        assert_eq!(guess_columns(&headers(&["Ref", "Value"])), None);
+    }
+
+    #[test]
+    fn suggests_presets_for_similar_tables() {
+        let mut presets = MappingPresets::in_memory();
+        let supplier = headers(&["Item", "Cust PN", "Desc", "Qty", "Price"]);
+        presets.remember(&supplier, mapping("Cust PN", "Desc", "Qty"));
+        assert_eq!(
+            presets.suggest(&supplier),
+            Some(mapping("Cust PN", "Desc", "Qty"))
+        );
+        // The next order from the same supplier has an extra column
+        let next = headers(&["Item", "Cust PN", "Desc", "Qty", "Price", "Lead time"]);
+        assert_eq!(
+            presets.suggest(&next),
+            Some(mapping("Cust PN", "Desc", "Qty"))
+        );
+        assert_eq!(presets.suggest(&headers(&["Name", "Qty"])), None);
+
+        presets.remember(&supplier, mapping("Item", "Desc", "Qty"));
+        assert_eq!(presets.presets().len(), 1);
+    }
+}
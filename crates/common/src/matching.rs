@@ -0,0 +1,443 @@
+use std::{
+    fmt::Display,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use tracing::error;
+
+use crate::{mapping::normalize, models::Part};
+
+/// Most suggestions offered for one BOM line
+const MAX_SUGGESTIONS: usize = 5;
+/// Names at least this similar are suggested even if they aren't equal
+const MIN_NAME_SIMILARITY: f64 = 0.6;
+/// Parameters alone only make parts duplicates when they agree on this many fields
+const MIN_DUPLICATE_PARAMETERS: usize = 2;
+
+/// Why an existing part was suggested for a BOM line, strongest first
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum MatchKind {
+    /// Same manufacturer or supplier part number
+    PartNumber,
+    /// Same name up to case, spacing and punctuation
+    Name,
+    /// Similar name, with no parameters telling them apart
+    SimilarName,
+    /// Same value and no conflicting package or ratings
+    Parameters,
+}
+
+impl MatchKind {
+    /// Matches that can be linked without asking when no other part matches as well
+    pub fn is_certain(&self) -> bool {
+        matches!(self, MatchKind::PartNumber | MatchKind::Name)
+    }
+}
+
+impl Display for MatchKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            MatchKind::PartNumber => "part number",
+            MatchKind::Name => "name",
+            MatchKind::SimilarName => "similar name",
+            MatchKind::Parameters => "parameters",
+        })
+    }
+}
+
+/// An existing part that a BOM line might describe
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MatchSuggestion {
+    pub part: Part,
+    pub kind: MatchKind,
+    /// How close the match is within its kind, from 0 to 1
+    pub score: f64,
+}
+
+/// Existing parts that might be the one a BOM line describes, best first
+pub fn suggest_matches(candidate: &Part, existing: &[Part]) -> Vec<MatchSuggestion> {
+    let mut out: Vec<MatchSuggestion> = existing
+        .iter()
+        .filter_map(|part| {
+            rate(candidate, part).map(|(kind, score)| MatchSuggestion {
+                part: part.clone(),
+                kind,
+                score,
+            })
+        })
+        .collect();
+    out.sort_by(|a, b| a.kind.cmp(&b.kind).then(b.score.total_cmp(&a.score)));
+    out.truncate(MAX_SUGGESTIONS);
+    out
+}
+
+/// Parts that look like one part entered several times, e.g. by imports that didn't link to it
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DuplicateGroup {
+    /// Oldest first, which is the one suggested to keep
+    pub parts: Vec<Part>,
+    /// The strongest reason any of them looks like the oldest one
+    pub kind: MatchKind,
+}
+
+/// Groups of parts that are probably the same part: the same part number, the same name up to
+/// case and spacing, similar names or fully agreeing parameters. Strongest groups first.
+pub fn find_duplicates(parts: &[Part], dismissed: &DismissedDuplicates) -> Vec<DuplicateGroup> {
+    let mut parts: Vec<&Part> = parts.iter().collect();
+    parts.sort_by_key(|p| p.id);
+    // The oldest part left anchors the next group. Members have to look like the anchor itself,
+    // looking like another member isn't enough: "LM358 opamp" is like "LM358" and like "opamp
+    // LM358 dual", but those two are nothing alike.
+    let mut grouped = vec![false; parts.len()];
+    let mut out = vec![];
+    for (i, anchor) in parts.iter().enumerate() {
+        if grouped[i] {
+            continue;
+        }
+        let mut members = vec![(*anchor).clone()];
+        let mut strongest: Option<MatchKind> = None;
+        for (j, part) in parts.iter().enumerate().skip(i + 1) {
+            if grouped[j] || dismissed.contains(anchor.id, part.id) {
+                continue;
+            }
+            let Some(kind) = duplicate_kind(anchor, part) else {
+                continue;
+            };
+            grouped[j] = true;
+            members.push((*part).clone());
+            strongest = Some(strongest.map_or(kind, |s| s.min(kind)));
+        }
+        if let Some(kind) = strongest {
+            out.push(DuplicateGroup {
+                parts: members,
+                kind,
+            });
+        }
+    }
+    out.sort_by(|a, b| {
+        a.kind
+            .cmp(&b.kind)
+            .then(a.parts[0].name.cmp(&b.parts[0].name))
+    });
+    out
+}
+
+fn duplicate_kind(a: &Part, b: &Part) -> Option<MatchKind> {
+    let kind = [rate(a, b), rate(b, a)]
+        .into_iter()
+        .flatten()
+        .map(|(kind, _)| kind)
+        .min()?;
+    if kind == MatchKind::Parameters {
+        // Plenty of different parts share a value, so parameters have to agree on everything
+        let covered = |x: &Part, y: &Part| {
+            x.parameters.len() >= MIN_DUPLICATE_PARAMETERS
+                && x.parameters.agreement(&y.parameters) == Some(1.0)
+        };
+        if !(covered(a, b) && covered(b, a)) {
+            return None;
+        }
+    }
+    Some(kind)
+}
+
+/// Pairs of parts the user said are not duplicates, remembered like the column mappings
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DismissedDuplicates {
+    #[serde(skip)]
+    path: Option<PathBuf>,
+    /// Part ids, the smaller one first
+    pairs: Vec<(i64, i64)>,
+}
+
+impl DismissedDuplicates {
+    pub fn open(path: &Path) -> Self {
+        let mut dismissed: DismissedDuplicates = match std::fs::read_to_string(path) {
+            Ok(contents) => serde_json::from_str(&contents).unwrap_or_else(|e| {
+                error!(
+                    "Couldnt parse dismissed duplicates {}: {}",
+                    path.display(),
+                    e
+                );
+                DismissedDuplicates::default()
+            }),
+            Err(_) => DismissedDuplicates::default(),
+        };
+        dismissed.path = Some(path.to_path_buf());
+        dismissed
+    }
+
+    /// Dismissals that are never written to disk
+    pub fn in_memory() -> Self {
+        Self::default()
+    }
+
+    pub fn contains(&self, a: i64, b: i64) -> bool {
+        self.pairs.contains(&(a.min(b), a.max(b)))
+    }
+
+    /// Remembers that no two of `parts` are the same part
+    pub fn dismiss(&mut self, parts: &[i64]) {
+        for (i, a) in parts.iter().enumerate() {
+            for b in &parts[i + 1..] {
+                if !self.contains(*a, *b) {
+                    self.pairs.push((*a.min(b), *a.max(b)));
+                }
+            }
+        }
+        if let Err(e) = self.save() {
+            error!("Couldnt save dismissed duplicates: {}", e);
+        }
+    }
+
+    fn save(&self) -> Result<()> {
+        if let Some(path) = &self.path {
+            std::fs::write(path, serde_json::to_string(&self)?)?;
+        }
+        Ok(())
+    }
+}
+
+/// The part to link without asking: a part number or name match no other part ties with
+pub fn certain_match(suggestions: &[MatchSuggestion]) -> Option<&Part> {
+    match suggestions {
+        [best, rest @ ..] if best.kind.is_certain() => rest
+            .first()
+            .is_none_or(|next| next.kind > best.kind)
+            .then_some(&best.part),
+        _ => None,
+    }
+}
+
+/// Whether the user has to decide what a BOM line links to, because several parts match it
+/// equally well or only loosely
+pub fn is_ambiguous(suggestions: &[MatchSuggestion]) -> bool {
+    !suggestions.is_empty() && certain_match(suggestions).is_none()
+}
+
+fn rate(candidate: &Part, part: &Part) -> Option<(MatchKind, f64)> {
+    let same_mpn = !candidate.mpn.is_empty() && candidate.mpn.eq_ignore_ascii_case(&part.mpn);
+    if same_mpn || part.skus.iter().any(|s| candidate.skus.contains(s)) {
+        return Some((MatchKind::PartNumber, 1.0));
+    }
+    // Different part numbers are different parts, whatever they are called
+    if !candidate.mpn.is_empty() && !part.mpn.is_empty() {
+        return None;
+    }
+    let name = normalize(&candidate.name);
+    // Parts merged into another one still match by their old names
+    let others: Vec<String> = std::iter::once(&part.name)
+        .chain(&part.aliases)
+        .map(|n| normalize(n))
+        .collect();
+    if !name.is_empty() && others.contains(&name) {
+        return Some((MatchKind::Name, 1.0));
+    }
+    // "10k" and "100k" have similar names but are not the same resistor
+    let agreement = candidate.parameters.agreement(&part.parameters);
+    let conflicting =
+        agreement.is_none() && !candidate.parameters.is_empty() && !part.parameters.is_empty();
+    if conflicting {
+        return None;
+    }
+    // Names with different numbers in them are different parts, like LTC3531 and LTC3533
+    let numbers = digits(&name);
+    let similarity = others
+        .iter()
+        .filter(|other| digits(other) == numbers)
+        .map(|other| similarity(&name, other))
+        .fold(0.0, f64::max);
+    if similarity >= MIN_NAME_SIMILARITY {
+        return Some((MatchKind::SimilarName, similarity));
+    }
+    agreement.map(|a| (MatchKind::Parameters, a))
+}
+
+/// The digits of a name, in order
+fn digits(name: &str) -> String {
+    name.chars().filter(char::is_ascii_digit).collect()
+}
+
+/// Dice coefficient of the character pairs of two strings
+fn similarity(a: &str, b: &str) -> f64 {
+    let pairs = |s: &str| -> Vec<(char, char)> {
+        let chars: Vec<char> = s.chars().collect();
+        chars.windows(2).map(|w| (w[0], w[1])).collect()
+    };
+    let (a, mut b) = (pairs(a), pairs(b));
+    let total = a.len() + b.len();
+    if total == 0 {
+        return 0.0;
+    }
+    let mut shared = 0;
+    for pair in a {
+        if let Some(i) = b.iter().position(|p| *p == pair) {
+            b.swap_remove(i);
+            shared += 1;
+        }
+    }
+    2.0 * shared as f64 / total as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::models::{Part, SupplierSku};
+
+    use super::{
+        DismissedDuplicates, MatchKind, certain_match, find_duplicates, is_ambiguous,
+        suggest_matches,
+    };
+
+    fn part(id: i64, name: &str, description: &str, mpn: &str) -> Part {
+        let mut part = Part {
+            id,
+            name: name.to_string(),
+            description: description.to_string(),
+            mpn: mpn.to_string(),
+            ..Default::default()
+        };
+        part.parse_parameters();
+        part
+    }
+
+    #[test]
+    fn ranks_part_numbers_over_names() {
+        let existing = vec![
+            part(1, "10k", "Resistor 0603", ""),
+            part(
+                2,
+                "RC0603FR-0710KL",
+                "Resistor 10k 1% 0603",
+                "RC0603FR-0710KL",
+            ),
+            part(3, "100k", "Resistor 0603", ""),
+            part(4, "ERJ-3EKF1002V", "Resistor 10k 0603", "ERJ-3EKF1002V"),
+        ];
+        let candidate = part(0, "10K", "Resistor 0603", "rc0603fr-0710kl");
+        let suggestions = suggest_matches(&candidate, &existing);
+        let ranked: Vec<(i64, MatchKind)> =
+            suggestions.iter().map(|s| (s.part.id, s.kind)).collect();
+        // The other 10k resistor has a different part number and 100k a different value
+        assert_eq!(ranked, [(2, MatchKind::PartNumber), (1, MatchKind::Name)]);
+        assert_eq!(certain_match(&suggestions).map(|p| p.id), Some(2));
+        assert!(!is_ambiguous(&suggestions));
+
+        let by_sku = Part {
+            skus: vec![SupplierSku {
+                supplier: "LCSC".to_string(),
+                sku: "C25804".to_string(),
+            }],
+            ..part(0, "Res", "", "")
+        };
+        let existing = vec![Part {
+            skus: by_sku.skus.clone(),
+            ..part(5, "10k", "", "")
+        }];
+        assert_eq!(
+            certain_match(&suggest_matches(&by_sku, &existing)).map(|p| p.id),
+            Some(5)
+        );
+    }
+
+    #[test]
+    fn flags_ties_and_loose_matches() {
+        let existing = vec![
+            part(1, "100nF", "Capacitor 0402", ""),
+            part(2, "100n F", "Capacitor 0603", ""),
+        ];
+        let tie = suggest_matches(&part(0, "100NF", "", ""), &existing);
+        assert_eq!(tie.len(), 2);
+        assert!(is_ambiguous(&tie));
+
+        let existing = vec![
+            part(1, "LM358 opamp", "", ""),
+            part(2, "4.7uF", "Capacitor 0805", ""),
+        ];
+        let similar = suggest_matches(&part(0, "LM358", "", ""), &existing);
+        assert_eq!(similar[0].kind, MatchKind::SimilarName);
+        assert_eq!(similar.len(), 1);
+        assert!(is_ambiguous(&similar));
+
+        let by_value = suggest_matches(&part(0, "C12", "4u7 X5R 0805", ""), &existing);
+        assert_eq!(by_value[0].kind, MatchKind::Parameters);
+        assert_eq!(by_value[0].part.id, 2);
+
+        assert!(!is_ambiguous(&suggest_matches(
+            &part(0, "Mystery", "", ""),
+            &existing
+        )));
+    }
+
+    fn groups(parts: &[Part], dismissed: &DismissedDuplicates) -> Vec<(MatchKind, Vec<i64>)> {
+        find_duplicates(parts, dismissed)
+            .into_iter()
+            .map(|g| (g.kind, g.parts.iter().map(|p| p.id).collect()))
+            .collect()
+    }
+
+    #[test]
+    fn groups_parts_entered_twice() {
+        let parts = vec![
+            part(1, "LTC3531", "Buck-boost", ""),
+            part(2, "10k", "Resistor 1% 0603", ""),
+            part(3, "LTC3531EDD", "Buck-boost", ""),
+            part(4, "ltc3531 ", "", ""),
+            part(5, "100k", "Resistor 1% 0603", ""),
+            part(6, "RC0603FR-0710KL", "Resistor 10k 1% 0603", ""),
+            part(7, "ERJ-3EKF1002V", "Resistor 10k 0603", "ERJ-3EKF1002V"),
+            part(8, "10k Yageo", "", "erj-3ekf1002v"),
+        ];
+        assert_eq!(
+            groups(&parts, &DismissedDuplicates::in_memory()),
+            [
+                (MatchKind::PartNumber, vec![7, 8]),
+                (MatchKind::Name, vec![1, 3, 4]),
+                (MatchKind::Parameters, vec![2, 6]),
+            ]
+        );
+
+        let merged = super::Part {
+            aliases: vec![String::from("LTC3531EDD")],
+            ..part(1, "LTC3531", "Buck-boost", "")
+        };
+        let suggestions = suggest_matches(&part(0, "ltc3531edd", "", ""), &[merged]);
+        assert_eq!(certain_match(&suggestions).map(|p| p.id), Some(1));
+    }
+
+    #[test]
+    fn groups_parts_alike_the_oldest_one() {
+        let parts = vec![
+            part(1, "LTC3531", "", ""),
+            part(2, "LTC3531EDD", "", ""),
+            part(3, "LTC3533", "", ""),
+            part(4, "LTC3533EDD", "", ""),
+            // Like LM358 opamp, but nothing like LM358
+            part(5, "LM358", "", ""),
+            part(6, "LM358 opamp", "", ""),
+            part(7, "opamp LM358 dual", "", ""),
+        ];
+        let mut dismissed = DismissedDuplicates::in_memory();
+        assert_eq!(
+            groups(&parts, &dismissed),
+            [
+                (MatchKind::SimilarName, vec![5, 6]),
+                (MatchKind::SimilarName, vec![1, 2]),
+                (MatchKind::SimilarName, vec![3, 4]),
+            ]
+        );
+
+        dismissed.dismiss(&[4, 3]);
+        assert!(dismissed.contains(3, 4));
+        assert_eq!(
+            groups(&parts, &dismissed),
+            [
+                (MatchKind::SimilarName, vec![5, 6]),
+                (MatchKind::SimilarName, vec![1, 2]),
+            ]
+        );
+    }
+}
@@ -0,0 +1,1497 @@
+use std::collections::BTreeMap;
+
+use async_trait::async_trait;
+use chrono::Utc;
+
+use crate::{
+    backend::{
+        PartsBackend, REMOVED_FROM_BIN_REASON, STOCK_SET_REASON, build_reason, check_alternates,
+        check_bom_line_count, check_build_transition, check_merge, check_purchase_rules,
+        check_variant, combine_bom_lines, line_alternates, line_variants, merged_part,
+    },
+    buildable::build_stock,
+    error::{BatchFailure, FailedLine, NetworkError},
+    models::{
+        Bin, BinLocation, Bom, BomLine, BomPartRelation, BomUsage, BuildOrder, BuildStatus, Part,
+        PartBinRelation, PartWithCountAndStock, PartWithStock, Profile, ProfileBomRelation,
+        PurchaseRules, Reservation, StockLedgerEntry, StockRows, User, count_in_variant,
+        default_bin_placement, draw_with_alternates, drawn_parts, first_revision, select_variant,
+    },
+    network::UserData,
+};
+
+#[derive(Debug, Clone)]
+struct StockEntry {
+    relation: PartBinRelation,
+    stock: i64,
+}
+
+/// A [`PartsBackend`] that keeps the whole inventory in memory. Mirrors the semantics of the
+/// server closely enough to exercise the GUI and CLI without a network connection.
+#[derive(Debug, Default)]
+pub struct MemoryBackend {
+    pub user_data: UserData,
+    /// Makes every request fail the way an unreachable server does
+    pub unreachable: bool,
+    users: Vec<User>,
+    profiles: Vec<Profile>,
+    parts: Vec<Part>,
+    bins: Vec<Bin>,
+    stock: Vec<StockEntry>,
+    boms: Vec<Bom>,
+    profile_boms: Vec<ProfileBomRelation>,
+    bom_parts: Vec<BomPartRelation>,
+    /// Stock ledger entries along with the profile they belong to
+    ledger: Vec<(i64, StockLedgerEntry)>,
+    /// Build orders along with the profile they belong to
+    build_orders: Vec<(i64, BuildOrder)>,
+    last_id: i64,
+}
+
+/// Points alternates at `merged` parts to `keep` instead, dropping the ones that end up listing
+/// `owner` itself or a part twice
+fn replace_merged_alternates(alternates: &mut Vec<i64>, owner: i64, merged: &[i64], keep: i64) {
+    let mut replaced: Vec<i64> = vec![];
+    for alternate in alternates.iter() {
+        let alternate = if merged.contains(alternate) {
+            keep
+        } else {
+            *alternate
+        };
+        if alternate != owner && !replaced.contains(&alternate) {
+            replaced.push(alternate);
+        }
+    }
+    *alternates = replaced;
+}
+
+fn contains_ignore_case(haystack: &str, needle: &str) -> bool {
+    haystack.to_lowercase().contains(&needle.to_lowercase())
+}
+
+/// Matches if any of the given filters match, the same way the search bar queries both name and
+/// description with the same string. The name filter also finds part numbers.
+fn matches_filters(part: &Part, name: &Option<String>, description: &Option<String>) -> bool {
+    if name.is_none() && description.is_none() {
+        return true;
+    }
+    name.as_ref().is_some_and(|n| {
+        contains_ignore_case(&part.name, n)
+            || contains_ignore_case(&part.manufacturer, n)
+            || contains_ignore_case(&part.mpn, n)
+            || part.aliases.iter().any(|a| contains_ignore_case(a, n))
+            || part.skus.iter().any(|s| contains_ignore_case(&s.sku, n))
+    }) || description
+        .as_ref()
+        .is_some_and(|d| contains_ignore_case(&part.description, d))
+}
+
+impl MemoryBackend {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn next_id(&mut self) -> i64 {
+        self.last_id += 1;
+        self.last_id
+    }
+
+    fn find_part(&self, part_id: i64) -> Result<&Part, NetworkError> {
+        self.parts
+            .iter()
+            .find(|p| p.id == part_id)
+            .ok_or(NetworkError::NotFound(format!(
+                "Part {} does not exist",
+                part_id
+            )))
+    }
+
+    fn find_bom(&self, profile_id: i64, bom_id: i64) -> Result<&Bom, NetworkError> {
+        self.boms
+            .iter()
+            .find(|b| {
+                b.id == bom_id
+                    && self
+                        .profile_boms
+                        .iter()
+                        .any(|r| r.profile_id == profile_id && r.bom_id == b.id)
+            })
+            .ok_or(NetworkError::NotFound(format!(
+                "BOM {} does not exist",
+                bom_id
+            )))
+    }
+
+    fn find_bom_line(
+        &mut self,
+        profile_id: i64,
+        bom_id: i64,
+        part_id: i64,
+    ) -> Result<&mut BomPartRelation, NetworkError> {
+        self.find_bom(profile_id, bom_id)?;
+        self.bom_parts
+            .iter_mut()
+            .find(|r| r.bom_id == bom_id && r.part_id == part_id)
+            .ok_or(NetworkError::NotFound(format!(
+                "BOM {} has no part {}",
+                bom_id, part_id
+            )))
+    }
+
+    fn find_build_order(
+        &mut self,
+        profile_id: i64,
+        order_id: i64,
+    ) -> Result<&mut BuildOrder, NetworkError> {
+        self.build_orders
+            .iter_mut()
+            .find(|(profile, o)| *profile == profile_id && o.id == order_id)
+            .map(|(_, o)| o)
+            .ok_or(NetworkError::NotFound(format!(
+                "Build order {} does not exist",
+                order_id
+            )))
+    }
+
+    fn find_or_create_bin(&mut self, profile_id: i64, column: i64, row: i64, z: i64) -> i64 {
+        if let Some(bin) = self
+            .bins
+            .iter()
+            .find(|b| b.profile_id == profile_id && b.column == column && b.row == row && b.z == z)
+        {
+            return bin.id;
+        }
+        let id = self.next_id();
+        self.bins.push(Bin {
+            id,
+            profile_id,
+            row,
+            column,
+            z,
+        });
+        id
+    }
+
+    /// Returns the stock entry and bin of a part within a profile, if it has been stocked.
+    fn stock_in_profile(&self, profile_id: i64, part_id: i64) -> Option<(&StockEntry, &Bin)> {
+        self.stock.iter().find_map(|s| {
+            if s.relation.part_id != part_id {
+                return None;
+            }
+            self.bins
+                .iter()
+                .find(|b| b.id == s.relation.bin_id && b.profile_id == profile_id)
+                .map(|b| (s, b))
+        })
+    }
+
+    fn part_with_stock(&self, profile_id: i64, part: &Part) -> PartWithStock {
+        match self.stock_in_profile(profile_id, part.id) {
+            Some((entry, bin)) => PartWithStock {
+                id: part.id,
+                name: part.name.clone(),
+                description: part.description.clone(),
+                manufacturer: part.manufacturer.clone(),
+                mpn: part.mpn.clone(),
+                skus: part.skus.clone(),
+                parameters: part.parameters.clone(),
+                purchasing: part.purchasing,
+                stock: entry.stock,
+                column: bin.column,
+                row: bin.row,
+                z: bin.z,
+            },
+            None => PartWithStock {
+                id: part.id,
+                name: part.name.clone(),
+                description: part.description.clone(),
+                manufacturer: part.manufacturer.clone(),
+                mpn: part.mpn.clone(),
+                skus: part.skus.clone(),
+                parameters: part.parameters.clone(),
+                purchasing: part.purchasing,
+                stock: 0,
+                column: default_bin_placement(),
+                row: default_bin_placement(),
+                z: default_bin_placement(),
+            },
+        }
+    }
+
+    fn set_stock(
+        &mut self,
+        profile_id: i64,
+        part_id: i64,
+        stock: i64,
+        bin: BinLocation,
+        reason: &str,
+    ) -> Result<(), NetworkError> {
+        self.find_part(part_id)?;
+        let previous = self
+            .stock_in_profile(profile_id, part_id)
+            .map(|(s, _)| s.stock)
+            .unwrap_or(0);
+        let bin_id = self.find_or_create_bin(profile_id, bin.column, bin.row, bin.z);
+        // A part lives in a single bin per profile, so stocking it elsewhere moves it
+        let profile_bins: Vec<i64> = self
+            .bins
+            .iter()
+            .filter(|b| b.profile_id == profile_id)
+            .map(|b| b.id)
+            .collect();
+        self.stock.retain(|s| {
+            s.relation.part_id != part_id || !profile_bins.contains(&s.relation.bin_id)
+        });
+        let id = self.next_id();
+        self.stock.push(StockEntry {
+            relation: PartBinRelation {
+                id,
+                part_id,
+                bin_id,
+            },
+            stock,
+        });
+        self.record(profile_id, part_id, bin, stock - previous, stock, reason);
+        Ok(())
+    }
+
+    fn record(
+        &mut self,
+        profile_id: i64,
+        part_id: i64,
+        bin: BinLocation,
+        delta: i64,
+        stock: i64,
+        reason: &str,
+    ) {
+        let name = self
+            .find_part(part_id)
+            .map(|p| p.name.clone())
+            .unwrap_or_default();
+        let id = self.next_id();
+        let user = self
+            .user_data
+            .user
+            .as_ref()
+            .map(|u| u.email.clone())
+            .unwrap_or_default();
+        self.ledger.push((
+            profile_id,
+            StockLedgerEntry {
+                id,
+                timestamp: Utc::now(),
+                user,
+                part_id,
+                name,
+                row: bin.row,
+                column: bin.column,
+                z: bin.z,
+                delta,
+                stock,
+                reason: reason.to_string(),
+            },
+        ));
+    }
+
+    fn adjust(
+        &mut self,
+        profile_id: i64,
+        part_id: i64,
+        bin: BinLocation,
+        delta: i64,
+        reason: &str,
+    ) -> Result<i64, NetworkError> {
+        let current = self
+            .stock_in_profile(profile_id, part_id)
+            .map(|(s, _)| s.stock)
+            .unwrap_or(0);
+        self.set_stock(profile_id, part_id, current + delta, bin, reason)?;
+        Ok(current + delta)
+    }
+
+    fn reachable(&self) -> Result<(), NetworkError> {
+        if self.unreachable {
+            Err(NetworkError::Server {
+                status: 503,
+                body: String::from("Service unavailable"),
+            })
+        } else {
+            Ok(())
+        }
+    }
+
+    fn logged_in_user(&self) -> Result<&User, NetworkError> {
+        self.user_data
+            .user
+            .as_ref()
+            .and_then(|u| self.users.iter().find(|known| known.email == u.email))
+            .ok_or(NetworkError::Unauthorized)
+    }
+}
+
+#[async_trait]
+impl PartsBackend for MemoryBackend {
+    fn user_data(&self) -> &UserData {
+        &self.user_data
+    }
+
+    fn user_data_mut(&mut self) -> &mut UserData {
+        &mut self.user_data
+    }
+
+    fn host_name(&self) -> String {
+        String::from("memory")
+    }
+
+    async fn create_user(&mut self, pending: User) -> Result<(), NetworkError> {
+        self.reachable()?;
+        if self.users.iter().any(|u| u.email == pending.email) {
+            return Err(NetworkError::Conflict(format!(
+                "A user with email {} already exists",
+                pending.email
+            )));
+        }
+        let id = self.next_id();
+        self.users.push(User { id, ..pending });
+        Ok(())
+    }
+
+    async fn login(&mut self, user: User) -> Result<(), NetworkError> {
+        self.reachable()?;
+        let known = self
+            .users
+            .iter()
+            .find(|u| u.email == user.email && u.password == user.password)
+            .cloned()
+            .ok_or(NetworkError::Unauthorized)?;
+        self.user_data.user = Some(known);
+        let profiles = self.get_profiles(None).await?;
+        self.user_data.profile = profiles.first().cloned();
+        Ok(())
+    }
+
+    async fn get_parts(
+        &mut self,
+        name: Option<String>,
+        description: Option<String>,
+    ) -> Result<Vec<Part>, NetworkError> {
+        self.reachable()?;
+        Ok(self
+            .parts
+            .iter()
+            .filter(|p| matches_filters(p, &name, &description))
+            .cloned()
+            .collect())
+    }
+
+    async fn new_part(&mut self, part: Part) -> Result<(), NetworkError> {
+        self.reachable()?;
+        let id = self.next_id();
+        self.parts.push(Part { id, ..part });
+        Ok(())
+    }
+
+    async fn update_part(&mut self, part: Part) -> Result<(), NetworkError> {
+        self.reachable()?;
+        self.find_part(part.id)?;
+        if let Some(existing) = self.parts.iter_mut().find(|p| p.id == part.id) {
+            *existing = Part {
+                aliases: existing.aliases.clone(),
+                alternates: existing.alternates.clone(),
+                purchasing: existing.purchasing,
+                ..part
+            };
+        }
+        Ok(())
+    }
+
+    async fn set_part_alternates(
+        &mut self,
+        part_id: i64,
+        alternates: Vec<i64>,
+    ) -> Result<(), NetworkError> {
+        self.reachable()?;
+        check_alternates(part_id, &alternates)?;
+        self.find_part(part_id)?;
+        for alternate in &alternates {
+            self.find_part(*alternate)?;
+        }
+        if let Some(part) = self.parts.iter_mut().find(|p| p.id == part_id) {
+            part.alternates = alternates;
+        }
+        Ok(())
+    }
+
+    async fn set_purchase_rules(
+        &mut self,
+        part_id: i64,
+        rules: PurchaseRules,
+    ) -> Result<(), NetworkError> {
+        self.reachable()?;
+        check_purchase_rules(&rules)?;
+        self.find_part(part_id)?;
+        if let Some(part) = self.parts.iter_mut().find(|p| p.id == part_id) {
+            part.purchasing = rules;
+        }
+        Ok(())
+    }
+
+    async fn delete_part(&mut self, part_id: i64) -> Result<(), NetworkError> {
+        self.reachable()?;
+        let name = self.find_part(part_id)?.name.clone();
+        if let Some(bom) = self
+            .bom_parts
+            .iter()
+            .find(|r| r.part_id == part_id)
+            .and_then(|r| self.boms.iter().find(|b| b.id == r.bom_id))
+        {
+            return Err(NetworkError::Conflict(format!(
+                "{} is used by BOM {}",
+                name, bom.name
+            )));
+        }
+        if self.stock.iter().any(|s| s.relation.part_id == part_id) {
+            return Err(NetworkError::Conflict(format!(
+                "{} is still in a bin, remove it first",
+                name
+            )));
+        }
+        self.parts.retain(|p| p.id != part_id);
+        self.ledger.retain(|(_, e)| e.part_id != part_id);
+        for part in &mut self.parts {
+            part.alternates.retain(|a| *a != part_id);
+        }
+        for line in &mut self.bom_parts {
+            line.alternates.retain(|a| *a != part_id);
+        }
+        Ok(())
+    }
+
+    async fn merge_parts(&mut self, keep: i64, merged: Vec<i64>) -> Result<Part, NetworkError> {
+        self.reachable()?;
+        check_merge(keep, &merged)?;
+        let kept = self.find_part(keep)?.clone();
+        let others = merged
+            .iter()
+            .map(|id| self.find_part(*id).cloned())
+            .collect::<Result<Vec<_>, _>>()?;
+        let part = merged_part(&kept, &others);
+
+        for other in &others {
+            // History of the merged part carries on as the history of the kept one
+            for (_, entry) in self
+                .ledger
+                .iter_mut()
+                .filter(|(_, e)| e.part_id == other.id)
+            {
+                entry.part_id = keep;
+                entry.name = kept.name.clone();
+            }
+            let stocked: Vec<(i64, i64, i64)> = self
+                .stock
+                .iter()
+                .filter(|s| s.relation.part_id == other.id)
+                .filter_map(|s| {
+                    self.bins
+                        .iter()
+                        .find(|b| b.id == s.relation.bin_id)
+                        .map(|b| (b.profile_id, b.id, s.stock))
+                })
+                .collect();
+            self.stock.retain(|s| s.relation.part_id != other.id);
+            for (profile_id, bin_id, stock) in stocked {
+                // The kept part stays where it is, or moves into the merged part's bin
+                match self.stock.iter().position(|s| {
+                    s.relation.part_id == keep
+                        && self
+                            .bins
+                            .iter()
+                            .any(|b| b.id == s.relation.bin_id && b.profile_id == profile_id)
+                }) {
+                    Some(i) => self.stock[i].stock += stock,
+                    None => {
+                        let id = self.next_id();
+                        self.stock.push(StockEntry {
+                            relation: PartBinRelation {
+                                id,
+                                part_id: keep,
+                                bin_id,
+                            },
+                            stock,
+                        });
+                    }
+                }
+            }
+
+            for i in 0..self.bom_parts.len() {
+                if self.bom_parts[i].part_id != other.id {
+                    continue;
+                }
+                let bom_id = self.bom_parts[i].bom_id;
+                match self
+                    .bom_parts
+                    .iter()
+                    .position(|r| r.bom_id == bom_id && r.part_id == keep)
+                {
+                    Some(j) => {
+                        self.bom_parts[j] =
+                            combine_bom_lines(&self.bom_parts[j], &self.bom_parts[i])
+                    }
+                    None => self.bom_parts[i].part_id = keep,
+                }
+            }
+            self.bom_parts.retain(|r| r.part_id != other.id);
+        }
+
+        // The moved entries only counted the merged part's stock, the kept part's ledger adds
+        // up to both
+        let mut totals: BTreeMap<i64, i64> = BTreeMap::new();
+        for (profile_id, entry) in self.ledger.iter_mut().filter(|(_, e)| e.part_id == keep) {
+            let total = totals.entry(*profile_id).or_default();
+            *total += entry.delta;
+            entry.stock = *total;
+        }
+
+        self.parts.retain(|p| !merged.contains(&p.id));
+        if let Some(existing) = self.parts.iter_mut().find(|p| p.id == keep) {
+            *existing = part.clone();
+        }
+        for other in &mut self.parts {
+            replace_merged_alternates(&mut other.alternates, other.id, &merged, keep);
+        }
+        for line in &mut self.bom_parts {
+            replace_merged_alternates(&mut line.alternates, line.part_id, &merged, keep);
+        }
+        Ok(part)
+    }
+
+    async fn get_profiles(&mut self, name: Option<String>) -> Result<Vec<Profile>, NetworkError> {
+        self.reachable()?;
+        let user_id = self.logged_in_user()?.id;
+        Ok(self
+            .profiles
+            .iter()
+            .filter(|p| p.user_id == user_id)
+            .filter(|p| {
+                name.as_ref()
+                    .is_none_or(|n| contains_ignore_case(&p.name, n))
+            })
+            .cloned()
+            .collect())
+    }
+
+    async fn new_profile(&mut self, name: String) -> Result<(), NetworkError> {
+        self.reachable()?;
+        let user_id = self.logged_in_user()?.id;
+        let id = self.next_id();
+        self.profiles.push(Profile { id, user_id, name });
+        Ok(())
+    }
+
+    async fn update_profile(&mut self, profile_id: i64, name: String) -> Result<(), NetworkError> {
+        self.reachable()?;
+        let user_id = self.logged_in_user()?.id;
+        let profile = self
+            .profiles
+            .iter_mut()
+            .find(|p| p.id == profile_id && p.user_id == user_id)
+            .ok_or(NetworkError::NotFound(format!(
+                "Profile {} does not exist",
+                profile_id
+            )))?;
+        profile.name = name;
+        // Keep the selected profile in step with its new name
+        if let Some(selected) = self
+            .user_data
+            .profile
+            .as_mut()
+            .filter(|p| p.id == profile_id)
+        {
+            selected.name = profile.name.clone();
+        }
+        Ok(())
+    }
+
+    async fn list_stock(&mut self, profile_id: i64) -> Result<Vec<StockRows>, NetworkError> {
+        self.reachable()?;
+        let mut out = vec![];
+        for entry in &self.stock {
+            let Some(bin) = self
+                .bins
+                .iter()
+                .find(|b| b.id == entry.relation.bin_id && b.profile_id == profile_id)
+            else {
+                continue;
+            };
+            let part = self.find_part(entry.relation.part_id)?;
+            out.push(StockRows {
+                id: entry.relation.id,
+                bin_id: bin.id,
+                row: bin.row,
+                column: bin.column,
+                z: bin.z,
+                part_id: part.id,
+                name: part.name.clone(),
+                description: part.description.clone(),
+                stock: entry.stock,
+            });
+        }
+        Ok(out)
+    }
+
+    async fn stock_part(
+        &mut self,
+        profile_id: i64,
+        part_id: i64,
+        stock: i64,
+        column: i64,
+        row: i64,
+        z: i64,
+    ) -> Result<(), NetworkError> {
+        self.reachable()?;
+        let bin = BinLocation { column, row, z };
+        self.set_stock(profile_id, part_id, stock, bin, STOCK_SET_REASON)
+    }
+
+    async fn adjust_stock(
+        &mut self,
+        profile_id: i64,
+        part_id: i64,
+        bin: BinLocation,
+        delta: i64,
+        reason: String,
+    ) -> Result<i64, NetworkError> {
+        self.reachable()?;
+        self.adjust(profile_id, part_id, bin, delta, &reason)
+    }
+
+    async fn stock_parts(
+        &mut self,
+        profile_id: i64,
+        parts: &[PartWithCountAndStock],
+        diff: i64,
+        reason: String,
+    ) -> Result<(), NetworkError> {
+        self.reachable()?;
+        let failed: Vec<FailedLine> = parts
+            .iter()
+            .filter_map(|p| {
+                self.find_part(p.id).err().map(|error| FailedLine {
+                    part_id: p.id,
+                    name: p.name.clone(),
+                    error,
+                })
+            })
+            .collect();
+        if !failed.is_empty() {
+            return Err(NetworkError::Batch(BatchFailure {
+                failed,
+                unreverted: vec![],
+            }));
+        }
+        for p in parts {
+            self.adjust(profile_id, p.id, p.bin(), diff * p.count, &reason)?;
+        }
+        Ok(())
+    }
+
+    async fn remove_from_bin(&mut self, profile_id: i64, part_id: i64) -> Result<(), NetworkError> {
+        self.reachable()?;
+        self.find_part(part_id)?;
+        let (entry, bin) =
+            self.stock_in_profile(profile_id, part_id)
+                .ok_or(NetworkError::NotFound(format!(
+                    "Part {} is not in a bin",
+                    part_id
+                )))?;
+        let (entry_id, stock) = (entry.relation.id, entry.stock);
+        let location = BinLocation {
+            column: bin.column,
+            row: bin.row,
+            z: bin.z,
+        };
+        self.stock.retain(|s| s.relation.id != entry_id);
+        self.record(
+            profile_id,
+            part_id,
+            location,
+            -stock,
+            0,
+            REMOVED_FROM_BIN_REASON,
+        );
+        Ok(())
+    }
+
+    async fn stock_history(
+        &mut self,
+        profile_id: i64,
+        part_id: Option<i64>,
+    ) -> Result<Vec<StockLedgerEntry>, NetworkError> {
+        self.reachable()?;
+        Ok(self
+            .ledger
+            .iter()
+            .rev()
+            .filter(|(profile, e)| {
+                *profile == profile_id && part_id.is_none_or(|id| e.part_id == id)
+            })
+            .map(|(_, e)| e.clone())
+            .collect())
+    }
+
+    async fn list_boms(
+        &mut self,
+        profile_id: i64,
+        bom_id: Option<i64>,
+        bom_name: Option<String>,
+    ) -> Result<Vec<Bom>, NetworkError> {
+        self.reachable()?;
+        Ok(self
+            .boms
+            .iter()
+            .filter(|b| {
+                self.profile_boms
+                    .iter()
+                    .any(|r| r.profile_id == profile_id && r.bom_id == b.id)
+            })
+            .filter(|b| bom_id.is_none_or(|id| b.id == id))
+            .filter(|b| {
+                bom_name
+                    .as_ref()
+                    .is_none_or(|n| contains_ignore_case(&b.name, n))
+            })
+            .cloned()
+            .collect())
+    }
+
+    async fn new_bom(
+        &mut self,
+        profile_id: i64,
+        name: String,
+        description: String,
+        candidates: Vec<BomLine>,
+        revision_of: Option<i64>,
+    ) -> Result<(), NetworkError> {
+        self.reachable()?;
+        // Validate before touching anything so a bad BOM leaves no trace
+        for line in &candidates {
+            if line.part.id != 0 {
+                self.find_part(line.part.id)?;
+            }
+        }
+        let revision = match revision_of {
+            Some(previous_id) => {
+                let previous = self
+                    .list_boms(profile_id, Some(previous_id), None)
+                    .await?
+                    .pop()
+                    .ok_or(NetworkError::NotFound(format!(
+                        "BOM {} does not exist",
+                        previous_id
+                    )))?;
+                previous.revision + 1
+            }
+            None => first_revision(),
+        };
+
+        let bom_id = self.next_id();
+        self.boms.push(Bom {
+            id: bom_id,
+            name,
+            description,
+            variants: line_variants(&candidates),
+            revision,
+            revision_of,
+        });
+        let id = self.next_id();
+        self.profile_boms.push(ProfileBomRelation {
+            id,
+            profile_id,
+            bom_id,
+        });
+
+        for line in candidates {
+            // Unlinked candidates become new parts, just like on the server
+            let part_id = if line.part.id == 0 {
+                let id = self.next_id();
+                self.parts.push(Part { id, ..line.part });
+                id
+            } else {
+                line.part.id
+            };
+            let id = self.next_id();
+            self.bom_parts.push(BomPartRelation {
+                id,
+                bom_id,
+                part_id,
+                count: line.count,
+                designators: line.designators,
+                variants: line.variants,
+                alternates: vec![],
+            });
+        }
+        Ok(())
+    }
+
+    async fn update_bom(
+        &mut self,
+        profile_id: i64,
+        bom_id: i64,
+        name: String,
+        description: String,
+    ) -> Result<(), NetworkError> {
+        self.reachable()?;
+        self.find_bom(profile_id, bom_id)?;
+        if let Some(bom) = self.boms.iter_mut().find(|b| b.id == bom_id) {
+            bom.name = name;
+            bom.description = description;
+        }
+        Ok(())
+    }
+
+    async fn delete_bom(&mut self, profile_id: i64, bom_id: i64) -> Result<(), NetworkError> {
+        self.reachable()?;
+        let bom = self.find_bom(profile_id, bom_id)?.clone();
+        if self.build_orders.iter().any(|(_, o)| o.bom_id == bom_id) {
+            return Err(NetworkError::Conflict(format!(
+                "{} has build orders",
+                bom.name
+            )));
+        }
+        self.boms.retain(|b| b.id != bom_id);
+        self.profile_boms.retain(|r| r.bom_id != bom_id);
+        self.bom_parts.retain(|r| r.bom_id != bom_id);
+        for later in self
+            .boms
+            .iter_mut()
+            .filter(|b| b.revision_of == Some(bom_id))
+        {
+            later.revision_of = bom.revision_of;
+        }
+        Ok(())
+    }
+
+    async fn update_bom_line(
+        &mut self,
+        profile_id: i64,
+        bom_id: i64,
+        part_id: i64,
+        count: i64,
+    ) -> Result<(), NetworkError> {
+        self.reachable()?;
+        check_bom_line_count(count)?;
+        self.find_bom_line(profile_id, bom_id, part_id)?.count = count;
+        Ok(())
+    }
+
+    async fn delete_bom_line(
+        &mut self,
+        profile_id: i64,
+        bom_id: i64,
+        part_id: i64,
+    ) -> Result<(), NetworkError> {
+        self.reachable()?;
+        let id = self.find_bom_line(profile_id, bom_id, part_id)?.id;
+        self.bom_parts.retain(|r| r.id != id);
+        Ok(())
+    }
+
+    async fn set_bom_line_alternates(
+        &mut self,
+        profile_id: i64,
+        bom_id: i64,
+        part_id: i64,
+        alternates: Vec<i64>,
+    ) -> Result<(), NetworkError> {
+        self.reachable()?;
+        check_alternates(part_id, &alternates)?;
+        for alternate in &alternates {
+            self.find_part(*alternate)?;
+        }
+        self.find_bom_line(profile_id, bom_id, part_id)?.alternates = alternates;
+        Ok(())
+    }
+
+    async fn parts_in_bom(
+        &mut self,
+        profile_id: i64,
+        bom_id: i64,
+    ) -> Result<Vec<PartWithCountAndStock>, NetworkError> {
+        self.reachable()?;
+        let mut out = vec![];
+        for relation in self.bom_parts.iter().filter(|r| r.bom_id == bom_id) {
+            let part = self.find_part(relation.part_id)?;
+            let p = self.part_with_stock(profile_id, part);
+            let alternates = line_alternates(part.id, &relation.alternates, &part.alternates)
+                .into_iter()
+                .map(|id| {
+                    self.find_part(id)
+                        .map(|alternate| self.part_with_stock(profile_id, alternate))
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            out.push(PartWithCountAndStock {
+                id: p.id,
+                name: p.name,
+                description: p.description,
+                count: relation.count,
+                designators: relation.designators.clone(),
+                variants: relation.variants.clone(),
+                stock: p.stock,
+                column: p.column,
+                row: p.row,
+                z: p.z,
+                alternates,
+            });
+        }
+        Ok(out)
+    }
+
+    async fn where_used(
+        &mut self,
+        profile_id: i64,
+        part_id: i64,
+    ) -> Result<Vec<BomUsage>, NetworkError> {
+        self.reachable()?;
+        self.find_part(part_id)?;
+        let mut out = vec![];
+        for bom in self.list_boms(profile_id, None, None).await? {
+            if let Some(relation) = self
+                .bom_parts
+                .iter()
+                .find(|r| r.bom_id == bom.id && r.part_id == part_id)
+            {
+                out.push(BomUsage {
+                    bom_id: bom.id,
+                    bom_name: bom.name,
+                    revision: bom.revision,
+                    count: relation.count,
+                    variants: relation.variants.clone(),
+                });
+            }
+        }
+        Ok(out)
+    }
+
+    async fn parts_with_stock(
+        &mut self,
+        name: Option<String>,
+        description: Option<String>,
+        profile_id: i64,
+    ) -> Result<Vec<PartWithStock>, NetworkError> {
+        self.reachable()?;
+        Ok(self
+            .parts
+            .iter()
+            .filter(|p| matches_filters(p, &name, &description))
+            .map(|p| self.part_with_stock(profile_id, p))
+            .collect())
+    }
+
+    async fn list_build_orders(
+        &mut self,
+        profile_id: i64,
+    ) -> Result<Vec<BuildOrder>, NetworkError> {
+        self.reachable()?;
+        Ok(self
+            .build_orders
+            .iter()
+            .filter(|(profile, _)| *profile == profile_id)
+            .map(|(_, o)| o.clone())
+            .collect())
+    }
+
+    async fn new_build_order(
+        &mut self,
+        profile_id: i64,
+        bom_id: i64,
+        quantity: i64,
+        variant: Option<String>,
+    ) -> Result<BuildOrder, NetworkError> {
+        self.reachable()?;
+        if quantity <= 0 {
+            return Err(NetworkError::Validation {
+                field: String::from("quantity"),
+                msg: String::from("Must be at least 1"),
+            });
+        }
+        let bom = self
+            .list_boms(profile_id, Some(bom_id), None)
+            .await?
+            .pop()
+            .ok_or(NetworkError::NotFound(format!(
+                "BOM {} does not exist",
+                bom_id
+            )))?;
+        check_variant(&bom, variant.as_deref())?;
+        let order = BuildOrder {
+            id: self.next_id(),
+            bom_id,
+            bom_name: bom.name,
+            variant: variant.unwrap_or_default(),
+            quantity,
+            status: BuildStatus::Planned,
+            created: Utc::now(),
+        };
+        self.build_orders.push((profile_id, order.clone()));
+        Ok(order)
+    }
+
+    async fn set_build_status(
+        &mut self,
+        profile_id: i64,
+        order_id: i64,
+        status: BuildStatus,
+    ) -> Result<(), NetworkError> {
+        self.reachable()?;
+        let order = self.find_build_order(profile_id, order_id)?.clone();
+        check_build_transition(&order, status)?;
+        if status == BuildStatus::Built {
+            let parts = select_variant(
+                &self.parts_in_bom(profile_id, order.bom_id).await?,
+                order.variant(),
+            );
+            let reserved = self
+                .reservations(profile_id)
+                .await?
+                .into_iter()
+                .map(|r| (r.part_id, r.reserved))
+                .collect();
+            // What other build orders hold stays theirs
+            let mut available = build_stock(&parts, &reserved, order.quantity);
+            let draws = draw_with_alternates(&parts, order.quantity, &mut available);
+            self.stock_parts(
+                profile_id,
+                &drawn_parts(&parts, &draws),
+                -1,
+                build_reason(&order),
+            )
+            .await?;
+        }
+        self.find_build_order(profile_id, order_id)?.status = status;
+        Ok(())
+    }
+
+    async fn reservations(&mut self, profile_id: i64) -> Result<Vec<Reservation>, NetworkError> {
+        self.reachable()?;
+        let mut reserved: BTreeMap<i64, i64> = BTreeMap::new();
+        for (_, order) in self
+            .build_orders
+            .iter()
+            .filter(|(profile, o)| *profile == profile_id && o.status.reserves())
+        {
+            for relation in self.bom_parts.iter().filter(|r| r.bom_id == order.bom_id) {
+                let count = count_in_variant(relation.count, &relation.variants, order.variant());
+                *reserved.entry(relation.part_id).or_default() += count * order.quantity;
+            }
+        }
+        Ok(reserved
+            .into_iter()
+            .map(|(part_id, reserved)| Reservation { part_id, reserved })
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        backend::PartsBackend,
+        models::{BinLocation, BomChange, BomLine, BuildStatus, User, diff_boms},
+        test_util::{logged_in, part},
+    };
+
+    use super::MemoryBackend;
+
+    async fn logged_in_backend() -> MemoryBackend {
+        logged_in(MemoryBackend::new()).await
+    }
+
+    #[tokio::test]
+    async fn login_selects_first_profile() {
+        let backend = logged_in_backend().await;
+        assert_eq!(
+            backend
+                .user_data()
+                .profile
+                .as_ref()
+                .map(|p| p.name.as_str()),
+            Some("Workshop")
+        );
+    }
+
+    #[tokio::test]
+    async fn login_rejects_wrong_password() {
+        let mut backend = logged_in_backend().await;
+        let result = backend
+            .login(User {
+                id: 0,
+                email: String::from("test@example.com"),
+                password: String::from("wrong"),
+            })
+            .await;
+        assert!(result.is_err());
+    }
+
+    #[tokio::test]
+    async fn search_matches_name_or_description() {
+        let mut backend = logged_in_backend().await;
+        backend
+            .new_part(part("LTC3531", "Buck-boost"))
+            .await
+            .unwrap();
+        backend
+            .new_part(part("10k", "Resistor 0603"))
+            .await
+            .unwrap();
+
+        let by_name = backend
+            .get_parts(Some(String::from("ltc")), Some(String::from("ltc")))
+            .await
+            .unwrap();
+        assert_eq!(by_name.len(), 1);
+        let by_desc = backend
+            .get_parts(Some(String::from("0603")), Some(String::from("0603")))
+            .await
+            .unwrap();
+        assert_eq!(by_desc[0].name, "10k");
+    }
+
+    #[tokio::test]
+    async fn stocking_moves_part_between_bins() {
+        let mut backend = logged_in_backend().await;
+        let profile_id = backend.user_data().profile.as_ref().unwrap().id;
+        backend.new_part(part("10k", "Resistor")).await.unwrap();
+        let id = backend.get_parts(None, None).await.unwrap()[0].id;
+
+        backend
+            .stock_part(profile_id, id, 10, 1, 2, 0)
+            .await
+            .unwrap();
+        backend
+            .stock_part(profile_id, id, 15, 3, 4, 0)
+            .await
+            .unwrap();
+
+        let stock = backend.list_stock(profile_id).await.unwrap();
+        assert_eq!(stock.len(), 1);
+        assert_eq!((stock[0].stock, stock[0].column, stock[0].row), (15, 3, 4));
+    }
+
+    #[tokio::test]
+    async fn adjustments_are_relative_to_current_stock() {
+        let mut backend = logged_in_backend().await;
+        let profile_id = backend.user_data().profile.as_ref().unwrap().id;
+        backend.new_part(part("100nF", "Capacitor")).await.unwrap();
+        let id = backend.get_parts(None, None).await.unwrap()[0].id;
+        let bin = BinLocation {
+            column: 2,
+            row: 2,
+            z: 0,
+        };
+
+        // Unstocked parts start from zero
+        let stock = backend
+            .adjust_stock(profile_id, id, bin, 50, String::from("import"))
+            .await
+            .unwrap();
+        assert_eq!(stock, 50);
+        // Two people depleting from the same stale view both count
+        backend
+            .adjust_stock(profile_id, id, bin, -3, String::from("manual deplete"))
+            .await
+            .unwrap();
+        let stock = backend
+            .adjust_stock(profile_id, id, bin, -5, String::from("manual deplete"))
+            .await
+            .unwrap();
+        assert_eq!(stock, 42);
+    }
+
+    #[tokio::test]
+    async fn bom_creates_unlinked_parts_and_depletes() {
+        let mut backend = logged_in_backend().await;
+        let profile_id = backend.user_data().profile.as_ref().unwrap().id;
+        backend.new_part(part("10k", "Resistor")).await.unwrap();
+        let existing = backend.get_parts(None, None).await.unwrap()[0].clone();
+        backend
+            .stock_part(profile_id, existing.id, 100, 0, 0, 0)
+            .await
+            .unwrap();
+
+        backend
+            .new_bom(
+                profile_id,
+                String::from("Magnet Harvesting"),
+                String::from("1.2"),
+                vec![
+                    BomLine::new(4, existing),
+                    BomLine::new(1, part("LTC3531", "Buck-boost")),
+                ],
+                None,
+            )
+            .await
+            .unwrap();
+        assert_eq!(backend.get_parts(None, None).await.unwrap().len(), 2);
+
+        let bom = backend.list_boms(profile_id, None, None).await.unwrap()[0].clone();
+        let parts = backend.parts_in_bom(profile_id, bom.id).await.unwrap();
+        backend
+            .stock_parts(profile_id, &parts, -3, String::from("BOM build"))
+            .await
+            .unwrap();
+
+        let parts = backend.parts_in_bom(profile_id, bom.id).await.unwrap();
+        let resistor = parts.iter().find(|p| p.name == "10k").unwrap();
+        assert_eq!(resistor.stock, 88);
+    }
+
+    #[tokio::test]
+    async fn revisions_count_up_and_diff() {
+        let mut backend = logged_in_backend().await;
+        let profile_id = backend.user_data().profile.as_ref().unwrap().id;
+        let resistor = part("10k", "Resistor");
+        backend.new_part(resistor.clone()).await.unwrap();
+        let resistor = backend.get_parts(None, None).await.unwrap()[0].clone();
+        backend
+            .new_bom(
+                profile_id,
+                String::from("Magnet Harvesting 1.2"),
+                String::new(),
+                vec![
+                    BomLine {
+                        designators: ["R1", "R2"].map(String::from).to_vec(),
+                        ..BomLine::new(2, resistor.clone())
+                    },
+                    BomLine::new(1, part("LTC3531", "Buck-boost")),
+                ],
+                None,
+            )
+            .await
+            .unwrap();
+        let first = backend.list_boms(profile_id, None, None).await.unwrap()[0].clone();
+        assert_eq!((first.revision, first.revision_of), (1, None));
+        assert!(
+            backend
+                .new_bom(profile_id, String::new(), String::new(), vec![], Some(1000))
+                .await
+                .is_err()
+        );
+
+        backend
+            .new_bom(
+                profile_id,
+                String::from("Magnet Harvesting 1.3"),
+                String::new(),
+                vec![
+                    BomLine {
+                        designators: ["R1", "R3", "R4"].map(String::from).to_vec(),
+                        ..BomLine::new(3, resistor)
+                    },
+                    BomLine::new(1, part("TPS63020", "Buck-boost")),
+                ],
+                Some(first.id),
+            )
+            .await
+            .unwrap();
+        let second = backend
+            .list_boms(profile_id, None, Some(String::from("1.3")))
+            .await
+            .unwrap()[0]
+            .clone();
+        assert_eq!((second.revision, second.revision_of), (2, Some(first.id)));
+
+        let diff = diff_boms(
+            &backend.parts_in_bom(profile_id, first.id).await.unwrap(),
+            &backend.parts_in_bom(profile_id, second.id).await.unwrap(),
+        );
+        let changes: Vec<(BomChange, &str, i64, i64)> = diff
+            .iter()
+            .map(|l| (l.change, l.name.as_str(), l.old_count, l.new_count))
+            .collect();
+        assert_eq!(
+            changes,
+            [
+                (BomChange::Added, "TPS63020", 0, 1),
+                (BomChange::Removed, "LTC3531", 1, 0),
+                (BomChange::Changed, "10k", 2, 3),
+            ]
+        );
+        assert_eq!(diff[2].added_designators, ["R3", "R4"]);
+        assert_eq!(diff[2].removed_designators, ["R2"]);
+    }
+
+    #[tokio::test]
+    async fn deleting_a_revision_keeps_the_chain() {
+        let mut backend = logged_in_backend().await;
+        let profile_id = backend.user_data().profile.as_ref().unwrap().id;
+        let mut ids = vec![];
+        for _ in 0..3 {
+            backend
+                .new_bom(
+                    profile_id,
+                    String::from("Sensor"),
+                    String::new(),
+                    vec![],
+                    ids.last().copied(),
+                )
+                .await
+                .unwrap();
+            let boms = backend.list_boms(profile_id, None, None).await.unwrap();
+            ids.push(boms.iter().map(|b| b.id).max().unwrap());
+        }
+        backend.delete_bom(profile_id, ids[1]).await.unwrap();
+        let last = backend
+            .list_boms(profile_id, Some(ids[2]), None)
+            .await
+            .unwrap()[0]
+            .clone();
+        assert_eq!(last.revision_of, Some(ids[0]));
+
+        backend
+            .update_profile(profile_id, String::from("Lab"))
+            .await
+            .unwrap();
+        assert_eq!(backend.user_data().profile.as_ref().unwrap().name, "Lab");
+    }
+
+    #[tokio::test]
+    async fn boms_are_scoped_to_profiles() {
+        let mut backend = logged_in_backend().await;
+        backend
+            .new_bom(1000, String::from("Other"), String::new(), vec![], None)
+            .await
+            .unwrap();
+        let profile_id = backend.user_data().profile.as_ref().unwrap().id;
+        assert!(
+            backend
+                .list_boms(profile_id, None, None)
+                .await
+                .unwrap()
+                .is_empty()
+        );
+    }
+
+    #[tokio::test]
+    async fn build_orders_reserve_until_built() {
+        let mut backend = logged_in_backend().await;
+        let profile_id = backend.user_data().profile.as_ref().unwrap().id;
+        backend
+            .new_bom(
+                profile_id,
+                String::from("Sensor"),
+                String::new(),
+                vec![BomLine::new(2, part("10k", "Resistor"))],
+                None,
+            )
+            .await
+            .unwrap();
+        let bom = backend.list_boms(profile_id, None, None).await.unwrap()[0].clone();
+        let part_id = backend.get_parts(None, None).await.unwrap()[0].id;
+        backend
+            .stock_part(profile_id, part_id, 30, 0, 0, 0)
+            .await
+            .unwrap();
+
+        let order = backend
+            .new_build_order(profile_id, bom.id, 5, None)
+            .await
+            .unwrap();
+        let reserved = backend.reservations(profile_id).await.unwrap();
+        assert_eq!((reserved[0].part_id, reserved[0].reserved), (part_id, 10));
+
+        backend
+            .set_build_status(profile_id, order.id, BuildStatus::Built)
+            .await
+            .unwrap();
+        assert!(backend.reservations(profile_id).await.unwrap().is_empty());
+        assert_eq!(backend.list_stock(profile_id).await.unwrap()[0].stock, 20);
+        // Built orders are final
+        assert!(
+            backend
+                .set_build_status(profile_id, order.id, BuildStatus::Cancelled)
+                .await
+                .is_err()
+        );
+    }
+
+    #[tokio::test]
+    async fn merging_keeps_the_ledger_adding_up() {
+        let mut backend = logged_in_backend().await;
+        let profile_id = backend.user_data().profile.as_ref().unwrap().id;
+        for name in ["LTC3531", "LTC3531EDD"] {
+            backend.new_part(part(name, "Buck-boost")).await.unwrap();
+        }
+        let ids: Vec<i64> = backend
+            .get_parts(None, None)
+            .await
+            .unwrap()
+            .iter()
+            .map(|p| p.id)
+            .collect();
+        backend
+            .stock_part(profile_id, ids[0], 5, 1, 1, 0)
+            .await
+            .unwrap();
+        backend
+            .stock_part(profile_id, ids[1], 3, 4, 2, 0)
+            .await
+            .unwrap();
+
+        backend.merge_parts(ids[0], vec![ids[1]]).await.unwrap();
+        let stock = backend.list_stock(profile_id).await.unwrap();
+        assert_eq!((stock[0].stock, stock[0].column, stock[0].row), (8, 1, 1));
+        let history = backend
+            .stock_history(profile_id, Some(ids[0]))
+            .await
+            .unwrap();
+        assert_eq!(history.iter().map(|e| e.delta).sum::<i64>(), 8);
+        assert!(history.iter().any(|e| e.stock == 8));
+    }
+
+    #[tokio::test]
+    async fn alternates_follow_merges_and_deletions() {
+        let mut backend = logged_in_backend().await;
+        let profile_id = backend.user_data().profile.as_ref().unwrap().id;
+        backend
+            .new_bom(
+                profile_id,
+                String::from("Sensor"),
+                String::new(),
+                vec![BomLine::new(2, part("100nF", "Capacitor 0402"))],
+                None,
+            )
+            .await
+            .unwrap();
+        for name in ["100nF X7R", "100nF X5R", "100nF Y5V"] {
+            backend.new_part(part(name, "Capacitor")).await.unwrap();
+        }
+        let ids: Vec<i64> = backend
+            .get_parts(None, None)
+            .await
+            .unwrap()
+            .iter()
+            .map(|p| p.id)
+            .collect();
+        let (cap, x7r, x5r, y5v) = (ids[0], ids[1], ids[2], ids[3]);
+        let bom_id = backend.list_boms(profile_id, None, None).await.unwrap()[0].id;
+        backend
+            .set_bom_line_alternates(profile_id, bom_id, cap, vec![x7r, x5r, y5v])
+            .await
+            .unwrap();
+
+        // The X7R becomes the kept part's alternate and the line's one, listed once
+        backend.merge_parts(x7r, vec![x5r]).await.unwrap();
+        backend.delete_part(y5v).await.unwrap();
+        let alternates: Vec<i64> = backend.parts_in_bom(profile_id, bom_id).await.unwrap()[0]
+            .alternates
+            .iter()
+            .map(|a| a.id)
+            .collect();
+        assert_eq!(alternates, [x7r]);
+
+        // Merging the line's part into its alternate leaves nothing to stand in for it
+        backend.merge_parts(x7r, vec![cap]).await.unwrap();
+        let line = &backend.parts_in_bom(profile_id, bom_id).await.unwrap()[0];
+        assert_eq!(line.id, x7r);
+        assert!(line.alternates.is_empty());
+    }
+}
@@ -1,11 +1,348 @@
+use std::{collections::HashMap, fmt::Display, str::FromStr};
+
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use tabled::Tabled;
 
-#[derive(Debug, Clone, Serialize, Deserialize, Tabled)]
+use crate::parameters::Parameters;
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, Tabled)]
 pub struct Part {
     pub id: i64,
     pub name: String,
     pub description: String,
+    #[serde(default)]
+    pub manufacturer: String,
+    /// Manufacturer part number
+    #[serde(default)]
+    pub mpn: String,
+    #[serde(default)]
+    #[tabled(skip)]
+    pub skus: Vec<SupplierSku>,
+    #[serde(default)]
+    #[tabled(skip)]
+    pub parameters: Parameters,
+    /// Names of the parts merged into this one, which imports still link to it
+    #[serde(default)]
+    #[tabled(skip)]
+    pub aliases: Vec<String>,
+    /// Ids of the parts approved to stand in for this one in every BOM, in priority order
+    #[serde(default)]
+    #[tabled(skip)]
+    pub alternates: Vec<i64>,
+    #[serde(default)]
+    #[tabled(skip)]
+    pub purchasing: PurchaseRules,
+}
+
+impl Part {
+    /// Whether an imported BOM line describes this part. Manufacturer and supplier part numbers
+    /// are unambiguous so they win over the name, which often is just a value like "10k".
+    pub fn matches(&self, other: &Part) -> bool {
+        if !self.mpn.is_empty() && !other.mpn.is_empty() {
+            return self.mpn.eq_ignore_ascii_case(&other.mpn);
+        }
+        if self.skus.iter().any(|s| other.skus.contains(s)) {
+            return true;
+        }
+        self.name == other.name || self.aliases.contains(&other.name)
+    }
+}
+
+/// How a part is bought, applied on top of the strict shortfall of a purchase plan
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+pub struct PurchaseRules {
+    /// Minimum order quantity, 0 for none
+    #[serde(default)]
+    pub moq: i64,
+    /// Pack size orders are rounded up to, e.g. 100 for a cut tape, 0 or 1 for none
+    #[serde(default)]
+    pub order_multiple: i64,
+    /// Stock to have left once the planned builds are done
+    #[serde(default)]
+    pub safety_stock: i64,
+    /// Share of parts lost during assembly, in percent of what the builds use
+    #[serde(default)]
+    pub attrition_percent: f64,
+}
+
+impl PurchaseRules {
+    /// What building `required` takes once assembly losses are accounted for
+    pub fn with_attrition(&self, required: i64) -> i64 {
+        if required <= 0 {
+            return required;
+        }
+        required + (required as f64 * self.attrition_percent / 100.0).ceil() as i64
+    }
+
+    /// What to order to cover `needed`, respecting the MOQ and order multiple
+    pub fn order_quantity(&self, needed: i64) -> i64 {
+        if needed <= 0 {
+            return 0;
+        }
+        let quantity = needed.max(self.moq);
+        if self.order_multiple > 1 {
+            (quantity + self.order_multiple - 1) / self.order_multiple * self.order_multiple
+        } else {
+            quantity
+        }
+    }
+}
+
+/// A supplier's order code for a part, e.g. DigiKey's `587-2984-1-ND`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SupplierSku {
+    pub supplier: String,
+    pub sku: String,
+}
+
+impl Display for SupplierSku {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.supplier, self.sku)
+    }
+}
+
+impl FromStr for SupplierSku {
+    type Err = String;
+
+    /// Parses `supplier:sku`, e.g. `LCSC:C1525`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once(':') {
+            Some((supplier, sku)) if !supplier.trim().is_empty() && !sku.trim().is_empty() => {
+                Ok(SupplierSku {
+                    supplier: supplier.trim().to_string(),
+                    sku: sku.trim().to_string(),
+                })
+            }
+            _ => Err(format!("Expected supplier:sku, got {}", s)),
+        }
+    }
+}
+
+pub fn skus_to_string(skus: &[SupplierSku]) -> String {
+    skus.iter()
+        .map(|s| s.to_string())
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Sorts designators the way they read on a board, R2 before R10
+pub fn sort_designators(designators: &mut [String]) {
+    fn key(designator: &str) -> (&str, u64, &str) {
+        let digits = designator
+            .find(|c: char| c.is_ascii_digit())
+            .unwrap_or(designator.len());
+        let (prefix, rest) = designator.split_at(digits);
+        let end = rest
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(rest.len());
+        (prefix, rest[..end].parse().unwrap_or(0), &rest[end..])
+    }
+    designators.sort_by(|a, b| key(a).cmp(&key(b)));
+}
+
+/// Naturally sorted and comma separated, e.g. "C1, C2, C10"
+pub fn designators_to_string(designators: &[String]) -> String {
+    let mut sorted = designators.to_vec();
+    sort_designators(&mut sorted);
+    sorted.join(", ")
+}
+
+/// One line of a BOM being created, linked to an existing part when `part.id` isn't 0
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BomLine {
+    pub count: i64,
+    pub part: Part,
+    /// Naturally sorted, see [`sort_designators`]. Empty when the source had none.
+    #[serde(default)]
+    pub designators: Vec<String>,
+    /// Assembly variants fitting a different count than `count`
+    #[serde(default)]
+    pub variants: Vec<VariantCount>,
+}
+
+impl BomLine {
+    pub fn new(count: i64, part: Part) -> Self {
+        BomLine {
+            count,
+            part,
+            designators: vec![],
+            variants: vec![],
+        }
+    }
+}
+
+/// How many of a BOM line an assembly variant fits, when that differs from the base design
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct VariantCount {
+    pub variant: String,
+    pub count: i64,
+}
+
+/// The count of a line in `variant`, or in the base design for `None`
+pub fn count_in_variant(count: i64, variants: &[VariantCount], variant: Option<&str>) -> i64 {
+    variant
+        .and_then(|name| variants.iter().find(|v| v.variant == name))
+        .map_or(count, |v| v.count)
+}
+
+/// The lines of a BOM as built in `variant`, leaving out the ones it doesn't fit at all
+pub fn select_variant(
+    parts: &[PartWithCountAndStock],
+    variant: Option<&str>,
+) -> Vec<PartWithCountAndStock> {
+    parts
+        .iter()
+        .map(|p| PartWithCountAndStock {
+            count: count_in_variant(p.count, &p.variants, variant),
+            ..p.clone()
+        })
+        .filter(|p| p.count > 0)
+        .collect()
+}
+
+/// Stock a build takes from an alternate because the part its BOM line calls for ran short
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Substitution {
+    /// The part the line calls for
+    pub part_id: i64,
+    pub part_name: String,
+    pub alternate_id: i64,
+    pub alternate_name: String,
+    pub count: i64,
+}
+
+impl Display for Substitution {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} {} for {}",
+            self.count, self.alternate_name, self.part_name
+        )
+    }
+}
+
+/// Where a BOM line takes its parts from, see [`draw_with_alternates`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LineDraw {
+    pub part_id: i64,
+    pub needed: i64,
+    /// Taken from the line's own part
+    pub own: i64,
+    pub substitutions: Vec<Substitution>,
+}
+
+impl LineDraw {
+    /// What neither the part nor its alternates had
+    pub fn short(&self) -> i64 {
+        self.needed - self.own - self.substituted()
+    }
+
+    pub fn substituted(&self) -> i64 {
+        self.substitutions.iter().map(|s| s.count).sum()
+    }
+}
+
+/// Where `quantity` builds of `lines` take their parts from. Every line first takes what its own
+/// part has, then the lines still short take from their alternates in priority order, so an
+/// alternate only stands in once the lines calling for it are served. `available` is the stock
+/// each part can give, parts missing from it give the stock they have in `lines`. It is left
+/// holding what remains.
+pub fn draw_with_alternates(
+    lines: &[PartWithCountAndStock],
+    quantity: i64,
+    available: &mut HashMap<i64, i64>,
+) -> Vec<LineDraw> {
+    for line in lines {
+        available.entry(line.id).or_insert(line.stock);
+        for alternate in &line.alternates {
+            available.entry(alternate.id).or_insert(alternate.stock);
+        }
+    }
+    let mut take = |part_id: i64, wanted: i64| {
+        let left = available.get_mut(&part_id).unwrap();
+        let taken = wanted.min((*left).max(0));
+        *left -= taken;
+        taken
+    };
+    let mut draws: Vec<LineDraw> = lines
+        .iter()
+        .map(|line| {
+            let needed = line.count * quantity;
+            LineDraw {
+                part_id: line.id,
+                needed,
+                own: take(line.id, needed),
+                substitutions: vec![],
+            }
+        })
+        .collect();
+    for (line, draw) in lines.iter().zip(&mut draws) {
+        for alternate in &line.alternates {
+            let short = draw.short();
+            if short <= 0 {
+                break;
+            }
+            let count = take(alternate.id, short);
+            if count > 0 {
+                draw.substitutions.push(Substitution {
+                    part_id: line.id,
+                    part_name: line.name.clone(),
+                    alternate_id: alternate.id,
+                    alternate_name: alternate.name.clone(),
+                    count,
+                });
+            }
+        }
+    }
+    draws
+}
+
+/// The stock each part gives up for `draws`, as lines counting everything taken from the part.
+/// Deplete them with a diff of -1. What no part had stays on the line's own part, so the build
+/// still takes it below zero the way it would without alternates.
+pub fn drawn_parts(
+    lines: &[PartWithCountAndStock],
+    draws: &[LineDraw],
+) -> Vec<PartWithCountAndStock> {
+    let mut out = vec![];
+    for (line, draw) in lines.iter().zip(draws) {
+        let own = draw.needed - draw.substituted();
+        if own > 0 {
+            out.push(PartWithCountAndStock {
+                count: own,
+                alternates: vec![],
+                ..line.clone()
+            });
+        }
+        for substitution in &draw.substitutions {
+            let Some(alternate) = line
+                .alternates
+                .iter()
+                .find(|a| a.id == substitution.alternate_id)
+            else {
+                continue;
+            };
+            out.push(PartWithCountAndStock {
+                id: alternate.id,
+                name: alternate.name.clone(),
+                description: alternate.description.clone(),
+                count: substitution.count,
+                designators: vec![],
+                variants: vec![],
+                stock: alternate.stock,
+                column: alternate.column,
+                row: alternate.row,
+                z: alternate.z,
+                alternates: vec![],
+            });
+        }
+    }
+    out
 }
 
 impl PartialEq for Part {
@@ -27,6 +364,12 @@ pub struct PartWithCountAndStock {
     pub description: String,
     pub count: i64,
     #[serde(default)]
+    #[tabled(skip)]
+    pub designators: Vec<String>,
+    #[serde(default)]
+    #[tabled(skip)]
+    pub variants: Vec<VariantCount>,
+    #[serde(default)]
     pub stock: i64,
     #[serde(default = "default_bin_placement")]
     pub column: i64,
@@ -34,6 +377,11 @@ pub struct PartWithCountAndStock {
     pub row: i64,
     #[serde(default = "default_bin_placement")]
     pub z: i64,
+    /// Parts that may stand in for this one when it runs short, in priority order. The line's
+    /// own alternates come before the ones of the part.
+    #[serde(default)]
+    #[tabled(skip)]
+    pub alternates: Vec<PartWithStock>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, Tabled)]
@@ -42,6 +390,19 @@ pub struct PartWithStock {
     pub name: String,
     pub description: String,
     #[serde(default)]
+    pub manufacturer: String,
+    #[serde(default)]
+    pub mpn: String,
+    #[serde(default)]
+    #[tabled(skip)]
+    pub skus: Vec<SupplierSku>,
+    #[serde(default)]
+    #[tabled(skip)]
+    pub parameters: Parameters,
+    #[serde(default)]
+    #[tabled(skip)]
+    pub purchasing: PurchaseRules,
+    #[serde(default)]
     pub stock: i64,
     #[serde(default = "default_bin_placement")]
     pub column: i64,
@@ -51,6 +412,34 @@ pub struct PartWithStock {
     pub z: i64,
 }
 
+/// Where a part sits in the grid, without the id of the bin record itself
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BinLocation {
+    pub column: i64,
+    pub row: i64,
+    pub z: i64,
+}
+
+impl PartWithStock {
+    pub fn bin(&self) -> BinLocation {
+        BinLocation {
+            column: self.column,
+            row: self.row,
+            z: self.z,
+        }
+    }
+}
+
+impl PartWithCountAndStock {
+    pub fn bin(&self) -> BinLocation {
+        BinLocation {
+            column: self.column,
+            row: self.row,
+            z: self.z,
+        }
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize, Tabled)]
 #[serde(rename_all = "camelCase")]
 pub struct Bin {
@@ -74,6 +463,102 @@ pub struct Bom {
     pub id: i64,
     pub name: String,
     pub description: String,
+    /// Names of the assembly variants some line of the BOM differs in
+    #[serde(default)]
+    #[tabled(skip)]
+    pub variants: Vec<String>,
+    /// Counts up from 1 along the BOMs imported as revisions of each other
+    #[serde(default = "first_revision")]
+    pub revision: i64,
+    /// The BOM this one is the next revision of
+    #[serde(default)]
+    #[tabled(skip)]
+    pub revision_of: Option<i64>,
+}
+
+pub fn first_revision() -> i64 {
+    1
+}
+
+/// How a line differs between two BOMs
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum BomChange {
+    Added,
+    Removed,
+    /// Same part, different count or designators
+    Changed,
+}
+
+impl Display for BomChange {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            BomChange::Added => "added",
+            BomChange::Removed => "removed",
+            BomChange::Changed => "changed",
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BomDiffLine {
+    pub change: BomChange,
+    pub part_id: i64,
+    pub name: String,
+    pub description: String,
+    pub old_count: i64,
+    pub new_count: i64,
+    pub added_designators: Vec<String>,
+    pub removed_designators: Vec<String>,
+}
+
+/// Lines added, removed or changed going from the `old` BOM to the `new` one, matched by part.
+/// Added lines come first, then removed and changed ones, each sorted by name.
+pub fn diff_boms(old: &[PartWithCountAndStock], new: &[PartWithCountAndStock]) -> Vec<BomDiffLine> {
+    let not_in = |designators: &[String], other: &[String]| -> Vec<String> {
+        designators
+            .iter()
+            .filter(|d| !other.contains(d))
+            .cloned()
+            .collect()
+    };
+    let line = |change, part: &PartWithCountAndStock, old_count, new_count| BomDiffLine {
+        change,
+        part_id: part.id,
+        name: part.name.clone(),
+        description: part.description.clone(),
+        old_count,
+        new_count,
+        added_designators: vec![],
+        removed_designators: vec![],
+    };
+    let mut out = vec![];
+    for part in new {
+        match old.iter().find(|o| o.id == part.id) {
+            None => out.push(BomDiffLine {
+                added_designators: part.designators.clone(),
+                ..line(BomChange::Added, part, 0, part.count)
+            }),
+            Some(before) => {
+                let added = not_in(&part.designators, &before.designators);
+                let removed = not_in(&before.designators, &part.designators);
+                if before.count != part.count || !added.is_empty() || !removed.is_empty() {
+                    out.push(BomDiffLine {
+                        added_designators: added,
+                        removed_designators: removed,
+                        ..line(BomChange::Changed, part, before.count, part.count)
+                    });
+                }
+            }
+        }
+    }
+    for part in old.iter().filter(|o| !new.iter().any(|n| n.id == o.id)) {
+        out.push(BomDiffLine {
+            removed_designators: part.designators.clone(),
+            ..line(BomChange::Removed, part, part.count, 0)
+        });
+    }
+    out.sort_by(|a, b| a.change.cmp(&b.change).then(a.name.cmp(&b.name)));
+    out
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, Tabled)]
@@ -83,6 +568,16 @@ pub struct BomPartRelation {
     pub bom_id: i64,
     pub part_id: i64,
     pub count: i64,
+    #[serde(default)]
+    #[tabled(skip)]
+    pub designators: Vec<String>,
+    #[serde(default)]
+    #[tabled(skip)]
+    pub variants: Vec<VariantCount>,
+    /// Ids of the parts approved to stand in for the line's part, in priority order
+    #[serde(default)]
+    #[tabled(skip)]
+    pub alternates: Vec<i64>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, Default, Tabled)]
@@ -129,15 +624,34 @@ pub struct Session {
 #[derive(Debug, Clone, Serialize, Deserialize, Tabled)]
 #[serde(rename_all = "camelCase")]
 pub struct StockRows {
-    id: i64,
-    bin_id: i64,
-    row: i64,
-    column: i64,
-    z: i64,
-    part_id: i64,
-    name: String,
-    description: String,
-    stock: i64,
+    pub id: i64,
+    pub bin_id: i64,
+    pub row: i64,
+    pub column: i64,
+    pub z: i64,
+    pub part_id: i64,
+    pub name: String,
+    pub description: String,
+    pub stock: i64,
+}
+
+/// One change to the stock of a part, as recorded in the stock ledger
+#[derive(Debug, Clone, Serialize, Deserialize, Tabled)]
+#[serde(rename_all = "camelCase")]
+pub struct StockLedgerEntry {
+    pub id: i64,
+    pub timestamp: DateTime<Utc>,
+    /// Email of whoever made the change
+    pub user: String,
+    pub part_id: i64,
+    pub name: String,
+    pub row: i64,
+    pub column: i64,
+    pub z: i64,
+    pub delta: i64,
+    /// The stock after the change
+    pub stock: i64,
+    pub reason: String,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -150,9 +664,19 @@ pub struct BomWithParts {
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct PurchaseRequirement {
     pub part: PartWithStock,
+    /// Held by open build orders, so not available for the planned builds
+    #[serde(default)]
+    pub reserved: i64,
     pub required: i64,
+    /// What the stock and alternates don't cover, without any purchasing rules applied
     pub shortfall: i64,
+    /// What to order once attrition, safety stock, MOQ and order multiple are applied
+    #[serde(default)]
+    pub order_quantity: i64,
     pub bom_sources: Vec<BomSource>,
+    /// Alternates covering part of `required`, so they don't need to be bought
+    #[serde(default)]
+    pub substitutions: Vec<Substitution>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -163,3 +687,109 @@ pub struct BomSource {
     pub quantity_needed: i64,
     pub builds: i64,
 }
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum BuildStatus {
+    Planned,
+    /// The parts have been pulled from their bins but not used up yet
+    Kitted,
+    Built,
+    Cancelled,
+}
+
+impl BuildStatus {
+    /// Whether orders in this state hold on to their parts
+    pub fn reserves(self) -> bool {
+        matches!(self, BuildStatus::Planned | BuildStatus::Kitted)
+    }
+
+    /// Built and cancelled orders are final
+    pub fn can_become(self, next: BuildStatus) -> bool {
+        match self {
+            BuildStatus::Planned => next != BuildStatus::Planned,
+            BuildStatus::Kitted => matches!(next, BuildStatus::Built | BuildStatus::Cancelled),
+            BuildStatus::Built | BuildStatus::Cancelled => false,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            BuildStatus::Planned => "planned",
+            BuildStatus::Kitted => "kitted",
+            BuildStatus::Built => "built",
+            BuildStatus::Cancelled => "cancelled",
+        }
+    }
+}
+
+impl Display for BuildStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for BuildStatus {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "planned" => Ok(BuildStatus::Planned),
+            "kitted" => Ok(BuildStatus::Kitted),
+            "built" => Ok(BuildStatus::Built),
+            "cancelled" | "canceled" => Ok(BuildStatus::Cancelled),
+            _ => Err(format!("Unknown build status {}", s)),
+        }
+    }
+}
+
+/// A planned build of `quantity` boards of a BOM. Reserves the parts it needs until it is built
+/// or cancelled.
+#[derive(Debug, Clone, Serialize, Deserialize, Tabled)]
+#[serde(rename_all = "camelCase")]
+pub struct BuildOrder {
+    pub id: i64,
+    pub bom_id: i64,
+    pub bom_name: String,
+    /// The assembly variant being built, empty for the base design
+    #[serde(default)]
+    pub variant: String,
+    pub quantity: i64,
+    pub status: BuildStatus,
+    pub created: DateTime<Utc>,
+}
+
+impl BuildOrder {
+    pub fn variant(&self) -> Option<&str> {
+        Some(self.variant.as_str()).filter(|v| !v.is_empty())
+    }
+}
+
+/// How much of a part open build orders hold on to
+#[derive(Debug, Clone, Serialize, Deserialize, Tabled)]
+#[serde(rename_all = "camelCase")]
+pub struct Reservation {
+    pub part_id: i64,
+    pub reserved: i64,
+}
+
+/// A BOM using a part, see [`crate::backend::PartsBackend::where_used`]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BomUsage {
+    pub bom_id: i64,
+    pub bom_name: String,
+    pub revision: i64,
+    /// How many of the part one board takes
+    pub count: i64,
+    #[serde(default)]
+    pub variants: Vec<VariantCount>,
+}
+
+impl BomUsage {
+    /// How many boards `available` of the part is enough for, `None` when the BOM fits none of it
+    pub fn boards(&self, available: i64, variant: Option<&str>) -> Option<i64> {
+        let count = count_in_variant(self.count, &self.variants, variant);
+        (count > 0).then(|| available.max(0) / count)
+    }
+}
@@ -1,14 +1,25 @@
-use std::{collections::HashMap, fmt::Display, fs, io::Read, sync::Arc};
+use std::{fmt::Display, sync::Arc};
 
-use anyhow::{Result, anyhow};
-use futures::future::join_all;
+use anyhow::Result;
+use async_trait::async_trait;
 use reqwest::{Client, RequestBuilder};
 use reqwest_cookie_store::CookieStoreMutex;
-use serde::{Deserialize, Serialize};
-use tracing::{debug, error, info};
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+use tracing::{debug, error};
 use url::Url;
 
-use crate::models::{Bom, Part, PartWithCountAndStock, PartWithStock, Profile, StockRows, User};
+use crate::{
+    backend::{PartsBackend, part_variants, stock_parts_with_reverts},
+    error::NetworkError,
+    models::{
+        BinLocation, Bom, BomLine, BomUsage, BuildOrder, BuildStatus, Part, PartWithCountAndStock,
+        PartWithStock, Profile, PurchaseRules, Reservation, StockLedgerEntry, StockRows, User,
+    },
+};
+
+/// How often an absolute stock write is retried when someone else changed the stock between
+/// reading and writing it
+const STOCK_WRITE_ATTEMPTS: usize = 3;
 
 #[derive(Debug)]
 pub struct NetworkClient {
@@ -16,6 +27,79 @@ pub struct NetworkClient {
     base_url: Url,
     cookie_store: Arc<CookieStoreMutex>,
     pub user_data: UserData,
+    /// Whether the server has the delta stock endpoint, unknown until the first adjustment
+    supports_adjust: Option<bool>,
+    /// Whether the server can apply several stock changes in one transaction
+    supports_batch: Option<bool>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct StockBody {
+    profile_id: i64,
+    part_id: i64,
+    stock: i64,
+    column: i64,
+    row: i64,
+    z: i64,
+    /// Makes the server refuse the write with a conflict if the stock isn't this anymore
+    #[serde(skip_serializing_if = "Option::is_none")]
+    expected_stock: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reason: Option<String>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct AdjustStockBody {
+    profile_id: i64,
+    part_id: i64,
+    column: i64,
+    row: i64,
+    z: i64,
+    delta: i64,
+    reason: String,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct BatchLine {
+    part_id: i64,
+    column: i64,
+    row: i64,
+    z: i64,
+    delta: i64,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct BatchStockBody {
+    profile_id: i64,
+    reason: String,
+    lines: Vec<BatchLine>,
+}
+
+#[derive(Deserialize)]
+struct AdjustedStock {
+    stock: i64,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct NewBuildOrderBody {
+    profile_id: i64,
+    bom_id: i64,
+    quantity: i64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    variant: Option<String>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct BuildStatusBody {
+    profile_id: i64,
+    id: i64,
+    status: BuildStatus,
 }
 
 #[derive(Serialize)]
@@ -25,8 +109,55 @@ struct CreateProfileBody {
 
 #[derive(Serialize)]
 struct UpdateProfileBody {
-    id: i32,
+    id: i64,
+    name: String,
+}
+
+#[derive(Serialize)]
+struct MergePartsBody {
+    keep: i64,
+    merged: Vec<i64>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct UpdateBomBody {
+    profile_id: i64,
+    id: i64,
     name: String,
+    description: String,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct BomLineBody {
+    profile_id: i64,
+    bom_id: i64,
+    part_id: i64,
+    count: i64,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct PartAlternatesBody {
+    part_id: i64,
+    alternates: Vec<i64>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct PurchaseRulesBody {
+    part_id: i64,
+    rules: PurchaseRules,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct BomLineAlternatesBody {
+    profile_id: i64,
+    bom_id: i64,
+    part_id: i64,
+    alternates: Vec<i64>,
 }
 
 #[derive(Debug, Default, Serialize, Deserialize, Clone)]
@@ -42,7 +173,7 @@ impl UserData {
 }
 
 impl NetworkClient {
-    fn build_get(&self, route: &str, params: &[(impl Display, impl Display)]) -> RequestBuilder {
+    fn url_with_query(&self, route: &str, params: &[(impl Display, impl Display)]) -> Url {
         let mut query_string = String::new();
         for (k, v) in params {
             query_string.push_str(&format!("&{}={}", k, v));
@@ -52,7 +183,25 @@ impl NetworkClient {
         }
         let mut url = self.base_url.join(route).unwrap();
         url.set_query(Some(&query_string));
-        self.client.get(url.as_str())
+        url
+    }
+
+    fn build_get(&self, route: &str, params: &[(impl Display, impl Display)]) -> RequestBuilder {
+        self.client.get(self.url_with_query(route, params).as_str())
+    }
+
+    fn build_delete(&self, route: &str, params: &[(impl Display, impl Display)]) -> RequestBuilder {
+        self.client
+            .delete(self.url_with_query(route, params).as_str())
+    }
+
+    fn build_put<T>(&self, route: &str, body: &T) -> RequestBuilder
+    where
+        T: Serialize + ?Sized,
+    {
+        self.client
+            .put(self.base_url.join(route).unwrap().as_str())
+            .json(body)
     }
 
     fn build_post<T>(&self, route: &str, body: &T) -> RequestBuilder
@@ -64,6 +213,64 @@ impl NetworkClient {
             .json(body)
     }
 
+    async fn send(request: RequestBuilder) -> Result<String, NetworkError> {
+        let resp = request.send().await?;
+        let status = resp.status();
+        let body = resp.text().await?;
+        if status.is_success() {
+            Ok(body)
+        } else {
+            Err(NetworkError::from_response(status, body))
+        }
+    }
+
+    async fn send_json<T: DeserializeOwned>(request: RequestBuilder) -> Result<T, NetworkError> {
+        let body = Self::send(request).await?;
+        Ok(serde_json::from_str(&body)?)
+    }
+
+    /// Read-modify-write fallback for servers that only take absolute stock values. The write
+    /// carries the stock it was based on so a concurrent change is refused instead of clobbered.
+    async fn compare_and_set_stock(
+        &mut self,
+        profile_id: i64,
+        part_id: i64,
+        bin: BinLocation,
+        delta: i64,
+        reason: String,
+    ) -> Result<i64, NetworkError> {
+        for _ in 0..STOCK_WRITE_ATTEMPTS {
+            let current = self
+                .list_stock(profile_id)
+                .await?
+                .into_iter()
+                .find(|r| r.part_id == part_id)
+                .map(|r| r.stock)
+                .unwrap_or(0);
+            let body = StockBody {
+                profile_id,
+                part_id,
+                stock: current + delta,
+                column: bin.column,
+                row: bin.row,
+                z: bin.z,
+                expected_stock: Some(current),
+                reason: Some(reason.clone()),
+            };
+            match Self::send(self.build_post("/api/stock", &body)).await {
+                Ok(_) => return Ok(current + delta),
+                Err(NetworkError::Conflict(msg)) => {
+                    debug!("Stock of part {} changed underneath us: {}", part_id, msg);
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        Err(NetworkError::Conflict(format!(
+            "Stock of part {} kept changing, gave up after {} attempts",
+            part_id, STOCK_WRITE_ATTEMPTS
+        )))
+    }
+
     fn cookie_store() -> std::sync::Arc<reqwest_cookie_store::CookieStoreMutex> {
         let cookie_store = {
             if let Ok(file) = std::fs::File::open(".cookies.json").map(std::io::BufReader::new) {
@@ -92,6 +299,8 @@ impl NetworkClient {
             base_url: Url::parse("http://localhost:3000").unwrap(),
             cookie_store,
             user_data,
+            supports_adjust: None,
+            supports_batch: None,
         }
     }
 
@@ -110,52 +319,60 @@ impl NetworkClient {
             base_url: Url::parse("https://bom.vincentuden.xyz").unwrap(),
             cookie_store,
             user_data,
+            supports_adjust: None,
+            supports_batch: None,
         }
     }
+}
 
-    pub fn host_name(&self) -> String {
-        self.base_url.host_str().unwrap_or_default().to_string()
+#[async_trait]
+impl PartsBackend for NetworkClient {
+    fn user_data(&self) -> &UserData {
+        &self.user_data
     }
 
-    pub async fn create_user(&mut self, pending: User) -> Result<()> {
-        let resp_text = self
-            .build_post("/api/user/create", &pending)
-            .send()
-            .await?
-            .text()
-            .await?;
-        println!("{:?}", resp_text);
+    fn user_data_mut(&mut self) -> &mut UserData {
+        &mut self.user_data
+    }
 
+    fn host_name(&self) -> String {
+        self.base_url.host_str().unwrap_or_default().to_string()
+    }
+
+    async fn create_user(&mut self, pending: User) -> Result<(), NetworkError> {
+        Self::send(self.build_post("/api/user/create", &pending)).await?;
         Ok(())
     }
 
-    pub async fn login(&mut self, user: User) -> Result<()> {
-        let _resp_text = self.build_post("/api/user/session", &user).send().await?;
+    async fn login(&mut self, user: User) -> Result<(), NetworkError> {
+        Self::send(self.build_post("/api/user/session", &user)).await?;
         {
             let cs = self.cookie_store.lock().unwrap();
             if let Ok(mut file) =
                 std::fs::File::create(".cookies.json").map(std::io::BufWriter::new)
             {
-                println!("Writing");
+                debug!("Writing session cookies");
                 #[allow(deprecated)]
                 cs.save_incl_expired_and_nonpersistent_json(&mut file)
                     .unwrap();
                 self.user_data.user = Some(user);
             } else {
-                println!("Couldnt open file");
+                error!("Couldnt open cookie file");
             }
         };
         let profiles = self.get_profiles(None).await?;
         self.user_data.profile = profiles.first().cloned();
-        self.user_data.save()?;
+        if let Err(e) = self.user_data.save() {
+            error!("Couldnt save user data: {}", e);
+        }
         Ok(())
     }
 
-    pub async fn get_parts(
+    async fn get_parts(
         &mut self,
         name: Option<String>,
         description: Option<String>,
-    ) -> Result<Vec<Part>> {
+    ) -> Result<Vec<Part>, NetworkError> {
         let mut params = vec![];
         if let Some(name) = name {
             params.push(("name", name));
@@ -163,62 +380,105 @@ impl NetworkClient {
         if let Some(description) = description {
             params.push(("description", description));
         }
-        let resp = self
-            .build_get("/api/parts", &params)
-            .send()
-            .await?
-            .text()
-            .await?;
-        Ok(serde_json::from_str(&resp)?)
+        Self::send_json(self.build_get("/api/parts", &params)).await
     }
 
-    pub async fn new_part(&mut self, part: Part) -> Result<()> {
-        let resp_text = self
-            .build_post("/api/parts", &part)
-            .send()
-            .await?
-            .text()
-            .await?;
-        println!("{:?}", resp_text);
+    async fn new_part(&mut self, part: Part) -> Result<(), NetworkError> {
+        Self::send(self.build_post("/api/parts", &part)).await?;
+        Ok(())
+    }
+
+    async fn update_part(&mut self, part: Part) -> Result<(), NetworkError> {
+        Self::send(self.build_put("/api/parts", &part)).await?;
+        Ok(())
+    }
+
+    async fn set_part_alternates(
+        &mut self,
+        part_id: i64,
+        alternates: Vec<i64>,
+    ) -> Result<(), NetworkError> {
+        let body = PartAlternatesBody {
+            part_id,
+            alternates,
+        };
+        Self::send(self.build_put("/api/parts/alternates", &body)).await?;
         Ok(())
     }
 
-    pub async fn get_profiles(&mut self, name: Option<String>) -> Result<Vec<Profile>> {
+    async fn set_purchase_rules(
+        &mut self,
+        part_id: i64,
+        rules: PurchaseRules,
+    ) -> Result<(), NetworkError> {
+        let body = PurchaseRulesBody { part_id, rules };
+        match Self::send(self.build_put("/api/parts/purchase-rules", &body)).await {
+            Err(NetworkError::NotFound(_) | NetworkError::Server { status: 405, .. }) => {
+                debug!("Server has no purchase rules endpoint");
+                Err(NetworkError::Unsupported(String::from("purchase rules")))
+            }
+            result => result.map(|_| ()),
+        }
+    }
+
+    async fn delete_part(&mut self, part_id: i64) -> Result<(), NetworkError> {
+        Self::send(self.build_delete("/api/parts", &[("id", part_id)])).await?;
+        Ok(())
+    }
+
+    async fn merge_parts(&mut self, keep: i64, merged: Vec<i64>) -> Result<Part, NetworkError> {
+        let body = MergePartsBody { keep, merged };
+        match Self::send_json(self.build_post("/api/parts/merge", &body)).await {
+            // The other endpoints can't move BOM lines over to the kept part, so rather than
+            // merging half of it nothing is merged
+            Err(NetworkError::NotFound(_) | NetworkError::Server { status: 405, .. }) => {
+                debug!("Server has no merge endpoint");
+                Err(NetworkError::Unsupported(String::from("merging parts")))
+            }
+            result => result,
+        }
+    }
+
+    async fn get_profiles(&mut self, name: Option<String>) -> Result<Vec<Profile>, NetworkError> {
         let mut params = vec![];
         if let Some(name) = name {
             params.push(("name", name));
         }
-        let resp = self
-            .build_get("/api/profile", &params)
-            .send()
-            .await?
-            .text()
-            .await?;
-        Ok(serde_json::from_str(&resp)?)
+        Self::send_json(self.build_get("/api/profile", &params)).await
     }
 
-    pub async fn new_profile(&mut self, name: String) -> Result<()> {
+    async fn new_profile(&mut self, name: String) -> Result<(), NetworkError> {
         let body = CreateProfileBody { name };
-        let _resp_text = self
-            .build_post("/api/profile", &body)
-            .send()
-            .await?
-            .text()
-            .await?;
+        Self::send(self.build_post("/api/profile", &body)).await?;
         Ok(())
     }
 
-    pub async fn list_stock(&mut self, profile_id: i64) -> Result<Vec<StockRows>> {
-        let resp_text = self
-            .build_get("/api/stock", &[("profileId", profile_id)])
-            .send()
-            .await?
-            .text()
-            .await?;
-        Ok(serde_json::from_str(&resp_text)?)
+    async fn update_profile(&mut self, profile_id: i64, name: String) -> Result<(), NetworkError> {
+        let body = UpdateProfileBody {
+            id: profile_id,
+            name,
+        };
+        Self::send(self.build_put("/api/profile", &body)).await?;
+        // Keep the selected profile in step with its new name
+        if let Some(selected) = self
+            .user_data
+            .profile
+            .as_mut()
+            .filter(|p| p.id == profile_id)
+        {
+            selected.name = body.name;
+            if let Err(e) = self.user_data.save() {
+                error!("Couldnt save user data: {}", e);
+            }
+        }
+        Ok(())
+    }
+
+    async fn list_stock(&mut self, profile_id: i64) -> Result<Vec<StockRows>, NetworkError> {
+        Self::send_json(self.build_get("/api/stock", &[("profileId", profile_id)])).await
     }
 
-    pub async fn stock_part(
+    async fn stock_part(
         &mut self,
         profile_id: i64,
         part_id: i64,
@@ -226,17 +486,7 @@ impl NetworkClient {
         column: i64,
         row: i64,
         z: i64,
-    ) -> Result<()> {
-        #[derive(Serialize)]
-        #[serde(rename_all = "camelCase")]
-        struct StockBody {
-            profile_id: i64,
-            part_id: i64,
-            stock: i64,
-            column: i64,
-            row: i64,
-            z: i64,
-        }
+    ) -> Result<(), NetworkError> {
         let body = StockBody {
             profile_id,
             part_id,
@@ -244,72 +494,118 @@ impl NetworkClient {
             column,
             row,
             z,
+            expected_stock: None,
+            reason: None,
         };
-        let _resp_text = self
-            .build_post("/api/stock", &body)
-            .send()
-            .await?
-            .text()
-            .await?;
-
+        Self::send(self.build_post("/api/stock", &body)).await?;
         Ok(())
     }
 
-    pub async fn stock_parts(
+    async fn adjust_stock(
+        &mut self,
+        profile_id: i64,
+        part_id: i64,
+        bin: BinLocation,
+        delta: i64,
+        reason: String,
+    ) -> Result<i64, NetworkError> {
+        if self.supports_adjust != Some(false) {
+            let body = AdjustStockBody {
+                profile_id,
+                part_id,
+                column: bin.column,
+                row: bin.row,
+                z: bin.z,
+                delta,
+                reason: reason.clone(),
+            };
+            match Self::send_json::<AdjustedStock>(self.build_post("/api/stock/adjust", &body))
+                .await
+            {
+                Ok(adjusted) => {
+                    self.supports_adjust = Some(true);
+                    return Ok(adjusted.stock);
+                }
+                Err(NetworkError::NotFound(_) | NetworkError::Server { status: 405, .. })
+                    if self.supports_adjust.is_none() =>
+                {
+                    debug!("Server has no delta stock endpoint, falling back to absolute writes");
+                    self.supports_adjust = Some(false);
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        self.compare_and_set_stock(profile_id, part_id, bin, delta, reason)
+            .await
+    }
+
+    async fn stock_parts(
         &mut self,
         profile_id: i64,
         parts: &[PartWithCountAndStock],
         diff: i64,
-    ) -> Result<()> {
-        let mut futures = vec![];
-        #[derive(Serialize)]
-        #[serde(rename_all = "camelCase")]
-        struct StockBody {
-            profile_id: i64,
-            part_id: i64,
-            stock: i64,
-            column: i64,
-            row: i64,
-            z: i64,
-        }
-        for p in parts {
-            let body = StockBody {
+        reason: String,
+    ) -> Result<(), NetworkError> {
+        if self.supports_batch != Some(false) {
+            let body = BatchStockBody {
                 profile_id,
-                part_id: p.id,
-                stock: p.stock + diff * p.count,
-                column: p.column,
-                row: p.row,
-                z: p.z,
+                reason: reason.clone(),
+                lines: parts
+                    .iter()
+                    .map(|p| BatchLine {
+                        part_id: p.id,
+                        column: p.column,
+                        row: p.row,
+                        z: p.z,
+                        delta: diff * p.count,
+                    })
+                    .collect(),
             };
-            futures.push(self.build_post("/api/stock", &body).send());
-        }
-        let results = join_all(futures).await;
-        let mut success = true;
-        for (i, r) in results.into_iter().enumerate() {
-            match r {
-                Ok(resp) => {
-                    let text = resp.text().await?;
-                    info!("{}", text);
+            // The server applies all lines or none, so its errors can be passed on as they are
+            match Self::send(self.build_post("/api/stock/batch", &body)).await {
+                Ok(_) => {
+                    self.supports_batch = Some(true);
+                    return Ok(());
                 }
-                Err(e) => {
-                    success = false;
-                    error!("Error: {:?} while stocking {:?}", e, parts[i].name)
+                Err(NetworkError::NotFound(_) | NetworkError::Server { status: 405, .. })
+                    if self.supports_batch.is_none() =>
+                {
+                    debug!("Server has no batch stock endpoint, reverting by hand on failure");
+                    self.supports_batch = Some(false);
                 }
+                Err(e) => return Err(e),
             }
         }
-        if success {
-            Ok(())
-        } else {
-            Err(anyhow!("Couldnt change stock"))
+        stock_parts_with_reverts(self, profile_id, parts, diff, reason).await
+    }
+
+    async fn remove_from_bin(&mut self, profile_id: i64, part_id: i64) -> Result<(), NetworkError> {
+        Self::send(self.build_delete(
+            "/api/stock",
+            &[("profileId", profile_id), ("partId", part_id)],
+        ))
+        .await?;
+        Ok(())
+    }
+
+    async fn stock_history(
+        &mut self,
+        profile_id: i64,
+        part_id: Option<i64>,
+    ) -> Result<Vec<StockLedgerEntry>, NetworkError> {
+        let mut params = vec![("profileId", profile_id)];
+        if let Some(part_id) = part_id {
+            params.push(("partId", part_id));
         }
+        Self::send_json(self.build_get("/api/stock/history", &params)).await
     }
 
-    pub async fn list_boms(
+    async fn list_boms(
         &mut self,
         profile_id: i64,
         bom_id: Option<i64>,
         bom_name: Option<String>,
-    ) -> Result<Vec<Bom>> {
+    ) -> Result<Vec<Bom>, NetworkError> {
         let mut params = vec![("profileId", format!("{}", profile_id))];
         if let Some(bom_id) = bom_id {
             params.push(("bomId", format!("{}", bom_id)));
@@ -317,81 +613,174 @@ impl NetworkClient {
         if let Some(bom_name) = bom_name {
             params.push(("bomName", bom_name));
         }
-        let resp_text = self
-            .build_get("/api/bom", &params)
-            .send()
-            .await?
-            .text()
-            .await?;
-        Ok(serde_json::from_str(&resp_text)?)
+        let mut boms: Vec<Bom> = Self::send_json(self.build_get("/api/bom", &params)).await?;
+        // The server doesn't list the variants of a BOM, so a single one looked up by id gets
+        // them from its lines
+        if bom_id.is_some() {
+            for bom in boms.iter_mut().filter(|b| b.variants.is_empty()) {
+                bom.variants = part_variants(&self.parts_in_bom(profile_id, bom.id).await?);
+            }
+        }
+        Ok(boms)
     }
 
-    pub async fn new_bom(
+    async fn new_bom(
         &mut self,
         profile_id: i64,
         name: String,
         description: String,
-        candidates: Vec<(i64, Part)>,
-    ) -> Result<()> {
-        #[derive(Serialize)]
-        #[serde(rename_all = "camelCase")]
-        struct BomRow {
-            count: i64,
-            part: Part,
-        }
-
+        candidates: Vec<BomLine>,
+        revision_of: Option<i64>,
+    ) -> Result<(), NetworkError> {
         #[derive(Serialize)]
         #[serde(rename_all = "camelCase")]
         struct BomBody {
             profile_id: i64,
             name: String,
             description: String,
-            parts: Vec<BomRow>,
+            parts: Vec<BomLine>,
+            #[serde(skip_serializing_if = "Option::is_none")]
+            revision_of: Option<i64>,
         }
 
         let body = BomBody {
             profile_id,
             name,
             description,
-            parts: candidates
-                .into_iter()
-                .map(|(count, part)| BomRow { count, part })
-                .collect(),
+            parts: candidates,
+            revision_of,
         };
 
-        let resp_text = self
-            .build_post("/api/bom", &body)
-            .send()
-            .await?
-            .text()
+        Self::send(self.build_post("/api/bom", &body)).await?;
+        Ok(())
+    }
+
+    async fn update_bom(
+        &mut self,
+        profile_id: i64,
+        bom_id: i64,
+        name: String,
+        description: String,
+    ) -> Result<(), NetworkError> {
+        let body = UpdateBomBody {
+            profile_id,
+            id: bom_id,
+            name,
+            description,
+        };
+        Self::send(self.build_put("/api/bom", &body)).await?;
+        Ok(())
+    }
+
+    async fn delete_bom(&mut self, profile_id: i64, bom_id: i64) -> Result<(), NetworkError> {
+        Self::send(self.build_delete("/api/bom", &[("profileId", profile_id), ("bomId", bom_id)]))
             .await?;
-        println!("{}", resp_text);
         Ok(())
     }
 
-    pub async fn parts_in_bom(
+    async fn update_bom_line(
         &mut self,
         profile_id: i64,
         bom_id: i64,
-    ) -> Result<Vec<PartWithCountAndStock>> {
-        let resp = self
-            .build_get("/api/bom/parts", &[
+        part_id: i64,
+        count: i64,
+    ) -> Result<(), NetworkError> {
+        let body = BomLineBody {
+            profile_id,
+            bom_id,
+            part_id,
+            count,
+        };
+        Self::send(self.build_put("/api/bom/parts", &body)).await?;
+        Ok(())
+    }
+
+    async fn set_bom_line_alternates(
+        &mut self,
+        profile_id: i64,
+        bom_id: i64,
+        part_id: i64,
+        alternates: Vec<i64>,
+    ) -> Result<(), NetworkError> {
+        let body = BomLineAlternatesBody {
+            profile_id,
+            bom_id,
+            part_id,
+            alternates,
+        };
+        Self::send(self.build_put("/api/bom/parts/alternates", &body)).await?;
+        Ok(())
+    }
+
+    async fn delete_bom_line(
+        &mut self,
+        profile_id: i64,
+        bom_id: i64,
+        part_id: i64,
+    ) -> Result<(), NetworkError> {
+        Self::send(self.build_delete(
+            "/api/bom/parts",
+            &[
                 ("profileId", profile_id),
                 ("bomId", bom_id),
-            ])
-            .send()
-            .await?
-            .text()
-            .await?;
-        Ok(serde_json::from_str(&resp)?)
+                ("partId", part_id),
+            ],
+        ))
+        .await?;
+        Ok(())
+    }
+
+    async fn parts_in_bom(
+        &mut self,
+        profile_id: i64,
+        bom_id: i64,
+    ) -> Result<Vec<PartWithCountAndStock>, NetworkError> {
+        Self::send_json(self.build_get(
+            "/api/bom/parts",
+            &[("profileId", profile_id), ("bomId", bom_id)],
+        ))
+        .await
     }
 
-    pub async fn parts_with_stock(
+    async fn where_used(
+        &mut self,
+        profile_id: i64,
+        part_id: i64,
+    ) -> Result<Vec<BomUsage>, NetworkError> {
+        match Self::send_json(self.build_get(
+            "/api/parts/where-used",
+            &[("profileId", profile_id), ("partId", part_id)],
+        ))
+        .await
+        {
+            // Older servers only list the lines of a BOM, so every BOM gets looked through
+            Err(NetworkError::NotFound(_) | NetworkError::Server { status: 405, .. }) => {
+                debug!("Server has no where-used endpoint, looking through every BOM");
+                let mut usages = vec![];
+                for bom in self.list_boms(profile_id, None, None).await? {
+                    let lines = self.parts_in_bom(profile_id, bom.id).await?;
+                    if let Some(line) = lines.into_iter().find(|l| l.id == part_id) {
+                        usages.push(BomUsage {
+                            bom_id: bom.id,
+                            bom_name: bom.name,
+                            revision: bom.revision,
+                            count: line.count,
+                            variants: line.variants,
+                        });
+                    }
+                }
+                Ok(usages)
+            }
+            result => result,
+        }
+    }
+
+    async fn parts_with_stock(
         &mut self,
         name: Option<String>,
         description: Option<String>,
         profile_id: i64,
-    ) -> Result<Vec<PartWithStock>> {
+    ) -> Result<Vec<PartWithStock>, NetworkError> {
         let mut params = vec![("profileId", format!("{}", profile_id))];
         if let Some(name) = name {
             params.push(("name", name));
@@ -399,13 +788,56 @@ impl NetworkClient {
         if let Some(description) = description {
             params.push(("description", description));
         }
-        let resp = self
-            .build_get("/api/parts/stock", &params)
-            .send()
-            .await?
-            .text()
-            .await?;
-        info!("{}", resp);
-        Ok(serde_json::from_str(&resp)?)
+        Self::send_json(self.build_get("/api/parts/stock", &params)).await
+    }
+    async fn list_build_orders(
+        &mut self,
+        profile_id: i64,
+    ) -> Result<Vec<BuildOrder>, NetworkError> {
+        Self::send_json(self.build_get("/api/build", &[("profileId", profile_id)])).await
+    }
+
+    async fn new_build_order(
+        &mut self,
+        profile_id: i64,
+        bom_id: i64,
+        quantity: i64,
+        variant: Option<String>,
+    ) -> Result<BuildOrder, NetworkError> {
+        let body = NewBuildOrderBody {
+            profile_id,
+            bom_id,
+            quantity,
+            variant,
+        };
+        Self::send_json(self.build_post("/api/build", &body)).await
+    }
+
+    async fn set_build_status(
+        &mut self,
+        profile_id: i64,
+        order_id: i64,
+        status: BuildStatus,
+    ) -> Result<(), NetworkError> {
+        let body = BuildStatusBody {
+            profile_id,
+            id: order_id,
+            status,
+        };
+        Self::send(self.build_post("/api/build/status", &body)).await?;
+        Ok(())
+    }
+
+    async fn reservations(&mut self, profile_id: i64) -> Result<Vec<Reservation>, NetworkError> {
+        match Self::send_json(self.build_get("/api/build/reserved", &[("profileId", profile_id)]))
+            .await
+        {
+            // Servers without build orders hold nothing back
+            Err(NetworkError::NotFound(_) | NetworkError::Server { status: 405, .. }) => {
+                debug!("Server has no reservations endpoint, treating nothing as reserved");
+                Ok(vec![])
+            }
+            result => result,
+        }
     }
 }
@@ -0,0 +1,603 @@
+use std::fmt::Display;
+
+use serde::{Deserialize, Serialize};
+
+use crate::models::{Part, PartWithStock, SupplierSku};
+
+/// Chip sizes written as bare numbers, which would otherwise be read as values
+const CHIP_PACKAGES: &[&str] = &[
+    "01005", "0201", "0402", "0603", "0805", "1206", "1210", "1812", "2010", "2512",
+];
+const PACKAGE_PREFIXES: &[&str] = &[
+    "SOT", "SOD", "SOIC", "SSOP", "TSSOP", "MSOP", "QFN", "DFN", "QFP", "LQFP", "TQFP", "BGA",
+    "TO-", "DIP", "SMA", "SMB", "SMC",
+];
+const DIELECTRICS: &[&str] = &[
+    "C0G", "NP0", "X5R", "X6S", "X7R", "X7S", "X8R", "Y5V", "Z5U",
+];
+
+/// Electrical parameters of a part, in base SI units.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Parameters {
+    /// Ohms
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub resistance: Option<f64>,
+    /// Farads
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub capacitance: Option<f64>,
+    /// Henries
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub inductance: Option<f64>,
+    /// Voltage rating in volts
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub voltage: Option<f64>,
+    /// Percent
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tolerance: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub package: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dielectric: Option<String>,
+}
+
+const FIELDS: &[Field] = &[
+    Field::Resistance,
+    Field::Capacitance,
+    Field::Inductance,
+    Field::Voltage,
+    Field::Tolerance,
+    Field::Package,
+    Field::Dielectric,
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Field {
+    Resistance,
+    Capacitance,
+    Inductance,
+    Voltage,
+    Tolerance,
+    Package,
+    Dielectric,
+}
+
+impl Field {
+    fn from_key(key: &str) -> Option<Self> {
+        match key.to_lowercase().as_str() {
+            "r" | "res" | "resistance" => Some(Field::Resistance),
+            "c" | "cap" | "capacitance" => Some(Field::Capacitance),
+            "l" | "ind" | "inductance" => Some(Field::Inductance),
+            "v" | "volt" | "voltage" => Some(Field::Voltage),
+            "tol" | "tolerance" => Some(Field::Tolerance),
+            "pkg" | "package" | "footprint" => Some(Field::Package),
+            "diel" | "dielectric" => Some(Field::Dielectric),
+            _ => None,
+        }
+    }
+
+    fn is_numeric(self) -> bool {
+        !matches!(self, Field::Package | Field::Dielectric)
+    }
+}
+
+/// Unit symbols and the field they imply
+const UNITS: &[(&str, Field)] = &[
+    ("ohms", Field::Resistance),
+    ("ohm", Field::Resistance),
+    ("Ω", Field::Resistance),
+    ("F", Field::Capacitance),
+    ("H", Field::Inductance),
+    ("V", Field::Voltage),
+    ("%", Field::Tolerance),
+];
+
+/// Catalogue descriptions often shout, as in "1UF", so after a unit the small prefixes are also
+/// accepted in upper case. Without one "1N4148" would read as a capacitor.
+fn multiplier(prefix: char, after_unit: bool) -> Option<f64> {
+    match prefix {
+        'P' | 'N' | 'U' if after_unit => multiplier(prefix.to_ascii_lowercase(), false),
+        'p' => Some(1e-12),
+        'n' => Some(1e-9),
+        'u' | 'µ' | 'μ' => Some(1e-6),
+        'm' => Some(1e-3),
+        'R' | 'r' => Some(1.0),
+        'k' | 'K' => Some(1e3),
+        'M' => Some(1e6),
+        'G' => Some(1e9),
+        _ => None,
+    }
+}
+
+/// Reads a number in engineering notation, with the prefix either after the number (`100n`) or
+/// in place of the decimal point (`4k7`, `4R7`). Returns the value and the prefix, if any.
+fn parse_engineering(s: &str, after_unit: bool) -> Option<(f64, Option<char>)> {
+    let Some((idx, prefix)) = s
+        .char_indices()
+        .find(|(_, c)| !c.is_ascii_digit() && *c != '.')
+    else {
+        return s.parse().ok().map(|v| (v, None));
+    };
+    let mult = multiplier(prefix, after_unit)?;
+    let (whole, rest) = (&s[..idx], &s[idx + prefix.len_utf8()..]);
+    if whole.is_empty() || !rest.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    let number = if rest.is_empty() {
+        whole.to_string()
+    } else if whole.contains('.') {
+        return None;
+    } else {
+        format!("{}.{}", whole, rest)
+    };
+    number.parse::<f64>().ok().map(|v| (v * mult, Some(prefix)))
+}
+
+/// Reads a value like `10k`, `4.7uF`, `25V` or `1%`, along with the field its unit or prefix
+/// implies. Bare numbers imply nothing.
+pub fn parse_value(token: &str) -> Option<(f64, Option<Field>)> {
+    let token = token.trim().trim_start_matches('±');
+    for (unit, field) in UNITS {
+        if let Some(number) = token.strip_suffix(unit) {
+            return parse_engineering(number, true).map(|(v, _)| (v, Some(*field)));
+        }
+    }
+    let (value, prefix) = parse_engineering(token, false)?;
+    let field = match prefix {
+        Some('k' | 'K' | 'M' | 'G' | 'R' | 'r') => Some(Field::Resistance),
+        Some('p' | 'n' | 'u' | 'µ' | 'μ') => Some(Field::Capacitance),
+        _ => None,
+    };
+    Some((value, field))
+}
+
+fn parse_package(token: &str) -> Option<String> {
+    let upper = token.to_uppercase();
+    if CHIP_PACKAGES.contains(&upper.as_str())
+        || PACKAGE_PREFIXES.iter().any(|p| upper.starts_with(p))
+    {
+        Some(upper)
+    } else {
+        None
+    }
+}
+
+fn parse_dielectric(token: &str) -> Option<String> {
+    let upper = token
+        .to_uppercase()
+        .replace("COG", "C0G")
+        .replace("NPO", "NP0");
+    DIELECTRICS.contains(&upper.as_str()).then_some(upper)
+}
+
+/// Formats a value with an engineering prefix, e.g. 4700 as `4.7k`
+pub fn format_value(value: f64) -> String {
+    const PREFIXES: &[(f64, &str)] = &[
+        (1e9, "G"),
+        (1e6, "M"),
+        (1e3, "k"),
+        (1.0, ""),
+        (1e-3, "m"),
+        (1e-6, "µ"),
+        (1e-9, "n"),
+        (1e-12, "p"),
+    ];
+    // Zero is below every prefix, but has no need for one
+    if value == 0.0 {
+        return String::from("0");
+    }
+    let (scale, prefix) = PREFIXES
+        .iter()
+        .find(|(scale, _)| value.abs() >= *scale * 0.999_999)
+        .unwrap_or(&PREFIXES[PREFIXES.len() - 1]);
+    let scaled = format!("{:.3}", value / scale);
+    let scaled = scaled.trim_end_matches('0').trim_end_matches('.');
+    format!("{}{}", scaled, prefix)
+}
+
+fn approx_eq(a: f64, b: f64) -> bool {
+    (a - b).abs() <= a.abs().max(b.abs()) * 1e-6
+}
+
+impl Parameters {
+    /// Picks out whatever parameters a free form text like "10k 1% 0603" mentions.
+    pub fn from_text(text: &str) -> Self {
+        let mut out = Parameters::default();
+        let tokens = text
+            .split(|c: char| c.is_whitespace() || matches!(c, ',' | ';' | '_' | ':'))
+            .filter(|t| !t.is_empty());
+        for token in tokens {
+            if let Some(package) = parse_package(token) {
+                out.package.get_or_insert(package);
+            } else if let Some(dielectric) = parse_dielectric(token) {
+                out.dielectric.get_or_insert(dielectric);
+            } else if let Some((value, Some(field))) = parse_value(token) {
+                out.set_number(field, value);
+            }
+        }
+        out
+    }
+
+    pub fn is_empty(&self) -> bool {
+        *self == Parameters::default()
+    }
+
+    /// How many fields are set
+    pub fn len(&self) -> usize {
+        FIELDS
+            .iter()
+            .filter(|f| self.number(**f).is_some() || self.text(**f).is_some())
+            .count()
+    }
+
+    fn number(&self, field: Field) -> Option<f64> {
+        match field {
+            Field::Resistance => self.resistance,
+            Field::Capacitance => self.capacitance,
+            Field::Inductance => self.inductance,
+            Field::Voltage => self.voltage,
+            Field::Tolerance => self.tolerance,
+            Field::Package | Field::Dielectric => None,
+        }
+    }
+
+    fn text(&self, field: Field) -> Option<&str> {
+        match field {
+            Field::Package => self.package.as_deref(),
+            Field::Dielectric => self.dielectric.as_deref(),
+            _ => None,
+        }
+    }
+
+    /// Keeps the first value of each field, so "10k 1% 25V 100k" is a 10k resistor
+    fn set_number(&mut self, field: Field, value: f64) {
+        let slot = match field {
+            Field::Resistance => &mut self.resistance,
+            Field::Capacitance => &mut self.capacitance,
+            Field::Inductance => &mut self.inductance,
+            Field::Voltage => &mut self.voltage,
+            Field::Tolerance => &mut self.tolerance,
+            Field::Package | Field::Dielectric => return,
+        };
+        slot.get_or_insert(value);
+    }
+
+    /// Share of the fields set on both that agree, or `None` when any of them differ or no
+    /// value (resistance, capacitance or inductance) is shared. "10k 0603" and "10k 1% 0603"
+    /// agree fully, "10k" and "100k" not at all.
+    pub fn agreement(&self, other: &Parameters) -> Option<f64> {
+        let mut shared = 0;
+        let mut shares_value = false;
+        for field in FIELDS {
+            let same = match (self.number(*field), other.number(*field)) {
+                (Some(a), Some(b)) => approx_eq(a, b),
+                _ => match (self.text(*field), other.text(*field)) {
+                    (Some(a), Some(b)) => a.eq_ignore_ascii_case(b),
+                    _ => continue,
+                },
+            };
+            if !same {
+                return None;
+            }
+            shared += 1;
+            shares_value |= matches!(
+                field,
+                Field::Resistance | Field::Capacitance | Field::Inductance
+            );
+        }
+        shares_value.then(|| shared as f64 / self.len() as f64)
+    }
+}
+
+impl Display for Parameters {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut parts = vec![];
+        if let Some(v) = self.resistance {
+            parts.push(format!("{}Ω", format_value(v)));
+        }
+        if let Some(v) = self.capacitance {
+            parts.push(format!("{}F", format_value(v)));
+        }
+        if let Some(v) = self.inductance {
+            parts.push(format!("{}H", format_value(v)));
+        }
+        if let Some(v) = self.voltage {
+            parts.push(format!("{}V", format_value(v)));
+        }
+        if let Some(v) = self.tolerance {
+            parts.push(format!("{}%", format_value(v)));
+        }
+        parts.extend(self.dielectric.clone());
+        parts.extend(self.package.clone());
+        f.write_str(&parts.join(" "))
+    }
+}
+
+impl Part {
+    /// Fills in the parameters from the name and description
+    pub fn parse_parameters(&mut self) {
+        self.parameters = Parameters::from_text(&format!("{} {}", self.name, self.description));
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Comparison {
+    Eq,
+    Ge,
+    Le,
+    Gt,
+    Lt,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum FilterValue {
+    Number(f64),
+    Text(String),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Filter {
+    field: Field,
+    comparison: Comparison,
+    value: FilterValue,
+}
+
+impl Filter {
+    fn matches(&self, params: &Parameters) -> bool {
+        match &self.value {
+            FilterValue::Number(wanted) => {
+                let Some(value) = params.number(self.field) else {
+                    return false;
+                };
+                match self.comparison {
+                    Comparison::Eq => approx_eq(value, *wanted),
+                    Comparison::Ge => value >= *wanted || approx_eq(value, *wanted),
+                    Comparison::Le => value <= *wanted || approx_eq(value, *wanted),
+                    Comparison::Gt => value > *wanted && !approx_eq(value, *wanted),
+                    Comparison::Lt => value < *wanted && !approx_eq(value, *wanted),
+                }
+            }
+            FilterValue::Text(wanted) => params
+                .text(self.field)
+                .is_some_and(|v| v.eq_ignore_ascii_case(wanted)),
+        }
+    }
+}
+
+/// A search like `R=10k pkg=0603 V>=25 thick film`. `key<op>value` terms filter on parameters,
+/// everything else has to appear in the name, description or part numbers, or describe the same
+/// parameter value as the part has, so plain `10k 0603` finds 10kΩ resistors in 0603.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PartQuery {
+    filters: Vec<Filter>,
+    terms: Vec<String>,
+}
+
+impl PartQuery {
+    pub fn parse(query: &str) -> Result<Self, String> {
+        let mut out = PartQuery::default();
+        for token in query.split_whitespace() {
+            match Self::parse_filter(token)? {
+                Some(filter) => out.filters.push(filter),
+                None => out.terms.push(token.to_lowercase()),
+            }
+        }
+        Ok(out)
+    }
+
+    fn parse_filter(token: &str) -> Result<Option<Filter>, String> {
+        const OPERATORS: &[(&str, Comparison)] = &[
+            (">=", Comparison::Ge),
+            ("<=", Comparison::Le),
+            ("=", Comparison::Eq),
+            (">", Comparison::Gt),
+            ("<", Comparison::Lt),
+        ];
+        let Some((idx, op, comparison)) = OPERATORS
+            .iter()
+            .filter_map(|(op, cmp)| token.find(op).map(|i| (i, *op, *cmp)))
+            .min_by_key(|(i, op, _)| (*i, usize::MAX - op.len()))
+        else {
+            return Ok(None);
+        };
+        let Some(field) = Field::from_key(&token[..idx]) else {
+            return Ok(None);
+        };
+        let raw = &token[idx + op.len()..];
+        let value = if field.is_numeric() {
+            let (value, _) = parse_value(raw)
+                .ok_or_else(|| format!("Can't read {} as a value for {}", raw, &token[..idx]))?;
+            FilterValue::Number(value)
+        } else if comparison == Comparison::Eq {
+            FilterValue::Text(raw.to_string())
+        } else {
+            return Err(format!("{} can only be compared with =", &token[..idx]));
+        };
+        Ok(Some(Filter {
+            field,
+            comparison,
+            value,
+        }))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.filters.is_empty() && self.terms.is_empty()
+    }
+
+    /// A term every matching part has in its name, description or part numbers, which a backend
+    /// can narrow the parts down by before the rest of the query is applied. `None` when every
+    /// term could just as well match a parameter value.
+    pub fn backend_term(&self) -> Option<&str> {
+        self.terms
+            .iter()
+            .filter(|t| Parameters::from_text(t).is_empty())
+            .max_by_key(|t| t.len())
+            .map(String::as_str)
+    }
+
+    fn term_matches(term: &str, texts: &[&str], params: &Parameters) -> bool {
+        if texts.iter().any(|t| t.to_lowercase().contains(term)) {
+            return true;
+        }
+        let wanted = Parameters::from_text(term);
+        if let Some(package) = &wanted.package {
+            return params
+                .package
+                .as_ref()
+                .is_some_and(|p| p.eq_ignore_ascii_case(package));
+        }
+        if let Some(dielectric) = &wanted.dielectric {
+            return params.dielectric.as_ref() == Some(dielectric);
+        }
+        [
+            Field::Resistance,
+            Field::Capacitance,
+            Field::Inductance,
+            Field::Voltage,
+            Field::Tolerance,
+        ]
+        .into_iter()
+        .any(|field| match (wanted.number(field), params.number(field)) {
+            (Some(a), Some(b)) => approx_eq(a, b),
+            _ => false,
+        })
+    }
+
+    fn matches(&self, texts: &[&str], params: &Parameters) -> bool {
+        self.filters.iter().all(|f| f.matches(params))
+            && self
+                .terms
+                .iter()
+                .all(|t| Self::term_matches(t, texts, params))
+    }
+
+    /// Matches a part against the query. Parts stored before they had parameters get them parsed
+    /// from their name and description on the fly.
+    fn matches_fields(
+        &self,
+        name: &str,
+        description: &str,
+        manufacturer: &str,
+        mpn: &str,
+        skus: &[SupplierSku],
+        params: &Parameters,
+    ) -> bool {
+        let parsed;
+        let params = if params.is_empty() {
+            parsed = Parameters::from_text(&format!("{} {}", name, description));
+            &parsed
+        } else {
+            params
+        };
+        let mut texts = vec![name, description, manufacturer, mpn];
+        texts.extend(skus.iter().map(|s| s.sku.as_str()));
+        self.matches(&texts, params)
+    }
+
+    pub fn matches_part(&self, part: &Part) -> bool {
+        self.matches_fields(
+            &part.name,
+            &part.description,
+            &part.manufacturer,
+            &part.mpn,
+            &part.skus,
+            &part.parameters,
+        )
+    }
+
+    pub fn matches_stocked(&self, part: &PartWithStock) -> bool {
+        self.matches_fields(
+            &part.name,
+            &part.description,
+            &part.manufacturer,
+            &part.mpn,
+            &part.skus,
+            &part.parameters,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Parameters, PartQuery, format_value, parse_value};
+
+    fn close(a: f64, b: f64) -> bool {
+        (a - b).abs() <= b.abs() * 1e-9
+    }
+
+    #[test]
+    fn reads_engineering_notation() {
+        for (token, value) in [
+            ("10k", 10e3),
+            ("4k7", 4.7e3),
+            ("4R7", 4.7),
+            ("100n", 100e-9),
+            ("1µ", 1e-6),
+            ("2.2uF", 2.2e-6),
+            ("25V", 25.0),
+            ("±5%", 5.0),
+            ("10Ω", 10.0),
+        ] {
+            let (parsed, _) = parse_value(token).unwrap();
+            assert!(close(parsed, value), "{} read as {}", token, parsed);
+        }
+        assert!(parse_value("0603").unwrap().1.is_none());
+        assert!(parse_value("LTC3531").is_none());
+        assert!(parse_value("1N4148").is_none());
+        assert_eq!(format_value(4700.0), "4.7k");
+        assert_eq!(format_value(100e-9), "100n");
+        assert_eq!(format_value(0.0), "0");
+    }
+
+    #[test]
+    fn parses_descriptions() {
+        let params = Parameters::from_text("CAP CER 1UF 25V X7R 0603");
+        assert!(close(params.capacitance.unwrap(), 1e-6));
+        assert_eq!(params.voltage, Some(25.0));
+        assert_eq!(params.dielectric.as_deref(), Some("X7R"));
+        assert_eq!(params.package.as_deref(), Some("0603"));
+
+        let params = Parameters::from_text("Resistor_SMD:R_0603_1608Metric 10k 1%");
+        assert_eq!(params.resistance, Some(10e3));
+        assert_eq!(params.tolerance, Some(1.0));
+        assert_eq!(params.package.as_deref(), Some("0603"));
+    }
+
+    #[test]
+    fn filters_on_parameters() {
+        let resistor = Parameters::from_text("10k 1% 0603");
+        let cap = Parameters::from_text("100nF 50V X7R 0402");
+
+        let query = PartQuery::parse("R=10k pkg=0603").unwrap();
+        assert!(query.matches(&[], &resistor));
+        assert!(!query.matches(&[], &cap));
+
+        let query = PartQuery::parse("C=100n V>=25").unwrap();
+        assert!(query.matches(&[], &cap));
+        assert!(!PartQuery::parse("V>50").unwrap().matches(&[], &cap));
+
+        // Plain terms match values as well as text
+        let query = PartQuery::parse("10k 0603").unwrap();
+        assert!(query.matches(&["Thick film"], &resistor));
+        assert!(
+            PartQuery::parse("thick")
+                .unwrap()
+                .matches(&["Thick film"], &resistor)
+        );
+        assert!(PartQuery::parse("R=abc").is_err());
+    }
+
+    #[test]
+    fn only_text_terms_go_to_the_backend() {
+        let backend_term = |query: &str| {
+            PartQuery::parse(query)
+                .unwrap()
+                .backend_term()
+                .map(String::from)
+        };
+        assert_eq!(backend_term("R=10k metal film").as_deref(), Some("metal"));
+        assert_eq!(backend_term("opa2134 dip").as_deref(), Some("opa2134"));
+        assert_eq!(backend_term("10k 0603 x7r"), None);
+        assert_eq!(backend_term(""), None);
+    }
+}
@@ -0,0 +1,323 @@
+use std::collections::HashMap;
+
+use crate::{
+    backend::PartsBackend,
+    error::NetworkError,
+    models::{
+        Bom, BomSource, PartWithCountAndStock, PurchaseRequirement, draw_with_alternates,
+        select_variant,
+    },
+};
+
+/// Boards of a BOM to plan purchases for
+#[derive(Debug, Clone)]
+pub struct PlannedBuild {
+    pub bom: Bom,
+    pub quantity: i64,
+    /// Assembly variant to build, `None` for the base design
+    pub variant: Option<String>,
+}
+
+impl PlannedBuild {
+    /// The BOM's name along with the variant being built, if any
+    pub fn name(&self) -> String {
+        match &self.variant {
+            Some(variant) => format!("{} ({})", self.bom.name, variant),
+            None => self.bom.name.clone(),
+        }
+    }
+}
+
+/// What has to be bought for `builds`, one requirement per part in any of their BOMs by part id.
+/// Stock held by build orders counts as gone, and parts that run short fall back on their
+/// alternates before anything needs buying. The order quantity applies each part's
+/// [`crate::models::PurchaseRules`] on top of the strict shortfall.
+pub async fn purchase_plan(
+    backend: &mut dyn PartsBackend,
+    profile_id: i64,
+    builds: &[PlannedBuild],
+) -> Result<Vec<PurchaseRequirement>, NetworkError> {
+    // Total required of every part, along with the alternates any of the BOMs allow
+    let mut part_map: HashMap<i64, (PartWithCountAndStock, Vec<BomSource>)> = HashMap::new();
+    for build in builds {
+        let parts = select_variant(
+            &backend.parts_in_bom(profile_id, build.bom.id).await?,
+            build.variant.as_deref(),
+        );
+        for part in parts {
+            let total_needed = part.count * build.quantity;
+            let source = BomSource {
+                bom_name: build.name(),
+                bom_id: build.bom.id,
+                quantity_needed: total_needed,
+                builds: build.quantity,
+            };
+            part_map
+                .entry(part.id)
+                .and_modify(|(line, sources)| {
+                    line.count += total_needed;
+                    for alternate in &part.alternates {
+                        if !line.alternates.iter().any(|a| a.id == alternate.id) {
+                            line.alternates.push(alternate.clone());
+                        }
+                    }
+                    sources.push(source.clone());
+                })
+                .or_insert_with(|| {
+                    (
+                        PartWithCountAndStock {
+                            count: total_needed,
+                            ..part.clone()
+                        },
+                        vec![source.clone()],
+                    )
+                });
+        }
+    }
+
+    let reservations: HashMap<i64, i64> = backend
+        .reservations(profile_id)
+        .await?
+        .into_iter()
+        .map(|r| (r.part_id, r.reserved))
+        .collect();
+    let reserved = |part_id: i64| reservations.get(&part_id).copied().unwrap_or(0);
+    let mut lines: Vec<(PartWithCountAndStock, Vec<BomSource>)> = part_map.into_values().collect();
+    lines.sort_by_key(|(line, _)| line.id);
+    let (lines, sources): (Vec<_>, Vec<_>) = lines.into_iter().unzip();
+
+    let mut available: HashMap<i64, i64> = HashMap::new();
+    for line in &lines {
+        available.insert(line.id, line.stock - reserved(line.id));
+        for alternate in &line.alternates {
+            available.insert(alternate.id, alternate.stock - reserved(alternate.id));
+        }
+    }
+    // BOM lines carry neither the part numbers suppliers need to fill a cart nor purchasing rules
+    let mut parts: HashMap<i64, _> = backend
+        .parts_with_stock(None, None, profile_id)
+        .await?
+        .into_iter()
+        .map(|p| (p.id, p))
+        .collect();
+    let mut lost = lines.clone();
+    for line in &mut lost {
+        let Some(part) = parts.get(&line.id) else {
+            return Err(NetworkError::NotFound(format!(
+                "Part {} does not exist",
+                line.id
+            )));
+        };
+        line.count = part.purchasing.with_attrition(line.count);
+    }
+
+    // What the builds take as designed gives the strict shortfall, what they take with assembly
+    // losses gives what to order
+    let mut left = available.clone();
+    let draws = draw_with_alternates(&lines, 1, &mut available);
+    let lossy_draws = draw_with_alternates(&lost, 1, &mut left);
+
+    let mut requirements = vec![];
+    for (((line, bom_sources), draw), lossy) in
+        lines.into_iter().zip(sources).zip(draws).zip(lossy_draws)
+    {
+        let part = parts.remove(&line.id).unwrap();
+        let rules = part.purchasing;
+        let below_safety = (rules.safety_stock - left[&line.id].max(0)).max(0);
+        requirements.push(PurchaseRequirement {
+            part,
+            reserved: reserved(line.id),
+            required: line.count,
+            shortfall: draw.short(),
+            order_quantity: rules.order_quantity(lossy.short() + below_safety),
+            bom_sources,
+            substitutions: draw.substitutions,
+        });
+    }
+    Ok(requirements)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        backend::PartsBackend,
+        memory::MemoryBackend,
+        models::{BomLine, Part, PurchaseRules, SupplierSku},
+        test_util::logged_in,
+    };
+
+    use super::{PlannedBuild, purchase_plan};
+
+    async fn backend_with_profile() -> (MemoryBackend, i64) {
+        let backend = logged_in(MemoryBackend::new()).await;
+        let profile_id = backend.user_data().profile.as_ref().unwrap().id;
+        (backend, profile_id)
+    }
+
+    #[tokio::test]
+    async fn shared_parts_add_up_across_boms() {
+        let (mut backend, profile_id) = backend_with_profile().await;
+        let resistor = Part {
+            name: String::from("10k"),
+            mpn: String::from("RC0603FR-0710KL"),
+            skus: vec![SupplierSku {
+                supplier: String::from("LCSC"),
+                sku: String::from("C98220"),
+            }],
+            ..Default::default()
+        };
+        let op_amp = Part {
+            name: String::from("OPA2134"),
+            ..Default::default()
+        };
+        backend
+            .new_bom(
+                profile_id,
+                String::from("Amplifier"),
+                String::new(),
+                vec![BomLine::new(4, resistor), BomLine::new(2, op_amp)],
+                None,
+            )
+            .await
+            .unwrap();
+        let parts = backend.get_parts(None, None).await.unwrap();
+        backend
+            .new_bom(
+                profile_id,
+                String::from("Sensor"),
+                String::new(),
+                vec![BomLine::new(3, parts[0].clone())],
+                None,
+            )
+            .await
+            .unwrap();
+        backend
+            .stock_part(profile_id, parts[0].id, 10, 0, 0, 0)
+            .await
+            .unwrap();
+
+        let boms = backend.list_boms(profile_id, None, None).await.unwrap();
+        let builds: Vec<PlannedBuild> = boms
+            .into_iter()
+            .map(|bom| PlannedBuild {
+                bom,
+                quantity: 2,
+                variant: None,
+            })
+            .collect();
+        let plan = purchase_plan(&mut backend, profile_id, &builds)
+            .await
+            .unwrap();
+        assert_eq!(plan.len(), 2);
+        assert_eq!(plan[0].part.name, "10k");
+        assert_eq!(plan[0].part.skus.len(), 1);
+        assert_eq!((plan[0].required, plan[0].shortfall), (14, 4));
+        assert_eq!(plan[0].bom_sources.len(), 2);
+        assert_eq!((plan[1].required, plan[1].shortfall), (4, 4));
+    }
+
+    #[tokio::test]
+    async fn purchasing_rules_round_up_the_order() {
+        let (mut backend, profile_id) = backend_with_profile().await;
+        let lines = vec![
+            BomLine::new(
+                7,
+                Part {
+                    name: String::from("10k"),
+                    ..Default::default()
+                },
+            ),
+            BomLine::new(
+                2,
+                Part {
+                    name: String::from("OPA2134"),
+                    ..Default::default()
+                },
+            ),
+        ];
+        backend
+            .new_bom(
+                profile_id,
+                String::from("Amplifier"),
+                String::new(),
+                lines,
+                None,
+            )
+            .await
+            .unwrap();
+        let parts = backend.get_parts(None, None).await.unwrap();
+        let (resistor, op_amp) = (parts[0].id, parts[1].id);
+        backend
+            .stock_part(profile_id, resistor, 10, 0, 0, 0)
+            .await
+            .unwrap();
+        let bom = backend.list_boms(profile_id, None, None).await.unwrap()[0].clone();
+        let builds = [PlannedBuild {
+            bom,
+            quantity: 2,
+            variant: None,
+        }];
+
+        // Without rules the order is the strict shortfall
+        let plan = purchase_plan(&mut backend, profile_id, &builds)
+            .await
+            .unwrap();
+        assert_eq!((plan[0].shortfall, plan[0].order_quantity), (4, 4));
+        assert_eq!((plan[1].shortfall, plan[1].order_quantity), (4, 4));
+
+        // 14 resistors with 10% lost take 16, leaving 6 to buy plus 5 to keep in stock, on a
+        // tape of 100
+        backend
+            .set_purchase_rules(
+                resistor,
+                PurchaseRules {
+                    order_multiple: 100,
+                    safety_stock: 5,
+                    attrition_percent: 10.0,
+                    ..Default::default()
+                },
+            )
+            .await
+            .unwrap();
+        backend
+            .set_purchase_rules(
+                op_amp,
+                PurchaseRules {
+                    moq: 10,
+                    ..Default::default()
+                },
+            )
+            .await
+            .unwrap();
+        let plan = purchase_plan(&mut backend, profile_id, &builds)
+            .await
+            .unwrap();
+        assert_eq!((plan[0].shortfall, plan[0].order_quantity), (4, 100));
+        assert_eq!((plan[1].shortfall, plan[1].order_quantity), (4, 10));
+
+        // Safety stock is bought even when the builds are covered
+        backend
+            .stock_part(profile_id, resistor, 100, 0, 0, 0)
+            .await
+            .unwrap();
+        let plan = purchase_plan(&mut backend, profile_id, &builds)
+            .await
+            .unwrap();
+        assert_eq!((plan[0].shortfall, plan[0].order_quantity), (0, 0));
+        backend
+            .set_purchase_rules(
+                resistor,
+                PurchaseRules {
+                    safety_stock: 90,
+                    ..Default::default()
+                },
+            )
+            .await
+            .unwrap();
+        let plan = purchase_plan(&mut backend, profile_id, &builds)
+            .await
+            .unwrap();
+        // 86 are left after the builds
+        assert_eq!((plan[0].shortfall, plan[0].order_quantity), (0, 4));
+    }
+}
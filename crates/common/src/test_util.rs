@@ -0,0 +1,32 @@
+use crate::{
+    backend::PartsBackend,
+    models::{Part, User},
+};
+
+pub fn test_user() -> User {
+    User {
+        id: 0,
+        email: String::from("test@example.com"),
+        password: String::from("hunter2"),
+    }
+}
+
+/// `backend` logged in as [`test_user`] with a "Workshop" profile selected
+pub async fn logged_in<B: PartsBackend>(mut backend: B) -> B {
+    let user = test_user();
+    backend.create_user(user.clone()).await.unwrap();
+    backend.login(user.clone()).await.unwrap();
+    backend.new_profile(String::from("Workshop")).await.unwrap();
+    // Logging in again selects the new profile
+    backend.login(user).await.unwrap();
+    backend
+}
+
+pub fn part(name: &str, description: &str) -> Part {
+    Part {
+        id: 0,
+        name: name.to_string(),
+        description: description.to_string(),
+        ..Default::default()
+    }
+}
@@ -1,11 +1,17 @@
 use anyhow::Result;
-use std::sync::Arc;
+use std::{path::Path, sync::Arc, time::Duration};
 use tokio::sync::Mutex;
-use tracing::debug;
+use tracing::{debug, error};
 
 use common::{
+    backend::PartsBackend,
+    error::{NetworkError, is_unauthorized},
+    journal::{Journal, StockChange},
+    local::LocalBackend,
+    mapping::MappingPresets,
+    matching::DismissedDuplicates,
     models::{Part, PartWithCountAndStock, PartWithStock, Profile, User},
-    network::{self, NetworkClient},
+    network::NetworkClient,
 };
 use iced::{
     Border, Color, Element, Length, Padding, Subscription, Theme, alignment,
@@ -17,11 +23,13 @@ use iced::{
 use crate::{
     CONFIG,
     bom_importer::{self, widget::BomImporter},
+    duplicates::{self, PendingMerge, widget::DuplicateReview},
     grid::{GridMessage, widget::GridWidget},
     icons,
     purchase_planner::{self, widget::PurchasePlanner},
-    search::{SearchMessage, widget::Search},
+    search::{Deletion, SearchMessage, widget::Search},
     settings::Grid,
+    sync::{self, widget::Reconciliation},
 };
 
 #[derive(Debug, Clone)]
@@ -31,6 +39,8 @@ pub enum AppMessage {
     SearchMessage(SearchMessage),
     BomImportMessage(bom_importer::Msg),
     PurchasePlannerMessage(purchase_planner::Msg),
+    SyncMessage(sync::Msg),
+    DuplicatesMessage(duplicates::Msg),
     GridMessage(GridMessage),
     Modal(OpenModal),
     Tab(AppTab),
@@ -54,6 +64,13 @@ pub enum AppMessage {
     NewProfile,
     NewProfilePending(String),
     NewProfileFailed,
+    RenameProfilePending(String),
+    RenameProfile,
+    RenameProfileFailed,
+    /// The deletion waiting in the confirmation modal goes ahead
+    ConfirmDelete,
+    /// The merge waiting in the confirmation modal goes ahead
+    ConfirmMerge,
     Quit,
     StockModalFail,
     LoginFail,
@@ -72,6 +89,8 @@ pub enum AppTab {
     Settings,
     BomImport,
     PurchasePlanner,
+    Sync,
+    Duplicates,
 }
 
 #[derive(Debug, Clone, Default)]
@@ -81,6 +100,8 @@ pub enum OpenModal {
     ChangeStock(PartWithStock),
     Login,
     SelectProfile,
+    ConfirmDelete(Deletion),
+    ConfirmMerge(PendingMerge),
 }
 
 #[derive(Debug, Clone, Default)]
@@ -93,6 +114,8 @@ pub struct StockModalData {
 
 #[derive(Debug, Clone, Default)]
 pub struct LoginModalData {
+    /// Why the app needs a server to log in to
+    pub error: Option<String>,
     pub email: String,
     pub password: String,
     pub new_email: String,
@@ -102,6 +125,8 @@ pub struct LoginModalData {
 #[derive(Debug, Clone, Default)]
 pub struct ProfileModalData {
     pub new_name: String,
+    /// New name for the selected profile
+    pub rename: String,
     pub profiles: Vec<Profile>,
     pub selected_prof: Option<i64>,
 }
@@ -110,7 +135,7 @@ pub struct ProfileModalData {
 pub struct App {
     pub dark_mode: bool,
     tab: AppTab,
-    network: Arc<Mutex<NetworkClient>>,
+    network: Arc<Mutex<dyn PartsBackend>>,
     search: Search,
     grid: GridWidget,
     modal: OpenModal,
@@ -119,24 +144,70 @@ pub struct App {
     profile_modal_data: ProfileModalData,
     bom_importer: BomImporter,
     purchase_planner: PurchasePlanner,
+    journal: Arc<Mutex<Journal>>,
+    reconciliation: Reconciliation,
+    duplicates: DuplicateReview,
 }
 
 impl App {
     pub fn new() -> Self {
-        // TODO: Error states
-        let config = CONFIG.read().unwrap();
-        let network = Arc::new(Mutex::new(match config.server_kind {
-            crate::settings::keymap::ServerKind::Production => NetworkClient::production_client(),
-            crate::settings::keymap::ServerKind::Development => NetworkClient::local_client(),
-        }));
+        let server_kind = CONFIG.read().unwrap().server_kind.clone();
+        let mut startup_error = None;
+        let network: Arc<Mutex<dyn PartsBackend>> = match server_kind {
+            crate::settings::keymap::ServerKind::Production => {
+                Arc::new(Mutex::new(NetworkClient::production_client()))
+            }
+            crate::settings::keymap::ServerKind::Development => {
+                Arc::new(Mutex::new(NetworkClient::local_client()))
+            }
+            crate::settings::keymap::ServerKind::Local(path) => match LocalBackend::open(&path) {
+                Ok(local) => Arc::new(Mutex::new(local)),
+                // Rather than not starting at all, the app asks to log in to the server instead
+                Err(e) => {
+                    error!("Couldn't open local inventory {}: {}", path.display(), e);
+                    startup_error = Some(format!(
+                        "Couldn't open the local inventory at {}: {}",
+                        path.display(),
+                        e
+                    ));
+                    Arc::new(Mutex::new(NetworkClient::production_client()))
+                }
+            },
+        };
+        let mut app = Self::with_backend(
+            network,
+            CONFIG.read().unwrap().grid,
+            Journal::open(Path::new(".journal.json")),
+            MappingPresets::open(Path::new(".bom_presets.json")),
+            DismissedDuplicates::open(Path::new(".dismissed_duplicates.json")),
+        );
+        if startup_error.is_some() {
+            app.login_modal_data.error = startup_error;
+            app.modal = OpenModal::Login;
+        }
+        app
+    }
 
+    /// Builds the app on top of any backend and stores, e.g. a [`common::memory::MemoryBackend`]
+    /// and in-memory stores in tests.
+    pub fn with_backend(
+        network: Arc<Mutex<dyn PartsBackend>>,
+        grid: Grid,
+        journal: Journal,
+        presets: MappingPresets,
+        dismissed: DismissedDuplicates,
+    ) -> Self {
+        let journal = Arc::new(Mutex::new(journal));
         Self {
             dark_mode: true,
             tab: AppTab::default(),
-            search: Search::new(network.clone()),
-            bom_importer: BomImporter::new(network.clone()),
+            search: Search::new(network.clone(), journal.clone()),
+            reconciliation: Reconciliation::new(network.clone(), journal.clone()),
+            journal,
+            bom_importer: BomImporter::new(network.clone(), presets),
             purchase_planner: PurchasePlanner::new(network.clone()),
-            grid: GridWidget::new(config.grid),
+            duplicates: DuplicateReview::new(network.clone(), dismissed),
+            grid: GridWidget::new(grid),
             network,
             modal: OpenModal::default(),
             stock_modal_data: StockModalData::default(),
@@ -162,10 +233,13 @@ impl App {
                     name: part.name.clone(),
                     description: part.description.clone(),
                     count: 1,
+                    designators: vec![],
+                    variants: vec![],
                     stock: part.stock,
                     column: part.column,
                     row: part.row,
                     z: part.z,
+                    alternates: vec![],
                 };
                 self.search
                     .update(msg.clone())
@@ -213,6 +287,44 @@ impl App {
                     .map(AppMessage::SearchMessage)
                     .chain(iced::Task::done(AppMessage::GridMessage(GridMessage::HighlightTargetBin(coords))))
             }
+            AppMessage::SearchMessage(ref msg @ SearchMessage::StockChangeQueued) => self
+                .search
+                .update(msg.clone())
+                .map(AppMessage::SearchMessage)
+                .chain(iced::Task::done(AppMessage::SyncMessage(sync::Msg::Reload))),
+            AppMessage::SearchMessage(SearchMessage::ConfirmDelete(deletion)) => {
+                iced::Task::done(AppMessage::Modal(OpenModal::ConfirmDelete(deletion)))
+            }
+            AppMessage::ConfirmDelete => match std::mem::take(&mut self.modal) {
+                OpenModal::ConfirmDelete(deletion) => self
+                    .search
+                    .update(SearchMessage::Delete(deletion))
+                    .map(AppMessage::SearchMessage),
+                other => {
+                    self.modal = other;
+                    iced::Task::none()
+                }
+            },
+            AppMessage::DuplicatesMessage(duplicates::Msg::ConfirmMerge(merge)) => {
+                iced::Task::done(AppMessage::Modal(OpenModal::ConfirmMerge(merge)))
+            }
+            AppMessage::ConfirmMerge => match std::mem::take(&mut self.modal) {
+                OpenModal::ConfirmMerge(merge) => self
+                    .duplicates
+                    .update(duplicates::Msg::Merge(merge))
+                    .map(AppMessage::DuplicatesMessage),
+                other => {
+                    self.modal = other;
+                    iced::Task::none()
+                }
+            },
+            AppMessage::SearchMessage(SearchMessage::Unauthorized)
+            | AppMessage::BomImportMessage(bom_importer::Msg::Unauthorized)
+            | AppMessage::PurchasePlannerMessage(purchase_planner::Msg::Unauthorized)
+            | AppMessage::SyncMessage(sync::Msg::Unauthorized)
+            | AppMessage::DuplicatesMessage(duplicates::Msg::Unauthorized) => {
+                iced::Task::done(AppMessage::Modal(OpenModal::Login))
+            }
             AppMessage::SearchMessage(search_message) => self
                 .search
                 .update(search_message)
@@ -231,12 +343,14 @@ impl App {
                         self.login_modal_data = LoginModalData::default();
                         iced::Task::none()
                     }
+                    OpenModal::ConfirmDelete(_) | OpenModal::ConfirmMerge(_) => iced::Task::none(),
                     OpenModal::SelectProfile => {
                         self.profile_modal_data.profiles.clear();
+                        self.profile_modal_data.rename.clear();
                         if let Some(id) = self
                             .network
                             .blocking_lock()
-                            .user_data
+                            .user_data()
                             .profile
                             .as_ref()
                             .map(|p| p.id)
@@ -246,6 +360,9 @@ impl App {
                         iced::Task::perform(Self::fetch_profiles(self.network.clone()), |output| {
                             match output {
                                 Ok(p) => AppMessage::ProfilesFetched(p),
+                                Err(e) if is_unauthorized(&e) => {
+                                    AppMessage::Modal(OpenModal::Login)
+                                }
                                 Err(_) => AppMessage::ProfilesFetchFail,
                             }
                         })
@@ -254,7 +371,15 @@ impl App {
             }
             AppMessage::Tab(app_tab) => {
                 self.tab = app_tab;
-                iced::Task::none()
+                match self.tab {
+                    AppTab::PurchasePlanner => iced::Task::done(
+                        AppMessage::PurchasePlannerMessage(purchase_planner::Msg::LoadBuilds),
+                    ),
+                    AppTab::Duplicates => {
+                        iced::Task::done(AppMessage::DuplicatesMessage(duplicates::Msg::Scan))
+                    }
+                    _ => iced::Task::none(),
+                }
             }
             AppMessage::StockModalAmount(s) => {
                 self.stock_modal_data.stock_diff = s;
@@ -274,21 +399,42 @@ impl App {
             }
             AppMessage::ChangeStock(diff) => match &self.modal {
                 OpenModal::ChangeStock(part) => {
+                    let Some(profile_id) = self
+                        .network
+                        .blocking_lock()
+                        .user_data()
+                        .profile
+                        .as_ref()
+                        .map(|p| p.id)
+                    else {
+                        return iced::Task::done(AppMessage::Modal(OpenModal::SelectProfile));
+                    };
                     let network = self.network.clone();
-                    let row = self.stock_modal_data.row.parse().unwrap();
-                    let column = self.stock_modal_data.column.parse().unwrap();
-                    let z = self.stock_modal_data.z.parse().unwrap();
-                    let id = part.id;
-                    let stock = part.stock;
+                    let journal = self.journal.clone();
+                    let change = StockChange {
+                        profile_id,
+                        part_id: part.id,
+                        name: part.name.clone(),
+                        expected: part.stock,
+                        delta: diff,
+                        column: self.stock_modal_data.column.parse().unwrap(),
+                        row: self.stock_modal_data.row.parse().unwrap(),
+                        z: self.stock_modal_data.z.parse().unwrap(),
+                        reason: String::from(if diff < 0 {
+                            "manual deplete"
+                        } else {
+                            "manual restock"
+                        }),
+                    };
                     iced::Task::perform(
                         async move {
+                            let mut journal = journal.lock().await;
                             let mut network = network.lock().await;
-                            network
-                                .stock_part(1, id, stock + diff, column, row, z)
-                                .await
+                            journal.submit(&mut *network, change).await
                         },
                         |result| match result {
                             Ok(_) => AppMessage::StockModalSuccess,
+                            Err(NetworkError::Unauthorized) => AppMessage::Modal(OpenModal::Login),
                             Err(_) => AppMessage::StockModalFail,
                         },
                     )
@@ -298,7 +444,10 @@ impl App {
             AppMessage::StockModalSuccess => {
                 self.stock_modal_data = StockModalData::default();
                 self.modal = OpenModal::None;
-                iced::Task::done(AppMessage::SearchMessage(SearchMessage::SubmitQuery))
+                iced::Task::batch([
+                    iced::Task::done(AppMessage::SearchMessage(SearchMessage::SubmitQuery)),
+                    iced::Task::done(AppMessage::SyncMessage(sync::Msg::Reload)),
+                ])
             }
             AppMessage::StockModalFail => iced::Task::none(),
             AppMessage::GridMessage(grid_msg) => {
@@ -327,7 +476,8 @@ impl App {
             AppMessage::LoginSuccess => {
                 self.login_modal_data = LoginModalData::default();
                 self.modal = OpenModal::None;
-                iced::Task::none()
+                // Anything queued while logged out can go through now
+                iced::Task::done(AppMessage::SyncMessage(sync::Msg::Replay))
             }
             AppMessage::LoginFail => iced::Task::none(),
             AppMessage::ProfilesFetched(vec) => {
@@ -362,6 +512,28 @@ impl App {
                 },
             ),
             AppMessage::NewProfileFailed => iced::Task::none(),
+            AppMessage::RenameProfilePending(s) => {
+                self.profile_modal_data.rename = s;
+                iced::Task::none()
+            }
+            AppMessage::RenameProfile => match self.profile_modal_data.selected_prof {
+                Some(id) if !self.profile_modal_data.rename.trim().is_empty() => {
+                    iced::Task::perform(
+                        Self::rename_profile(
+                            self.network.clone(),
+                            id,
+                            self.profile_modal_data.rename.trim().to_string(),
+                        ),
+                        |output| match output {
+                            Ok(_) => AppMessage::Modal(OpenModal::SelectProfile),
+                            Err(e) if is_unauthorized(&e) => AppMessage::Modal(OpenModal::Login),
+                            Err(_) => AppMessage::RenameProfileFailed,
+                        },
+                    )
+                }
+                _ => iced::Task::none(),
+            },
+            AppMessage::RenameProfileFailed => iced::Task::none(),
             AppMessage::NewProfilePending(s) => {
                 self.profile_modal_data.new_name = s;
                 iced::Task::none()
@@ -401,10 +573,13 @@ impl App {
                     name: part.name.clone(),
                     description: part.description.clone(),
                     count: 1,
+                    designators: vec![],
+                    variants: vec![],
                     stock: part.stock,
                     column: part.column,
                     row: part.row,
                     z: part.z,
+                    alternates: vec![],
                 };
                 self.purchase_planner
                     .update(msg.clone())
@@ -421,6 +596,13 @@ impl App {
                 .purchase_planner
                 .update(msg)
                 .map(AppMessage::PurchasePlannerMessage),
+            AppMessage::SyncMessage(msg) => {
+                self.reconciliation.update(msg).map(AppMessage::SyncMessage)
+            }
+            AppMessage::DuplicatesMessage(msg) => self
+                .duplicates
+                .update(msg)
+                .map(AppMessage::DuplicatesMessage),
             AppMessage::FocusNext => widget::focus_next(),
             AppMessage::FocusPrevious => widget::focus_previous(),
             AppMessage::Back => match self.modal {
@@ -441,6 +623,8 @@ impl App {
                     AppTab::Search => self.draw_search_tab(),
                     AppTab::BomImport => self.draw_bom_import_tab(),
                     AppTab::PurchasePlanner => self.draw_purchase_planner_tab(),
+                    AppTab::Sync => self.draw_sync_tab(),
+                    AppTab::Duplicates => self.draw_duplicates_tab(),
                     _ => todo!(),
                 },
                 self.grid.view().map(AppMessage::GridMessage),
@@ -466,6 +650,26 @@ impl App {
                 self.draw_profile_modal(),
                 AppMessage::Modal(OpenModal::None),
             ),
+            OpenModal::ConfirmDelete(deletion) => modal(
+                root,
+                draw_confirm_modal(
+                    deletion.question(),
+                    "Delete",
+                    AppMessage::ConfirmDelete,
+                    AppMessage::Modal(OpenModal::None),
+                ),
+                AppMessage::Modal(OpenModal::None),
+            ),
+            OpenModal::ConfirmMerge(merge) => modal(
+                root,
+                draw_confirm_modal(
+                    merge.question(),
+                    "Merge",
+                    AppMessage::ConfirmMerge,
+                    AppMessage::Modal(OpenModal::None),
+                ),
+                AppMessage::Modal(OpenModal::None),
+            ),
         }
     }
 
@@ -491,6 +695,18 @@ impl App {
             .into()
     }
 
+    fn draw_sync_tab(&self) -> iced::Element<'_, AppMessage> {
+        widget::container(self.reconciliation.view().map(AppMessage::SyncMessage))
+            .width(Length::Fill)
+            .into()
+    }
+
+    fn draw_duplicates_tab(&self) -> iced::Element<'_, AppMessage> {
+        widget::container(self.duplicates.view().map(AppMessage::DuplicatesMessage))
+            .width(Length::Fill)
+            .into()
+    }
+
     fn draw_change_stock_modal(&self, part: &PartWithStock) -> iced::Element<'_, AppMessage> {
         widget::container(
             widget::column![
@@ -557,12 +773,27 @@ impl App {
             AppTab::PurchasePlanner => AppTab::Search,
             _ => AppTab::PurchasePlanner,
         });
-        let user_data = n.user_data.clone();
+        let sync_event = AppMessage::Tab(match self.tab {
+            AppTab::Sync => AppTab::Search,
+            _ => AppTab::Sync,
+        });
+        let duplicates_event = AppMessage::Tab(match self.tab {
+            AppTab::Duplicates => AppTab::Search,
+            _ => AppTab::Duplicates,
+        });
+        let unsynced = self.reconciliation.pending_count() + self.reconciliation.conflict_count();
+        let sync_label = match unsynced {
+            0 => String::from("Sync"),
+            n => format!("Sync ({})", n),
+        };
+        let user_data = n.user_data().clone();
         widget::row![
             widget::button("Account").on_press(AppMessage::Modal(OpenModal::Login)),
             widget::button("Profile").on_press(AppMessage::Modal(OpenModal::SelectProfile)),
             widget::button("Import BOM").on_press(import_bom_event),
             widget::button("Purchase Planner").on_press(purchase_planner_event),
+            widget::button(widget::text(sync_label)).on_press(sync_event),
+            widget::button("Duplicates").on_press(duplicates_event),
             widget::horizontal_space().width(Length::Fill),
             widget::text(user_data.user.unwrap_or_default().email),
             widget::vertical_rule(2.0),
@@ -609,7 +840,15 @@ impl App {
             _ => None,
         });
 
-        Subscription::batch(vec![keys])
+        let mut subscriptions = vec![keys];
+        if self.reconciliation.pending_count() > 0 {
+            subscriptions.push(
+                iced::time::every(Duration::from_secs(30))
+                    .map(|_| AppMessage::SyncMessage(sync::Msg::Replay)),
+            );
+        }
+
+        Subscription::batch(subscriptions)
     }
 
     fn draw_login_modal(&self) -> iced::Element<'_, AppMessage> {
@@ -617,6 +856,8 @@ impl App {
             widget::column![
                 widget::text("Login"),
                 widget::vertical_space().height(8.0),
+                widget::text(self.login_modal_data.error.clone().unwrap_or_default())
+                    .style(widget::text::danger),
                 widget::text_input("Email", &self.login_modal_data.email)
                     .on_input(AppMessage::LoginModalEmail),
                 widget::text_input("Password", &self.login_modal_data.password)
@@ -679,6 +920,19 @@ impl App {
             );
         }
         col = col.push(widget::button("Select").on_press(AppMessage::ConfirmProfile));
+        col = col.extend(vec![
+            widget::horizontal_rule(4.0).into(),
+            widget::text_input("Rename selected profile", &self.profile_modal_data.rename)
+                .on_input(AppMessage::RenameProfilePending)
+                .into(),
+            widget::button("Rename")
+                .on_press_maybe(
+                    self.profile_modal_data
+                        .selected_prof
+                        .map(|_| AppMessage::RenameProfile),
+                )
+                .into(),
+        ]);
         col = col.extend(vec![
             widget::horizontal_rule(4.0).into(),
             widget::text_input("New profile name", &self.profile_modal_data.new_name)
@@ -703,34 +957,44 @@ impl App {
             .into()
     }
 
-    async fn login(network: Arc<Mutex<NetworkClient>>, data: LoginModalData) -> Result<()> {
+    async fn login(network: Arc<Mutex<dyn PartsBackend>>, data: LoginModalData) -> Result<()> {
         let mut n = network.lock().await;
         n.login(User {
             id: 0,
             email: data.email,
             password: data.password,
         })
-        .await
+        .await?;
+        Ok(())
     }
 
-    async fn fetch_profiles(network: Arc<Mutex<NetworkClient>>) -> Result<Vec<Profile>> {
+    async fn fetch_profiles(network: Arc<Mutex<dyn PartsBackend>>) -> Result<Vec<Profile>> {
         let mut n = network.lock().await;
-        n.get_profiles(None).await
+        Ok(n.get_profiles(None).await?)
     }
 
-    async fn confirm_login(network: Arc<Mutex<NetworkClient>>, profile: Profile) -> Result<()> {
+    async fn confirm_login(network: Arc<Mutex<dyn PartsBackend>>, profile: Profile) -> Result<()> {
         let mut n = network.lock().await;
-        n.user_data.profile = Some(profile);
+        n.user_data_mut().profile = Some(profile);
         Ok(())
     }
 
-    async fn create_new_profile(network: Arc<Mutex<NetworkClient>>, name: String) -> Result<()> {
+    async fn create_new_profile(network: Arc<Mutex<dyn PartsBackend>>, name: String) -> Result<()> {
+        let mut n = network.lock().await;
+        Ok(n.new_profile(name).await?)
+    }
+
+    async fn rename_profile(
+        network: Arc<Mutex<dyn PartsBackend>>,
+        profile_id: i64,
+        name: String,
+    ) -> Result<()> {
         let mut n = network.lock().await;
-        n.new_profile(name).await
+        Ok(n.update_profile(profile_id, name).await?)
     }
 
     async fn create_user(
-        network: Arc<Mutex<NetworkClient>>,
+        network: Arc<Mutex<dyn PartsBackend>>,
         email: String,
         password: String,
     ) -> Result<()> {
@@ -740,7 +1004,8 @@ impl App {
             password,
             ..Default::default()
         })
-        .await
+        .await?;
+        Ok(())
     }
 }
 
@@ -772,3 +1037,240 @@ where
     ]
     .into()
 }
+
+/// Asks `question` with a button doing `action` and one backing out
+fn draw_confirm_modal<'a>(
+    question: String,
+    action: &'a str,
+    confirm: AppMessage,
+    cancel: AppMessage,
+) -> iced::Element<'a, AppMessage> {
+    widget::container(
+        widget::column![
+            widget::text(question),
+            widget::vertical_space().height(8.0),
+            widget::row![
+                widget::button(action)
+                    .width(Length::Fill)
+                    .style(widget::button::danger)
+                    .on_press(confirm),
+                widget::button("Cancel")
+                    .width(Length::Fill)
+                    .on_press(cancel),
+            ]
+            .spacing(4.0),
+        ]
+        .spacing(4.0),
+    )
+    .style(|theme: &Theme| {
+        let palette = theme.extended_palette();
+        widget::container::Style {
+            text_color: Some(palette.background.weak.text),
+            background: Some(palette.background.weak.color.into()),
+            border: Border::default().rounded(8.0),
+            ..Default::default()
+        }
+    })
+    .padding(16.0)
+    .width(300.0)
+    .into()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::VecDeque, sync::Arc};
+
+    use common::{
+        backend::PartsBackend,
+        journal::Journal,
+        mapping::MappingPresets,
+        matching::DismissedDuplicates,
+        memory::MemoryBackend,
+        models::{Part, User},
+    };
+    use iced::futures::StreamExt;
+    use iced_runtime::{Action, task::into_stream};
+    use tokio::{runtime::Runtime, sync::Mutex};
+
+    use super::{App, AppMessage, OpenModal};
+    use crate::{
+        search::{Deletion, SearchMessage},
+        settings::Grid,
+        sync,
+    };
+
+    /// Runs an [`App`] on top of a [`MemoryBackend`] the way iced would, minus the window
+    struct Harness {
+        app: App,
+        backend: Arc<Mutex<MemoryBackend>>,
+        runtime: Runtime,
+    }
+
+    impl Harness {
+        fn new(backend: MemoryBackend) -> Self {
+            let backend = Arc::new(Mutex::new(backend));
+            let app = App::with_backend(
+                backend.clone(),
+                Grid {
+                    rows: 4,
+                    columns: 4,
+                    zs: 1,
+                },
+                Journal::in_memory(),
+                MappingPresets::in_memory(),
+                DismissedDuplicates::in_memory(),
+            );
+            Self {
+                app,
+                backend,
+                runtime: Runtime::new().unwrap(),
+            }
+        }
+
+        /// Logged in with a "Workshop" profile and 50 of a 100nF capacitor in stock
+        fn stocked() -> Self {
+            let runtime = Runtime::new().unwrap();
+            let backend = runtime.block_on(async {
+                let mut backend = MemoryBackend::new();
+                let user = User {
+                    id: 0,
+                    email: String::from("test@example.com"),
+                    password: String::from("hunter2"),
+                };
+                backend.create_user(user.clone()).await.unwrap();
+                backend.login(user.clone()).await.unwrap();
+                backend.new_profile(String::from("Workshop")).await.unwrap();
+                backend.login(user).await.unwrap();
+                backend
+                    .new_part(Part {
+                        id: 0,
+                        name: String::from("100nF"),
+                        description: String::from("Capacitor 0402"),
+                        ..Default::default()
+                    })
+                    .await
+                    .unwrap();
+                let profile_id = backend.user_data().profile.as_ref().unwrap().id;
+                let part_id = backend.get_parts(None, None).await.unwrap()[0].id;
+                backend
+                    .stock_part(profile_id, part_id, 50, 1, 1, 0)
+                    .await
+                    .unwrap();
+                backend
+            });
+            Self::new(backend)
+        }
+
+        /// Sends `message` through [`App::update`] along with every message the tasks it starts
+        /// produce, until there is nothing left to do
+        fn send(&mut self, message: AppMessage) {
+            let mut queue = VecDeque::from([message]);
+            while let Some(message) = queue.pop_front() {
+                let Some(stream) = into_stream(self.app.update(message)) else {
+                    continue;
+                };
+                let actions: Vec<_> = self.runtime.block_on(stream.collect());
+                queue.extend(actions.into_iter().filter_map(|action| match action {
+                    Action::Output(message) => Some(message),
+                    _ => None,
+                }));
+            }
+        }
+
+        fn search(&mut self) {
+            self.send(AppMessage::SearchMessage(SearchMessage::SubmitQuery));
+        }
+
+        fn stock(&self) -> i64 {
+            let mut backend = self.backend.blocking_lock();
+            let profile_id = backend.user_data().profile.as_ref().unwrap().id;
+            self.runtime
+                .block_on(backend.list_stock(profile_id))
+                .unwrap()
+                .iter()
+                .map(|row| row.stock)
+                .sum()
+        }
+    }
+
+    #[test]
+    fn signing_up_logs_in() {
+        let mut harness = Harness::new(MemoryBackend::new());
+        harness.send(AppMessage::Modal(OpenModal::Login));
+        harness.send(AppMessage::LoginModalNewEmail(String::from(
+            "new@example.com",
+        )));
+        harness.send(AppMessage::LoginModalNewPassword(String::from("hunter2")));
+        harness.send(AppMessage::ConfirmUserCreation);
+
+        assert!(matches!(harness.app.modal, OpenModal::None));
+        let backend = harness.backend.blocking_lock();
+        let user = backend.user_data().user.as_ref().unwrap();
+        assert_eq!(user.email, "new@example.com");
+    }
+
+    #[test]
+    fn searching_lists_parts_with_their_stock() {
+        let mut harness = Harness::stocked();
+        harness.search();
+
+        let matching = &harness.app.search.part_searcher.matching;
+        assert_eq!(matching.len(), 1);
+        assert_eq!(matching[0].name, "100nF");
+        assert_eq!(matching[0].stock, 50);
+    }
+
+    #[test]
+    fn changing_stock_goes_through_to_the_backend() {
+        let mut harness = Harness::stocked();
+        harness.search();
+        let part = harness.app.search.part_searcher.matching[0].clone();
+
+        harness.send(AppMessage::Modal(OpenModal::ChangeStock(part)));
+        harness.send(AppMessage::ChangeStock(-3));
+
+        assert!(matches!(harness.app.modal, OpenModal::None));
+        assert_eq!(harness.stock(), 47);
+        assert_eq!(harness.app.search.part_searcher.matching[0].stock, 47);
+    }
+
+    #[test]
+    fn stock_changes_wait_for_the_server_to_come_back() {
+        let mut harness = Harness::stocked();
+        harness.search();
+        let part = harness.app.search.part_searcher.matching[0].clone();
+
+        harness.backend.blocking_lock().unreachable = true;
+        harness.send(AppMessage::Modal(OpenModal::ChangeStock(part)));
+        harness.send(AppMessage::ChangeStock(-3));
+        assert_eq!(harness.app.journal.blocking_lock().pending().len(), 1);
+
+        harness.backend.blocking_lock().unreachable = false;
+        harness.send(AppMessage::SyncMessage(sync::Msg::Replay));
+        assert!(harness.app.journal.blocking_lock().pending().is_empty());
+        assert_eq!(harness.stock(), 47);
+    }
+
+    #[test]
+    fn deleting_asks_first() {
+        let mut harness = Harness::stocked();
+        harness.search();
+        let part = harness.app.search.part_searcher.matching[0].clone();
+
+        harness.send(AppMessage::SearchMessage(SearchMessage::ConfirmDelete(
+            Deletion::FromBin(part.clone()),
+        )));
+        assert!(matches!(harness.app.modal, OpenModal::ConfirmDelete(_)));
+        assert_eq!(harness.stock(), 50);
+
+        harness.send(AppMessage::ConfirmDelete);
+        assert!(matches!(harness.app.modal, OpenModal::None));
+        assert_eq!(harness.stock(), 0);
+
+        harness.send(AppMessage::SearchMessage(SearchMessage::ConfirmDelete(
+            Deletion::Part(part),
+        )));
+        harness.send(AppMessage::ConfirmDelete);
+        assert!(harness.app.search.part_searcher.matching.is_empty());
+    }
+}
@@ -1,7 +1,12 @@
-use std::path::PathBuf;
+use std::{fmt::Display, path::PathBuf};
 
 use anyhow::Error;
-use common::models::{Part, PartWithStock};
+use common::{
+    import::SheetCounts,
+    matching::{MatchKind, MatchSuggestion},
+    models::{Bom, Part, PartWithStock, SupplierSku, VariantCount},
+    parameters::Parameters,
+};
 use serde::{Deserialize, Serialize};
 
 pub mod widget;
@@ -9,14 +14,104 @@ pub mod widget;
 #[derive(Debug, Clone)]
 pub struct PendingBom {
     candidates: Vec<PartCandidate>,
+    /// BOMs already in the profile, any of which the import can be a new revision of
+    existing_boms: Vec<Bom>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct PartCandidate {
     pub name: String,
     pub description: String,
+    #[serde(default)]
+    pub manufacturer: String,
+    #[serde(default)]
+    pub mpn: String,
+    #[serde(default)]
+    pub skus: Vec<SupplierSku>,
+    #[serde(default)]
+    pub parameters: Parameters,
+    /// How many of the part each sheet of a multi-sheet project holds
+    #[serde(default)]
+    pub sheets: SheetCounts,
     pub count: i64,
+    #[serde(default)]
+    pub designators: Vec<String>,
+    /// Assembly variants fitting a different count of the part
+    #[serde(default)]
+    pub variants: Vec<VariantCount>,
     pub linked_part: Option<Part>,
+    /// Existing parts the line might describe, best first
+    #[serde(default)]
+    pub suggestions: Vec<MatchSuggestion>,
+    /// Whether the link, or creating a new part, is settled. Lines matching several parts
+    /// equally or only loosely wait for the user to pick.
+    #[serde(default)]
+    pub resolved: bool,
+}
+
+impl PartCandidate {
+    /// Creating a new part, the suggested matches, and the linked part if it was searched for
+    pub fn link_choices(&self) -> Vec<LinkChoice> {
+        let mut out = vec![LinkChoice::New];
+        out.extend(
+            self.suggestions
+                .iter()
+                .map(|s| LinkChoice::Existing(Box::new(s.part.clone()), Some(s.kind))),
+        );
+        let searched = self
+            .linked_part
+            .as_ref()
+            .filter(|linked| !self.suggestions.iter().any(|s| s.part.id == linked.id));
+        if let Some(linked) = searched {
+            out.push(LinkChoice::Existing(Box::new(linked.clone()), None));
+        }
+        out
+    }
+
+    /// The current choice, none while it is waiting for the user
+    pub fn link_choice(&self) -> Option<LinkChoice> {
+        if !self.resolved {
+            return None;
+        }
+        Some(match &self.linked_part {
+            Some(part) => LinkChoice::Existing(
+                Box::new(part.clone()),
+                self.suggestions
+                    .iter()
+                    .find(|s| s.part.id == part.id)
+                    .map(|s| s.kind),
+            ),
+            None => LinkChoice::New,
+        })
+    }
+}
+
+/// What a BOM line becomes on import
+#[derive(Debug, Clone)]
+pub enum LinkChoice {
+    New,
+    /// An existing part, with why it was suggested unless the user searched for it
+    Existing(Box<Part>, Option<MatchKind>),
+}
+
+impl PartialEq for LinkChoice {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (LinkChoice::New, LinkChoice::New) => true,
+            (LinkChoice::Existing(a, _), LinkChoice::Existing(b, _)) => a.id == b.id,
+            _ => false,
+        }
+    }
+}
+
+impl Display for LinkChoice {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LinkChoice::New => write!(f, "Create new"),
+            LinkChoice::Existing(part, Some(kind)) => write!(f, "{} ({} match)", part.name, kind),
+            LinkChoice::Existing(part, None) => write!(f, "{}", part.name),
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -24,19 +119,31 @@ pub enum Msg {
     PendingPath(String),
     OpenFile,
     OpenSuccess(Vec<String>),
+    /// Pick the worksheet of a workbook holding the BOM
+    SelectSheet(String),
     OpenFailed(String),
     BomName(String),
     BomDescription(String),
+    /// Import as the next revision of an existing BOM, or as a BOM of its own
+    SelectRevisionOf(Option<Bom>),
     SelectNameColumn(String),
     SelectDescriptionColumn(String),
     SelectCountColumn(String),
     TryLoadPending,
     PendingFetched(PendingBom),
     PendingFailed,
+    /// Link a line of the preview to an existing part or have it create a new one
+    LinkCandidate(usize, LinkChoice),
+    /// Search all parts for the one a line should link to
+    SearchLink(usize),
+    LinkQuery(String),
+    LinkSearchResults(Vec<Part>),
+    CloseLinkSearch,
     SubmitBom,
     SubmitFailed(String),
     SubmitSuccess,
     OpenFilePicker,
     NoFilePicked,
     FilePicked(PathBuf),
+    Unauthorized,
 }
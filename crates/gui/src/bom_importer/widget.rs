@@ -2,18 +2,33 @@ use std::{path::PathBuf, str::FromStr, sync::Arc};
 
 use anyhow::{Result, anyhow};
 use common::{
-    import::{altium_schematic_file_to_bom, csv_to_bom, csv_to_headers},
-    models::Part,
-    network::NetworkClient,
+    backend::PartsBackend,
+    error::{NetworkError, is_unauthorized},
+    import::{
+        SheetCounts, altium_project_file_to_bom, csv_to_bom, csv_to_headers, is_workbook,
+        schematic_file_to_bom, workbook_sheets, workbook_to_bom, workbook_to_headers,
+    },
+    kicad::kicad_csv_columns,
+    mapping::{ColumnMapping, MappingPresets, guess_columns},
+    matching::{certain_match, is_ambiguous, suggest_matches},
+    models::{Bom, BomLine, Part, VariantCount, designators_to_string},
 };
 use iced::{Alignment, Border, Length, Padding, Theme, widget};
 use rfd::AsyncFileDialog;
 use tokio::sync::Mutex;
 use tracing::{debug, error};
 
-use crate::search::widget::table_header;
+use crate::search::widget::{BomOption, table_header};
 
-use super::{Msg, PartCandidate, PendingBom};
+use super::{LinkChoice, Msg, PartCandidate, PendingBom};
+
+/// Searching all parts for the one a line of the preview should link to
+#[derive(Debug, Clone)]
+struct LinkSearch {
+    candidate: usize,
+    query: String,
+    results: Vec<Part>,
+}
 
 // TODO: File picker for importing
 #[derive(Debug, Clone)]
@@ -22,24 +37,39 @@ pub struct BomImporter {
     pending: Option<PendingBom>,
     bom_name: String,
     bom_description: String,
+    /// The BOM this import is the next revision of
+    revision_of: Option<Bom>,
+    /// Worksheets of the opened workbook, empty for other files
+    sheet_names: Vec<String>,
+    sheet: Option<String>,
     column_names: Vec<String>,
     name_column: Option<String>,
     description_column: Option<String>,
     count_column: Option<String>,
-    network: Arc<Mutex<NetworkClient>>,
+    /// Where the picked columns came from, shown until the user changes them
+    column_hint: Option<&'static str>,
+    presets: MappingPresets,
+    link_search: Option<LinkSearch>,
+    network: Arc<Mutex<dyn PartsBackend>>,
 }
 
 impl BomImporter {
-    pub fn new(network: Arc<Mutex<NetworkClient>>) -> Self {
+    pub fn new(network: Arc<Mutex<dyn PartsBackend>>, presets: MappingPresets) -> Self {
         Self {
             path: String::new(),
             bom_name: String::new(),
             bom_description: String::new(),
+            revision_of: None,
             pending: None,
+            sheet_names: vec![],
+            sheet: None,
             column_names: vec![],
             name_column: None,
             description_column: None,
             count_column: None,
+            column_hint: None,
+            presets,
+            link_search: None,
             network,
         }
     }
@@ -47,20 +77,71 @@ impl BomImporter {
     pub fn update(&mut self, msg: Msg) -> iced::Task<Msg> {
         match msg {
             Msg::OpenFile => {
-                if self.path.ends_with(".csv") {
-                    match csv_to_headers(&PathBuf::from_str(&self.path).unwrap_or_default()) {
-                        Ok(headers) => iced::Task::done(Msg::OpenSuccess(headers)),
+                let path = PathBuf::from_str(&self.path).unwrap_or_default();
+                if is_workbook(&path) {
+                    match workbook_sheets(&path) {
+                        Ok(sheets) => {
+                            self.sheet = sheets.first().cloned();
+                            self.sheet_names = sheets;
+                            self.open_table()
+                        }
                         Err(e) => iced::Task::done(Msg::OpenFailed(e.to_string())),
                     }
-                } else if self.path.ends_with(".SchDoc") {
+                } else if self.path.ends_with(".csv") {
+                    self.sheet_names.clear();
+                    self.sheet = None;
+                    self.open_table()
+                } else if self.path.ends_with(".SchDoc")
+                    || self.path.ends_with(".kicad_sch")
+                    || self.path.ends_with(".PrjPcb")
+                {
                     iced::Task::done(Msg::TryLoadPending)
                 } else {
                     iced::Task::none()
                 }
             }
             Msg::OpenSuccess(column_names) => {
+                // The columns picked for a table like this before win, then KiCad's BOM export
+                // which always has the same columns, then whatever the headers look like
+                let suggestion = self
+                    .presets
+                    .suggest(&column_names)
+                    .map(|mapping| (mapping, "Columns used for a similar BOM before"))
+                    .or_else(|| {
+                        kicad_csv_columns(&column_names).map(|(name, description, count)| {
+                            let mapping = ColumnMapping {
+                                name,
+                                description,
+                                count,
+                            };
+                            (mapping, "Columns of a KiCad BOM export")
+                        })
+                    })
+                    .or_else(|| {
+                        guess_columns(&column_names)
+                            .map(|mapping| (mapping, "Columns guessed from the headers"))
+                    });
                 self.column_names = column_names;
-                iced::Task::none()
+                match suggestion {
+                    Some((mapping, hint)) => {
+                        self.name_column = Some(mapping.name);
+                        self.description_column = Some(mapping.description);
+                        self.count_column = Some(mapping.count);
+                        self.column_hint = Some(hint);
+                        iced::Task::done(Msg::TryLoadPending)
+                    }
+                    None => iced::Task::none(),
+                }
+            }
+            Msg::SelectSheet(sheet) => {
+                // Every sheet has its own columns
+                self.sheet = Some(sheet);
+                self.pending = None;
+                self.name_column = None;
+                self.description_column = None;
+                self.count_column = None;
+                self.column_hint = None;
+                self.open_table()
             }
             Msg::OpenFailed(e) => {
                 error!("Failed to open file {}", e);
@@ -70,19 +151,31 @@ impl BomImporter {
                 self.bom_name = s;
                 iced::Task::none()
             }
+            Msg::SelectRevisionOf(bom) => {
+                // A new revision usually keeps the name of the one before
+                if let Some(previous) = bom.as_ref().filter(|_| self.bom_name.is_empty()) {
+                    self.bom_name = previous.name.clone();
+                    self.bom_description = previous.description.clone();
+                }
+                self.revision_of = bom;
+                iced::Task::none()
+            }
             Msg::BomDescription(s) => {
                 self.bom_description = s;
                 iced::Task::none()
             }
             Msg::SelectNameColumn(s) => {
+                self.column_hint = None;
                 self.name_column = Some(s);
                 iced::Task::done(Msg::TryLoadPending)
             }
             Msg::SelectDescriptionColumn(s) => {
+                self.column_hint = None;
                 self.description_column = Some(s);
                 iced::Task::done(Msg::TryLoadPending)
             }
             Msg::SelectCountColumn(s) => {
+                self.column_hint = None;
                 self.count_column = Some(s);
                 iced::Task::done(Msg::TryLoadPending)
             }
@@ -91,36 +184,51 @@ impl BomImporter {
                 iced::Task::none()
             }
             Msg::TryLoadPending => {
-                if self.path.ends_with(".csv") {
+                if self.path.ends_with(".csv")
+                    || is_workbook(&PathBuf::from_str(&self.path).unwrap_or_default())
+                {
                     if let (Some(name), Some(desc), Some(count)) = (
                         &self.name_column,
                         &self.description_column,
                         &self.count_column,
                     ) {
-                        match csv_to_bom(
-                            &PathBuf::from_str(&self.path).unwrap_or_default(),
-                            name,
-                            desc,
-                            count,
-                        ) {
+                        match self.read_table(name, desc, count) {
                             Ok(parts) => iced::Task::perform(
-                                Self::fetch_pending_bom(self.network.clone(), parts),
+                                Self::fetch_pending_bom(
+                                    self.network.clone(),
+                                    without_sheets(parts),
+                                ),
                                 |output| match output {
                                     Ok(pending) => Msg::PendingFetched(pending),
+                                    Err(e) if is_unauthorized(&e) => Msg::Unauthorized,
                                     Err(_) => Msg::PendingFailed,
                                 },
                             ),
-                            Err(_) => iced::Task::none(),
+                            // E.g. a quantity that doesn't match the designators
+                            Err(e) => iced::Task::done(Msg::OpenFailed(e.to_string())),
                         }
                     } else {
                         iced::Task::none()
                     }
-                } else if self.path.ends_with(".SchDoc") {
-                    match altium_schematic_file_to_bom(&PathBuf::from_str(&self.path).unwrap()) {
+                } else if self.path.ends_with(".SchDoc") || self.path.ends_with(".kicad_sch") {
+                    match schematic_file_to_bom(&PathBuf::from_str(&self.path).unwrap()) {
+                        Ok(parts) => iced::Task::perform(
+                            Self::fetch_pending_bom(self.network.clone(), without_sheets(parts)),
+                            |output| match output {
+                                Ok(pending) => Msg::PendingFetched(pending),
+                                Err(e) if is_unauthorized(&e) => Msg::Unauthorized,
+                                Err(_) => Msg::PendingFailed,
+                            },
+                        ),
+                        Err(_) => iced::Task::none(),
+                    }
+                } else if self.path.ends_with(".PrjPcb") {
+                    match altium_project_file_to_bom(&PathBuf::from_str(&self.path).unwrap()) {
                         Ok(parts) => iced::Task::perform(
                             Self::fetch_pending_bom(self.network.clone(), parts),
                             |output| match output {
                                 Ok(pending) => Msg::PendingFetched(pending),
+                                Err(e) if is_unauthorized(&e) => Msg::Unauthorized,
                                 Err(_) => Msg::PendingFailed,
                             },
                         ),
@@ -132,20 +240,85 @@ impl BomImporter {
             }
             Msg::PendingFetched(pending_bom) => {
                 self.pending = Some(pending_bom);
+                self.link_search = None;
                 iced::Task::none()
             }
             Msg::PendingFailed => todo!(),
+            Msg::LinkCandidate(i, choice) => {
+                if let Some(candidate) = self.pending.as_mut().and_then(|p| p.candidates.get_mut(i))
+                {
+                    candidate.linked_part = match choice {
+                        LinkChoice::New => None,
+                        LinkChoice::Existing(part, _) => Some(*part),
+                    };
+                    candidate.resolved = true;
+                }
+                self.link_search = None;
+                iced::Task::none()
+            }
+            Msg::SearchLink(i) => {
+                let query = self
+                    .pending
+                    .as_ref()
+                    .and_then(|p| p.candidates.get(i))
+                    .map(|c| c.name.clone())
+                    .unwrap_or_default();
+                self.link_search = Some(LinkSearch {
+                    candidate: i,
+                    query: query.clone(),
+                    results: vec![],
+                });
+                iced::Task::done(Msg::LinkQuery(query))
+            }
+            Msg::LinkQuery(query) => {
+                let Some(search) = &mut self.link_search else {
+                    return iced::Task::none();
+                };
+                search.query = query.clone();
+                let network = self.network.clone();
+                iced::Task::perform(
+                    async move {
+                        let mut n = network.lock().await;
+                        n.get_parts(Some(query), None).await
+                    },
+                    |output| match output {
+                        Ok(parts) => Msg::LinkSearchResults(parts),
+                        Err(NetworkError::Unauthorized) => Msg::Unauthorized,
+                        Err(e) => {
+                            error!("Part search failed {}", e);
+                            Msg::LinkSearchResults(vec![])
+                        }
+                    },
+                )
+            }
+            Msg::LinkSearchResults(parts) => {
+                if let Some(search) = &mut self.link_search {
+                    search.results = parts;
+                }
+                iced::Task::none()
+            }
+            Msg::CloseLinkSearch => {
+                self.link_search = None;
+                iced::Task::none()
+            }
             Msg::SubmitBom => {
-                if let Some(pending) = self.pending.clone() {
+                // Ambiguous lines have to be settled first, see view_bom_contents
+                let pending = self
+                    .pending
+                    .clone()
+                    .filter(|p| p.candidates.iter().all(|c| c.resolved));
+                if let Some(pending) = pending {
                     iced::Task::perform(
                         Self::submit_bom(
                             self.network.clone(),
                             pending,
                             self.bom_name.clone(),
                             self.bom_description.clone(),
+                            self.revision_of.as_ref().map(|b| b.id),
                         ),
                         |output| match output {
                             Ok(_) => Msg::SubmitSuccess,
+                            Err(e) if is_unauthorized(&e) => Msg::Unauthorized,
                             Err(e) => Msg::SubmitFailed(e.to_string()),
                         },
                     )
@@ -158,12 +331,29 @@ impl BomImporter {
                 iced::Task::none()
             }
             Msg::SubmitSuccess => {
+                if let (Some(name), Some(description), Some(count)) = (
+                    self.name_column.clone(),
+                    self.description_column.clone(),
+                    self.count_column.clone(),
+                ) {
+                    self.presets.remember(
+                        &self.column_names,
+                        ColumnMapping {
+                            name,
+                            description,
+                            count,
+                        },
+                    );
+                }
                 self.reset();
                 iced::Task::none()
             }
             Msg::OpenFilePicker => iced::Task::perform(
                 AsyncFileDialog::new()
-                    .add_filter("BOM files", &["csv", "SchDoc"])
+                    .add_filter(
+                        "BOM files",
+                        &["csv", "xlsx", "xls", "ods", "SchDoc", "PrjPcb", "kicad_sch"],
+                    )
                     .pick_file(),
                 |file| match file {
                     Some(f) => Msg::FilePicked(f.path().to_owned()),
@@ -175,6 +365,10 @@ impl BomImporter {
                     .chain(iced::Task::done(Msg::OpenFile))
             }
             Msg::NoFilePicked => iced::Task::none(),
+            Msg::Unauthorized => {
+                // Handled in app.rs by reopening the login modal
+                iced::Task::none()
+            }
         }
     }
 
@@ -189,9 +383,24 @@ impl BomImporter {
             widget::text_input("", &self.bom_description)
                 .on_input(Msg::BomDescription)
                 .into(),
+            self.view_revision_picker(),
             self.view_bom_contents(),
         ]);
         let mut column_pickers = widget::Column::new().spacing(4.0);
+        if self.sheet_names.len() > 1 {
+            column_pickers = column_pickers.push(
+                widget::row![
+                    widget::text("Sheet"),
+                    widget::pick_list(
+                        self.sheet_names.clone(),
+                        self.sheet.clone(),
+                        Msg::SelectSheet,
+                    ),
+                ]
+                .spacing(8.0)
+                .align_y(Alignment::Center),
+            );
+        }
         if !self.path.is_empty() && !self.column_names.is_empty() {
             column_pickers = column_pickers.push(
                 widget::row![
@@ -224,6 +433,9 @@ impl BomImporter {
                 ]
                 .spacing(4.0),
             );
+            if let Some(hint) = self.column_hint {
+                column_pickers = column_pickers.push(widget::text(hint).size(12.0));
+            }
         }
 
         widget::container(widget::column![
@@ -255,8 +467,40 @@ impl BomImporter {
         .into()
     }
 
+    fn view_revision_picker(&self) -> iced::Element<'_, Msg> {
+        let Some(pending) = &self.pending else {
+            return widget::vertical_space().height(0.0).into();
+        };
+        let options: Vec<BomOption> = pending
+            .existing_boms
+            .iter()
+            .cloned()
+            .map(BomOption)
+            .collect();
+        widget::row![
+            widget::text("Revision of"),
+            widget::pick_list(
+                options,
+                self.revision_of.clone().map(BomOption),
+                |BomOption(bom)| Msg::SelectRevisionOf(Some(bom)),
+            )
+            .placeholder("New BOM"),
+        ]
+        .push(
+            self.revision_of
+                .as_ref()
+                .map(|_| widget::button("New BOM instead").on_press(Msg::SelectRevisionOf(None))),
+        )
+        .spacing(8.0)
+        .padding(Padding::default().top(8.0))
+        .align_y(Alignment::Center)
+        .into()
+    }
+
     fn view_bom_contents(&self) -> iced::Element<'_, Msg> {
         if let Some(pending) = &self.pending {
+            let multi_sheet = pending.candidates.iter().any(|p| !p.sheets.is_empty());
+            let has_variants = pending.candidates.iter().any(|p| !p.variants.is_empty());
             let mut rows = vec![
                 widget::vertical_space().height(12.0).into(),
                 widget::horizontal_rule(2.0).into(),
@@ -264,34 +508,59 @@ impl BomImporter {
                 widget::row![
                     table_header("Name").width(Length::Fill),
                     table_header("Description").width(Length::Fill),
-                    table_header("Count").width(60.0).align_x(Alignment::End),
-                    table_header("Linked").width(60.0).align_x(Alignment::End),
+                    table_header("MPN").width(Length::Fill),
+                    table_header("Designators").width(Length::Fill),
                 ]
+                .push(multi_sheet.then(|| table_header("Sheets").width(Length::Fill)))
+                .push(has_variants.then(|| table_header("Variants").width(Length::Fill)))
+                .push(table_header("Count").width(60.0).align_x(Alignment::End))
+                .push(table_header("Link").width(280.0))
                 .spacing(16.0)
                 .padding(Padding::default().right(16.0))
                 .into(),
             ];
 
             let mut parts = widget::column(vec![]);
-            parts = parts.extend(pending.candidates.iter().map(|p| {
+            parts = parts.extend(pending.candidates.iter().enumerate().map(|(i, p)| {
                 widget::row![
                     widget::text(&p.name).width(Length::Fill),
                     widget::text(&p.description).width(Length::Fill),
-                    widget::text(&p.count).width(60.0).align_x(Alignment::End),
-                    widget::text(if p.linked_part.is_some() { "Yes" } else { "No" })
-                        .width(60.0)
-                        .style(|theme: &Theme| {
-                            let palette = theme.extended_palette();
-                            widget::text::Style {
-                                color: Some(if p.linked_part.is_some() {
-                                    palette.success.base.color
-                                } else {
-                                    palette.danger.base.color
-                                }),
-                            }
-                        })
-                        .align_x(Alignment::End),
+                    widget::text(&p.mpn).width(Length::Fill),
+                    widget::text(designators_to_string(&p.designators)).width(Length::Fill),
                 ]
+                .push(
+                    multi_sheet
+                        .then(|| widget::text(sheets_to_string(&p.sheets)).width(Length::Fill)),
+                )
+                .push(
+                    has_variants
+                        .then(|| widget::text(variants_to_string(&p.variants)).width(Length::Fill)),
+                )
+                .push(widget::text(&p.count).width(60.0).align_x(Alignment::End))
+                .push(
+                    widget::row![
+                        widget::pick_list(p.link_choices(), p.link_choice(), move |choice| {
+                            Msg::LinkCandidate(i, choice)
+                        })
+                        .placeholder("Pick a match")
+                        .width(Length::Fill)
+                        .style(move |theme: &Theme, status| {
+                            let palette = theme.extended_palette();
+                            let mut style = widget::pick_list::default(theme, status);
+                            style.border.color = if !p.resolved {
+                                palette.danger.base.color
+                            } else if p.linked_part.is_some() {
+                                palette.success.base.color
+                            } else {
+                                style.border.color
+                            };
+                            style
+                        }),
+                        widget::button("Search").on_press(Msg::SearchLink(i)),
+                    ]
+                    .spacing(4.0)
+                    .width(280.0),
+                )
                 .align_y(Alignment::Center)
                 .spacing(16.0)
                 .padding(Padding::default().right(16.0))
@@ -299,10 +568,26 @@ impl BomImporter {
             }));
 
             rows.push(widget::scrollable(parts).height(Length::Fill).into());
+            if let Some(search) = &self.link_search {
+                rows.push(self.view_link_search(search));
+            }
+            let unresolved = pending.candidates.iter().filter(|c| !c.resolved).count();
             rows.push(
-                widget::container(widget::button("Import").on_press(Msg::SubmitBom))
-                    .center_x(Length::Fill)
-                    .into(),
+                widget::column![
+                    widget::button("Import")
+                        .on_press_maybe((unresolved == 0).then_some(Msg::SubmitBom))
+                ]
+                .push((unresolved > 0).then(|| {
+                    widget::text(format!(
+                        "{} lines match several parts or only loosely, pick what they link to",
+                        unresolved
+                    ))
+                    .style(widget::text::danger)
+                }))
+                .spacing(4.0)
+                .align_x(Alignment::Center)
+                .width(Length::Fill)
+                .into(),
             );
 
             widget::column(rows).into()
@@ -311,31 +596,113 @@ impl BomImporter {
         }
     }
 
+    fn view_link_search<'a>(&'a self, search: &'a LinkSearch) -> iced::Element<'a, Msg> {
+        let i = search.candidate;
+        let results = search.results.iter().map(|part| {
+            widget::button(
+                widget::row![
+                    widget::text(&part.name).width(Length::Fill),
+                    widget::text(&part.description).width(Length::Fill),
+                    widget::text(&part.mpn).width(Length::Fill),
+                ]
+                .spacing(16.0),
+            )
+            .style(widget::button::text)
+            .width(Length::Fill)
+            .on_press(Msg::LinkCandidate(
+                i,
+                LinkChoice::Existing(Box::new(part.clone()), None),
+            ))
+            .into()
+        });
+        widget::column![
+            widget::row![
+                widget::text_input("Search parts", &search.query)
+                    .on_input(Msg::LinkQuery)
+                    .width(Length::Fill),
+                widget::button("Close").on_press(Msg::CloseLinkSearch),
+            ]
+            .spacing(4.0),
+            widget::scrollable(widget::column(results)).height(160.0),
+        ]
+        .spacing(4.0)
+        .padding(Padding::default().top(8.0).bottom(8.0))
+        .into()
+    }
+
     fn reset(&mut self) {
         self.path.clear();
         self.pending = None;
+        self.link_search = None;
         self.bom_name.clear();
         self.bom_description.clear();
+        self.revision_of = None;
+        self.sheet_names.clear();
+        self.sheet = None;
         self.column_names.clear();
         self.name_column = None;
         self.description_column = None;
         self.count_column = None;
+        self.column_hint = None;
+    }
+
+    /// Reads the header row of the opened CSV or worksheet
+    fn open_table(&self) -> iced::Task<Msg> {
+        let path = PathBuf::from_str(&self.path).unwrap_or_default();
+        let headers = if is_workbook(&path) {
+            workbook_to_headers(&path, self.sheet.as_deref())
+        } else {
+            csv_to_headers(&path)
+        };
+        match headers {
+            Ok(headers) => iced::Task::done(Msg::OpenSuccess(headers)),
+            Err(e) => iced::Task::done(Msg::OpenFailed(e.to_string())),
+        }
+    }
+
+    fn read_table(&self, name: &str, desc: &str, count: &str) -> Result<Vec<BomLine>> {
+        let path = PathBuf::from_str(&self.path).unwrap_or_default();
+        if is_workbook(&path) {
+            workbook_to_bom(&path, self.sheet.as_deref(), name, desc, count)
+        } else {
+            csv_to_bom(&path, name, desc, count)
+        }
     }
 
     async fn fetch_pending_bom(
-        network: Arc<Mutex<NetworkClient>>,
-        parts: Vec<(i64, common::models::Part)>,
+        network: Arc<Mutex<dyn PartsBackend>>,
+        parts: Vec<(BomLine, SheetCounts)>,
     ) -> Result<PendingBom> {
-        let mut out = PendingBom { candidates: vec![] };
         let mut n = network.lock().await;
+        let existing = n.get_parts(None, None).await?;
+        let profile_id = match &n.user_data().profile {
+            Some(p) => p.id,
+            None => return Err(anyhow!("No profile selected")),
+        };
+        let mut out = PendingBom {
+            candidates: vec![],
+            existing_boms: n.list_boms(profile_id, None, None).await?,
+        };
 
-        for (count, p) in parts {
-            let linked = n.get_parts(Some(p.name.clone()), None).await?;
+        for (line, sheets) in parts {
+            let p = line.part;
+            let suggestions = suggest_matches(&p, &existing);
+            let linked = certain_match(&suggestions).cloned();
+            let resolved = !is_ambiguous(&suggestions);
             out.candidates.push(PartCandidate {
                 name: p.name,
                 description: p.description,
-                count,
-                linked_part: linked.first().cloned(),
+                manufacturer: p.manufacturer,
+                mpn: p.mpn,
+                skus: p.skus,
+                parameters: p.parameters,
+                sheets,
+                count: line.count,
+                designators: line.designators,
+                variants: line.variants,
+                linked_part: linked,
+                suggestions,
+                resolved,
             });
         }
 
@@ -343,31 +710,64 @@ impl BomImporter {
     }
 
     async fn submit_bom(
-        network: Arc<Mutex<NetworkClient>>,
+        network: Arc<Mutex<dyn PartsBackend>>,
         pending: PendingBom,
         name: String,
         description: String,
+        revision_of: Option<i64>,
     ) -> Result<()> {
         let mut parts = vec![];
         for p in pending.candidates {
-            match p.linked_part {
-                Some(linked) => parts.push((p.count, linked)),
-                None => parts.push((
-                    p.count,
-                    Part {
-                        id: 0,
-                        name: p.name,
-                        description: p.description,
-                    },
-                )),
-            }
+            let part = match p.linked_part {
+                Some(linked) => linked,
+                None => Part {
+                    id: 0,
+                    name: p.name,
+                    description: p.description,
+                    manufacturer: p.manufacturer,
+                    mpn: p.mpn,
+                    skus: p.skus,
+                    parameters: p.parameters,
+                    ..Default::default()
+                },
+            };
+            parts.push(BomLine {
+                count: p.count,
+                part,
+                designators: p.designators,
+                variants: p.variants,
+            });
         }
 
         let mut n = network.lock().await;
-        let profile_id = match &n.user_data.profile {
+        let profile_id = match &n.user_data().profile {
             Some(p) => p.id,
             None => return Err(anyhow!("No profile selected")),
         };
-        n.new_bom(profile_id, name, description, parts).await
+        Ok(n.new_bom(profile_id, name, description, parts, revision_of)
+            .await?)
     }
 }
+
+/// Single documents have no per-sheet breakdown
+fn without_sheets(parts: Vec<BomLine>) -> Vec<(BomLine, SheetCounts)> {
+    parts.into_iter().map(|line| (line, vec![])).collect()
+}
+
+/// E.g. "Channel ×4, Power ×1"
+fn sheets_to_string(sheets: &SheetCounts) -> String {
+    sheets
+        .iter()
+        .map(|(sheet, count)| format!("{} ×{}", sheet.trim_end_matches(".SchDoc"), count))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// E.g. "Lite ×0, No display ×2"
+fn variants_to_string(variants: &[VariantCount]) -> String {
+    variants
+        .iter()
+        .map(|v| format!("{} ×{}", v.variant, v.count))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
@@ -0,0 +1,45 @@
+use common::{matching::DuplicateGroup, models::Part};
+
+pub mod widget;
+
+#[derive(Debug, Clone)]
+pub enum Msg {
+    // Finding duplicates
+    Scan,
+    Scanned(Vec<DuplicateGroup>),
+    ScanFailed(String),
+
+    // Reviewing a group, by its index
+    Keep(usize, i64),
+    /// Whether a part of the group is merged into the kept one
+    Include(usize, i64, bool),
+    /// Asks before merging, handled in app.rs by opening the confirmation modal
+    ConfirmMerge(PendingMerge),
+    Merge(PendingMerge),
+    Merged(Box<Part>),
+    MergeFailed(String),
+    /// The parts only look alike, leave them be from now on
+    Dismiss(usize),
+
+    // Session
+    Unauthorized,
+}
+
+/// Parts of a group to merge into the kept one, waiting for confirmation
+#[derive(Debug, Clone)]
+pub struct PendingMerge {
+    pub group: usize,
+    pub keep: Part,
+    pub merged: Vec<Part>,
+}
+
+impl PendingMerge {
+    pub fn question(&self) -> String {
+        let names: Vec<&str> = self.merged.iter().map(|p| p.name.as_str()).collect();
+        format!(
+            "Merge {} into {}? Their stock and BOM lines move over and they are deleted.",
+            names.join(", "),
+            self.keep.name
+        )
+    }
+}
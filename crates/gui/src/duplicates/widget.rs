@@ -0,0 +1,280 @@
+use std::sync::Arc;
+
+use anyhow::Result;
+use common::{
+    backend::PartsBackend,
+    error::is_unauthorized,
+    matching::{DismissedDuplicates, DuplicateGroup, find_duplicates},
+    models::Part,
+};
+use iced::{Alignment, Border, Length, Theme, widget};
+use tokio::sync::Mutex;
+use tracing::error;
+
+use crate::search::widget::table_header;
+
+use super::{Msg, PendingMerge};
+
+/// Lists parts that look like the same part entered more than once and merges the ones the
+/// user confirms, keeping the part they pick.
+#[derive(Debug)]
+pub struct DuplicateReview {
+    network: Arc<Mutex<dyn PartsBackend>>,
+    /// Parts the user said aren't duplicates, never grouped again
+    dismissed: DismissedDuplicates,
+    groups: Vec<DuplicateGroup>,
+    /// The part kept by each group, the oldest unless picked otherwise
+    keep: Vec<i64>,
+    /// Parts of each group the user left out of the merge
+    left_out: Vec<Vec<i64>>,
+    scanning: bool,
+    /// Group being merged
+    merging: Option<usize>,
+    status: Option<String>,
+}
+
+impl DuplicateReview {
+    pub fn new(network: Arc<Mutex<dyn PartsBackend>>, dismissed: DismissedDuplicates) -> Self {
+        Self {
+            network,
+            dismissed,
+            groups: vec![],
+            keep: vec![],
+            left_out: vec![],
+            scanning: false,
+            merging: None,
+            status: None,
+        }
+    }
+
+    pub fn update(&mut self, msg: Msg) -> iced::Task<Msg> {
+        match msg {
+            Msg::Scan => {
+                self.scanning = true;
+                iced::Task::perform(
+                    Self::scan(self.network.clone(), self.dismissed.clone()),
+                    |result| match result {
+                        Ok(groups) => Msg::Scanned(groups),
+                        Err(e) if is_unauthorized(&e) => Msg::Unauthorized,
+                        Err(e) => Msg::ScanFailed(e.to_string()),
+                    },
+                )
+            }
+            Msg::Scanned(groups) => {
+                self.scanning = false;
+                self.keep = groups.iter().map(|g| g.parts[0].id).collect();
+                self.left_out = vec![vec![]; groups.len()];
+                self.groups = groups;
+                iced::Task::none()
+            }
+            Msg::ScanFailed(e) => {
+                self.scanning = false;
+                error!("Finding duplicate parts failed: {}", e);
+                self.status = Some(format!("Couldn't fetch parts: {}", e));
+                iced::Task::none()
+            }
+            Msg::Keep(group, part_id) => {
+                if let Some(keep) = self.keep.get_mut(group) {
+                    *keep = part_id;
+                }
+                iced::Task::none()
+            }
+            Msg::Include(group, part_id, included) => {
+                if let Some(left_out) = self.left_out.get_mut(group) {
+                    left_out.retain(|id| *id != part_id);
+                    if !included {
+                        left_out.push(part_id);
+                    }
+                }
+                iced::Task::none()
+            }
+            Msg::ConfirmMerge(_) => {
+                error!("ConfirmMerge should be consumed by parent");
+                iced::Task::none()
+            }
+            Msg::Merge(merge) => {
+                let merged = merge.merged.iter().map(|p| p.id).collect();
+                self.merging = Some(merge.group);
+                iced::Task::perform(
+                    Self::merge(self.network.clone(), merge.keep.id, merged),
+                    |result| match result {
+                        Ok(part) => Msg::Merged(Box::new(part)),
+                        Err(e) if is_unauthorized(&e) => Msg::Unauthorized,
+                        Err(e) => Msg::MergeFailed(e.to_string()),
+                    },
+                )
+            }
+            Msg::Merged(part) => {
+                self.merging = None;
+                self.status = Some(format!(
+                    "Merged into {}, also known as {}",
+                    part.name,
+                    part.aliases.join(", ")
+                ));
+                iced::Task::done(Msg::Scan)
+            }
+            Msg::MergeFailed(e) => {
+                self.merging = None;
+                error!("Merging parts failed: {}", e);
+                self.status = Some(format!("Merge failed: {}", e));
+                iced::Task::none()
+            }
+            Msg::Dismiss(group) => {
+                if group < self.groups.len() {
+                    let removed = self.groups.remove(group);
+                    self.keep.remove(group);
+                    self.left_out.remove(group);
+                    let ids: Vec<i64> = removed.parts.iter().map(|p| p.id).collect();
+                    self.dismissed.dismiss(&ids);
+                }
+                iced::Task::none()
+            }
+            Msg::Unauthorized => {
+                // Handled in app.rs by reopening the login modal
+                self.scanning = false;
+                self.merging = None;
+                iced::Task::none()
+            }
+        }
+    }
+
+    async fn scan(
+        network: Arc<Mutex<dyn PartsBackend>>,
+        dismissed: DismissedDuplicates,
+    ) -> Result<Vec<DuplicateGroup>> {
+        let mut network = network.lock().await;
+        Ok(find_duplicates(
+            &network.get_parts(None, None).await?,
+            &dismissed,
+        ))
+    }
+
+    /// The kept part of a group and the others still picked to merge into it
+    fn pending_merge(&self, group: usize) -> Option<PendingMerge> {
+        let keep = self.keep.get(group)?;
+        let left_out = self.left_out.get(group)?;
+        let parts = &self.groups.get(group)?.parts;
+        let merged: Vec<Part> = parts
+            .iter()
+            .filter(|p| p.id != *keep && !left_out.contains(&p.id))
+            .cloned()
+            .collect();
+        (!merged.is_empty()).then_some(PendingMerge {
+            group,
+            keep: parts.iter().find(|p| p.id == *keep)?.clone(),
+            merged,
+        })
+    }
+
+    async fn merge(
+        network: Arc<Mutex<dyn PartsBackend>>,
+        keep: i64,
+        merged: Vec<i64>,
+    ) -> Result<Part> {
+        let mut network = network.lock().await;
+        Ok(network.merge_parts(keep, merged).await?)
+    }
+
+    pub fn view(&self) -> iced::Element<'_, Msg> {
+        let mut content = widget::column![
+            widget::row![
+                widget::text("Duplicate parts")
+                    .size(36.0)
+                    .width(Length::Fill),
+                widget::button(if self.scanning {
+                    "Scanning..."
+                } else {
+                    "Scan again"
+                })
+                .on_press_maybe((!self.scanning).then_some(Msg::Scan)),
+            ]
+            .align_y(Alignment::Center),
+            widget::text(self.status.clone().unwrap_or_default()).size(14.0),
+            widget::vertical_space().height(8.0),
+        ]
+        .spacing(8.0);
+
+        if self.groups.is_empty() && !self.scanning {
+            content = content.push(widget::text("No parts look alike"));
+        }
+        let mut groups = widget::column![].spacing(16.0);
+        for (i, group) in self.groups.iter().enumerate() {
+            groups = groups.push(self.view_group(i, group));
+        }
+        content
+            .push(widget::scrollable(groups).height(Length::Fill))
+            .into()
+    }
+
+    fn view_group<'a>(&'a self, index: usize, group: &'a DuplicateGroup) -> iced::Element<'a, Msg> {
+        let keep = self.keep.get(index).copied();
+        let left_out = self.left_out.get(index);
+        let mut rows = widget::column![
+            widget::text(format!("Matched by {}", group.kind)).size(18.0),
+            widget::row![
+                table_header("Keep").width(Length::Fill),
+                table_header("Merge").width(60.0),
+                table_header("Description").width(Length::Fill),
+                table_header("MPN").width(Length::Fill),
+            ]
+            .spacing(16.0),
+        ]
+        .spacing(4.0);
+        for part in &group.parts {
+            let kept = keep == Some(part.id);
+            let included = kept || left_out.is_none_or(|l| !l.contains(&part.id));
+            let id = part.id;
+            rows = rows.push(
+                widget::row![
+                    widget::radio(part.name.as_str(), part.id, keep, move |id| {
+                        Msg::Keep(index, id)
+                    })
+                    .width(Length::Fill),
+                    // The kept part is always part of the merge
+                    widget::checkbox("", included)
+                        .on_toggle_maybe(
+                            (!kept).then_some(move |included| Msg::Include(index, id, included))
+                        )
+                        .width(60.0),
+                    widget::text(&part.description).width(Length::Fill),
+                    widget::text(&part.mpn).width(Length::Fill),
+                ]
+                .spacing(16.0)
+                .align_y(Alignment::Center),
+            );
+        }
+        let merging = self.merging.is_some();
+        rows = rows.push(
+            widget::row![
+                widget::button(if self.merging == Some(index) {
+                    "Merging..."
+                } else {
+                    "Merge into kept part"
+                })
+                .on_press_maybe(
+                    self.pending_merge(index)
+                        .filter(|_| !merging)
+                        .map(Msg::ConfirmMerge),
+                ),
+                widget::button("Not duplicates")
+                    .style(widget::button::secondary)
+                    .on_press_maybe((!merging).then_some(Msg::Dismiss(index))),
+            ]
+            .spacing(8.0),
+        );
+        widget::container(rows)
+            .padding(8.0)
+            .style(|theme: &Theme| {
+                let palette = theme.extended_palette();
+                widget::container::Style {
+                    border: Border {
+                        color: palette.background.strong.color,
+                        width: 1.0,
+                        radius: iced::border::Radius::from(4.0),
+                    },
+                    ..Default::default()
+                }
+            })
+            .into()
+    }
+}
@@ -16,11 +16,13 @@ use tracing_subscriber::EnvFilter;
 
 mod app;
 mod bom_importer;
+mod duplicates;
 mod grid;
 mod icons;
 mod purchase_planner;
 mod search;
 mod settings;
+mod sync;
 
 const DARK_THEME: Theme = Theme::TokyoNight;
 const LIGHT_THEME: Theme = Theme::Light;
@@ -55,7 +57,10 @@ fn main() -> iced::Result {
         || {
             (
                 App::new(),
-                iced::Task::done(AppMessage::SearchMessage(SearchMessage::SubmitQuery)),
+                iced::Task::batch([
+                    iced::Task::done(AppMessage::SearchMessage(SearchMessage::SubmitQuery)),
+                    iced::Task::done(AppMessage::SyncMessage(sync::Msg::Replay)),
+                ]),
             )
         },
         App::update,
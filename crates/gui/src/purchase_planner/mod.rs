@@ -1,4 +1,7 @@
-use common::models::{Bom, PartWithStock, PurchaseRequirement};
+use common::{
+    export::ExportFormat,
+    models::{Bom, BuildOrder, BuildStatus, PartWithStock, PurchaseRequirement},
+};
 
 pub mod widget;
 
@@ -10,27 +13,36 @@ pub enum Msg {
     SearchResults(Vec<Bom>),
     SearchFailed(String),
     SelectBom(Bom),
+    /// The selected BOM looked up again, along with its variants
+    BomLoaded(Bom),
     RemoveBom(i64),
     UpdateQuantity(i64, String),
+    SelectVariant(i64, Option<String>),
 
     // Planning calculation
     CalculatePlan,
     PlanCalculated(Vec<PurchaseRequirement>),
     PlanFailed(String),
 
+    // Build orders
+    LoadBuilds,
+    BuildsLoaded(Vec<BuildOrder>),
+    /// Turn the selected BOMs into build orders, reserving their parts
+    PlanBuilds,
+    SetBuildStatus(i64, BuildStatus),
+    BuildFailed(String),
+
     // Part interaction
-    HoverPart(PartWithStock),
+    HoverPart(Box<PartWithStock>),
     ClearHover,
 
     // Export
     ExportPath(String),
+    ExportFormat(ExportFormat),
     ExportCsv,
     ExportSuccess,
     ExportFailed(String),
-}
 
-#[derive(Debug, Clone)]
-pub struct SelectedBom {
-    pub bom: Bom,
-    pub quantity: i64,
+    // Session
+    Unauthorized,
 }
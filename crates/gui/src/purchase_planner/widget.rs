@@ -1,36 +1,46 @@
-use std::{collections::HashMap, path::PathBuf, sync::Arc};
+use std::{path::PathBuf, sync::Arc};
 
 use anyhow::{Result, anyhow};
 use common::{
-    export::export_purchase_plan_to_csv,
-    models::{Bom, BomSource, PartWithStock, PurchaseRequirement},
-    network::NetworkClient,
+    backend::PartsBackend,
+    error::is_unauthorized,
+    export::{ExportFormat, export_purchase_plan},
+    models::{Bom, BuildOrder, BuildStatus, PurchaseRequirement},
+    planner::{PlannedBuild, purchase_plan},
 };
 use iced::{Alignment, Border, Length, Padding, Theme, widget};
 use tokio::sync::Mutex;
 use tracing::error;
 
-use super::{Msg, SelectedBom};
+use crate::search::widget::variant_picker;
+
+use super::Msg;
 
 #[derive(Debug)]
 pub struct PurchasePlanner {
-    network: Arc<Mutex<NetworkClient>>,
+    network: Arc<Mutex<dyn PartsBackend>>,
     bom_search_query: String,
     bom_search_results: Vec<Bom>,
-    selected_boms: Vec<SelectedBom>,
+    selected_boms: Vec<PlannedBuild>,
     purchase_requirements: Vec<PurchaseRequirement>,
+    build_orders: Vec<BuildOrder>,
+    build_error: Option<String>,
     export_path: String,
+    export_format: ExportFormat,
 }
 
 impl PurchasePlanner {
-    pub fn new(network: Arc<Mutex<NetworkClient>>) -> Self {
+    pub fn new(network: Arc<Mutex<dyn PartsBackend>>) -> Self {
         Self {
             network,
             bom_search_query: String::new(),
             bom_search_results: vec![],
             selected_boms: vec![],
             purchase_requirements: vec![],
+            build_orders: vec![],
+            build_error: None,
             export_path: String::from("./purchase_plan.csv"),
+            export_format: ExportFormat::default(),
         }
     }
 
@@ -52,6 +62,7 @@ impl PurchasePlanner {
                     Self::fetch_boms(network, query),
                     |result| match result {
                         Ok(boms) => Msg::SearchResults(boms),
+                        Err(e) if is_unauthorized(&e) => Msg::Unauthorized,
                         Err(e) => Msg::SearchFailed(e.to_string()),
                     },
                 )
@@ -67,16 +78,41 @@ impl PurchasePlanner {
             Msg::SelectBom(bom) => {
                 // Check if BOM is already selected
                 if !self.selected_boms.iter().any(|sb| sb.bom.id == bom.id) {
-                    self.selected_boms.push(SelectedBom { bom, quantity: 1 });
+                    // Servers list BOMs without their variants, a BOM looked up by id has them
+                    let load = if bom.variants.is_empty() {
+                        iced::Task::perform(
+                            Self::fetch_bom(self.network.clone(), bom.id),
+                            |result| match result {
+                                Ok(bom) => Msg::BomLoaded(bom),
+                                Err(e) if is_unauthorized(&e) => Msg::Unauthorized,
+                                Err(e) => Msg::SearchFailed(e.to_string()),
+                            },
+                        )
+                    } else {
+                        iced::Task::none()
+                    };
+                    self.selected_boms.push(PlannedBuild {
+                        bom,
+                        quantity: 1,
+                        variant: None,
+                    });
                     // Clear search results after selection
                     self.bom_search_results.clear();
                     self.bom_search_query.clear();
                     // Trigger recalculation
-                    iced::Task::done(Msg::CalculatePlan)
+                    iced::Task::done(Msg::CalculatePlan).chain(load)
                 } else {
                     iced::Task::none()
                 }
             }
+            Msg::BomLoaded(bom) => {
+                if let Some(selected_bom) =
+                    self.selected_boms.iter_mut().find(|sb| sb.bom.id == bom.id)
+                {
+                    selected_bom.bom = bom;
+                }
+                iced::Task::none()
+            }
             Msg::RemoveBom(bom_id) => {
                 self.selected_boms.retain(|sb| sb.bom.id != bom_id);
                 // Trigger recalculation
@@ -100,6 +136,15 @@ impl PurchasePlanner {
                 }
                 iced::Task::none()
             }
+            Msg::SelectVariant(bom_id, variant) => {
+                if let Some(selected_bom) =
+                    self.selected_boms.iter_mut().find(|sb| sb.bom.id == bom_id)
+                {
+                    selected_bom.variant = variant;
+                    return iced::Task::done(Msg::CalculatePlan);
+                }
+                iced::Task::none()
+            }
             Msg::CalculatePlan => {
                 let network = self.network.clone();
                 let selected_boms = self.selected_boms.clone();
@@ -108,6 +153,7 @@ impl PurchasePlanner {
                     Self::calculate_purchase_plan(network, selected_boms),
                     |result| match result {
                         Ok(requirements) => Msg::PlanCalculated(requirements),
+                        Err(e) if is_unauthorized(&e) => Msg::Unauthorized,
                         Err(e) => Msg::PlanFailed(e.to_string()),
                     },
                 )
@@ -122,6 +168,48 @@ impl PurchasePlanner {
                 error!("Purchase plan calculation failed: {}", e);
                 iced::Task::none()
             }
+            Msg::LoadBuilds => iced::Task::perform(
+                Self::fetch_build_orders(self.network.clone()),
+                |result| match result {
+                    Ok(orders) => Msg::BuildsLoaded(orders),
+                    Err(e) if is_unauthorized(&e) => Msg::Unauthorized,
+                    Err(e) => Msg::BuildFailed(e.to_string()),
+                },
+            ),
+            Msg::BuildsLoaded(orders) => {
+                // Finished orders no longer hold anything, so there is nothing to do with them
+                self.build_orders = orders.into_iter().filter(|o| o.status.reserves()).collect();
+                iced::Task::none()
+            }
+            Msg::PlanBuilds => {
+                let selected_boms = std::mem::take(&mut self.selected_boms);
+                self.purchase_requirements.clear();
+                self.build_error = None;
+                iced::Task::perform(
+                    Self::plan_builds(self.network.clone(), selected_boms),
+                    |result| match result {
+                        Ok(()) => Msg::LoadBuilds,
+                        Err(e) if is_unauthorized(&e) => Msg::Unauthorized,
+                        Err(e) => Msg::BuildFailed(e.to_string()),
+                    },
+                )
+            }
+            Msg::SetBuildStatus(order_id, status) => {
+                self.build_error = None;
+                iced::Task::perform(
+                    Self::set_build_status(self.network.clone(), order_id, status),
+                    |result| match result {
+                        Ok(()) => Msg::LoadBuilds,
+                        Err(e) if is_unauthorized(&e) => Msg::Unauthorized,
+                        Err(e) => Msg::BuildFailed(e.to_string()),
+                    },
+                )
+            }
+            Msg::BuildFailed(e) => {
+                error!("Build order update failed: {}", e);
+                self.build_error = Some(e);
+                iced::Task::none()
+            }
             Msg::HoverPart(_) => {
                 // Handled in app.rs for grid integration
                 iced::Task::none()
@@ -134,11 +222,15 @@ impl PurchasePlanner {
                 self.export_path = path;
                 iced::Task::none()
             }
+            Msg::ExportFormat(format) => {
+                self.export_format = format;
+                iced::Task::none()
+            }
             Msg::ExportCsv => {
                 let path = PathBuf::from(&self.export_path);
                 let requirements = self.purchase_requirements.clone();
 
-                match export_purchase_plan_to_csv(&path, &requirements) {
+                match export_purchase_plan(&path, &requirements, self.export_format) {
                     Ok(_) => iced::Task::done(Msg::ExportSuccess),
                     Err(e) => iced::Task::done(Msg::ExportFailed(e.to_string())),
                 }
@@ -151,6 +243,10 @@ impl PurchasePlanner {
                 error!("Export failed: {}", e);
                 iced::Task::none()
             }
+            Msg::Unauthorized => {
+                // Handled in app.rs by reopening the login modal
+                iced::Task::none()
+            }
         }
     }
 
@@ -197,6 +293,7 @@ impl PurchasePlanner {
                 widget::row![
                     widget::text("Name").width(Length::FillPortion(3)),
                     widget::text("Description").width(Length::FillPortion(3)),
+                    widget::text("Variant").width(Length::Fixed(140.0)),
                     widget::text("Quantity").width(Length::Fixed(100.0)),
                     widget::text("").width(Length::Fixed(80.0)), // Remove button column
                 ]
@@ -209,6 +306,12 @@ impl PurchasePlanner {
                     widget::row![
                         widget::text(&selected_bom.bom.name).width(Length::FillPortion(3)),
                         widget::text(&selected_bom.bom.description).width(Length::FillPortion(3)),
+                        widget::container(variant_picker(
+                            &selected_bom.bom,
+                            selected_bom.variant.as_deref(),
+                            move |variant| Msg::SelectVariant(selected_bom.bom.id, variant),
+                        ))
+                        .width(Length::Fixed(140.0)),
                         widget::text_input("", &selected_bom.quantity.to_string())
                             .on_input(move |s| Msg::UpdateQuantity(selected_bom.bom.id, s))
                             .width(Length::Fixed(100.0)),
@@ -221,6 +324,58 @@ impl PurchasePlanner {
                 );
             }
 
+            content = content.push(widget::button("Plan builds").on_press(Msg::PlanBuilds));
+
+            content = content.push(widget::vertical_space().height(8.0));
+            content = content.push(widget::horizontal_rule(2.0));
+            content = content.push(widget::vertical_space().height(8.0));
+        }
+
+        // Build Orders Section (only show if there are open orders or something went wrong)
+        if !self.build_orders.is_empty() || self.build_error.is_some() {
+            content = content.push(widget::text("Build Orders:").size(20.0));
+            if let Some(e) = &self.build_error {
+                content = content.push(widget::text(e).style(|theme: &Theme| {
+                    let palette = theme.extended_palette();
+                    widget::text::Style {
+                        color: Some(palette.danger.base.color),
+                    }
+                }));
+            }
+            for order in &self.build_orders {
+                let mut row = widget::row![
+                    widget::text(format!("#{}", order.id)).width(Length::Fixed(60.0)),
+                    widget::text(match order.variant() {
+                        Some(variant) => format!("{} ({})", order.bom_name, variant),
+                        None => order.bom_name.clone(),
+                    })
+                    .width(Length::FillPortion(3)),
+                    widget::text(format!("x{}", order.quantity)).width(Length::Fixed(60.0)),
+                    widget::text(order.status.to_string()).width(Length::Fixed(80.0)),
+                ]
+                .spacing(8.0)
+                .align_y(Alignment::Center);
+                if order.status == BuildStatus::Planned {
+                    row = row.push(
+                        widget::button("Kitted")
+                            .on_press(Msg::SetBuildStatus(order.id, BuildStatus::Kitted))
+                            .width(Length::Fixed(80.0)),
+                    );
+                }
+                row = row
+                    .push(
+                        widget::button("Complete")
+                            .on_press(Msg::SetBuildStatus(order.id, BuildStatus::Built))
+                            .width(Length::Fixed(90.0)),
+                    )
+                    .push(
+                        widget::button("Cancel")
+                            .on_press(Msg::SetBuildStatus(order.id, BuildStatus::Cancelled))
+                            .width(Length::Fixed(80.0)),
+                    );
+                content = content.push(row);
+            }
+
             content = content.push(widget::vertical_space().height(8.0));
             content = content.push(widget::horizontal_rule(2.0));
             content = content.push(widget::vertical_space().height(8.0));
@@ -236,9 +391,12 @@ impl PurchasePlanner {
                     widget::text("Part Name").width(Length::FillPortion(2)),
                     widget::text("Description").width(Length::FillPortion(2)),
                     widget::text("Stock").width(Length::Fixed(80.0)).align_x(Alignment::End),
+                    widget::text("Reserved").width(Length::Fixed(80.0)).align_x(Alignment::End),
                     widget::text("Required").width(Length::Fixed(80.0)).align_x(Alignment::End),
-                    widget::text("Purchase").width(Length::Fixed(80.0)).align_x(Alignment::End),
+                    widget::text("Shortfall").width(Length::Fixed(80.0)).align_x(Alignment::End),
+                    widget::text("Order").width(Length::Fixed(80.0)).align_x(Alignment::End),
                     widget::text("Required By").width(Length::FillPortion(2)),
+                    widget::text("Alternates Used").width(Length::FillPortion(2)),
                 ]
                 .spacing(8.0)
                 .padding(Padding::default().bottom(4.0)),
@@ -260,8 +418,16 @@ impl PurchasePlanner {
                     .collect::<Vec<_>>()
                     .join(", ");
 
-                // Determine color based on stock status
-                let stock_color = if req.part.stock >= req.required {
+                let substitutes = req
+                    .substitutions
+                    .iter()
+                    .map(|s| format!("{} {}", s.count, s.alternate_name))
+                    .collect::<Vec<_>>()
+                    .join(", ");
+
+                // Determine color based on what is left to buy after stock other builds hold on
+                // to and alternates standing in
+                let stock_color = if req.shortfall == 0 {
                     // Green: sufficient stock
                     |theme: &Theme| {
                         let palette = theme.extended_palette();
@@ -269,7 +435,7 @@ impl PurchasePlanner {
                             color: Some(palette.success.base.color),
                         }
                     }
-                } else if req.part.stock > 0 {
+                } else if req.shortfall < req.required {
                     // Yellow: partial stock
                     |theme: &Theme| {
                         let palette = theme.extended_palette();
@@ -295,6 +461,9 @@ impl PurchasePlanner {
                             .width(Length::Fixed(80.0))
                             .align_x(Alignment::End)
                             .style(stock_color),
+                        widget::text(req.reserved.to_string())
+                            .width(Length::Fixed(80.0))
+                            .align_x(Alignment::End),
                         widget::text(req.required.to_string())
                             .width(Length::Fixed(80.0))
                             .align_x(Alignment::End),
@@ -302,12 +471,17 @@ impl PurchasePlanner {
                             .width(Length::Fixed(80.0))
                             .align_x(Alignment::End)
                             .style(stock_color),
+                        // Rounded to what the part is bought in, with losses and safety stock
+                        widget::text(req.order_quantity.to_string())
+                            .width(Length::Fixed(80.0))
+                            .align_x(Alignment::End),
                         widget::text(required_by).width(Length::FillPortion(2)),
+                        widget::text(substitutes).width(Length::FillPortion(2)),
                     ]
                     .spacing(8.0)
                     .align_y(Alignment::Center),
                 )
-                .on_enter(Msg::HoverPart(req.part.clone()))
+                .on_enter(Msg::HoverPart(Box::new(req.part.clone())))
                 .on_exit(Msg::ClearHover);
 
                 rows = rows.push(row);
@@ -326,6 +500,11 @@ impl PurchasePlanner {
                     widget::text_input("", &self.export_path)
                         .on_input(Msg::ExportPath)
                         .width(Length::Fill),
+                    widget::pick_list(
+                        ExportFormat::ALL,
+                        Some(self.export_format),
+                        Msg::ExportFormat,
+                    ),
                     widget::button("Export to CSV").on_press(Msg::ExportCsv),
                 ]
                 .spacing(8.0)
@@ -349,96 +528,98 @@ impl PurchasePlanner {
             .into()
     }
 
-    async fn fetch_boms(network: Arc<Mutex<NetworkClient>>, query: Option<String>) -> Result<Vec<Bom>> {
+    async fn fetch_boms(network: Arc<Mutex<dyn PartsBackend>>, query: Option<String>) -> Result<Vec<Bom>> {
         let mut n = network.lock().await;
         let profile_id = n
-            .user_data
+            .user_data()
             .profile
             .as_ref()
             .ok_or(anyhow!("No profile selected"))?
             .id;
 
-        n.list_boms(profile_id, None, query).await
+        Ok(n.list_boms(profile_id, None, query).await?)
+    }
+
+    async fn fetch_bom(network: Arc<Mutex<dyn PartsBackend>>, bom_id: i64) -> Result<Bom> {
+        let mut n = network.lock().await;
+        let profile_id = n
+            .user_data()
+            .profile
+            .as_ref()
+            .ok_or(anyhow!("No profile selected"))?
+            .id;
+
+        n.list_boms(profile_id, Some(bom_id), None)
+            .await?
+            .into_iter()
+            .next()
+            .ok_or(anyhow!("BOM {} not found", bom_id))
     }
 
     async fn calculate_purchase_plan(
-        network: Arc<Mutex<NetworkClient>>,
-        selected_boms: Vec<SelectedBom>,
+        network: Arc<Mutex<dyn PartsBackend>>,
+        selected_boms: Vec<PlannedBuild>,
     ) -> Result<Vec<PurchaseRequirement>> {
         let mut n = network.lock().await;
         let profile_id = n
-            .user_data
+            .user_data()
             .profile
             .as_ref()
             .ok_or(anyhow!("No profile selected"))?
             .id;
 
-        // Step 1: Aggregate parts from all selected BOMs
-        // Store (required_count, stock_info, bom_sources)
-        let mut part_map: HashMap<i64, (i64, (String, String, i64, i64, i64, i64), Vec<BomSource>)> = HashMap::new();
-
-        for selected_bom in &selected_boms {
-            let parts = n.parts_in_bom(profile_id, selected_bom.bom.id).await?;
-
-            for part in parts {
-                let total_needed = part.count * selected_bom.quantity;
-                let part_info = (
-                    part.name.clone(),
-                    part.description.clone(),
-                    part.stock,
-                    part.column,
-                    part.row,
-                    part.z,
-                );
+        Ok(purchase_plan(&mut *n, profile_id, &selected_boms).await?)
+    }
 
-                part_map
-                    .entry(part.id)
-                    .and_modify(|(total, _info, sources)| {
-                        *total += total_needed;
-                        sources.push(BomSource {
-                            bom_name: selected_bom.bom.name.clone(),
-                            bom_id: selected_bom.bom.id,
-                            quantity_needed: total_needed,
-                            builds: selected_bom.quantity,
-                        });
-                    })
-                    .or_insert_with(|| {
-                        (
-                            total_needed,
-                            part_info,
-                            vec![BomSource {
-                                bom_name: selected_bom.bom.name.clone(),
-                                bom_id: selected_bom.bom.id,
-                                quantity_needed: total_needed,
-                                builds: selected_bom.quantity,
-                            }],
-                        )
-                    });
-            }
-        }
+    async fn fetch_build_orders(network: Arc<Mutex<dyn PartsBackend>>) -> Result<Vec<BuildOrder>> {
+        let mut n = network.lock().await;
+        let profile_id = n
+            .user_data()
+            .profile
+            .as_ref()
+            .ok_or(anyhow!("No profile selected"))?
+            .id;
 
-        // Step 2: Build purchase requirements
-        let mut requirements = vec![];
-
-        for (part_id, (required, (name, description, stock, column, row, z), bom_sources)) in part_map {
-            let shortfall = (required - stock).max(0);
-
-            requirements.push(PurchaseRequirement {
-                part: common::models::PartWithStock {
-                    id: part_id,
-                    name,
-                    description,
-                    stock,
-                    column,
-                    row,
-                    z,
-                },
-                required,
-                shortfall,
-                bom_sources,
-            });
+        Ok(n.list_build_orders(profile_id).await?)
+    }
+
+    async fn plan_builds(
+        network: Arc<Mutex<dyn PartsBackend>>,
+        selected_boms: Vec<PlannedBuild>,
+    ) -> Result<()> {
+        let mut n = network.lock().await;
+        let profile_id = n
+            .user_data()
+            .profile
+            .as_ref()
+            .ok_or(anyhow!("No profile selected"))?
+            .id;
+
+        for selected_bom in selected_boms {
+            n.new_build_order(
+                profile_id,
+                selected_bom.bom.id,
+                selected_bom.quantity,
+                selected_bom.variant,
+            )
+            .await?;
         }
+        Ok(())
+    }
+
+    async fn set_build_status(
+        network: Arc<Mutex<dyn PartsBackend>>,
+        order_id: i64,
+        status: BuildStatus,
+    ) -> Result<()> {
+        let mut n = network.lock().await;
+        let profile_id = n
+            .user_data()
+            .profile
+            .as_ref()
+            .ok_or(anyhow!("No profile selected"))?
+            .id;
 
-        Ok(requirements)
+        Ok(n.set_build_status(profile_id, order_id, status).await?)
     }
 }
@@ -1,4 +1,11 @@
-use common::models::{Bom, Part, PartWithCountAndStock, PartWithStock};
+use std::collections::HashMap;
+
+use common::{
+    buildable::Buildable,
+    models::{Bom, BomUsage, Part, PartWithCountAndStock, PartWithStock, StockLedgerEntry},
+};
+
+use widget::BomOption;
 
 pub mod widget;
 
@@ -7,8 +14,13 @@ pub enum SearchMessage {
     Toggle,
     PendingQuery(String),
     SubmitQuery,
-    PartSearchResult(Vec<PartWithStock>),
+    /// Matching parts, and how much of each part build orders hold on to
+    PartSearchResult(Vec<PartWithStock>, HashMap<i64, i64>),
     BomSearchResult(Vec<Bom>),
+    /// Stock open build orders hold, by part id
+    ReservedResult(HashMap<i64, i64>),
+    /// How many of a listed BOM can be built, by BOM id
+    BuildableResult(i64, Buildable),
     BomPartsSearchResult(Vec<PartWithCountAndStock>),
     FailedSearch(String),
     ChangeStock(PartWithStock),
@@ -17,12 +29,24 @@ pub enum SearchMessage {
     DepleteBom(Bom),
     RestockBom(Bom),
     StockQuantity(String),
+    /// The assembly variant restocking and depleting a BOM works on, `None` for the base design
+    SelectVariant(Option<String>),
     OpenBom(Bom),
     CloseBom,
     RefreshBom(Bom),
-    StockChangeFailed,
+    /// Every BOM of the profile, for comparing the open one to
+    CompareCandidates(Vec<Bom>),
+    /// Show what changed going from the picked BOM to the open one
+    CompareBom(BomOption),
+    CompareParts(Vec<PartWithCountAndStock>),
+    CloseComparison,
+    StockChangeFailed(String),
     StockChangeSuccess(i64),
+    /// The server couldn't be reached, the change waits in the journal
+    StockChangeQueued,
     SelectPart(PartWithStock),
+    HistoryResult(Vec<StockLedgerEntry>),
+    WhereUsedResult(Vec<BomUsage>),
     CancelPartStock,
     PartStockQuantity(String),
     PartStockRow(String),
@@ -34,4 +58,58 @@ pub enum SearchMessage {
     EnableGridSelection(bool),
     UpdateTargetBinHighlight,
     CopyToClipboard(String),
+    StartEditPart,
+    CancelEditPart,
+    EditPartName(String),
+    EditPartDescription(String),
+    EditPartManufacturer(String),
+    EditPartMpn(String),
+    EditPartSkus(String),
+    EditPartMoq(String),
+    EditPartOrderMultiple(String),
+    EditPartSafetyStock(String),
+    EditPartAttrition(String),
+    SavePart,
+    /// The saved part, and why some of it couldn't be saved
+    PartSaved(Part, Option<String>),
+    StartEditBom,
+    CancelEditBom,
+    EditBomName(String),
+    EditBomDescription(String),
+    /// Pending count of a BOM line, by part id
+    EditBomLineCount(i64, String),
+    SaveBom,
+    BomSaved(Bom),
+    EditFailed(String),
+    /// Consumed by the app to ask before anything is deleted
+    ConfirmDelete(Deletion),
+    /// Sent by the app once the deletion is confirmed
+    Delete(Deletion),
+    Deleted(Deletion),
+    /// The session is missing or expired, consumed by the app to reopen the login modal
+    Unauthorized,
+}
+
+/// Something the search tab can delete, waiting for confirmation
+#[derive(Debug, Clone)]
+pub enum Deletion {
+    Part(PartWithStock),
+    /// Takes the part out of its bin, the part itself stays
+    FromBin(PartWithStock),
+    Bom(Bom),
+    BomLine(Bom, PartWithCountAndStock),
+}
+
+impl Deletion {
+    pub fn question(&self) -> String {
+        match self {
+            Deletion::Part(part) => format!("Delete {} and its stock history?", part.name),
+            Deletion::FromBin(part) => format!(
+                "Remove {} from bin {}, {}, {}? Its stock of {} is recorded as removed.",
+                part.name, part.row, part.column, part.z, part.stock
+            ),
+            Deletion::Bom(bom) => format!("Delete BOM {} and all of its lines?", bom.name),
+            Deletion::BomLine(bom, part) => format!("Remove {} from {}?", part.name, bom.name),
+        }
+    }
 }
@@ -1,18 +1,28 @@
 use anyhow::{Result, anyhow};
 use common::{
-    models::{Bom, BomWithParts, Part, PartWithCountAndStock, PartWithStock},
-    network::NetworkClient,
+    backend::{PartsBackend, check_purchase_rules, part_variants},
+    buildable::{Buildable, build_stock, buildable, unreserved_stock},
+    error::{NetworkError, is_unauthorized},
+    journal::{Journal, StockChange, Submitted},
+    models::{
+        Bom, BomChange, BomDiffLine, BomUsage, BomWithParts, Part, PartWithCountAndStock,
+        PartWithStock, PurchaseRules, StockLedgerEntry, Substitution, SupplierSku,
+        designators_to_string, diff_boms, draw_with_alternates, drawn_parts, select_variant,
+        skus_to_string,
+    },
+    parameters::PartQuery,
 };
 use iced::{
     Alignment, Border, Font, Length, Padding, Pixels, Theme, alignment, clipboard, font::Weight,
     futures::future::join_all, widget,
 };
-use std::fmt::Debug;
+use std::collections::HashMap;
+use std::fmt::{Debug, Display};
 use std::sync::Arc;
 use tokio::sync::Mutex;
 use tracing::error;
 
-use super::SearchMessage;
+use super::{Deletion, SearchMessage};
 
 #[derive(Debug, Default)]
 enum SearchMode {
@@ -27,7 +37,10 @@ pub struct Search {
     pub part_searcher: PartSearch,
     bom_searcher: BomSearch,
     query: String,
-    network: Arc<Mutex<NetworkClient>>,
+    /// Why the last edit or deletion was refused
+    edit_error: Option<String>,
+    network: Arc<Mutex<dyn PartsBackend>>,
+    journal: Arc<Mutex<Journal>>,
 }
 
 #[derive(Debug)]
@@ -39,6 +52,28 @@ pub struct PartSearch {
     pub stock_row: String,
     pub stock_column: String,
     pub stock_z: String,
+    /// Stock ledger of the selected part, newest first
+    pub history: Vec<StockLedgerEntry>,
+    /// BOMs using the selected part
+    pub where_used: Vec<BomUsage>,
+    /// Stock held by open build orders, by part id
+    pub reserved: HashMap<i64, i64>,
+    /// Pending changes to the selected part
+    pub editing: Option<PartEdit>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct PartEdit {
+    pub name: String,
+    pub description: String,
+    pub manufacturer: String,
+    pub mpn: String,
+    /// Supplier part numbers, comma separated
+    pub skus: String,
+    pub moq: String,
+    pub order_multiple: String,
+    pub safety_stock: String,
+    pub attrition_percent: String,
 }
 
 #[derive(Debug)]
@@ -47,16 +82,60 @@ pub struct BomSearch {
     pub expanded: Option<Bom>,
     pub parts: Vec<PartWithCountAndStock>,
     pub stock_quantity: String,
+    /// Why the last restock or deplete was not applied
+    pub stock_error: Option<String>,
+    /// Alternates the last deplete took parts from
+    pub substitutions: Vec<Substitution>,
+    /// Assembly variant of the expanded BOM, `None` for the base design
+    pub variant: Option<String>,
+    /// BOMs the expanded one can be compared to
+    pub compare_candidates: Vec<Bom>,
+    /// The BOM the expanded one is compared to, and the lines that changed since
+    pub comparison: Option<(Bom, Vec<BomDiffLine>)>,
+    /// Pending changes to the expanded BOM
+    pub editing: Option<BomEdit>,
+    /// How many of each listed BOM the unreserved stock covers, by BOM id
+    pub buildable: HashMap<i64, Buildable>,
+    /// How many of the expanded BOM's variant the unreserved stock covers
+    pub expanded_buildable: Buildable,
+    /// Stock held by open build orders, by part id
+    pub reserved: HashMap<i64, i64>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct BomEdit {
+    pub name: String,
+    pub description: String,
+    /// Line counts by part id, as typed
+    pub counts: HashMap<i64, String>,
+}
+
+/// A BOM in a pick list, shown with its revision
+#[derive(Debug, Clone)]
+pub struct BomOption(pub Bom);
+
+impl PartialEq for BomOption {
+    fn eq(&self, other: &Self) -> bool {
+        self.0.id == other.0.id
+    }
+}
+
+impl Display for BomOption {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} (rev {})", self.0.name, self.0.revision)
+    }
 }
 
 impl Search {
-    pub fn new(network: Arc<Mutex<NetworkClient>>) -> Self {
+    pub fn new(network: Arc<Mutex<dyn PartsBackend>>, journal: Arc<Mutex<Journal>>) -> Self {
         Self {
             mode: SearchMode::default(),
             part_searcher: PartSearch::new(),
             bom_searcher: BomSearch::new(),
             query: String::new(),
+            edit_error: None,
             network,
+            journal,
         }
     }
 
@@ -70,7 +149,8 @@ impl Search {
                 SearchMode::Parts => iced::Task::perform(
                     PartSearch::query(self.network.clone(), self.query.clone()),
                     |output| match output {
-                        Ok(output) => SearchMessage::PartSearchResult(output),
+                        Ok((parts, reserved)) => SearchMessage::PartSearchResult(parts, reserved),
+                        Err(e) if is_unauthorized(&e) => SearchMessage::Unauthorized,
                         Err(e) => SearchMessage::FailedSearch(format!("{}", e)),
                     },
                 ),
@@ -78,17 +158,55 @@ impl Search {
                     BomSearch::query(self.network.clone(), self.query.clone()),
                     |output| match output {
                         Ok(output) => SearchMessage::BomSearchResult(output),
+                        Err(e) if is_unauthorized(&e) => SearchMessage::Unauthorized,
                         Err(e) => SearchMessage::FailedSearch(format!("{}", e)),
                     },
                 ),
             },
-            SearchMessage::PartSearchResult(vec) => {
+            SearchMessage::PartSearchResult(vec, reserved) => {
                 self.part_searcher.matching = vec;
+                self.part_searcher.reserved = reserved;
                 self.part_searcher.hovered_part = None;
                 iced::Task::none()
             }
             SearchMessage::BomSearchResult(vec) => {
                 self.bom_searcher.matching = vec;
+                self.bom_searcher.buildable.clear();
+                iced::Task::perform(BomSearch::fetch_reserved(self.network.clone()), |output| {
+                    match output {
+                        Ok(reserved) => SearchMessage::ReservedResult(reserved),
+                        Err(e) if is_unauthorized(&e) => SearchMessage::Unauthorized,
+                        // What the stock covers beats no buildable quantities at all
+                        Err(e) => {
+                            error!("Loading reservations failed: {}", e);
+                            SearchMessage::ReservedResult(HashMap::new())
+                        }
+                    }
+                })
+            }
+            SearchMessage::ReservedResult(reserved) => {
+                self.bom_searcher.reserved = reserved;
+                self.bom_searcher.update_buildable();
+                // A task per BOM, so the backend is free for anything else in between
+                iced::Task::batch(self.bom_searcher.matching.iter().map(|bom| {
+                    iced::Task::perform(
+                        BomSearch::fetch_buildable(
+                            self.network.clone(),
+                            bom.clone(),
+                            self.bom_searcher.reserved.clone(),
+                        ),
+                        |output| match output {
+                            Ok((bom_id, buildable)) => {
+                                SearchMessage::BuildableResult(bom_id, buildable)
+                            }
+                            Err(e) if is_unauthorized(&e) => SearchMessage::Unauthorized,
+                            Err(e) => SearchMessage::FailedSearch(format!("{}", e)),
+                        },
+                    )
+                }))
+            }
+            SearchMessage::BuildableResult(bom_id, buildable) => {
+                self.bom_searcher.buildable.insert(bom_id, buildable);
                 iced::Task::none()
             }
             SearchMessage::FailedSearch(msg) => {
@@ -99,6 +217,10 @@ impl Search {
                 error!("ChangeStock should be consumed by parent");
                 iced::Task::none()
             }
+            SearchMessage::Unauthorized => {
+                error!("Unauthorized should be consumed by parent");
+                iced::Task::none()
+            }
             SearchMessage::HoverPart(part) => {
                 self.part_searcher.hovered_part = Some(part.id);
                 iced::Task::none()
@@ -110,18 +232,61 @@ impl Search {
             SearchMessage::OpenBom(bom) => {
                 self.bom_searcher.expanded = Some(bom.clone());
                 self.bom_searcher.parts.clear();
+                self.bom_searcher.stock_error = None;
+                self.bom_searcher.substitutions.clear();
+                self.bom_searcher.variant = None;
+                self.bom_searcher.comparison = None;
+                self.bom_searcher.editing = None;
+                self.edit_error = None;
                 iced::Task::perform(
                     BomSearch::fetch_bom_parts(self.network.clone(), bom),
                     |output| match output {
                         Ok(output) => SearchMessage::BomPartsSearchResult(output),
+                        Err(e) if is_unauthorized(&e) => SearchMessage::Unauthorized,
+                        Err(e) => SearchMessage::FailedSearch(format!("{}", e)),
+                    },
+                )
+                .chain(iced::Task::perform(
+                    BomSearch::query(self.network.clone(), String::new()),
+                    |output| match output {
+                        Ok(output) => SearchMessage::CompareCandidates(output),
+                        Err(e) if is_unauthorized(&e) => SearchMessage::Unauthorized,
+                        Err(e) => SearchMessage::FailedSearch(format!("{}", e)),
+                    },
+                ))
+            }
+            SearchMessage::CompareCandidates(boms) => {
+                let open = self.bom_searcher.expanded.as_ref().map(|b| b.id);
+                self.bom_searcher.compare_candidates =
+                    boms.into_iter().filter(|b| Some(b.id) != open).collect();
+                iced::Task::none()
+            }
+            SearchMessage::CompareBom(BomOption(other)) => {
+                self.bom_searcher.comparison = Some((other.clone(), vec![]));
+                iced::Task::perform(
+                    BomSearch::fetch_bom_parts(self.network.clone(), other),
+                    |output| match output {
+                        Ok(output) => SearchMessage::CompareParts(output),
+                        Err(e) if is_unauthorized(&e) => SearchMessage::Unauthorized,
                         Err(e) => SearchMessage::FailedSearch(format!("{}", e)),
                     },
                 )
             }
+            SearchMessage::CompareParts(old_parts) => {
+                if let Some((_, diff)) = &mut self.bom_searcher.comparison {
+                    *diff = diff_boms(&old_parts, &self.bom_searcher.parts);
+                }
+                iced::Task::none()
+            }
+            SearchMessage::CloseComparison => {
+                self.bom_searcher.comparison = None;
+                iced::Task::none()
+            }
             SearchMessage::RefreshBom(bom) => iced::Task::perform(
                 BomSearch::fetch_bom_parts(self.network.clone(), bom),
                 |output| match output {
                     Ok(output) => SearchMessage::BomPartsSearchResult(output),
+                    Err(e) if is_unauthorized(&e) => SearchMessage::Unauthorized,
                     Err(e) => SearchMessage::FailedSearch(format!("{}", e)),
                 },
             ),
@@ -133,7 +298,13 @@ impl Search {
                 iced::Task::done(SearchMessage::SubmitQuery)
             }
             SearchMessage::BomPartsSearchResult(vec) => {
+                // BOMs listed by a server come without their variants, which the lines carry
+                let expanded = self.bom_searcher.expanded.as_mut();
+                if let Some(bom) = expanded.filter(|b| b.variants.is_empty()) {
+                    bom.variants = part_variants(&vec);
+                }
                 self.bom_searcher.parts = vec;
+                self.bom_searcher.update_buildable();
                 iced::Task::none()
             }
             SearchMessage::RestockBom(bom) => {
@@ -141,15 +312,30 @@ impl Search {
                     Ok(x) => x,
                     Err(_) => return iced::Task::none(),
                 };
-                let old_parts = self.bom_searcher.parts.clone();
-                for p in self.bom_searcher.parts.iter_mut() {
-                    p.stock += diff * p.count;
+                let variant = self.bom_searcher.variant.clone();
+                let old_parts = select_variant(&self.bom_searcher.parts, variant.as_deref());
+                for p in &old_parts {
+                    self.bom_searcher.change_shown_stock(p.id, diff * p.count);
                 }
+                self.bom_searcher.update_buildable();
+                self.bom_searcher.substitutions.clear();
                 iced::Task::perform(
-                    BomSearch::change_bom_stock(self.network.clone(), bom, old_parts, diff),
+                    BomSearch::change_bom_stock(
+                        self.network.clone(),
+                        self.journal.clone(),
+                        old_parts,
+                        diff,
+                        format!(
+                            "BOM restock: {} x{}",
+                            variant_name(&bom, variant.as_deref()),
+                            diff
+                        ),
+                    ),
                     move |output| match output {
-                        Ok(_) => SearchMessage::StockChangeSuccess(diff),
-                        Err(_) => SearchMessage::StockChangeFailed,
+                        Ok(Submitted::Applied) => SearchMessage::StockChangeSuccess(diff),
+                        Ok(Submitted::Queued) => SearchMessage::StockChangeQueued,
+                        Err(e) if is_unauthorized(&e) => SearchMessage::Unauthorized,
+                        Err(e) => SearchMessage::StockChangeFailed(e.to_string()),
                     },
                 )
             }
@@ -158,15 +344,36 @@ impl Search {
                     Ok(x) => x,
                     Err(_) => return iced::Task::none(),
                 };
-                let old_parts = self.bom_searcher.parts.clone();
-                for p in self.bom_searcher.parts.iter_mut() {
-                    p.stock -= diff * p.count;
+                let variant = self.bom_searcher.variant.clone();
+                let old_parts = select_variant(&self.bom_searcher.parts, variant.as_deref());
+                // Lines short of their part take what they lack from the alternates build
+                // orders don't hold
+                let mut available = build_stock(&old_parts, &self.bom_searcher.reserved, 0);
+                let draws = draw_with_alternates(&old_parts, diff, &mut available);
+                let drawn = drawn_parts(&old_parts, &draws);
+                for d in &drawn {
+                    self.bom_searcher.change_shown_stock(d.id, -d.count);
                 }
+                self.bom_searcher.update_buildable();
+                self.bom_searcher.substitutions =
+                    draws.into_iter().flat_map(|d| d.substitutions).collect();
                 iced::Task::perform(
-                    BomSearch::change_bom_stock(self.network.clone(), bom, old_parts, -diff),
+                    BomSearch::change_bom_stock(
+                        self.network.clone(),
+                        self.journal.clone(),
+                        drawn,
+                        -1,
+                        format!(
+                            "BOM build: {} x{}",
+                            variant_name(&bom, variant.as_deref()),
+                            diff
+                        ),
+                    ),
                     move |output| match output {
-                        Ok(_) => SearchMessage::StockChangeSuccess(diff),
-                        Err(_) => SearchMessage::StockChangeFailed,
+                        Ok(Submitted::Applied) => SearchMessage::StockChangeSuccess(diff),
+                        Ok(Submitted::Queued) => SearchMessage::StockChangeQueued,
+                        Err(e) if is_unauthorized(&e) => SearchMessage::Unauthorized,
+                        Err(e) => SearchMessage::StockChangeFailed(e.to_string()),
                     },
                 )
             }
@@ -174,8 +381,25 @@ impl Search {
                 self.bom_searcher.stock_quantity = s;
                 iced::Task::none()
             }
-            SearchMessage::StockChangeFailed => iced::Task::none(),
-            SearchMessage::StockChangeSuccess(_) => {
+            SearchMessage::SelectVariant(variant) => {
+                self.bom_searcher.variant = variant;
+                self.bom_searcher.update_buildable();
+                iced::Task::none()
+            }
+            SearchMessage::StockChangeFailed(e) => {
+                error!("Changing stock failed: {}", e);
+                self.bom_searcher.substitutions.clear();
+                match &self.bom_searcher.expanded {
+                    // Undo the optimistic update, nothing was applied
+                    Some(bom) => {
+                        self.bom_searcher.stock_error = Some(e);
+                        iced::Task::done(SearchMessage::RefreshBom(bom.clone()))
+                    }
+                    None => iced::Task::none(),
+                }
+            }
+            SearchMessage::StockChangeSuccess(_) | SearchMessage::StockChangeQueued => {
+                self.bom_searcher.stock_error = None;
                 if let Some(bom) = &self.bom_searcher.expanded {
                     let bom = bom.clone();
                     iced::Task::done(SearchMessage::RefreshBom(bom))
@@ -186,6 +410,8 @@ impl Search {
                     self.part_searcher.stock_row.clear();
                     self.part_searcher.stock_column.clear();
                     self.part_searcher.stock_z.clear();
+                    self.part_searcher.history.clear();
+                    self.part_searcher.where_used.clear();
                     iced::Task::done(SearchMessage::SubmitQuery)
                         .chain(iced::Task::done(SearchMessage::EnableGridSelection(false)))
                 } else {
@@ -196,7 +422,15 @@ impl Search {
                 self.bom_searcher.expanded = None;
                 self.bom_searcher.parts.clear();
                 self.bom_searcher.stock_quantity.clear();
-                iced::Task::none()
+                self.bom_searcher.stock_error = None;
+                self.bom_searcher.substitutions.clear();
+                self.bom_searcher.variant = None;
+                self.bom_searcher.compare_candidates.clear();
+                self.bom_searcher.comparison = None;
+                self.bom_searcher.editing = None;
+                self.edit_error = None;
+                // Stock may have changed while the BOM was open
+                iced::Task::done(SearchMessage::SubmitQuery)
             }
             SearchMessage::SelectPart(part) => {
                 self.part_searcher.selected_part = Some(part.clone());
@@ -204,8 +438,38 @@ impl Search {
                 self.part_searcher.stock_column = part.column.to_string();
                 self.part_searcher.stock_z = part.z.to_string();
                 self.part_searcher.stock_quantity.clear();
-                iced::Task::done(SearchMessage::EnableGridSelection(true))
-                    .chain(iced::Task::done(SearchMessage::UpdateTargetBinHighlight))
+                self.part_searcher.history.clear();
+                self.part_searcher.where_used.clear();
+                self.part_searcher.editing = None;
+                self.edit_error = None;
+                iced::Task::batch([
+                    iced::Task::done(SearchMessage::EnableGridSelection(true))
+                        .chain(iced::Task::done(SearchMessage::UpdateTargetBinHighlight)),
+                    iced::Task::perform(
+                        PartSearch::fetch_history(self.network.clone(), part.id),
+                        |output| match output {
+                            Ok(output) => SearchMessage::HistoryResult(output),
+                            Err(e) if is_unauthorized(&e) => SearchMessage::Unauthorized,
+                            Err(e) => SearchMessage::FailedSearch(format!("{}", e)),
+                        },
+                    ),
+                    iced::Task::perform(
+                        PartSearch::fetch_where_used(self.network.clone(), part.id),
+                        |output| match output {
+                            Ok(output) => SearchMessage::WhereUsedResult(output),
+                            Err(e) if is_unauthorized(&e) => SearchMessage::Unauthorized,
+                            Err(e) => SearchMessage::FailedSearch(format!("{}", e)),
+                        },
+                    ),
+                ])
+            }
+            SearchMessage::HistoryResult(history) => {
+                self.part_searcher.history = history;
+                iced::Task::none()
+            }
+            SearchMessage::WhereUsedResult(usages) => {
+                self.part_searcher.where_used = usages;
+                iced::Task::none()
             }
             SearchMessage::CancelPartStock => {
                 self.part_searcher.selected_part = None;
@@ -213,6 +477,10 @@ impl Search {
                 self.part_searcher.stock_row.clear();
                 self.part_searcher.stock_column.clear();
                 self.part_searcher.stock_z.clear();
+                self.part_searcher.history.clear();
+                self.part_searcher.where_used.clear();
+                self.part_searcher.editing = None;
+                self.edit_error = None;
                 iced::Task::done(SearchMessage::EnableGridSelection(false))
                     .chain(iced::Task::done(SearchMessage::UpdateTargetBinHighlight))
             }
@@ -254,16 +522,18 @@ impl Search {
                     iced::Task::perform(
                         PartSearch::change_part_stock(
                             self.network.clone(),
-                            part.id,
-                            part.stock,
+                            self.journal.clone(),
+                            part.clone(),
                             diff,
                             row,
                             column,
                             z,
                         ),
                         move |output| match output {
-                            Ok(_) => SearchMessage::StockChangeSuccess(diff),
-                            Err(_) => SearchMessage::StockChangeFailed,
+                            Ok(Submitted::Applied) => SearchMessage::StockChangeSuccess(diff),
+                            Ok(Submitted::Queued) => SearchMessage::StockChangeQueued,
+                            Err(e) if is_unauthorized(&e) => SearchMessage::Unauthorized,
+                            Err(e) => SearchMessage::StockChangeFailed(e.to_string()),
                         },
                     )
                 } else {
@@ -292,16 +562,18 @@ impl Search {
                     iced::Task::perform(
                         PartSearch::change_part_stock(
                             self.network.clone(),
-                            part.id,
-                            part.stock,
+                            self.journal.clone(),
+                            part.clone(),
                             -diff,
                             row,
                             column,
                             z,
                         ),
                         move |output| match output {
-                            Ok(_) => SearchMessage::StockChangeSuccess(diff),
-                            Err(_) => SearchMessage::StockChangeFailed,
+                            Ok(Submitted::Applied) => SearchMessage::StockChangeSuccess(diff),
+                            Ok(Submitted::Queued) => SearchMessage::StockChangeQueued,
+                            Err(e) if is_unauthorized(&e) => SearchMessage::Unauthorized,
+                            Err(e) => SearchMessage::StockChangeFailed(e.to_string()),
                         },
                     )
                 } else {
@@ -326,7 +598,264 @@ impl Search {
                 iced::Task::none()
             }
             SearchMessage::CopyToClipboard(to_copy) => iced::clipboard::write(to_copy),
+            SearchMessage::StartEditPart => {
+                self.part_searcher.editing =
+                    self.part_searcher
+                        .selected_part
+                        .as_ref()
+                        .map(|part| PartEdit {
+                            name: part.name.clone(),
+                            description: part.description.clone(),
+                            manufacturer: part.manufacturer.clone(),
+                            mpn: part.mpn.clone(),
+                            skus: skus_to_string(&part.skus),
+                            moq: part.purchasing.moq.to_string(),
+                            order_multiple: part.purchasing.order_multiple.to_string(),
+                            safety_stock: part.purchasing.safety_stock.to_string(),
+                            attrition_percent: part.purchasing.attrition_percent.to_string(),
+                        });
+                iced::Task::none()
+            }
+            SearchMessage::CancelEditPart => {
+                self.part_searcher.editing = None;
+                self.edit_error = None;
+                iced::Task::none()
+            }
+            SearchMessage::EditPartName(s) => {
+                if let Some(edit) = &mut self.part_searcher.editing {
+                    edit.name = s;
+                }
+                iced::Task::none()
+            }
+            SearchMessage::EditPartDescription(s) => {
+                if let Some(edit) = &mut self.part_searcher.editing {
+                    edit.description = s;
+                }
+                iced::Task::none()
+            }
+            SearchMessage::EditPartManufacturer(s) => {
+                if let Some(edit) = &mut self.part_searcher.editing {
+                    edit.manufacturer = s;
+                }
+                iced::Task::none()
+            }
+            SearchMessage::EditPartMpn(s) => {
+                if let Some(edit) = &mut self.part_searcher.editing {
+                    edit.mpn = s;
+                }
+                iced::Task::none()
+            }
+            SearchMessage::EditPartSkus(s) => {
+                if let Some(edit) = &mut self.part_searcher.editing {
+                    edit.skus = s;
+                }
+                iced::Task::none()
+            }
+            SearchMessage::EditPartMoq(s) => {
+                if let Some(edit) = &mut self.part_searcher.editing {
+                    edit.moq = s;
+                }
+                iced::Task::none()
+            }
+            SearchMessage::EditPartOrderMultiple(s) => {
+                if let Some(edit) = &mut self.part_searcher.editing {
+                    edit.order_multiple = s;
+                }
+                iced::Task::none()
+            }
+            SearchMessage::EditPartSafetyStock(s) => {
+                if let Some(edit) = &mut self.part_searcher.editing {
+                    edit.safety_stock = s;
+                }
+                iced::Task::none()
+            }
+            SearchMessage::EditPartAttrition(s) => {
+                if let Some(edit) = &mut self.part_searcher.editing {
+                    edit.attrition_percent = s;
+                }
+                iced::Task::none()
+            }
+            SearchMessage::SavePart => {
+                let (Some(part), Some(edit)) = (
+                    &self.part_searcher.selected_part,
+                    &self.part_searcher.editing,
+                ) else {
+                    return iced::Task::none();
+                };
+                let skus = match parse_skus(&edit.skus) {
+                    Ok(skus) => skus,
+                    Err(e) => {
+                        self.edit_error = Some(e);
+                        return iced::Task::none();
+                    }
+                };
+                let purchasing = match parse_purchase_rules(edit) {
+                    Ok(rules) => rules,
+                    Err(e) => {
+                        self.edit_error = Some(e);
+                        return iced::Task::none();
+                    }
+                };
+                let edited = Part {
+                    id: part.id,
+                    name: edit.name.clone(),
+                    description: edit.description.clone(),
+                    manufacturer: edit.manufacturer.clone(),
+                    mpn: edit.mpn.clone(),
+                    skus,
+                    purchasing,
+                    ..Default::default()
+                };
+                iced::Task::perform(
+                    PartSearch::save_part(self.network.clone(), part.name.clone(), edited),
+                    |output| match output {
+                        Ok((part, note)) => SearchMessage::PartSaved(part, note),
+                        Err(e) if is_unauthorized(&e) => SearchMessage::Unauthorized,
+                        Err(e) => SearchMessage::EditFailed(e.to_string()),
+                    },
+                )
+            }
+            SearchMessage::PartSaved(part, note) => {
+                if let Some(selected) = &mut self.part_searcher.selected_part {
+                    selected.name = part.name;
+                    selected.description = part.description;
+                    selected.manufacturer = part.manufacturer;
+                    selected.mpn = part.mpn;
+                    selected.skus = part.skus;
+                    selected.parameters = part.parameters;
+                    selected.purchasing = part.purchasing;
+                }
+                self.part_searcher.editing = None;
+                self.edit_error = note;
+                iced::Task::done(SearchMessage::SubmitQuery)
+            }
+            SearchMessage::StartEditBom => {
+                self.bom_searcher.editing =
+                    self.bom_searcher.expanded.as_ref().map(|bom| BomEdit {
+                        name: bom.name.clone(),
+                        description: bom.description.clone(),
+                        counts: self
+                            .bom_searcher
+                            .parts
+                            .iter()
+                            .map(|p| (p.id, p.count.to_string()))
+                            .collect(),
+                    });
+                iced::Task::none()
+            }
+            SearchMessage::CancelEditBom => {
+                self.bom_searcher.editing = None;
+                self.edit_error = None;
+                iced::Task::none()
+            }
+            SearchMessage::EditBomName(s) => {
+                if let Some(edit) = &mut self.bom_searcher.editing {
+                    edit.name = s;
+                }
+                iced::Task::none()
+            }
+            SearchMessage::EditBomDescription(s) => {
+                if let Some(edit) = &mut self.bom_searcher.editing {
+                    edit.description = s;
+                }
+                iced::Task::none()
+            }
+            SearchMessage::EditBomLineCount(part_id, s) => {
+                if let Some(edit) = &mut self.bom_searcher.editing {
+                    edit.counts.insert(part_id, s);
+                }
+                iced::Task::none()
+            }
+            SearchMessage::SaveBom => {
+                let (Some(bom), Some(edit)) =
+                    (&self.bom_searcher.expanded, &self.bom_searcher.editing)
+                else {
+                    return iced::Task::none();
+                };
+                // Only lines whose count changed are sent
+                let mut counts = vec![];
+                for p in &self.bom_searcher.parts {
+                    match edit.counts.get(&p.id).map(|c| c.trim().parse::<i64>()) {
+                        Some(Ok(count)) if count != p.count => counts.push((p.id, count)),
+                        Some(Err(_)) => {
+                            self.edit_error = Some(format!("Count of {} isn't a number", p.name));
+                            return iced::Task::none();
+                        }
+                        _ => {}
+                    }
+                }
+                let bom = Bom {
+                    name: edit.name.clone(),
+                    description: edit.description.clone(),
+                    ..bom.clone()
+                };
+                iced::Task::perform(
+                    BomSearch::save_bom(self.network.clone(), bom, counts),
+                    |output| match output {
+                        Ok(bom) => SearchMessage::BomSaved(bom),
+                        Err(e) if is_unauthorized(&e) => SearchMessage::Unauthorized,
+                        Err(e) => SearchMessage::EditFailed(e.to_string()),
+                    },
+                )
+            }
+            SearchMessage::BomSaved(bom) => {
+                self.bom_searcher.expanded = Some(bom.clone());
+                self.bom_searcher.editing = None;
+                self.edit_error = None;
+                iced::Task::done(SearchMessage::RefreshBom(bom))
+                    .chain(iced::Task::done(SearchMessage::SubmitQuery))
+            }
+            SearchMessage::EditFailed(e) => {
+                error!("Edit failed: {}", e);
+                self.edit_error = Some(e);
+                iced::Task::none()
+            }
+            SearchMessage::ConfirmDelete(_) => {
+                error!("ConfirmDelete should be consumed by parent");
+                iced::Task::none()
+            }
+            SearchMessage::Delete(deletion) => {
+                self.edit_error = None;
+                iced::Task::perform(
+                    Search::delete(self.network.clone(), deletion.clone()),
+                    move |output| match output {
+                        Ok(()) => SearchMessage::Deleted(deletion.clone()),
+                        Err(e) if is_unauthorized(&e) => SearchMessage::Unauthorized,
+                        Err(e) => SearchMessage::EditFailed(e.to_string()),
+                    },
+                )
+            }
+            SearchMessage::Deleted(deletion) => match deletion {
+                Deletion::Part(_) | Deletion::FromBin(_) => {
+                    iced::Task::done(SearchMessage::CancelPartStock)
+                        .chain(iced::Task::done(SearchMessage::SubmitQuery))
+                }
+                Deletion::Bom(_) => iced::Task::done(SearchMessage::CloseBom),
+                Deletion::BomLine(bom, part) => {
+                    if let Some(edit) = &mut self.bom_searcher.editing {
+                        edit.counts.remove(&part.id);
+                    }
+                    iced::Task::done(SearchMessage::RefreshBom(bom))
+                }
+            },
+        }
+    }
+
+    async fn delete(network: Arc<Mutex<dyn PartsBackend>>, deletion: Deletion) -> Result<()> {
+        let mut network = network.lock().await;
+        let profile_id = match &network.user_data().profile {
+            Some(p) => p.id,
+            None => return Err(anyhow!("No profile selected")),
+        };
+        match deletion {
+            Deletion::Part(part) => network.delete_part(part.id).await?,
+            Deletion::FromBin(part) => network.remove_from_bin(profile_id, part.id).await?,
+            Deletion::Bom(bom) => network.delete_bom(profile_id, bom.id).await?,
+            Deletion::BomLine(bom, part) => {
+                network.delete_bom_line(profile_id, bom.id, part.id).await?
+            }
         }
+        Ok(())
     }
 
     pub fn view(&self, focused: bool) -> iced::Element<'_, SearchMessage> {
@@ -383,11 +912,16 @@ impl Search {
                 .height(Length::Shrink)
                 .align_y(alignment::Vertical::Center),
                 widget::horizontal_rule(4.0),
-                match self.mode {
-                    SearchMode::Parts => self.part_searcher.view(),
-                    SearchMode::Boms => self.bom_searcher.view(),
-                },
             )
+            .push(
+                self.edit_error
+                    .as_ref()
+                    .map(|e| widget::text(e).style(widget::text::danger)),
+            )
+            .push(match self.mode {
+                SearchMode::Parts => self.part_searcher.view(),
+                SearchMode::Boms => self.bom_searcher.view(),
+            })
             .spacing(8.0),
         )
         .height(Length::Fill)
@@ -415,54 +949,179 @@ impl PartSearch {
             stock_row: String::new(),
             stock_column: String::new(),
             stock_z: String::new(),
+            history: vec![],
+            where_used: vec![],
+            reserved: HashMap::new(),
+            editing: None,
         }
     }
     async fn query(
-        network: Arc<Mutex<NetworkClient>>,
+        network: Arc<Mutex<dyn PartsBackend>>,
         query: String,
-    ) -> Result<Vec<PartWithStock>> {
+    ) -> Result<(Vec<PartWithStock>, HashMap<i64, i64>)> {
+        // The backend only matches text, so it narrows the parts down by a plain term and
+        // parameter filters like "R=10k" are applied here
+        let query = PartQuery::parse(&query).map_err(|e| anyhow!(e))?;
+        let term = query.backend_term().map(String::from);
         let mut network = network.lock().await;
-        let out = network
-            .parts_with_stock(Some(query.clone()), Some(query), 1)
+        let profile_id = match &network.user_data().profile {
+            Some(p) => p.id,
+            None => return Err(anyhow!("No profile selected")),
+        };
+        let mut out = network
+            .parts_with_stock(term.clone(), term, profile_id)
             .await?;
-        Ok(out)
+        out.retain(|p| query.matches_stocked(p));
+        let reserved = network
+            .reservations(profile_id)
+            .await?
+            .into_iter()
+            .map(|r| (r.part_id, r.reserved))
+            .collect();
+        Ok((out, reserved))
     }
 
-    async fn change_part_stock(
-        network: Arc<Mutex<NetworkClient>>,
+    fn reserved(&self, part_id: i64) -> i64 {
+        self.reserved.get(&part_id).copied().unwrap_or(0)
+    }
+
+    async fn fetch_history(
+        network: Arc<Mutex<dyn PartsBackend>>,
         part_id: i64,
-        current_stock: i64,
+    ) -> Result<Vec<StockLedgerEntry>> {
+        let mut network = network.lock().await;
+        let profile_id = match &network.user_data().profile {
+            Some(p) => p.id,
+            None => return Err(anyhow!("No profile selected")),
+        };
+        Ok(network.stock_history(profile_id, Some(part_id)).await?)
+    }
+
+    async fn fetch_where_used(
+        network: Arc<Mutex<dyn PartsBackend>>,
+        part_id: i64,
+    ) -> Result<Vec<BomUsage>> {
+        let mut network = network.lock().await;
+        let profile_id = match &network.user_data().profile {
+            Some(p) => p.id,
+            None => return Err(anyhow!("No profile selected")),
+        };
+        Ok(network.where_used(profile_id, part_id).await?)
+    }
+
+    /// Writes the edited fields over the stored part named `name`, keeping the rest of it, like
+    /// its aliases and alternates, as it is
+    async fn save_part(
+        network: Arc<Mutex<dyn PartsBackend>>,
+        name: String,
+        edited: Part,
+    ) -> Result<(Part, Option<String>)> {
+        // Refused rules would otherwise leave the rest of the part saved
+        check_purchase_rules(&edited.purchasing)?;
+        let mut network = network.lock().await;
+        let mut part = network
+            .get_parts(Some(name), None)
+            .await?
+            .into_iter()
+            .find(|p| p.id == edited.id)
+            .ok_or(anyhow!("{} doesn't exist anymore", edited.name))?;
+        part.name = edited.name;
+        part.description = edited.description;
+        part.manufacturer = edited.manufacturer;
+        part.mpn = edited.mpn;
+        part.skus = edited.skus;
+        part.parse_parameters();
+        network.update_part(part.clone()).await?;
+        if part.purchasing == edited.purchasing {
+            return Ok((part, None));
+        }
+        match network.set_purchase_rules(part.id, edited.purchasing).await {
+            Ok(()) => {
+                part.purchasing = edited.purchasing;
+                Ok((part, None))
+            }
+            // The rest of the part is saved, only its rules stay as they were
+            Err(e @ NetworkError::Unsupported(_)) => {
+                Ok((part, Some(format!("{}, the rest of the part is saved", e))))
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    async fn change_part_stock(
+        network: Arc<Mutex<dyn PartsBackend>>,
+        journal: Arc<Mutex<Journal>>,
+        part: PartWithStock,
         diff: i64,
         row: i64,
         column: i64,
         z: i64,
-    ) -> Result<()> {
+    ) -> Result<Submitted> {
+        let mut journal = journal.lock().await;
         let mut network = network.lock().await;
-        let profile_id = match &network.user_data.profile {
+        let profile_id = match &network.user_data().profile {
             Some(p) => p.id,
             None => return Err(anyhow!("No profile selected")),
         };
-        let new_stock = current_stock + diff;
-        network
-            .stock_part(profile_id, part_id, new_stock, column, row, z)
-            .await?;
-        Ok(())
+        let change = StockChange {
+            profile_id,
+            part_id: part.id,
+            name: part.name,
+            expected: part.stock,
+            delta: diff,
+            column,
+            row,
+            z,
+            reason: String::from(if diff < 0 {
+                "manual deplete"
+            } else {
+                "manual restock"
+            }),
+        };
+        Ok(journal.submit(&mut *network, change).await?)
     }
 
     fn view(&self) -> iced::Element<'_, SearchMessage> {
         if let Some(selected_part) = &self.selected_part {
+            let details: iced::Element<'_, SearchMessage> = match &self.editing {
+                Some(edit) => Self::view_edit(edit),
+                None => widget::column![
+                    widget::text(&selected_part.name).size(18.0).font(Font {
+                        weight: Weight::Bold,
+                        ..Default::default()
+                    }),
+                    widget::text(&selected_part.description).size(14.0),
+                    widget::text(part_numbers(selected_part)).size(12.0),
+                    widget::text(selected_part.parameters.to_string()).size(12.0),
+                    widget::text(match self.reserved(selected_part.id) {
+                        0 => format!("Current stock: {}", selected_part.stock),
+                        reserved => format!(
+                            "Current stock: {} ({} reserved for builds)",
+                            selected_part.stock, reserved
+                        ),
+                    })
+                    .size(12.0),
+                ]
+                .width(Length::Fill)
+                .into(),
+            };
+            let edit_button = match &self.editing {
+                Some(_) => widget::button("Save").on_press(SearchMessage::SavePart),
+                None => widget::button("Edit").on_press(SearchMessage::StartEditPart),
+            };
             widget::column![
                 widget::row![
-                    widget::column![
-                        widget::text(&selected_part.name).size(18.0).font(Font {
-                            weight: Weight::Bold,
-                            ..Default::default()
-                        }),
-                        widget::text(&selected_part.description).size(14.0),
-                        widget::text(format!("Current stock: {}", selected_part.stock)).size(12.0),
-                    ]
-                    .width(Length::Fill),
-                    widget::button("Cancel").on_press(SearchMessage::CancelPartStock),
+                    details,
+                    edit_button,
+                    widget::button("Delete")
+                        .style(widget::button::danger)
+                        .on_press(SearchMessage::ConfirmDelete(Deletion::Part(
+                            selected_part.clone()
+                        ))),
+                    widget::button("Cancel").on_press(match &self.editing {
+                        Some(_) => SearchMessage::CancelEditPart,
+                        None => SearchMessage::CancelPartStock,
+                    }),
                 ]
                 .spacing(16.0)
                 .align_y(Alignment::Center),
@@ -524,10 +1183,19 @@ impl PartSearch {
                                 }
                             })
                             .on_press(SearchMessage::DepletePart),
+                        widget::button("Remove from bin")
+                            .style(widget::button::secondary)
+                            .on_press(SearchMessage::ConfirmDelete(Deletion::FromBin(
+                                selected_part.clone()
+                            ))),
                     ]
                     .spacing(16.0),
                 ]
                 .spacing(8.0),
+                widget::horizontal_rule(2.0),
+                self.view_where_used(selected_part.stock - self.reserved(selected_part.id)),
+                widget::horizontal_rule(2.0),
+                self.view_history(),
             ]
             .spacing(16.0)
             .into()
@@ -537,6 +1205,9 @@ impl PartSearch {
                     table_header("Name").width(Length::Fill),
                     table_header("Description").width(Length::Fill),
                     table_header("Stock").width(60.0).align_x(Alignment::End),
+                    table_header("Available")
+                        .width(80.0)
+                        .align_x(Alignment::End),
                     table_header("").width(140.0),
                 ]
                 .spacing(16.0)
@@ -544,12 +1215,26 @@ impl PartSearch {
             ];
             rows.extend(self.matching.iter().map(|p| {
                 let is_hovered = self.hovered_part == Some(p.id);
+                let available = p.stock - self.reserved(p.id);
                 widget::mouse_area(
                     widget::container(
                         widget::row![
-                            widget::text(&p.name).width(Length::Fill),
+                            widget::column![
+                                widget::text(&p.name),
+                                widget::text(part_numbers(p)).size(12.0),
+                            ]
+                            .width(Length::Fill),
                             widget::text(&p.description).width(Length::Fill),
                             widget::text(&p.stock).width(60.0).align_x(Alignment::End),
+                            widget::text(available)
+                                .width(80.0)
+                                .align_x(Alignment::End)
+                                .style(move |theme: &Theme| {
+                                    let palette = theme.extended_palette();
+                                    widget::text::Style {
+                                        color: (available < 0).then_some(palette.danger.base.color),
+                                    }
+                                }),
                             widget::button("Change stock")
                                 .width(140.0)
                                 .on_press(SearchMessage::SelectPart(p.clone())),
@@ -584,6 +1269,127 @@ impl PartSearch {
     }
 }
 
+impl PartSearch {
+    fn view_edit(edit: &PartEdit) -> iced::Element<'_, SearchMessage> {
+        widget::column![
+            widget::text_input("Name", &edit.name).on_input(SearchMessage::EditPartName),
+            widget::text_input("Description", &edit.description)
+                .on_input(SearchMessage::EditPartDescription),
+            widget::row![
+                widget::text_input("Manufacturer", &edit.manufacturer)
+                    .on_input(SearchMessage::EditPartManufacturer),
+                widget::text_input("MPN", &edit.mpn).on_input(SearchMessage::EditPartMpn),
+            ]
+            .spacing(4.0),
+            widget::text_input("Supplier part numbers, e.g. LCSC:C1525", &edit.skus)
+                .on_input(SearchMessage::EditPartSkus),
+            widget::text("Purchasing").size(12.0),
+            widget::row![
+                widget::text_input("MOQ", &edit.moq).on_input(SearchMessage::EditPartMoq),
+                widget::text_input("Order multiple", &edit.order_multiple)
+                    .on_input(SearchMessage::EditPartOrderMultiple),
+                widget::text_input("Safety stock", &edit.safety_stock)
+                    .on_input(SearchMessage::EditPartSafetyStock),
+                widget::text_input("Attrition %", &edit.attrition_percent)
+                    .on_input(SearchMessage::EditPartAttrition),
+            ]
+            .spacing(4.0),
+        ]
+        .spacing(4.0)
+        .width(Length::Fill)
+        .into()
+    }
+
+    /// The BOMs using the selected part, with how many boards `available` of it covers
+    fn view_where_used(&self, available: i64) -> iced::Element<'_, SearchMessage> {
+        let mut rows = vec![
+            widget::text("Used in").size(18.0).into(),
+            widget::row![
+                table_header("BOM").width(Length::Fill),
+                table_header("Per board")
+                    .width(80.0)
+                    .align_x(Alignment::End),
+                table_header("Variants").width(Length::Fill),
+                table_header("Boards covered")
+                    .width(120.0)
+                    .align_x(Alignment::End),
+            ]
+            .spacing(16.0)
+            .into(),
+        ];
+        if self.where_used.is_empty() {
+            rows.push(widget::text("Not used by any BOM").size(12.0).into());
+        }
+        rows.extend(self.where_used.iter().map(|u| {
+            let boards = u.boards(available, None);
+            widget::row![
+                widget::text(format!("{} (rev {})", u.bom_name, u.revision)).width(Length::Fill),
+                widget::text(u.count).width(80.0).align_x(Alignment::End),
+                widget::text(
+                    u.variants
+                        .iter()
+                        .map(|v| format!("{}: {}", v.variant, v.count))
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                )
+                .width(Length::Fill),
+                widget::text(boards.map_or(String::from("-"), |b| b.to_string()))
+                    .width(120.0)
+                    .align_x(Alignment::End)
+                    .style(move |theme: &Theme| {
+                        let palette = theme.extended_palette();
+                        widget::text::Style {
+                            color: (boards == Some(0)).then_some(palette.danger.base.color),
+                        }
+                    }),
+            ]
+            .spacing(16.0)
+            .into()
+        }));
+        widget::column(rows).spacing(4.0).into()
+    }
+
+    fn view_history(&self) -> iced::Element<'_, SearchMessage> {
+        let mut rows = vec![
+            widget::text("History").size(18.0).into(),
+            widget::row![
+                table_header("When").width(130.0),
+                table_header("By").width(Length::Fill),
+                table_header("Change").width(60.0).align_x(Alignment::End),
+                table_header("Stock").width(60.0).align_x(Alignment::End),
+                table_header("Reason").width(Length::FillPortion(2)),
+            ]
+            .spacing(16.0)
+            .into(),
+        ];
+        if self.history.is_empty() {
+            rows.push(widget::text("No recorded stock changes").size(12.0).into());
+        }
+        rows.extend(self.history.iter().map(|e| {
+            widget::row![
+                widget::text(
+                    e.timestamp
+                        .with_timezone(&chrono::Local)
+                        .format("%Y-%m-%d %H:%M")
+                        .to_string()
+                )
+                .width(130.0),
+                widget::text(&e.user).width(Length::Fill),
+                widget::text(format!("{:+}", e.delta))
+                    .width(60.0)
+                    .align_x(Alignment::End),
+                widget::text(e.stock).width(60.0).align_x(Alignment::End),
+                widget::text(&e.reason).width(Length::FillPortion(2)),
+            ]
+            .spacing(16.0)
+            .into()
+        }));
+        widget::scrollable(widget::column(rows).spacing(4.0))
+            .height(Length::Fill)
+            .into()
+    }
+}
+
 impl BomSearch {
     pub fn new() -> Self {
         Self {
@@ -591,38 +1397,126 @@ impl BomSearch {
             expanded: None,
             parts: vec![],
             stock_quantity: String::new(),
+            stock_error: None,
+            substitutions: vec![],
+            variant: None,
+            compare_candidates: vec![],
+            comparison: None,
+            editing: None,
+            buildable: HashMap::new(),
+            expanded_buildable: Buildable::default(),
+            reserved: HashMap::new(),
         }
     }
-    async fn query(network: Arc<Mutex<NetworkClient>>, query: String) -> Result<Vec<Bom>> {
+    async fn query(network: Arc<Mutex<dyn PartsBackend>>, query: String) -> Result<Vec<Bom>> {
         let mut network = network.lock().await;
-        let profile_id = match &network.user_data.profile {
+        let profile_id = match &network.user_data().profile {
             Some(p) => p.id,
             None => return Err(anyhow!("No profile selected")),
         };
-        network.list_boms(profile_id, None, Some(query)).await
+        Ok(network.list_boms(profile_id, None, Some(query)).await?)
     }
 
     async fn fetch_bom_parts(
-        network: Arc<Mutex<NetworkClient>>,
+        network: Arc<Mutex<dyn PartsBackend>>,
         bom: Bom,
     ) -> Result<Vec<PartWithCountAndStock>> {
         let mut network = network.lock().await;
-        let profile_id = match &network.user_data.profile {
+        let profile_id = match &network.user_data().profile {
             Some(p) => p.id,
             None => return Err(anyhow!("No profile selected")),
         };
-        network.parts_in_bom(profile_id, bom.id).await
+        Ok(network.parts_in_bom(profile_id, bom.id).await?)
     }
 
-    async fn change_bom_stock(
-        network: Arc<Mutex<NetworkClient>>,
+    /// Stock open build orders hold, by part id
+    async fn fetch_reserved(network: Arc<Mutex<dyn PartsBackend>>) -> Result<HashMap<i64, i64>> {
+        let mut network = network.lock().await;
+        let profile_id = match &network.user_data().profile {
+            Some(p) => p.id,
+            None => return Err(anyhow!("No profile selected")),
+        };
+        Ok(network
+            .reservations(profile_id)
+            .await?
+            .into_iter()
+            .map(|r| (r.part_id, r.reserved))
+            .collect())
+    }
+
+    /// How many of `bom` can be built from the stock `reserved` leaves, along with its id
+    async fn fetch_buildable(
+        network: Arc<Mutex<dyn PartsBackend>>,
         bom: Bom,
+        reserved: HashMap<i64, i64>,
+    ) -> Result<(i64, Buildable)> {
+        let bom_id = bom.id;
+        let parts = Self::fetch_bom_parts(network, bom).await?;
+        Ok((
+            bom_id,
+            buildable(&parts, &unreserved_stock(&parts, &reserved)),
+        ))
+    }
+
+    /// Saves the name and description of `bom` and the changed line counts, by part id
+    async fn save_bom(
+        network: Arc<Mutex<dyn PartsBackend>>,
+        bom: Bom,
+        counts: Vec<(i64, i64)>,
+    ) -> Result<Bom> {
+        let mut network = network.lock().await;
+        let profile_id = match &network.user_data().profile {
+            Some(p) => p.id,
+            None => return Err(anyhow!("No profile selected")),
+        };
+        network
+            .update_bom(
+                profile_id,
+                bom.id,
+                bom.name.clone(),
+                bom.description.clone(),
+            )
+            .await?;
+        for (part_id, count) in counts {
+            network
+                .update_bom_line(profile_id, bom.id, part_id, count)
+                .await?;
+        }
+        Ok(bom)
+    }
+
+    /// Works out how many of the expanded BOM's variant the unreserved stock covers, once its
+    /// lines, variant or the reservations change
+    fn update_buildable(&mut self) {
+        let fitted = select_variant(&self.parts, self.variant.as_deref());
+        self.expanded_buildable = buildable(&fitted, &unreserved_stock(&fitted, &self.reserved));
+    }
+
+    /// Shows `diff` more of a part on its line and wherever it stands in as an alternate
+    fn change_shown_stock(&mut self, part_id: i64, diff: i64) {
+        for line in self.parts.iter_mut() {
+            if line.id == part_id {
+                line.stock += diff;
+            }
+            for alternate in line.alternates.iter_mut().filter(|a| a.id == part_id) {
+                alternate.stock += diff;
+            }
+        }
+    }
+
+    async fn change_bom_stock(
+        network: Arc<Mutex<dyn PartsBackend>>,
+        journal: Arc<Mutex<Journal>>,
         parts: Vec<PartWithCountAndStock>,
         diff: i64,
-    ) -> Result<()> {
+        reason: String,
+    ) -> Result<Submitted> {
+        let mut journal = journal.lock().await;
         let mut network = network.lock().await;
-        let profile_id = network.user_data.profile.as_ref().unwrap().id;
-        network.stock_parts(profile_id, &parts, diff).await
+        let profile_id = network.user_data().profile.as_ref().unwrap().id;
+        Ok(journal
+            .submit_batch(&mut *network, profile_id, &parts, diff, reason)
+            .await?)
     }
 
     fn view(&self) -> iced::Element<'_, SearchMessage> {
@@ -637,6 +1531,11 @@ impl BomSearch {
             widget::row![
                 table_header("Name").width(Length::Fill),
                 table_header("Description").width(Length::Fill),
+                table_header("Rev").width(40.0).align_x(Alignment::End),
+                table_header("Buildable")
+                    .width(80.0)
+                    .align_x(Alignment::End),
+                table_header("Limited by").width(Length::Fill),
                 table_header("").width(140.0),
             ]
             .spacing(16.0)
@@ -644,9 +1543,22 @@ impl BomSearch {
             widget::vertical_space().height(4.0).into(),
         ];
         rows.extend(self.matching.iter().map(|p| {
+            let buildable = self.buildable.get(&p.id);
+            let limiting = buildable.map_or(String::new(), |b| {
+                b.limiting
+                    .iter()
+                    .map(|l| l.name.as_str())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            });
             widget::row![
                 widget::text(&p.name).width(Length::Fill),
                 widget::text(&p.description).width(Length::Fill),
+                widget::text(p.revision).width(40.0).align_x(Alignment::End),
+                widget::text(buildable_quantity(buildable))
+                    .width(80.0)
+                    .align_x(Alignment::End),
+                widget::text(limiting).width(Length::Fill),
                 widget::button("Open")
                     .width(140.0)
                     .on_press(SearchMessage::OpenBom(p.clone())),
@@ -659,44 +1571,169 @@ impl BomSearch {
     }
 
     fn view_bom_contents(&self, bom: &Bom) -> iced::Element<'_, SearchMessage> {
-        let mut rows = vec![
-            widget::row![
+        let (title, description): (
+            iced::Element<'_, SearchMessage>,
+            iced::Element<'_, SearchMessage>,
+        ) = match &self.editing {
+            Some(edit) => (
+                widget::text_input("Name", &edit.name)
+                    .on_input(SearchMessage::EditBomName)
+                    .width(Length::Fill)
+                    .into(),
+                widget::text_input("Description", &edit.description)
+                    .on_input(SearchMessage::EditBomDescription)
+                    .into(),
+            ),
+            None => (
                 widget::text(format!("{}", bom.name))
                     .width(Length::Fill)
-                    .size(36.0),
-                widget::text_input("", &self.stock_quantity)
-                    .width(60.0)
-                    .on_input(SearchMessage::StockQuantity),
-                widget::button("Restock")
-                    .width(80.0)
-                    .on_press(SearchMessage::RestockBom(self.expanded.clone().unwrap())),
-                widget::button("Deplete")
-                    .width(80.0)
-                    .on_press(SearchMessage::DepleteBom(self.expanded.clone().unwrap())),
-            ]
-            .spacing(4.0)
-            .align_y(Alignment::Center)
-            .into(),
+                    .size(36.0)
+                    .into(),
+                widget::text(format!("{}", bom.description)).into(),
+            ),
+        };
+        let edit_buttons = match &self.editing {
+            Some(_) => widget::row![
+                widget::button("Save").on_press(SearchMessage::SaveBom),
+                widget::button("Cancel").on_press(SearchMessage::CancelEditBom),
+            ],
+            None => widget::row![
+                widget::button("Edit").on_press(SearchMessage::StartEditBom),
+                widget::button("Delete")
+                    .style(widget::button::danger)
+                    .on_press(SearchMessage::ConfirmDelete(Deletion::Bom(bom.clone()))),
+            ],
+        }
+        .spacing(4.0);
+        let fitted = select_variant(&self.parts, self.variant.as_deref());
+        let buildable = &self.expanded_buildable;
+        let buildable_text = if self.parts.is_empty() {
+            String::new()
+        } else if buildable.limiting.is_empty() {
+            format!("Buildable: {}", buildable_quantity(Some(buildable)))
+        } else {
+            format!(
+                "Buildable: {}, limited by {}",
+                buildable_quantity(Some(buildable)),
+                buildable.limiting_to_string()
+            )
+        };
+        let mut rows = vec![
+            widget::row![title]
+                .push(variant_picker(
+                    bom,
+                    self.variant.as_deref(),
+                    SearchMessage::SelectVariant,
+                ))
+                .push(
+                    widget::pick_list(
+                        self.compare_candidates
+                            .iter()
+                            .cloned()
+                            .map(BomOption)
+                            .collect::<Vec<_>>(),
+                        self.comparison
+                            .as_ref()
+                            .map(|(other, _)| BomOption(other.clone())),
+                        SearchMessage::CompareBom,
+                    )
+                    .placeholder("Compare to"),
+                )
+                .push(
+                    widget::text_input("", &self.stock_quantity)
+                        .width(60.0)
+                        .on_input(SearchMessage::StockQuantity),
+                )
+                .push(
+                    widget::button("Restock")
+                        .width(80.0)
+                        .on_press(SearchMessage::RestockBom(self.expanded.clone().unwrap())),
+                )
+                .push(
+                    widget::button("Deplete")
+                        .width(80.0)
+                        .on_press(SearchMessage::DepleteBom(self.expanded.clone().unwrap())),
+                )
+                .push(edit_buttons)
+                .spacing(4.0)
+                .align_y(Alignment::Center)
+                .into(),
             widget::vertical_space().height(12.0).into(),
-            widget::text(format!("{}", bom.description)).into(),
+            description,
+            widget::text(buildable_text).into(),
+            widget::text(self.stock_error.clone().unwrap_or_default())
+                .style(|theme: &Theme| {
+                    let palette = theme.extended_palette();
+                    widget::text::Style {
+                        color: Some(palette.danger.base.color),
+                    }
+                })
+                .into(),
+            widget::text(if self.substitutions.is_empty() {
+                String::new()
+            } else {
+                format!(
+                    "Used alternates: {}",
+                    self.substitutions
+                        .iter()
+                        .map(|s| s.to_string())
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                )
+            })
+            .style(widget::text::warning)
+            .into(),
             widget::vertical_space().height(8.0).into(),
             widget::horizontal_rule(8.0).into(),
             widget::vertical_space().height(8.0).into(),
+        ];
+
+        if let Some((other, diff)) = &self.comparison {
+            rows.push(self.view_comparison(other, diff));
+            return widget::column(rows).into();
+        }
+        rows.push(
             widget::row![
                 table_header("Name").width(Length::Fill),
                 table_header("Description").width(Length::Fill),
+                table_header("Designators").width(Length::Fill),
+                table_header("Alternates").width(Length::Fill),
                 table_header("Count").width(60.0).align_x(Alignment::End),
                 table_header("Stock").width(60.0).align_x(Alignment::End),
             ]
+            .push(self.editing.as_ref().map(|_| table_header("").width(80.0)))
             .spacing(16.0)
             .padding(Padding::default().right(16.0))
             .into(),
-        ];
+        );
 
         let mut parts = widget::column(vec![]);
-        parts = parts.extend(self.parts.iter().map(|p| {
+        // Lines the selected variant doesn't fit are left out
+        parts = parts.extend(fitted.into_iter().map(|p| {
+            let count: iced::Element<'_, SearchMessage> = match &self.editing {
+                Some(edit) => {
+                    let id = p.id;
+                    widget::text_input("", edit.counts.get(&id).map_or("", |c| c.as_str()))
+                        .on_input(move |c| SearchMessage::EditBomLineCount(id, c))
+                        .width(60.0)
+                        .into()
+                }
+                None => widget::text(p.count)
+                    .width(60.0)
+                    .align_x(Alignment::End)
+                    .into(),
+            };
+            let remove = self.editing.as_ref().map(|_| {
+                widget::button("Remove")
+                    .width(80.0)
+                    .style(widget::button::danger)
+                    .on_press(SearchMessage::ConfirmDelete(Deletion::BomLine(
+                        bom.clone(),
+                        p.clone(),
+                    )))
+            });
             widget::row![
-                widget::button(widget::text(&p.name))
+                widget::button(widget::text(p.name.clone()))
                     .width(Length::Fill)
                     .on_press(SearchMessage::CopyToClipboard(p.name.clone()))
                     .style(|theme: &Theme, status| {
@@ -707,10 +1744,20 @@ impl BomSearch {
                             ..Default::default()
                         }
                     }),
-                widget::text(&p.description).width(Length::Fill),
-                widget::text(&p.count).width(60.0).align_x(Alignment::End),
-                widget::text(&p.stock).width(60.0).align_x(Alignment::End),
+                widget::text(p.description).width(Length::Fill),
+                widget::text(designators_to_string(&p.designators)).width(Length::Fill),
+                widget::text(
+                    p.alternates
+                        .iter()
+                        .map(|a| format!("{} ({})", a.name, a.stock))
+                        .collect::<Vec<_>>()
+                        .join(", "),
+                )
+                .width(Length::Fill),
+                count,
+                widget::text(p.stock).width(60.0).align_x(Alignment::End),
             ]
+            .push(remove)
             .align_y(Alignment::Center)
             .spacing(16.0)
             .padding(Padding::default().right(16.0))
@@ -726,6 +1773,168 @@ impl BomSearch {
 
         widget::column(rows).into()
     }
+
+    fn view_comparison<'a>(
+        &'a self,
+        other: &'a Bom,
+        diff: &'a [BomDiffLine],
+    ) -> iced::Element<'a, SearchMessage> {
+        let header = widget::row![
+            table_header("Change").width(80.0),
+            table_header("Name").width(Length::Fill),
+            table_header("Description").width(Length::Fill),
+            table_header("Designators").width(Length::Fill),
+            table_header("Was").width(60.0).align_x(Alignment::End),
+            table_header("Now").width(60.0).align_x(Alignment::End),
+        ]
+        .spacing(16.0)
+        .padding(Padding::default().right(16.0));
+        let lines = diff.iter().map(|l| {
+            let change = l.change;
+            let designators = l
+                .added_designators
+                .iter()
+                .map(|d| format!("+{}", d))
+                .chain(l.removed_designators.iter().map(|d| format!("-{}", d)))
+                .collect::<Vec<_>>()
+                .join(" ");
+            widget::row![
+                widget::text(change.to_string())
+                    .width(80.0)
+                    .style(move |theme: &Theme| {
+                        let palette = theme.extended_palette();
+                        widget::text::Style {
+                            color: Some(match change {
+                                BomChange::Added => palette.success.base.color,
+                                BomChange::Removed => palette.danger.base.color,
+                                BomChange::Changed => palette.primary.base.color,
+                            }),
+                        }
+                    }),
+                widget::text(&l.name).width(Length::Fill),
+                widget::text(&l.description).width(Length::Fill),
+                widget::text(designators).width(Length::Fill),
+                widget::text(l.old_count)
+                    .width(60.0)
+                    .align_x(Alignment::End),
+                widget::text(l.new_count)
+                    .width(60.0)
+                    .align_x(Alignment::End),
+            ]
+            .align_y(Alignment::Center)
+            .spacing(16.0)
+            .padding(Padding::default().right(16.0))
+            .into()
+        });
+        let summary = if diff.is_empty() {
+            format!("Same lines as {}", BomOption(other.clone()))
+        } else {
+            format!("Changes since {}", BomOption(other.clone()))
+        };
+        widget::column![
+            widget::text(summary),
+            widget::vertical_space().height(8.0),
+            header,
+            widget::scrollable(widget::column(lines)).height(Length::Fill),
+            widget::container(
+                widget::row![
+                    widget::button("Back to BOM").on_press(SearchMessage::CloseComparison),
+                    widget::button("Close").on_press(SearchMessage::CloseBom),
+                ]
+                .spacing(8.0)
+            )
+            .center_x(Length::Fill),
+        ]
+        .into()
+    }
+}
+
+/// The buildable quantity of a BOM, blank until it is known
+fn buildable_quantity(buildable: Option<&Buildable>) -> String {
+    match buildable.map(|b| b.quantity) {
+        Some(Some(quantity)) => quantity.to_string(),
+        Some(None) => String::from("Any"),
+        None => String::new(),
+    }
+}
+
+/// Manufacturer, MPN and supplier part numbers on one line
+fn part_numbers(part: &PartWithStock) -> String {
+    let mut out = format!("{} {}", part.manufacturer, part.mpn)
+        .trim()
+        .to_string();
+    if !part.skus.is_empty() {
+        if !out.is_empty() {
+            out.push_str(" | ");
+        }
+        out.push_str(&skus_to_string(&part.skus));
+    }
+    out
+}
+
+/// Supplier part numbers as typed, comma separated
+fn parse_skus(text: &str) -> Result<Vec<SupplierSku>, String> {
+    text.split(',')
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+        .map(str::parse)
+        .collect()
+}
+
+/// The purchasing rules typed into `edit`, blank fields meaning none
+fn parse_purchase_rules(edit: &PartEdit) -> Result<PurchaseRules, String> {
+    let quantity = |text: &str, what: &str| -> Result<i64, String> {
+        match text.trim() {
+            "" => Ok(0),
+            text => text
+                .parse()
+                .map_err(|_| format!("{} has to be a whole number", what)),
+        }
+    };
+    let attrition_percent = match edit.attrition_percent.trim() {
+        "" => 0.0,
+        text => text
+            .trim_end_matches('%')
+            .parse()
+            .map_err(|_| String::from("Attrition has to be a percentage"))?,
+    };
+    Ok(PurchaseRules {
+        moq: quantity(&edit.moq, "MOQ")?,
+        order_multiple: quantity(&edit.order_multiple, "Order multiple")?,
+        safety_stock: quantity(&edit.safety_stock, "Safety stock")?,
+        attrition_percent,
+    })
+}
+
+/// Label of the base design in assembly variant pickers
+pub const BASE_DESIGN: &str = "Base design";
+
+/// Picks between the base design and the assembly variants of `bom`, if it has any
+pub fn variant_picker<'a, M: Clone + 'a>(
+    bom: &Bom,
+    selected: Option<&str>,
+    on_select: impl Fn(Option<String>) -> M + 'a,
+) -> Option<iced::Element<'a, M>> {
+    if bom.variants.is_empty() {
+        return None;
+    }
+    let mut options = vec![BASE_DESIGN.to_string()];
+    options.extend(bom.variants.iter().cloned());
+    let selected = selected.unwrap_or(BASE_DESIGN).to_string();
+    Some(
+        widget::pick_list(options, Some(selected), move |choice: String| {
+            on_select(Some(choice).filter(|c| c != BASE_DESIGN))
+        })
+        .into(),
+    )
+}
+
+/// A BOM's name along with the assembly variant, if any
+fn variant_name(bom: &Bom, variant: Option<&str>) -> String {
+    match variant {
+        Some(variant) => format!("{} ({})", bom.name, variant),
+        None => bom.name.clone(),
+    }
 }
 
 pub fn table_header(label: &str) -> widget::Text {
@@ -1,5 +1,5 @@
 use anyhow::Result;
-use std::{fs, str::FromStr};
+use std::{fs, path::PathBuf, str::FromStr};
 
 use keybinds::Keybinds;
 use logos::{Logos, Skip};
@@ -104,8 +104,7 @@ impl FromStr for Config {
                                 out.grid.zs = args[2].parse()?;
                             }
                             Command::SetServer => {
-                                assert!(args.len() == 1, "SetServer requires 1 argument");
-                                out.server_kind = ServerKind::from_str(&args[0]).unwrap();
+                                out.server_kind = ServerKind::from_args(&args);
                             }
                         }
                     } else {
@@ -145,8 +144,22 @@ enum Command {
     SetServer,
 }
 
-#[derive(Debug, EnumString, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, EnumString, Clone, PartialEq, Eq)]
 pub enum ServerKind {
     Production,
     Development,
+    /// No server at all, the inventory lives in a SQLite file at the given path.
+    Local(PathBuf),
+}
+
+impl ServerKind {
+    fn from_args(args: &[String]) -> Self {
+        assert!(!args.is_empty(), "SetServer requires at least 1 argument");
+        match (Self::from_str(&args[0]).unwrap(), &args[1..]) {
+            (Self::Local(_), [path]) => Self::Local(PathBuf::from(path)),
+            (Self::Local(_), _) => panic!("SetServer Local requires a path"),
+            (kind, []) => kind,
+            (_, _) => panic!("SetServer requires 1 argument"),
+        }
+    }
 }
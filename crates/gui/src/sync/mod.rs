@@ -0,0 +1,21 @@
+use common::journal::{Conflict, JournalEntry, ReplaySummary, Resolution};
+
+pub mod widget;
+
+#[derive(Debug, Clone)]
+pub enum Msg {
+    // Journal contents
+    Reload,
+    Loaded(Vec<JournalEntry>, Vec<Conflict>),
+
+    // Replaying
+    Replay,
+    Replayed(ReplaySummary),
+    ReplayFailed(String),
+
+    // Reconciliation
+    Resolve(u64, Resolution),
+
+    // Session
+    Unauthorized,
+}
@@ -0,0 +1,285 @@
+use std::{
+    sync::Arc,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::Result;
+use common::{
+    backend::PartsBackend,
+    error::is_unauthorized,
+    journal::{Conflict, ConflictReason, Journal, JournalEntry, ReplaySummary, Resolution},
+};
+use iced::{Alignment, Border, Length, Padding, Theme, widget};
+use tokio::sync::Mutex;
+use tracing::error;
+
+use crate::search::widget::table_header;
+
+use super::Msg;
+
+/// Shows stock changes that are waiting for the server, and lets the user settle the ones that
+/// clashed with changes made elsewhere in the meantime.
+#[derive(Debug)]
+pub struct Reconciliation {
+    network: Arc<Mutex<dyn PartsBackend>>,
+    journal: Arc<Mutex<Journal>>,
+    pending: Vec<JournalEntry>,
+    conflicts: Vec<Conflict>,
+    replaying: bool,
+    status: Option<String>,
+}
+
+fn bin_label(entry: &JournalEntry) -> String {
+    format!(
+        "{}, {}, {}",
+        entry.change.row, entry.change.column, entry.change.z
+    )
+}
+
+fn signed(delta: i64) -> String {
+    format!("{:+}", delta)
+}
+
+fn age(queued_at: u64) -> String {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default();
+    let minutes = now.saturating_sub(queued_at) / 60;
+    match minutes {
+        0 => String::from("just now"),
+        1..60 => format!("{} min ago", minutes),
+        60..1440 => format!("{} h ago", minutes / 60),
+        _ => format!("{} days ago", minutes / 1440),
+    }
+}
+
+impl Reconciliation {
+    pub fn new(network: Arc<Mutex<dyn PartsBackend>>, journal: Arc<Mutex<Journal>>) -> Self {
+        Self {
+            network,
+            journal,
+            pending: vec![],
+            conflicts: vec![],
+            replaying: false,
+            status: None,
+        }
+    }
+
+    pub fn pending_count(&self) -> usize {
+        self.pending.len()
+    }
+
+    pub fn conflict_count(&self) -> usize {
+        self.conflicts.len()
+    }
+
+    pub fn update(&mut self, msg: Msg) -> iced::Task<Msg> {
+        match msg {
+            Msg::Reload => iced::Task::perform(Self::snapshot(self.journal.clone()), |(p, c)| {
+                Msg::Loaded(p, c)
+            }),
+            Msg::Loaded(pending, conflicts) => {
+                self.pending = pending;
+                self.conflicts = conflicts;
+                iced::Task::none()
+            }
+            Msg::Replay => {
+                if self.replaying {
+                    return iced::Task::none();
+                }
+                self.replaying = true;
+                iced::Task::perform(
+                    Self::replay(self.network.clone(), self.journal.clone()),
+                    |result| match result {
+                        Ok(summary) => Msg::Replayed(summary),
+                        Err(e) if is_unauthorized(&e) => Msg::Unauthorized,
+                        Err(e) => Msg::ReplayFailed(e.to_string()),
+                    },
+                )
+            }
+            Msg::Replayed(summary) => {
+                self.replaying = false;
+                self.status = Some(format!(
+                    "Synced {} change(s), {} conflict(s)",
+                    summary.applied, summary.conflicts
+                ));
+                iced::Task::done(Msg::Reload)
+            }
+            Msg::ReplayFailed(e) => {
+                self.replaying = false;
+                error!("Replaying queued stock changes failed: {}", e);
+                self.status = Some(format!("Server still unreachable: {}", e));
+                iced::Task::done(Msg::Reload)
+            }
+            Msg::Resolve(entry_id, resolution) => iced::Task::perform(
+                Self::resolve(self.journal.clone(), entry_id, resolution),
+                move |_| match resolution {
+                    Resolution::KeepMine => Msg::Replay,
+                    Resolution::KeepServer => Msg::Reload,
+                },
+            ),
+            Msg::Unauthorized => {
+                // Handled in app.rs by reopening the login modal
+                self.replaying = false;
+                iced::Task::none()
+            }
+        }
+    }
+
+    pub fn view(&self) -> iced::Element<'_, Msg> {
+        let mut content = widget::column![
+            widget::row![
+                widget::text("Sync").size(36.0).width(Length::Fill),
+                widget::button(if self.replaying {
+                    "Syncing..."
+                } else {
+                    "Sync now"
+                })
+                .on_press_maybe((!self.replaying).then_some(Msg::Replay)),
+            ]
+            .align_y(Alignment::Center),
+            widget::text(self.status.clone().unwrap_or_default()).size(14.0),
+            widget::vertical_space().height(16.0),
+        ]
+        .spacing(8.0);
+
+        content = content.push(widget::text("Queued changes").size(20.0));
+        if self.pending.is_empty() {
+            content = content.push(widget::text("Everything has reached the server"));
+        } else {
+            content = content.push(
+                widget::row![
+                    table_header("Part").width(Length::Fill),
+                    table_header("Bin").width(100.0),
+                    table_header("Change").width(80.0).align_x(Alignment::End),
+                    table_header("Queued").width(100.0),
+                    table_header("Last error").width(Length::Fill),
+                ]
+                .spacing(16.0),
+            );
+            let mut rows = widget::column![].spacing(4.0);
+            for entry in &self.pending {
+                rows = rows.push(
+                    widget::row![
+                        widget::text(&entry.change.name).width(Length::Fill),
+                        widget::text(bin_label(entry)).width(100.0),
+                        widget::text(signed(entry.change.delta))
+                            .width(80.0)
+                            .align_x(Alignment::End),
+                        widget::text(age(entry.queued_at)).width(100.0),
+                        widget::text(entry.last_error.clone().unwrap_or_default())
+                            .width(Length::Fill),
+                    ]
+                    .spacing(16.0),
+                );
+            }
+            content = content.push(widget::scrollable(rows).height(Length::FillPortion(1)));
+        }
+
+        content = content.push(widget::vertical_space().height(8.0));
+        content = content.push(widget::horizontal_rule(2.0));
+        content = content.push(widget::vertical_space().height(8.0));
+
+        content = content.push(widget::text("Conflicts").size(20.0));
+        if self.conflicts.is_empty() {
+            content = content.push(widget::text("No conflicts"));
+        } else {
+            content = content.push(
+                widget::row![
+                    table_header("Part").width(Length::Fill),
+                    table_header("Based on").width(80.0).align_x(Alignment::End),
+                    table_header("Server").width(80.0).align_x(Alignment::End),
+                    table_header("Change").width(80.0).align_x(Alignment::End),
+                    table_header("").width(240.0),
+                ]
+                .spacing(8.0)
+                .padding(Padding::default().right(16.0)),
+            );
+            let mut rows = widget::column![].spacing(4.0);
+            for conflict in &self.conflicts {
+                let change = &conflict.entry.change;
+                let (server, detail) = match &conflict.reason {
+                    ConflictReason::StockChanged { found } => (found.to_string(), None),
+                    ConflictReason::Rejected(reason) => (String::from("-"), Some(reason)),
+                };
+                let mut part = widget::column![widget::text(&change.name)].width(Length::Fill);
+                if let Some(reason) = detail {
+                    part = part.push(
+                        widget::text(format!("Rejected: {}", reason))
+                            .size(12.0)
+                            .style(|theme: &Theme| {
+                                let palette = theme.extended_palette();
+                                widget::text::Style {
+                                    color: Some(palette.danger.base.color),
+                                }
+                            }),
+                    );
+                }
+                rows = rows.push(
+                    widget::row![
+                        part,
+                        widget::text(change.expected.to_string())
+                            .width(80.0)
+                            .align_x(Alignment::End),
+                        widget::text(server)
+                            .width(80.0)
+                            .align_x(Alignment::End)
+                            .style(|theme: &Theme| {
+                                let palette = theme.extended_palette();
+                                widget::text::Style {
+                                    color: Some(palette.warning.base.color),
+                                }
+                            }),
+                        widget::text(signed(change.delta))
+                            .width(80.0)
+                            .align_x(Alignment::End),
+                        widget::button("Keep mine")
+                            .width(116.0)
+                            .on_press(Msg::Resolve(conflict.entry.id, Resolution::KeepMine)),
+                        widget::button("Keep server")
+                            .width(116.0)
+                            .on_press(Msg::Resolve(conflict.entry.id, Resolution::KeepServer)),
+                    ]
+                    .spacing(8.0)
+                    .align_y(Alignment::Center)
+                    .padding(Padding::default().right(16.0)),
+                );
+            }
+            content = content.push(widget::scrollable(rows).height(Length::FillPortion(1)));
+        }
+
+        widget::container(content)
+            .height(Length::Fill)
+            .width(Length::Fill)
+            .style(|theme: &Theme| {
+                let palette = theme.extended_palette();
+                widget::container::Style {
+                    text_color: Some(palette.background.weak.text),
+                    background: Some(palette.background.weak.color.into()),
+                    border: Border::default().rounded(8.0),
+                    ..Default::default()
+                }
+            })
+            .padding(16.0)
+            .into()
+    }
+
+    async fn snapshot(journal: Arc<Mutex<Journal>>) -> (Vec<JournalEntry>, Vec<Conflict>) {
+        let journal = journal.lock().await;
+        (journal.pending().to_vec(), journal.conflicts().to_vec())
+    }
+
+    async fn replay(
+        network: Arc<Mutex<dyn PartsBackend>>,
+        journal: Arc<Mutex<Journal>>,
+    ) -> Result<ReplaySummary> {
+        let mut journal = journal.lock().await;
+        let mut network = network.lock().await;
+        Ok(journal.replay(&mut *network).await?)
+    }
+
+    async fn resolve(journal: Arc<Mutex<Journal>>, entry_id: u64, resolution: Resolution) {
+        journal.lock().await.resolve(entry_id, resolution);
+    }
+}